@@ -0,0 +1,125 @@
+//! Writing and reading large strings, the cases where copying them shows, and
+//! collecting short ones, where formatting them does.
+//!
+//! Run with `cargo bench --bench strings`.
+
+#![feature(test)]
+
+extern crate bincode2;
+extern crate serde;
+extern crate test;
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+use test::{black_box, Bencher};
+
+const LEN: usize = 1 << 20;
+
+fn text() -> String {
+    "bincode2 writes strings without copying them. ".repeat(LEN / 48)
+}
+
+/// Serialized with `collect_str` rather than `serialize_str`.
+struct Collected<'a>(&'a str);
+
+impl<'a> fmt::Display for Collected<'a> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str(self.0)
+    }
+}
+
+impl<'a> Serialize for Collected<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Serialized as serde's default `collect_str` does, through a `String`.
+struct Allocated<'a>(&'a str);
+
+impl<'a> Serialize for Allocated<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&Collected(self.0).to_string())
+    }
+}
+
+fn words() -> Vec<String> {
+    (0..4096).map(|i| format!("word {}", i)).collect()
+}
+
+#[bench]
+fn serialize_str(b: &mut Bencher) {
+    let text = text();
+    let config = bincode2::config();
+    let mut bytes = Vec::with_capacity(text.len() + 8);
+    b.bytes = text.len() as u64;
+    b.iter(|| {
+        bytes.clear();
+        config.serialize_into(&mut bytes, &text[..]).unwrap();
+        black_box(&bytes);
+    });
+}
+
+#[bench]
+fn collect_str(b: &mut Bencher) {
+    let text = text();
+    let config = bincode2::config();
+    let mut bytes = Vec::with_capacity(text.len() + 8);
+    b.bytes = text.len() as u64;
+    b.iter(|| {
+        bytes.clear();
+        config
+            .serialize_into(&mut bytes, &Collected(&text))
+            .unwrap();
+        black_box(&bytes);
+    });
+}
+
+#[bench]
+fn deserialize_borrowed_str(b: &mut Bencher) {
+    let text = text();
+    let config = bincode2::config();
+    let bytes = config.serialize(&text).unwrap();
+    b.bytes = text.len() as u64;
+    b.iter(|| black_box(config.deserialize::<&str>(&bytes).unwrap().len()));
+}
+
+#[bench]
+fn deserialize_string(b: &mut Bencher) {
+    let text = text();
+    let config = bincode2::config();
+    let bytes = config.serialize(&text).unwrap();
+    b.bytes = text.len() as u64;
+    b.iter(|| black_box(config.deserialize::<String>(&bytes).unwrap().len()));
+}
+
+#[bench]
+fn collect_short_str(b: &mut Bencher) {
+    let words = words();
+    let config = bincode2::config();
+    let mut bytes = Vec::with_capacity(words.len() * 24);
+    b.bytes = words.iter().map(|word| word.len() as u64).sum();
+    b.iter(|| {
+        bytes.clear();
+        for word in &words {
+            config.serialize_into(&mut bytes, &Collected(word)).unwrap();
+        }
+        black_box(&bytes);
+    });
+}
+
+#[bench]
+fn to_string_short_str(b: &mut Bencher) {
+    let words = words();
+    let config = bincode2::config();
+    let mut bytes = Vec::with_capacity(words.len() * 24);
+    b.bytes = words.iter().map(|word| word.len() as u64).sum();
+    b.iter(|| {
+        bytes.clear();
+        for word in &words {
+            config.serialize_into(&mut bytes, &Allocated(word)).unwrap();
+        }
+        black_box(&bytes);
+    });
+}
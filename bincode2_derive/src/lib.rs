@@ -0,0 +1,181 @@
+//! `#[derive(Encode, Decode)]` for bincode2.
+//!
+//! Use it through the `derive` feature of bincode2 rather than directly. The
+//! generated code writes fields in declaration order and enum variants as their
+//! index, which is the same layout serde's derives produce.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use syn::{parse_quote, Data, DeriveInput, Error, Fields, Generics, Ident, Index};
+
+/// Derives `bincode2::Encode`.
+#[proc_macro_derive(Encode)]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand_encode(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Derives `bincode2::Decode`.
+#[proc_macro_derive(Decode)]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand_decode(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Requires every type parameter to implement `bound`.
+fn add_bounds(generics: &Generics, bound: TokenStream2) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(#bound));
+    }
+    generics
+}
+
+/// Names for the fields of a variant when they are bound in a match arm.
+fn bindings(fields: &Fields) -> Vec<Ident> {
+    (0..fields.len())
+        .map(|i| Ident::new(&format!("__field{}", i), Span::call_site()))
+        .collect()
+}
+
+/// The pattern matching a variant by reference, binding its fields.
+fn pattern(fields: &Fields, bindings: &[Ident]) -> TokenStream2 {
+    match *fields {
+        Fields::Named(ref named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!({ #(#names: ref #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!((#(ref #bindings),*)),
+        Fields::Unit => quote!(),
+    }
+}
+
+/// Decodes the fields of a struct or variant in order.
+fn construct(fields: &Fields) -> TokenStream2 {
+    let decode = quote!(::bincode2::Decode::decode(decoder)?);
+    match *fields {
+        Fields::Named(ref named) => {
+            let names = named.named.iter().map(|field| &field.ident);
+            quote!({ #(#names: #decode),* })
+        }
+        Fields::Unnamed(ref unnamed) => {
+            let decodes = unnamed.unnamed.iter().map(|_| &decode);
+            quote!((#(#decodes),*))
+        }
+        Fields::Unit => quote!(),
+    }
+}
+
+fn expand_encode(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let members: Vec<TokenStream2> = match data.fields {
+                Fields::Named(ref named) => named
+                    .named
+                    .iter()
+                    .map(|field| {
+                        let ident = &field.ident;
+                        quote!(#ident)
+                    })
+                    .collect(),
+                _ => (0..data.fields.len())
+                    .map(|i| {
+                        let index = Index::from(i);
+                        quote!(#index)
+                    })
+                    .collect(),
+            };
+            quote! {
+                #(::bincode2::Encode::encode(&self.#members, encoder)?;)*
+                Ok(())
+            }
+        }
+        Data::Enum(ref data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let index = index as u32;
+                let ident = &variant.ident;
+                let bindings = bindings(&variant.fields);
+                let pattern = pattern(&variant.fields, &bindings);
+                quote! {
+                    #name::#ident #pattern => {
+                        ::bincode2::Encoder::encode_variant(encoder, #index)?;
+                        #(::bincode2::Encode::encode(#bindings, encoder)?;)*
+                    }
+                }
+            });
+            quote! {
+                match *self {
+                    #(#arms)*
+                }
+                Ok(())
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                input,
+                "bincode2 cannot derive Encode for unions",
+            ))
+        }
+    };
+
+    let generics = add_bounds(&input.generics, quote!(::bincode2::Encode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bincode2::Encode for #name #ty_generics #where_clause {
+            fn encode<__E: ::bincode2::Encoder>(&self, encoder: &mut __E) -> ::bincode2::Result<()> {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> Result<TokenStream2, Error> {
+    let name = &input.ident;
+    let body = match input.data {
+        Data::Struct(ref data) => {
+            let construct = construct(&data.fields);
+            quote!(Ok(#name #construct))
+        }
+        Data::Enum(ref data) => {
+            let arms = data.variants.iter().enumerate().map(|(index, variant)| {
+                let index = index as u32;
+                let ident = &variant.ident;
+                let construct = construct(&variant.fields);
+                quote!(#index => Ok(#name::#ident #construct),)
+            });
+            quote! {
+                match ::bincode2::Decoder::decode_variant(decoder)? {
+                    #(#arms)*
+                    tag => Err(::bincode2::ErrorKind::InvalidTagEncoding(tag as usize).into()),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                input,
+                "bincode2 cannot derive Decode for unions",
+            ))
+        }
+    };
+
+    let generics = add_bounds(&input.generics, quote!(::bincode2::Decode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::bincode2::Decode for #name #ty_generics #where_clause {
+            fn decode<__D: ::bincode2::Decoder>(decoder: &mut __D) -> ::bincode2::Result<Self> {
+                #body
+            }
+        }
+    })
+}
@@ -0,0 +1,141 @@
+//! Blocks of values that can be decoded one at a time.
+//!
+//! `Config::serialize_batch` writes the number of items as a `u64`, then the
+//! offset of every item from the end of the header as a `u64`, then the items
+//! themselves, each encoded as `Config::serialize` would. The header words are
+//! fixed-width `u64`s in the byte order of the config, whatever it writes integers
+//! with, so `Batch::get` finds any item without reading the others.
+
+use alloc::vec::Vec;
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use serde;
+
+use config::Config;
+use error::{Error, ErrorKind, Result};
+
+const WORD: usize = 8;
+
+pub(crate) fn serialize<I>(config: &Config, items: I) -> Result<Vec<u8>>
+where
+    I: IntoIterator,
+    I::Item: serde::Serialize,
+{
+    let mut offsets = Vec::new();
+    let mut body = Vec::new();
+    for item in items {
+        offsets.push(body.len() as u64);
+        config.serialize_into(&mut body, &item)?;
+    }
+
+    let header = WORD * (offsets.len() + 1);
+    let mut out = Vec::with_capacity(header + body.len());
+    out.resize(header, 0);
+    write_word(config, &mut out[..WORD], offsets.len() as u64);
+    for (word, offset) in out[WORD..].chunks_mut(WORD).zip(&offsets) {
+        write_word(config, word, *offset);
+    }
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+fn write_word(config: &Config, word: &mut [u8], value: u64) {
+    if config.is_big_endian() {
+        BigEndian::write_u64(word, value)
+    } else {
+        LittleEndian::write_u64(word, value)
+    }
+}
+
+fn read_word(config: &Config, word: &[u8]) -> u64 {
+    if config.is_big_endian() {
+        BigEndian::read_u64(word)
+    } else {
+        LittleEndian::read_u64(word)
+    }
+}
+
+/// A view over a block written by `Config::serialize_batch`, created by
+/// `Config::deserialize_batch`.
+///
+/// Items are decoded on demand with the configuration the view was created
+/// with. Only the header is checked up front; an item whose offsets are
+/// corrupt is reported when it is read.
+#[derive(Clone, Debug)]
+pub struct Batch<'a> {
+    config: Config,
+    offsets: &'a [u8],
+    items: &'a [u8],
+}
+
+impl<'a> Batch<'a> {
+    pub(crate) fn new(config: &Config, bytes: &'a [u8]) -> Result<Batch<'a>> {
+        if bytes.len() < WORD {
+            return Err(unexpected_eof());
+        }
+        let len = read_word(config, &bytes[..WORD]);
+        if len > ((bytes.len() - WORD) / WORD) as u64 {
+            return Err(unexpected_eof());
+        }
+        let header = len as usize * WORD;
+        Ok(Batch {
+            config: *config,
+            offsets: &bytes[WORD..WORD + header],
+            items: &bytes[WORD + header..],
+        })
+    }
+
+    /// The number of items in the batch.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.offsets.len() / WORD
+    }
+
+    /// Returns true if the batch holds no items.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// The encoded bytes of item `index`.
+    pub fn get_bytes(&self, index: usize) -> Result<&'a [u8]> {
+        if index >= self.len() {
+            return Err(serde::de::Error::custom(format_args!(
+                "batch index {} out of range for a batch of {} items",
+                index,
+                self.len()
+            )));
+        }
+        let start = self.offset(index);
+        let end = if index + 1 == self.len() {
+            self.items.len() as u64
+        } else {
+            self.offset(index + 1)
+        };
+        if start > end || end > self.items.len() as u64 {
+            return Err(serde::de::Error::custom(format_args!(
+                "invalid offsets for batch item {}",
+                index
+            )));
+        }
+        Ok(&self.items[start as usize..end as usize])
+    }
+
+    /// Decodes item `index`.
+    pub fn get<T: serde::Deserialize<'a>>(&self, index: usize) -> Result<T> {
+        self.config.deserialize(self.get_bytes(index)?)
+    }
+
+    fn offset(&self, index: usize) -> u64 {
+        let start = index * WORD;
+        read_word(&self.config, &self.offsets[start..start + WORD])
+    }
+}
+
+fn unexpected_eof() -> Error {
+    ErrorKind::Io(::core2::io::Error::new(
+        ::core2::io::ErrorKind::UnexpectedEof,
+        "",
+    ))
+    .into()
+}
@@ -0,0 +1,191 @@
+//! Interop with the `bytes` crate, enabled by the `bytes` feature.
+//!
+//! `BufSource` reads from any `bytes::Buf`, such as a chain of `Bytes` segments,
+//! and `BufMutWriter` writes into any `bytes::BufMut`, such as a pooled `BytesMut`.
+//! `BytesReader` reads from a single `Bytes`, handing out parts of it without copying.
+//! `Config::deserialize_from_buf`, `Config::serialize_into_buf` and
+//! `Config::serialize_to_bytes` use them.
+
+use alloc::vec::Vec;
+use bytes::{Buf, BufMut, Bytes};
+use core2::io;
+use serde;
+
+use de::read::{BincodeRead, FillBuf, SliceReader};
+use error::Result;
+
+/// A `FillBuf` over a `bytes::Buf`. Wrap it in a `FillBufReader` to deserialize
+/// from it.
+#[derive(Debug)]
+pub struct BufSource<B>(pub B);
+
+impl<B: Buf> FillBuf for BufSource<B> {
+    #[inline(always)]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.0.chunk())
+    }
+
+    #[inline(always)]
+    fn consume(&mut self, amount: usize) {
+        self.0.advance(amount)
+    }
+}
+
+/// Exposes a `bytes::BufMut` as the writer bincode2 expects. Writing past the
+/// capacity of a buffer that cannot grow fails with `WriteZero`.
+#[derive(Debug)]
+pub struct BufMutWriter<B> {
+    inner: B,
+}
+
+impl<B: BufMut> BufMutWriter<B> {
+    /// Wraps a buffer.
+    pub fn new(inner: B) -> BufMutWriter<B> {
+        BufMutWriter { inner }
+    }
+
+    /// Returns the wrapped buffer.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: BufMut> io::Write for BufMutWriter<B> {
+    #[inline(always)]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = buf.len().min(self.inner.remaining_mut());
+        self.inner.put_slice(&buf[..written]);
+        Ok(written)
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A `BincodeRead` over a `bytes::Bytes`, from which byte slices are borrowed and
+/// then turned into `Bytes` sharing its allocation, so that payloads can be passed
+/// on without copying them.
+///
+/// ```
+/// # extern crate bincode2;
+/// # extern crate bytes;
+/// # #[macro_use] extern crate serde_derive;
+/// use bincode2::BytesReader;
+/// use bytes::Bytes;
+/// use std::marker::PhantomData;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Frame<'a> {
+///     stream: u32,
+///     body: &'a [u8],
+/// }
+///
+/// # fn main() {
+/// let config = bincode2::config();
+/// let frame = Frame { stream: 3, body: b"payload" };
+/// let mut bytes = config.serialize(&frame).unwrap();
+/// bytes.extend_from_slice(b"next");
+/// let bytes = Bytes::from(bytes);
+///
+/// let mut reader = BytesReader::new(&bytes);
+/// let frame: Frame = config
+///     .deserialize_from_custom_seed(PhantomData, &mut reader)
+///     .unwrap();
+/// let body: Bytes = reader.slice_ref(frame.body);
+/// assert_eq!(body, "payload");
+/// assert_eq!(reader.into_remaining(), "next");
+/// # }
+/// ```
+pub struct BytesReader<'storage> {
+    bytes: &'storage Bytes,
+    reader: SliceReader<'storage>,
+}
+
+impl<'storage> BytesReader<'storage> {
+    /// Reads from the start of `bytes`.
+    pub fn new(bytes: &'storage Bytes) -> BytesReader<'storage> {
+        BytesReader {
+            bytes,
+            reader: SliceReader::new(bytes),
+        }
+    }
+
+    /// The number of bytes read so far.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.reader.position()
+    }
+
+    /// The part of the underlying `Bytes` that `subset` covers, sharing its allocation.
+    /// `subset` is typically a byte slice deserialized from this reader.
+    ///
+    /// Panics if `subset` is not a part of the bytes being read.
+    #[inline(always)]
+    pub fn slice_ref(&self, subset: &[u8]) -> Bytes {
+        self.bytes.slice_ref(subset)
+    }
+
+    /// Consumes the reader, returning the bytes that have not been read yet without
+    /// copying them.
+    #[inline(always)]
+    pub fn into_remaining(self) -> Bytes {
+        self.bytes.slice(self.position() as usize..)
+    }
+}
+
+impl<'storage> io::Read for BytesReader<'storage> {
+    #[inline(always)]
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(out)
+    }
+
+    #[inline(always)]
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(out)
+    }
+}
+
+impl<'storage> BincodeRead<'storage> for BytesReader<'storage> {
+    #[inline(always)]
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'storage>,
+    {
+        self.reader.forward_read_str(length, visitor)
+    }
+
+    #[inline(always)]
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        self.reader.get_byte_buffer(length)
+    }
+
+    #[inline(always)]
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'storage>,
+    {
+        self.reader.forward_read_bytes(length, visitor)
+    }
+
+    #[inline(always)]
+    fn skip(&mut self, length: usize) -> Result<()> {
+        self.reader.skip(length)
+    }
+
+    #[inline(always)]
+    fn at_end(&mut self) -> Result<bool> {
+        self.reader.at_end()
+    }
+
+    #[inline(always)]
+    fn seekable_position(&self) -> Option<u64> {
+        self.reader.seekable_position()
+    }
+
+    #[inline(always)]
+    fn seek(&mut self, position: u64) -> Result<()> {
+        self.reader.seek(position)
+    }
+}
@@ -0,0 +1,155 @@
+//! Length-delimited frames for `tokio_util::codec`, enabled by the `codec` feature.
+//!
+//! `BincodeCodec` writes each value as a frame: its length as a fixed-width `u32`,
+//! in the byte order of the config, followed by the value. The other options of the
+//! config, such as `IntEncoding::Varint` or `allow_untagged`, apply to the value
+//! only.
+//!
+//! The length is checked against the deserialize limit of the config as soon as it
+//! arrives, so an oversized frame is rejected before it is buffered. A frame holding
+//! bytes past the end of its value is rejected as well.
+//!
+//! ```
+//! # extern crate bincode2;
+//! # extern crate bytes;
+//! # extern crate tokio_util;
+//! use bincode2::codec::BincodeCodec;
+//! use bytes::BytesMut;
+//! use tokio_util::codec::{Decoder, Encoder};
+//!
+//! # fn main() {
+//! let mut codec = BincodeCodec::<(u8, String)>::new(bincode2::config());
+//! let mut buf = BytesMut::new();
+//! codec.encode((1, "frame".to_string()), &mut buf).unwrap();
+//!
+//! // Partial frames wait for more bytes.
+//! let mut partial = buf.split_to(6);
+//! assert!(codec.decode(&mut partial).unwrap().is_none());
+//! partial.unsplit(buf);
+//! assert_eq!(codec.decode(&mut partial).unwrap(), Some((1, "frame".to_string())));
+//! # }
+//! ```
+
+use core::marker::PhantomData;
+use core::{u32, usize};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use bytes::{Buf, BufMut, BytesMut};
+use serde;
+use tokio_util::codec;
+
+use config::Config;
+use error::{Error, ErrorKind, Result};
+
+/// The length of a frame header.
+const HEADER: usize = 4;
+
+/// Encodes and decodes values of type `T` as length-delimited frames, see the
+/// module documentation.
+#[derive(Debug)]
+pub struct BincodeCodec<T> {
+    config: Config,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T> BincodeCodec<T> {
+    /// Creates a codec serializing values with `config`.
+    pub fn new(config: Config) -> BincodeCodec<T> {
+        BincodeCodec {
+            config,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The configuration values are serialized with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+}
+
+impl<T> Clone for BincodeCodec<T> {
+    fn clone(&self) -> BincodeCodec<T> {
+        BincodeCodec::new(self.config)
+    }
+}
+
+impl<T: serde::Serialize> codec::Encoder<T> for BincodeCodec<T> {
+    type Error = Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<()> {
+        // The value is written after room for its header, which is filled in once
+        // its length is known.
+        let start = dst.len();
+        dst.put_slice(&[0; HEADER]);
+        if let Err(err) = self.config.serialize_into_buf(&mut *dst, &item) {
+            dst.truncate(start);
+            return Err(err);
+        }
+        let len = (dst.len() - start - HEADER) as u64;
+        if len > u64::from(u32::MAX) {
+            dst.truncate(start);
+            return Err(ErrorKind::SizeLimit {
+                limit: u64::from(u32::MAX),
+                attempted: len,
+            }
+            .into());
+        }
+        let header = &mut dst[start..start + HEADER];
+        if self.config.is_big_endian() {
+            BigEndian::write_u32(header, len as u32);
+        } else {
+            LittleEndian::write_u32(header, len as u32);
+        }
+        Ok(())
+    }
+}
+
+impl<T: serde::de::DeserializeOwned> codec::Decoder for BincodeCodec<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>> {
+        if src.len() < HEADER {
+            src.reserve(HEADER - src.len());
+            return Ok(None);
+        }
+        let len = if self.config.is_big_endian() {
+            BigEndian::read_u32(&src[..HEADER])
+        } else {
+            LittleEndian::read_u32(&src[..HEADER])
+        };
+        if let Some(limit) = self.config.deserialize_byte_limit() {
+            if u64::from(len) > limit {
+                return Err(ErrorKind::SizeLimit {
+                    limit,
+                    attempted: u64::from(len),
+                }
+                .into());
+            }
+        }
+        let end = match HEADER.checked_add(len as usize) {
+            Some(end) => end,
+            None => {
+                return Err(ErrorKind::SizeLimit {
+                    limit: usize::MAX as u64,
+                    attempted: HEADER as u64 + u64::from(len),
+                }
+                .into())
+            }
+        };
+        if src.len() < end {
+            src.reserve(end - src.len());
+            return Ok(None);
+        }
+        let mut frame = &src[HEADER..end];
+        let value = self.config.deserialize_from(&mut frame)?;
+        if !frame.is_empty() {
+            return Err(serde::de::Error::custom(format_args!(
+                "{} bytes left over after the value of a frame",
+                frame.len()
+            )));
+        }
+        src.advance(end);
+        Ok(Some(value))
+    }
+}
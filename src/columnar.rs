@@ -0,0 +1,419 @@
+//! Rows of structs stored column by column.
+//!
+//! `Config::serialize_columnar` writes a header holding the number of rows and,
+//! for every field of the row struct, its name and the offset of its column from
+//! the end of the header. Each column then holds that field of every row, each
+//! value encoded as `Config::serialize` would. `Columns::column` decodes a single
+//! field of every row without reading the other columns.
+
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::marker::PhantomData;
+use core::slice;
+
+use serde;
+use serde::de::{DeserializeSeed, SeqAccess, Visitor};
+use serde::ser::{Impossible, SerializeStruct};
+
+use config::Config;
+use de::read::SliceReader;
+use error::{Error, ErrorKind, Result};
+
+struct Column {
+    name: &'static str,
+    bytes: Vec<u8>,
+}
+
+pub(crate) fn serialize<I>(config: &Config, rows: I) -> Result<Vec<u8>>
+where
+    I: IntoIterator,
+    I::Item: serde::Serialize,
+{
+    let mut columns = Vec::new();
+    let mut len = 0u64;
+    for row in rows {
+        row.serialize(RowSplitter {
+            config,
+            columns: &mut columns,
+            first: len == 0,
+        })?;
+        len += 1;
+    }
+
+    let mut offsets = Vec::with_capacity(columns.len());
+    let mut offset = 0;
+    for column in &columns {
+        offsets.push((column.name, offset));
+        offset += column.bytes.len() as u64;
+    }
+    let mut out = config.serialize(&(len, offsets))?;
+    for column in &columns {
+        out.extend_from_slice(&column.bytes);
+    }
+    Ok(out)
+}
+
+/// A view over a block written by `Config::serialize_columnar`, created by
+/// `Config::deserialize_columnar`.
+///
+/// Columns are decoded on demand with the configuration the view was created
+/// with. Only the header is checked up front; a column whose offsets are
+/// corrupt is reported when it is read.
+///
+/// The number of rows comes from the header, so decoding a value of no bytes
+/// does not bound it by the size of the block. Columns of such values, and
+/// the rows of structs without fields, can only be read from blocks holding
+/// at least as many bytes after the header as there are rows.
+#[derive(Clone, Debug)]
+pub struct Columns<'a> {
+    config: Config,
+    len: usize,
+    offsets: Vec<(&'a str, u64)>,
+    body: &'a [u8],
+}
+
+impl<'a> Columns<'a> {
+    pub(crate) fn new(config: &Config, bytes: &'a [u8]) -> Result<Columns<'a>> {
+        let mut reader = SliceReader::new(bytes);
+        let (len, offsets): (u64, Vec<(&'a str, u64)>) =
+            config.deserialize_from_custom_seed(PhantomData, &mut reader)?;
+        let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+        Ok(Columns {
+            config: *config,
+            len,
+            offsets,
+            body: reader.remaining(),
+        })
+    }
+
+    /// The number of rows.
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if there are no rows.
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The names of the columns, in the order of the fields of the row struct.
+    pub fn names<'s>(&'s self) -> impl Iterator<Item = &'a str> + 's {
+        self.offsets.iter().map(|&(name, _)| name)
+    }
+
+    /// Decodes the field `name` of every row, without reading the other columns.
+    pub fn column<C: serde::Deserialize<'a>>(&self, name: &str) -> Result<Vec<C>> {
+        let index = match self.offsets.iter().position(|&(n, _)| n == name) {
+            Some(index) => index,
+            None => {
+                return Err(serde::de::Error::custom(format_args!(
+                    "no column named {}",
+                    name
+                )))
+            }
+        };
+        let mut reader = SliceReader::new(self.column_bytes(index)?);
+        let mut values = Vec::new();
+        for _ in 0..self.len {
+            let left = reader.remaining().len();
+            values.push(
+                self.config
+                    .deserialize_from_custom_seed(PhantomData::<C>, &mut reader)?,
+            );
+            if reader.remaining().len() == left {
+                self.check_empty_rows()?;
+            }
+        }
+        Ok(values)
+    }
+
+    /// Decodes every row, reading all columns.
+    pub fn rows<T: serde::Deserialize<'a>>(&self) -> Result<Vec<T>> {
+        let mut readers = Vec::with_capacity(self.offsets.len());
+        for index in 0..self.offsets.len() {
+            readers.push(SliceReader::new(self.column_bytes(index)?));
+        }
+        let mut rows = Vec::new();
+        for _ in 0..self.len {
+            let left = remaining(&readers);
+            rows.push(T::deserialize(Row {
+                config: &self.config,
+                names: &self.offsets,
+                readers: &mut readers,
+            })?);
+            if remaining(&readers) == left {
+                self.check_empty_rows()?;
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Fails if a row that took no bytes to decode could be one of more rows than
+    /// the block has bytes, which the header alone cannot be trusted to give.
+    fn check_empty_rows(&self) -> Result<()> {
+        if self.len > self.body.len() {
+            return Err(serde::de::Error::custom(format_args!(
+                "columnar block of {} bytes cannot hold {} rows of no bytes",
+                self.body.len(),
+                self.len
+            )));
+        }
+        Ok(())
+    }
+
+    fn column_bytes(&self, index: usize) -> Result<&'a [u8]> {
+        let start = self.offsets[index].1;
+        let end = match self.offsets.get(index + 1) {
+            Some(&(_, offset)) => offset,
+            None => self.body.len() as u64,
+        };
+        if start > end || end > self.body.len() as u64 {
+            return Err(serde::de::Error::custom(format_args!(
+                "invalid offsets for column {}",
+                self.offsets[index].0
+            )));
+        }
+        Ok(&self.body[start as usize..end as usize])
+    }
+}
+
+fn remaining(readers: &[SliceReader]) -> usize {
+    readers.iter().map(|reader| reader.remaining().len()).sum()
+}
+
+fn not_a_struct() -> Error {
+    serde::ser::Error::custom("columnar rows must be structs with named fields")
+}
+
+/// Serializes each field of a row struct onto the end of its column.
+struct RowSplitter<'c> {
+    config: &'c Config,
+    columns: &'c mut Vec<Column>,
+    first: bool,
+}
+
+macro_rules! not_a_struct {
+    ($($method:ident($($arg:ident: $ty:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $($arg: $ty),*) -> Result<$ok> {
+                $(let _ = $arg;)*
+                Err(not_a_struct())
+            }
+        )*
+    };
+}
+
+impl<'c> serde::Serializer for RowSplitter<'c> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Impossible<(), Error>;
+    type SerializeTuple = Impossible<(), Error>;
+    type SerializeTupleStruct = Impossible<(), Error>;
+    type SerializeTupleVariant = Impossible<(), Error>;
+    type SerializeMap = Impossible<(), Error>;
+    type SerializeStruct = RowFields<'c>;
+    type SerializeStructVariant = Impossible<(), Error>;
+
+    not_a_struct! {
+        serialize_bool(v: bool) -> ();
+        serialize_i8(v: i8) -> ();
+        serialize_i16(v: i16) -> ();
+        serialize_i32(v: i32) -> ();
+        serialize_i64(v: i64) -> ();
+        serialize_u8(v: u8) -> ();
+        serialize_u16(v: u16) -> ();
+        serialize_u32(v: u32) -> ();
+        serialize_u64(v: u64) -> ();
+        serialize_f32(v: f32) -> ();
+        serialize_f64(v: f64) -> ();
+        serialize_char(v: char) -> ();
+        serialize_str(v: &str) -> ();
+        serialize_bytes(v: &[u8]) -> ();
+        serialize_none() -> ();
+        serialize_unit() -> ();
+        serialize_unit_struct(name: &'static str) -> ();
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str) -> ();
+        serialize_seq(len: Option<usize>) -> Impossible<(), Error>;
+        serialize_tuple(len: usize) -> Impossible<(), Error>;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Impossible<(), Error>;
+        serialize_tuple_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize
+        ) -> Impossible<(), Error>;
+        serialize_map(len: Option<usize>) -> Impossible<(), Error>;
+        serialize_struct_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize
+        ) -> Impossible<(), Error>;
+    }
+
+    fn serialize_some<T: ?Sized + serde::Serialize>(self, _value: &T) -> Result<()> {
+        Err(not_a_struct())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + serde::Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + serde::Serialize>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<()> {
+        Err(not_a_struct())
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<RowFields<'c>> {
+        if self.first {
+            self.columns.reserve(len);
+        }
+        Ok(RowFields {
+            config: self.config,
+            columns: self.columns,
+            first: self.first,
+            index: 0,
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+struct RowFields<'c> {
+    config: &'c Config,
+    columns: &'c mut Vec<Column>,
+    first: bool,
+    index: usize,
+}
+
+impl<'c> SerializeStruct for RowFields<'c> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + serde::Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        if self.first {
+            self.columns.push(Column {
+                name: key,
+                bytes: Vec::new(),
+            });
+        } else if self.columns.get(self.index).map(|c| c.name) != Some(key) {
+            return Err(serde::ser::Error::custom(format_args!(
+                "columnar row has field {} where the first row had another",
+                key
+            )));
+        }
+        self.config
+            .serialize_into(&mut self.columns[self.index].bytes, value)?;
+        self.index += 1;
+        Ok(())
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<()> {
+        Err(serde::ser::Error::custom(format_args!(
+            "columnar rows cannot skip fields, but {} was skipped",
+            key
+        )))
+    }
+
+    fn end(self) -> Result<()> {
+        if self.index != self.columns.len() {
+            return Err(serde::ser::Error::custom(
+                "columnar row has fewer fields than the first row",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes a row struct by reading each field from its column.
+struct Row<'r, 'a: 'r> {
+    config: &'r Config,
+    names: &'r [(&'a str, u64)],
+    readers: &'r mut [SliceReader<'a>],
+}
+
+impl<'r, 'a> serde::Deserializer<'a> for Row<'r, 'a> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'a>>(self, _visitor: V) -> Result<V::Value> {
+        Err(not_a_struct())
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'a>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        if !fields
+            .iter()
+            .cloned()
+            .eq(self.names.iter().map(|&(name, _)| name))
+        {
+            return Err(serde::de::Error::custom(
+                "columnar rows do not have the fields of this struct",
+            ));
+        }
+        visitor.visit_seq(RowAccess {
+            config: self.config,
+            readers: self.readers.iter_mut(),
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf
+        option unit unit_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+struct RowAccess<'r, 'a: 'r> {
+    config: &'r Config,
+    readers: slice::IterMut<'r, SliceReader<'a>>,
+}
+
+impl<'r, 'a> SeqAccess<'a> for RowAccess<'r, 'a> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'a>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        match self.readers.next() {
+            Some(reader) => self
+                .config
+                .deserialize_from_custom_seed(seed, reader)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.readers.len())
+    }
+}
@@ -0,0 +1,50 @@
+//! Configurations producing the same bytes as upstream bincode.
+//!
+//! | Upstream                                     | Here                        |
+//! |----------------------------------------------|-----------------------------|
+//! | bincode 1.3 `serialize`/`deserialize`        | `bincode1()`                |
+//! | bincode 1.3 `config()` (deprecated)          | `bincode1()`                |
+//! | bincode 1.3 `DefaultOptions::new()`          | `bincode1_default_options()`|
+//! | bincode 2 `config::legacy()`                 | `bincode2_legacy()`         |
+//! | bincode 2 `config::standard()`               | `bincode2_standard()`       |
+//!
+//! All of these write integers little-endian, the tag of an `Option` as one byte
+//! (0 for `None`, 1 for `Some`), `char` as utf8, and enum variants as a `u32`
+//! index and the length of strings, sequences and maps as a `u64`.
+//! `bincode1()` and `bincode2_legacy()` write integers at their full width, the
+//! other two as varints, see `IntEncoding::Varint`; bytes and `u8`s are written
+//! as they are either way.
+//!
+//! The configurations here read values with trailing bytes after them without
+//! complaint, as the upstream functions above do, except for bincode 1.3's
+//! `DefaultOptions`, which rejects them. Values written here are read by it all
+//! the same.
+
+use config::{Config, ConfigBuilder, IntEncoding};
+
+/// The format of bincode 1.3's `serialize` and `deserialize` functions.
+pub const fn bincode1() -> Config {
+    Config::new()
+}
+
+/// The format of bincode 1.3 configured with `DefaultOptions::new()`, whose
+/// integers are varints.
+pub const fn bincode1_default_options() -> Config {
+    ConfigBuilder::new()
+        .int_encoding(IntEncoding::Varint)
+        .build()
+}
+
+/// The format of bincode 2 configured with `config::legacy()`, through either
+/// its serde integration or its own `Encode` and `Decode` traits.
+pub const fn bincode2_legacy() -> Config {
+    Config::new()
+}
+
+/// The format of bincode 2 configured with `config::standard()`, whose integers
+/// are varints, through its serde integration.
+pub const fn bincode2_standard() -> Config {
+    ConfigBuilder::new()
+        .int_encoding(IntEncoding::Varint)
+        .build()
+}
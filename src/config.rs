@@ -1,17 +1,48 @@
-use super::internal::{Bounded, Infinite, SizeLimit, SizeType, U16, U32, U64, U8};
+//! The `Config` builder and ready-made configurations.
+
+#[cfg(feature = "alloc")]
+use super::batch::{self, Batch};
+#[cfg(feature = "alloc")]
+use super::columnar::{self, Columns};
+#[cfg(feature = "alloc")]
+use super::internal::Cancellable;
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+use super::internal::Shared;
+use super::internal::{
+    Bounded, CustomArray, CustomString, Infinite, SizeLimit, SizeType, U16, U32, U64, U8,
+};
 use byteorder::{BigEndian, ByteOrder, LittleEndian, NativeEndian};
 use de::read::BincodeRead;
-use error::Result;
+use encode::{Decode, Encode};
+use intern::StringInterner;
+#[cfg(feature = "alloc")]
+use layer::{FrameLayer, LayeredConfig};
+#[cfg(feature = "std")]
+use pool::{Pool, PooledBytes};
+use error::{ErrorKind, Result};
+use ser::placeholder::Placeholder;
+use type_codec::{Codecs, TypeCodec};
 use serde;
-use core2::io::{Read, Write};
+use core2::io::{Read, Seek, SeekFrom, Write};
+use core::any::{type_name, TypeId};
+use core::fmt;
 use core::marker::PhantomData;
-use {DeserializerAcceptor, SerializerAcceptor};
 
-use alloc::boxed::Box;
+#[cfg(feature = "std")]
 use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+use core::sync::atomic::{AtomicU64, Ordering};
 
-struct DefaultOptions(Infinite);
+struct DefaultOptions<'c> {
+    limit: Infinite,
+    config: &'c Config,
+}
 
 pub(crate) trait Options {
     type Limit: SizeLimit + 'static;
@@ -20,6 +51,10 @@ pub(crate) trait Options {
     type ArraySize: SizeType + 'static;
 
     fn limit(&mut self) -> &mut Self::Limit;
+
+    /// The `Config` these options were built from, for settings that are
+    /// checked at runtime rather than encoded in the option types.
+    fn config(&self) -> &Config;
 }
 
 pub(crate) trait OptionsExt: Options + Sized {
@@ -28,7 +63,7 @@ pub(crate) trait OptionsExt: Options + Sized {
     }
 
     fn with_limit(self, limit: u64) -> WithOtherLimit<Self, Bounded> {
-        WithOtherLimit::new(self, Bounded(limit))
+        WithOtherLimit::new(self, Bounded::new(limit))
     }
 
     fn with_little_endian(self) -> WithOtherEndian<Self, LittleEndian> {
@@ -56,6 +91,24 @@ pub(crate) trait OptionsExt: Options + Sized {
     {
         WithOtherArrayLength::new(self)
     }
+
+    #[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+    fn with_shared_limit(
+        mut self,
+        budget: &Arc<AtomicU64>,
+    ) -> WithOtherLimit<Self, Shared<Self::Limit>> {
+        let limit = self.limit().clone();
+        WithOtherLimit::new(self, Shared::new(limit, budget.clone()))
+    }
+
+    #[cfg(feature = "alloc")]
+    fn with_cancel_token(
+        mut self,
+        cancelled: &Arc<dyn Fn() -> bool + Send + Sync>,
+    ) -> WithOtherLimit<Self, Cancellable<Self::Limit>> {
+        let limit = self.limit().clone();
+        WithOtherLimit::new(self, Cancellable::new(limit, cancelled.clone()))
+    }
 }
 
 impl<'a, O: Options> Options for &'a mut O {
@@ -68,17 +121,25 @@ impl<'a, O: Options> Options for &'a mut O {
     fn limit(&mut self) -> &mut Self::Limit {
         (*self).limit()
     }
+
+    #[inline(always)]
+    fn config(&self) -> &Config {
+        (**self).config()
+    }
 }
 
 impl<T: Options> OptionsExt for T {}
 
-impl DefaultOptions {
-    fn new() -> DefaultOptions {
-        DefaultOptions(Infinite)
+impl<'c> DefaultOptions<'c> {
+    fn new(config: &'c Config) -> DefaultOptions<'c> {
+        DefaultOptions {
+            limit: Infinite,
+            config,
+        }
     }
 }
 
-impl Options for DefaultOptions {
+impl<'c> Options for DefaultOptions<'c> {
     type Limit = Infinite;
     type Endian = LittleEndian;
     type StringSize = U64;
@@ -86,17 +147,22 @@ impl Options for DefaultOptions {
 
     #[inline(always)]
     fn limit(&mut self) -> &mut Infinite {
-        &mut self.0
+        &mut self.limit
+    }
+
+    #[inline(always)]
+    fn config(&self) -> &Config {
+        self.config
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum LimitOption {
     Unlimited,
     Limited(u64),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum EndianOption {
     Big,
     Little,
@@ -104,7 +170,7 @@ enum EndianOption {
 }
 
 /// Used to specify the unit used for length of strings and arrays via `config.string_length` or `config.array_length`.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum LengthOption {
     ///64 unsigned bits
     U64,
@@ -114,6 +180,313 @@ pub enum LengthOption {
     U16,
     ///8 unsigned bits
     U8,
+    /// A length prefix defined outside of this crate, see `Config::string_length_custom`.
+    Custom(CustomLength),
+}
+
+/// A length prefix defined outside of this crate, such as a 3 byte length or a varint,
+/// for `Config::string_length_custom` and `Config::array_length_custom`.
+///
+/// The built-in length types are fixed-width integers written in the byte order of the
+/// config; a `CustomSizeType` reads and writes the raw bytes of its prefix itself.
+///
+/// ```
+/// # extern crate bincode2;
+/// # extern crate core2;
+/// use bincode2::{CustomSizeType, ErrorKind, Result};
+/// use core2::io::{Read, Write};
+///
+/// /// A 3 byte big-endian length.
+/// struct U24;
+///
+/// impl CustomSizeType for U24 {
+///     fn write(len: u64, writer: &mut dyn Write) -> Result<()> {
+///         if len >= 1 << 24 {
+///             return Err(ErrorKind::SizeTypeLimit.into());
+///         }
+///         writer.write_all(&len.to_be_bytes()[5..])?;
+///         Ok(())
+///     }
+///
+///     fn read(reader: &mut dyn Read) -> Result<u64> {
+///         let mut bytes = [0; 8];
+///         reader.read_exact(&mut bytes[5..])?;
+///         Ok(u64::from_be_bytes(bytes))
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut config = bincode2::config();
+/// config.string_length_custom::<U24>();
+/// let bytes = config.serialize("hi").unwrap();
+/// assert_eq!(bytes, [0, 0, 2, b'h', b'i']);
+/// assert_eq!(config.deserialize::<String>(&bytes).unwrap(), "hi");
+/// # }
+/// ```
+pub trait CustomSizeType: 'static {
+    /// Writes `len`. Lengths the prefix cannot hold should fail with
+    /// `ErrorKind::SizeTypeLimit`.
+    fn write(len: u64, writer: &mut dyn Write) -> Result<()>;
+
+    /// Reads a length written by `write`.
+    fn read(reader: &mut dyn Read) -> Result<u64>;
+}
+
+/// A `CustomSizeType` chosen at runtime, held by `LengthOption::Custom`. The id and
+/// the name are functions giving them, so that it can be built in a `const`.
+#[derive(Clone, Copy)]
+pub struct CustomLength {
+    id: fn() -> TypeId,
+    name: fn() -> &'static str,
+    write: fn(u64, &mut dyn Write) -> Result<()>,
+    read: fn(&mut dyn Read) -> Result<u64>,
+}
+
+impl CustomLength {
+    /// The length prefix written by `L`.
+    pub const fn of<L: CustomSizeType>() -> CustomLength {
+        CustomLength {
+            id: TypeId::of::<L>,
+            name: type_name::<L>,
+            write: L::write,
+            read: L::read,
+        }
+    }
+
+    pub(crate) fn write(&self, len: u64, writer: &mut dyn Write) -> Result<()> {
+        (self.write)(len, writer)
+    }
+
+    pub(crate) fn read(&self, reader: &mut dyn Read) -> Result<u64> {
+        (self.read)(reader)
+    }
+}
+
+impl PartialEq for CustomLength {
+    fn eq(&self, other: &CustomLength) -> bool {
+        (self.id)() == (other.id)()
+    }
+}
+
+impl Eq for CustomLength {}
+
+impl fmt::Debug for CustomLength {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str((self.name)())
+    }
+}
+
+/// Translates between the variant indices serde numbers enum variants with and the
+/// discriminants written on the wire, set with `Config::variant_index_map`. Use it to
+/// speak a protocol whose tags do not start at 0 or skip values.
+///
+/// ```
+/// # extern crate bincode2;
+/// # #[macro_use] extern crate serde_derive;
+/// use bincode2::{UnknownVariant, VariantIndexMap};
+///
+/// #[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// enum Opcode {
+///     Read,
+///     Write,
+///     Other,
+/// }
+///
+/// /// The opcodes of the protocol are 0x10 and 0x20.
+/// struct Opcodes;
+///
+/// impl VariantIndexMap for Opcodes {
+///     fn to_wire(_name: &'static str, index: u32) -> u32 {
+///         (index + 1) << 4
+///     }
+///
+///     fn from_wire(_name: &'static str, tag: u32) -> Option<u32> {
+///         match tag {
+///             0x10 | 0x20 => Some((tag >> 4) - 1),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut config = bincode2::config();
+/// config
+///     .variant_index_map::<Opcodes>()
+///     .on_unknown_variant(UnknownVariant::Fallback(2));
+/// assert_eq!(config.serialize(&Opcode::Write).unwrap(), [0x20, 0, 0, 0]);
+/// assert_eq!(config.deserialize::<Opcode>(&[0x20, 0, 0, 0]).unwrap(), Opcode::Write);
+/// assert_eq!(config.deserialize::<Opcode>(&[0x30, 0, 0, 0]).unwrap(), Opcode::Other);
+/// # }
+/// ```
+pub trait VariantIndexMap: 'static {
+    /// The discriminant written for the variant at `index` of the enum `name`.
+    fn to_wire(name: &'static str, index: u32) -> u32;
+
+    /// The index of the variant of the enum `name` written as `tag`, or `None` if
+    /// the enum has no such variant.
+    fn from_wire(name: &'static str, tag: u32) -> Option<u32>;
+}
+
+/// A `VariantIndexMap` chosen at runtime, held by the config. The id and the name
+/// are functions giving them, so that it can be built in a `const`.
+#[derive(Clone, Copy)]
+struct VariantMap {
+    id: fn() -> TypeId,
+    name: fn() -> &'static str,
+    to_wire: fn(&'static str, u32) -> u32,
+    from_wire: fn(&'static str, u32) -> Option<u32>,
+}
+
+impl VariantMap {
+    const fn of<M: VariantIndexMap>() -> VariantMap {
+        VariantMap {
+            id: TypeId::of::<M>,
+            name: type_name::<M>,
+            to_wire: M::to_wire,
+            from_wire: M::from_wire,
+        }
+    }
+}
+
+impl PartialEq for VariantMap {
+    fn eq(&self, other: &VariantMap) -> bool {
+        (self.id)() == (other.id)()
+    }
+}
+
+impl Eq for VariantMap {}
+
+impl fmt::Debug for VariantMap {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str((self.name)())
+    }
+}
+
+/// What happens when deserializing an enum whose tag names no variant, set with
+/// `config.on_unknown_variant`.
+///
+/// A tag is unknown when the `VariantIndexMap` of the config has no variant for it,
+/// or when it is past the last variant of the enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownVariant {
+    /// Fail with `ErrorKind::InvalidTagEncoding`. Without a `VariantIndexMap`, tags past
+    /// the last variant are left to serde, which fails with `ErrorKind::Custom` unless
+    /// the enum has a `#[serde(other)]` variant.
+    Error,
+    /// Read the variant with this index instead, typically a unit variant kept for
+    /// tags from newer peers. Whatever the unknown variant holds is skipped if the
+    /// data was written with `config.variant_lengths`; otherwise it is not, so this
+    /// only suits protocols in which such variants hold nothing.
+    Fallback(u32),
+    /// Read the `#[serde(other)]` variant of the enum instead, failing with
+    /// `ErrorKind::Custom` if it has none. What the unknown variant holds is skipped
+    /// as with `Fallback`.
+    Other,
+}
+
+/// How lengths and `usize` values are written, set with `config.usize_encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UsizeMode {
+    /// 4 bytes. Values above `u32::MAX` fail to serialize.
+    U32,
+    /// 8 bytes.
+    U64,
+    /// 1 byte for values below 251; otherwise a byte of 251, 252 or 253 followed
+    /// by the value as a `u16`, `u32` or `u64` respectively.
+    Varint,
+}
+
+/// How integers wider than a byte are written, set with `config.int_encoding`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// At their full width.
+    Fixed,
+    /// As `UsizeMode::Varint` writes values, with a byte of 254 followed by the
+    /// value as a `u128` for values that don't fit in a `u64`. Signed integers are
+    /// zigzag encoded first, so that small negative values stay small. Reading fails
+    /// with `ErrorKind::InvalidTagEncoding` on a marker wider than the integer read.
+    Varint,
+}
+
+/// What happens to a length that does not fit in its `LengthOption`, set with
+/// `config.on_length_overflow`.
+///
+/// This applies to `U8`, `U16` and `U32` lengths; `usize_encoding` and custom
+/// length types keep failing with `ErrorKind::SizeTypeLimit`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Fail with `ErrorKind::SizeTypeLimit`.
+    Error,
+    /// Write only the longest prefix of a string that fits, cut on a character
+    /// boundary. Other lengths fail as with `Error`. Strings written under a
+    /// `StringMode` other than `LengthPrefixed` have no length, and are never cut.
+    Truncate,
+    /// Reserve the largest value of the length type as an escape, followed by
+    /// the length as a `u64`. Lengths from the escape up are written this way.
+    PromoteToLargerType,
+}
+
+/// How strings are written, set with `config.string_encoding`.
+///
+/// The C-style modes read strings into an owned `String`, so `&str` fields
+/// cannot borrow from the input with them. Strings holding a NUL byte fail to
+/// serialize with them, as they could not be read back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StringMode {
+    /// The length, as set by `string_length`, followed by the bytes.
+    LengthPrefixed,
+    /// The bytes followed by a NUL byte.
+    NulTerminated,
+    /// The bytes padded with NUL bytes to the given width. A string of exactly
+    /// that width has no NUL byte; longer strings fail to serialize.
+    FixedWidth(usize),
+}
+
+/// How floats are written, set with `config.float_encoding`.
+///
+/// Every encoding is read back by the same encoding; the lossy ones lose
+/// precision when writing, never when reading.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatEncoding {
+    /// `f32` in 4 bytes and `f64` in 8, as IEEE 754.
+    Ieee,
+    /// `f32` widened to 8 bytes, which is lossless. `f64` is unchanged.
+    F32AsF64,
+    /// `f64` narrowed to 4 bytes, rounding to the nearest `f32`. Values beyond the
+    /// range of `f32` become infinite. `f32` is unchanged.
+    F64AsF32,
+    /// A signed 32 bit fixed-point number with `fraction_bits` bits after the binary
+    /// point, so `Fixed32 { fraction_bits: 16 }` is 16.16. Values are rounded to the
+    /// nearest step of `2^-fraction_bits`; NaN, infinities and values out of range
+    /// fail to serialize, and to have their size measured.
+    Fixed32 {
+        /// The number of fractional bits, at most 31.
+        fraction_bits: u8,
+    },
+    /// Like `Fixed32`, in a signed 64 bit integer.
+    Fixed64 {
+        /// The number of fractional bits, at most 63.
+        fraction_bits: u8,
+    },
+    /// A signed 64 bit integer counting steps of `10^-places`, so that decimal
+    /// fractions with at most `places` digits survive the round trip. Rounding and
+    /// failures are as for `Fixed32`.
+    Decimal {
+        /// The number of decimal places, at most 18.
+        places: u8,
+    },
+}
+
+impl FloatEncoding {
+    /// Whether the fixed-point encodings leave room for an integer part.
+    const fn is_valid(self) -> bool {
+        match self {
+            FloatEncoding::Fixed32 { fraction_bits } => fraction_bits <= 31,
+            FloatEncoding::Fixed64 { fraction_bits } => fraction_bits <= 63,
+            FloatEncoding::Decimal { places } => places <= 18,
+            _ => true,
+        }
+    }
 }
 
 /// A configuration builder whose options Bincode will use
@@ -128,7 +501,8 @@ pub enum LengthOption {
 /// deserialization to crash your process by allocating too much memory or keeping a connection open for too long.
 ///
 /// When a byte limit is set, bincode will return `Err` on any deserialization that goes over the limit, or any
-/// serialization that goes over the limit.
+/// serialization that goes over the limit. `serialize_limit` and `deserialize_limit` set a different limit for
+/// each direction, for instance to accept only small requests while sending large responses.
 ///
 /// ### Array and String sizes
 /// When writing a string or an array is serialized the length is written at the beginning so that the data
@@ -137,12 +511,107 @@ pub enum LengthOption {
 ///
 /// If a string or array is attempted to be serialized that is not fit within the type specified bincode will return `Err`
 /// on serialization.
-#[derive(Clone, Debug)]
+///
+/// `on_length_overflow` truncates strings or escapes to a wider length instead, see `Overflow`.
+/// *default: `Overflow::Error`*
+///
+/// `string_encoding` writes strings NUL-terminated or padded to a fixed width instead, see `StringMode`.
+/// *default: `StringMode::LengthPrefixed`*
+///
+/// ### Flatten
+/// Structs using `#[serde(flatten)]` are serialized by serde as maps of unknown length and deserialized through
+/// `deserialize_any`, neither of which the plain encoding supports. `allow_flatten` switches every map to a
+/// self-describing form so these types round-trip. *default: disabled*
+///
+/// ### Untagged enums
+/// `#[serde(untagged)]` enums write no tag and are read by buffering the value through `deserialize_any` and
+/// trying each variant in order, which the plain encoding can't do as it doesn't say what a value is.
+/// `allow_untagged` writes every value in the self-describing form `allow_flatten` uses for maps, so that
+/// untagged and internally tagged enums round-trip. *default: disabled*
+///
+/// ### Tagged fields
+/// By default struct fields are written one after the other, so reader and writer must agree on the exact
+/// field list. `tagged_fields` prefixes each field with a small header identifying the field and how many
+/// bytes it occupies, so readers skip fields they don't know and fill missing ones from `#[serde(default)]`.
+/// *default: disabled*
+///
+/// `allow_trailing_default_fields` is a lighter alternative for structs that only ever gain fields at the end:
+/// a struct may stop where the input ends, and the missing fields take their `#[serde(default)]` values.
+/// *default: disabled*
+///
+/// ### Shared subtrees
+/// `dedup_subtrees` writes a back-reference instead of repeating a struct, sequence or map that was already
+/// written in the same message. *default: disabled*
+///
+/// ### Map order
+/// `sort_map_keys` writes the entries of every map in the order of their encoded keys, so that maps with a
+/// random iteration order like `HashMap` always serialize to the same bytes. *default: disabled*
+///
+/// ### Enum tags
+/// `variant_index_map` translates the index of each enum variant to the tag written on the wire, and
+/// `on_unknown_variant` decides what tags naming no variant turn into, skipping what they hold if
+/// `variant_lengths` wrote its length. *default: tags are variant indices, unknown tags are an error*
+///
+/// ### Per-type encodings
+/// `type_codec` replaces the serde encoding of one type with a `TypeCodec`, for types from other crates whose
+/// `Serialize` impl can't be changed. *default: none*
+///
+/// ### Alignment
+/// `aligned` pads the output so that integers and floats start at aligned offsets, which lets the receiver
+/// cast the bytes to `#[repr(C)]` structs in place. *default: unaligned*
+///
+/// ### usize
+/// `usize_encoding` fixes the width of lengths and of `usize` values independently of the target, see `UsizeMode`.
+/// *default: unset, lengths follow `string_length` and `array_length`*
+///
+/// ### Integers
+/// `int_encoding` writes integers, enum tags and the lengths set by `string_length` and `array_length` in as
+/// few bytes as their value needs, see `IntEncoding`. *default: `IntEncoding::Fixed`*
+///
+/// ### Floats
+/// `float_encoding` widens, narrows or converts floats to fixed-point integers on the wire, see `FloatEncoding`.
+/// *default: `FloatEncoding::Ieee`*
+///
+/// ### Borrowing
+/// `&str` and `&[u8]` values, and `Cow<str>` and `Cow<[u8]>` fields marked `#[serde(borrow)]`, borrow from the
+/// input when deserializing from a slice and are copied when reading from an `io::Read`. `force_owned` copies
+/// them from slices too. *default: borrowed from slices*
+///
+/// ### Presets
+/// `wire()`, `storage()` and `legacy_bincode1()` in this module return configurations for common uses, and with
+/// the `std` feature `register_preset` lets a team share its own under a name. `ConfigBuilder` builds a
+/// configuration in a `const` context, so that it can be a `static` shared by every thread. A `Config` holds no
+/// allocation and is `Copy`.
+///
+/// ### Without `alloc`
+/// Without the `alloc` feature only the methods writing to a `Write` or a slice and reading from a slice or a
+/// `BincodeRead` are available. `allow_flatten`, `allow_untagged`, `tagged_fields`, `dedup_subtrees`,
+/// `sort_map_keys`, `variant_lengths`, `type_codec` and `force_owned` need to buffer values and can't be set,
+/// and strings not written with `StringMode::LengthPrefixed` can't be read back. *default: `alloc` enabled*
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Config {
-    limit: LimitOption,
+    serialize_limit: LimitOption,
+    deserialize_limit: LimitOption,
     endian: EndianOption,
     string_size: LengthOption,
     array_size: LengthOption,
+    string_mode: StringMode,
+    length_overflow: Overflow,
+    allow_flatten: bool,
+    allow_untagged: bool,
+    tagged_fields: bool,
+    float_encoding: FloatEncoding,
+    int_encoding: IntEncoding,
+    usize_encoding: Option<UsizeMode>,
+    dedup_subtrees: bool,
+    trailing_default_fields: bool,
+    sort_map_keys: bool,
+    variant_map: Option<VariantMap>,
+    unknown_variant: UnknownVariant,
+    variant_lengths: bool,
+    codecs: Codecs,
+    alignment: u64,
+    force_owned: bool,
 }
 
 pub(crate) struct WithOtherLimit<O: Options, L: SizeLimit> {
@@ -215,6 +684,11 @@ impl<O: Options, E: ByteOrder + 'static> Options for WithOtherEndian<O, E> {
     fn limit(&mut self) -> &mut O::Limit {
         self.options.limit()
     }
+
+    #[inline(always)]
+    fn config(&self) -> &Config {
+        self.options.config()
+    }
 }
 
 impl<O: Options, L: SizeLimit + 'static> Options for WithOtherLimit<O, L> {
@@ -226,6 +700,10 @@ impl<O: Options, L: SizeLimit + 'static> Options for WithOtherLimit<O, L> {
     fn limit(&mut self) -> &mut L {
         &mut self.new_limit
     }
+
+    fn config(&self) -> &Config {
+        self._options.config()
+    }
 }
 
 impl<O: Options, L: SizeType + 'static> Options for WithOtherStringLength<O, L> {
@@ -237,6 +715,10 @@ impl<O: Options, L: SizeType + 'static> Options for WithOtherStringLength<O, L>
     fn limit(&mut self) -> &mut O::Limit {
         self.options.limit()
     }
+
+    fn config(&self) -> &Config {
+        self.options.config()
+    }
 }
 
 impl<O: Options, L: SizeType + 'static> Options for WithOtherArrayLength<O, L> {
@@ -248,11 +730,15 @@ impl<O: Options, L: SizeType + 'static> Options for WithOtherArrayLength<O, L> {
     fn limit(&mut self) -> &mut O::Limit {
         self.options.limit()
     }
+
+    fn config(&self) -> &Config {
+        self.options.config()
+    }
 }
 
 macro_rules! config_map_limit {
-    ($self:expr, $opts:ident => $call:expr) => {
-        match $self.limit {
+    ($limit:expr, $opts:ident => $call:expr) => {
+        match $limit {
             LimitOption::Unlimited => {
                 let $opts = $opts.with_no_limit();
                 $call
@@ -303,6 +789,10 @@ macro_rules! config_map_string_length {
                 let $opts = $opts.with_string_size::<U8>();
                 $call
             }
+            LengthOption::Custom(_) => {
+                let $opts = $opts.with_string_size::<CustomString>();
+                $call
+            }
         }
     };
 }
@@ -326,14 +816,44 @@ macro_rules! config_map_array_length {
                 let $opts = $opts.with_array_size::<U8>();
                 $call
             }
+            LengthOption::Custom(_) => {
+                let $opts = $opts.with_array_size::<CustomArray>();
+                $call
+            }
+        }
+    };
+}
+
+/// Calls `$call` with `$size` naming the length type a `FixedLength` stands for.
+macro_rules! fixed_length {
+    ($fixed:expr, $size:ident => $call:expr) => {
+        match $fixed {
+            ::internal::FixedLength::U64 => {
+                type $size = ::internal::U64;
+                $call
+            }
+            ::internal::FixedLength::U32 => {
+                type $size = ::internal::U32;
+                $call
+            }
+            ::internal::FixedLength::U16 => {
+                type $size = ::internal::U16;
+                $call
+            }
+            ::internal::FixedLength::U8 => {
+                type $size = ::internal::U8;
+                $call
+            }
         }
     };
 }
 
+/// `$limit` names the field holding the limit for the direction of the call,
+/// `serialize_limit` or `deserialize_limit`.
 macro_rules! config_map {
-    ($self:expr, $opts:ident => $call:expr) => {{
-        let $opts = DefaultOptions::new();
-        config_map_limit!($self, $opts =>
+    ($self:expr, $limit:ident, $opts:ident => $call:expr) => {{
+        let $opts = DefaultOptions::new($self);
+        config_map_limit!($self.$limit, $opts =>
             config_map_endian!($self, $opts =>
                 config_map_string_length!($self, $opts =>
                     config_map_array_length!($self, $opts => $call))))
@@ -343,27 +863,40 @@ macro_rules! config_map {
 #[allow(clippy::cognitive_complexity)] // https://github.com/rust-lang/rust-clippy/issues/3900
 impl Config {
     #[inline(always)]
-    pub(crate) fn new() -> Config {
-        Config {
-            limit: LimitOption::Unlimited,
-            endian: EndianOption::Little,
-            string_size: LengthOption::U64,
-            array_size: LengthOption::U64,
-        }
+    pub(crate) const fn new() -> Config {
+        ConfigBuilder::new().build()
     }
 
-    /// Sets the byte limit to be unlimited.
+    /// Sets the byte limit to be unlimited, in both directions.
     /// This is the default.
     #[inline(always)]
     pub fn no_limit(&mut self) -> &mut Self {
-        self.limit = LimitOption::Unlimited;
+        self.serialize_limit = LimitOption::Unlimited;
+        self.deserialize_limit = LimitOption::Unlimited;
         self
     }
 
-    /// Sets the byte limit to `limit`.
+    /// Sets the byte limit to `limit`, for both serialization and deserialization.
     #[inline(always)]
     pub fn limit(&mut self, limit: u64) -> &mut Self {
-        self.limit = LimitOption::Limited(limit);
+        self.serialize_limit = LimitOption::Limited(limit);
+        self.deserialize_limit = LimitOption::Limited(limit);
+        self
+    }
+
+    /// Sets the byte limit to `limit` when serializing, leaving the limit for
+    /// deserializing as it is.
+    #[inline(always)]
+    pub fn serialize_limit(&mut self, limit: u64) -> &mut Self {
+        self.serialize_limit = LimitOption::Limited(limit);
+        self
+    }
+
+    /// Sets the byte limit to `limit` when deserializing, leaving the limit for
+    /// serializing as it is.
+    #[inline(always)]
+    pub fn deserialize_limit(&mut self, limit: u64) -> &mut Self {
+        self.deserialize_limit = LimitOption::Limited(limit);
         self
     }
 
@@ -389,6 +922,16 @@ impl Config {
         self
     }
 
+    /// Whether values are written big-endian, resolving `native_endian`.
+    #[inline(always)]
+    pub(crate) fn is_big_endian(&self) -> bool {
+        match self.endian {
+            EndianOption::Little => false,
+            EndianOption::Big => true,
+            EndianOption::Native => cfg!(target_endian = "big"),
+        }
+    }
+
     /// Sets the size used for lengths of strings
     #[inline(always)]
     pub fn string_length(&mut self, size: LengthOption) -> &mut Self {
@@ -403,137 +946,1793 @@ impl Config {
         self
     }
 
-    /// Serializes a serializable object into a `Vec` of bytes using this configuration
+    /// Sets what happens to lengths that do not fit in their `LengthOption`, see
+    /// `Overflow`. The default is `Overflow::Error`.
     #[inline(always)]
-    pub fn serialize<T: ?Sized + serde::Serialize>(&self, t: &T) -> Result<Vec<u8>> {
-        config_map!(self, opts => ::internal::serialize(t, opts))
+    pub fn on_length_overflow(&mut self, overflow: Overflow) -> &mut Self {
+        self.length_overflow = overflow;
+        self
     }
 
-    /// Returns the size that an object would be if serialized using Bincode with this configuration
     #[inline(always)]
-    pub fn serialized_size<T: ?Sized + serde::Serialize>(&self, t: &T) -> Result<u64> {
-        config_map!(self, opts => ::internal::serialized_size(t, opts))
+    pub(crate) fn length_overflow(&self) -> Overflow {
+        self.length_overflow
     }
 
-    /// Serializes an object directly into a `Writer` using this configuration
+    /// Writes the variants of enums with the tags `M` maps their indices to, see
+    /// `VariantIndexMap`.
     ///
-    /// If the serialization would take more bytes than allowed by the size limit, an error
-    /// is returned and *no bytes* will be written into the `Writer`
+    /// This applies to values going through serde; `Encode` and `Decode` do not know
+    /// the names of enums, so they write variant indices as they are.
     #[inline(always)]
-    pub fn serialize_into<W: Write, T: ?Sized + serde::Serialize>(
-        &self,
-        w: W,
-        t: &T,
-    ) -> Result<()> {
-        config_map!(self, opts => ::internal::serialize_into(w, t, opts))
+    pub fn variant_index_map<M: VariantIndexMap>(&mut self) -> &mut Self {
+        self.variant_map = Some(VariantMap::of::<M>());
+        self
     }
 
-    /// Deserializes a slice of bytes into an instance of `T` using this configuration
+    /// Sets what happens to enum tags naming no variant, see `UnknownVariant`.
+    /// The default is `UnknownVariant::Error`.
     #[inline(always)]
-    pub fn deserialize<'a, T: serde::Deserialize<'a>>(&self, bytes: &'a [u8]) -> Result<T> {
-        config_map!(self, opts => ::internal::deserialize(bytes, opts))
+    pub fn on_unknown_variant(&mut self, unknown: UnknownVariant) -> &mut Self {
+        self.unknown_variant = unknown;
+        self
     }
 
-    /// TODO: document
-    #[doc(hidden)]
+    /// Reads enum tags naming no variant as the `#[serde(other)]` variant of the enum,
+    /// so that readers keep working when newer writers add variants. Shorthand for
+    /// `on_unknown_variant(UnknownVariant::Other)`.
+    ///
+    /// Set `variant_lengths` on both sides for the unknown variants to hold data:
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// # extern crate bincode2;
+    ///
+    /// #[derive(Serialize)]
+    /// enum New {
+    ///     Ping,
+    ///     Resize { width: u32, height: u32 },
+    /// }
+    ///
+    /// #[derive(Deserialize, Debug, PartialEq)]
+    /// enum Old {
+    ///     Ping,
+    ///     #[serde(other)]
+    ///     Unknown,
+    /// }
+    ///
+    /// # fn main() {
+    /// let mut config = bincode2::config();
+    /// config.variant_lengths().unknown_variant_fallback();
+    ///
+    /// let bytes = config
+    ///     .serialize(&(New::Resize { width: 640, height: 480 }, 7u8))
+    ///     .unwrap();
+    /// let value: (Old, u8) = config.deserialize(&bytes).unwrap();
+    /// assert_eq!(value, (Old::Unknown, 7));
+    /// # }
+    /// ```
     #[inline(always)]
-    pub fn deserialize_in_place<'a, R, T>(&self, reader: R, place: &mut T) -> Result<()>
-    where
-        R: BincodeRead<'a>,
-        T: serde::de::Deserialize<'a>,
-    {
-        config_map!(self, opts => ::internal::deserialize_in_place(reader, opts, place))
+    pub fn unknown_variant_fallback(&mut self) -> &mut Self {
+        self.on_unknown_variant(UnknownVariant::Other)
     }
 
-    /// Deserializes a slice of bytes with state `seed` using this configuration.
+    /// Writes the length in bytes of what each enum variant holds after its tag, using
+    /// the array length size, so that readers can skip variants they do not know.
+    /// See `unknown_variant_fallback`.
+    ///
+    /// Each variant is buffered before being written, so that its length is known.
+    /// This is ignored when `allow_untagged` is set. Data written with this option must
+    /// be read with it, and vice versa.
+    #[cfg(feature = "alloc")]
     #[inline(always)]
-    pub fn deserialize_seed<'a, T: serde::de::DeserializeSeed<'a>>(
-        &self,
-        seed: T,
-        bytes: &'a [u8],
-    ) -> Result<T::Value> {
-        config_map!(self, opts => ::internal::deserialize_seed(seed, bytes, opts))
+    pub fn variant_lengths(&mut self) -> &mut Self {
+        self.variant_lengths = true;
+        self
     }
 
-    /// Deserializes an object directly from a `Read`er using this configuration
-    ///
-    /// If this returns an `Error`, `reader` may be in an invalid state.
     #[inline(always)]
-    pub fn deserialize_from<R: Read, T: serde::de::DeserializeOwned>(
-        &self,
-        reader: R,
-    ) -> Result<T> {
-        config_map!(self, opts => ::internal::deserialize_from(reader, opts))
+    pub(crate) fn variants_sized(&self) -> bool {
+        self.variant_lengths && !self.allow_untagged
     }
 
-    /// Deserializes an object directly from a `Read`er with state `seed` using this configuration
+    /// Writes and reads values of type `C::Value` with `C` instead of their serde impls,
+    /// replacing the codec registered for that type if any. See `TypeCodec` for where
+    /// it applies.
     ///
-    /// If this returns an `Error`, `reader` may be in an invalid state.
+    /// Data written with a codec must be read with it, and vice versa.
+    ///
+    /// # Panics
+    ///
+    /// If codecs for 8 other types are registered already. The codecs are held in
+    /// the config itself, so that it stays `Copy`.
+    #[cfg(feature = "alloc")]
     #[inline(always)]
-    pub fn deserialize_from_seed<'a, R: Read, T: serde::de::DeserializeSeed<'a>>(
-        &self,
-        seed: T,
-        reader: R,
-    ) -> Result<T::Value> {
-        config_map!(self, opts => ::internal::deserialize_from_seed(seed, reader, opts))
+    pub fn type_codec<C: TypeCodec>(&mut self) -> &mut Self {
+        self.codecs.insert::<C>();
+        self
     }
 
-    /// Deserializes an object from a custom `BincodeRead`er using the default configuration.
-    /// It is highly recommended to use `deserialize_from` unless you need to implement
-    /// `BincodeRead` for performance reasons.
-    ///
-    /// If this returns an `Error`, `reader` may be in an invalid state.
     #[inline(always)]
-    pub fn deserialize_from_custom<'a, R: BincodeRead<'a>, T: serde::de::DeserializeOwned>(
-        &self,
-        reader: R,
-    ) -> Result<T> {
-        config_map!(self, opts => ::internal::deserialize_from_custom(reader, opts))
+    pub(crate) fn codecs(&self) -> &Codecs {
+        &self.codecs
     }
 
-    /// Deserializes an object from a custom `BincodeRead`er with state `seed` using the default
-    /// configuration. It is highly recommended to use `deserialize_from` unless you need to
-    /// implement `BincodeRead` for performance reasons.
-    ///
-    /// If this returns an `Error`, `reader` may be in an invalid state.
+    /// The tag written for the variant at `index` of the enum `name`.
     #[inline(always)]
-    pub fn deserialize_from_custom_seed<
-        'a,
-        R: BincodeRead<'a>,
-        T: serde::de::DeserializeSeed<'a>,
-    >(
+    pub(crate) fn variant_to_wire(&self, name: &'static str, index: u32) -> u32 {
+        match self.variant_map {
+            Some(map) => (map.to_wire)(name, index),
+            None => index,
+        }
+    }
+
+    /// The index of the variant of the enum `name`, which has `len` variants,
+    /// written as `tag`, and whether the tag names one of them.
+    pub(crate) fn variant_from_wire(
         &self,
-        seed: T,
-        reader: R,
-    ) -> Result<T::Value> {
-        config_map!(self, opts => ::internal::deserialize_from_custom_seed(seed, reader, opts))
+        name: &'static str,
+        len: usize,
+        tag: u32,
+    ) -> Result<(u32, bool)> {
+        let index = match self.variant_map {
+            Some(map) => (map.from_wire)(name, tag),
+            None if self.unknown_variant == UnknownVariant::Error => {
+                return Ok((tag, (tag as usize) < len))
+            }
+            None => Some(tag),
+        };
+        match (index, self.unknown_variant) {
+            (Some(index), _) if (index as usize) < len => Ok((index, true)),
+            (_, UnknownVariant::Fallback(index)) => Ok((index, false)),
+            // Past the last variant, which serde reads as the `#[serde(other)]` one.
+            (_, UnknownVariant::Other) => Ok((len as u32, false)),
+            (_, UnknownVariant::Error) => {
+                Err(ErrorKind::InvalidTagEncoding(tag as usize).into())
+            }
+        }
     }
 
-    /// Executes the acceptor with a serde::Deserializer instance.
-    /// NOT A PART OF THE STABLE PUBLIC API
-    #[doc(hidden)]
-    pub fn with_deserializer<'a, A, R>(&self, reader: R, acceptor: A) -> A::Output
-    where
-        A: DeserializerAcceptor<'a>,
-        R: BincodeRead<'a>,
-    {
-        config_map!(self, opts => {
-            let mut deserializer = ::de::Deserializer::new(reader, opts);
-            acceptor.accept(&mut deserializer)
-        })
+    /// Sets how strings are written, see `StringMode`.
+    /// The default is `StringMode::LengthPrefixed`.
+    #[inline(always)]
+    pub fn string_encoding(&mut self, mode: StringMode) -> &mut Self {
+        self.string_mode = mode;
+        self
     }
 
-    /// Executes the acceptor with a serde::Serializer instance.
-    /// NOT A PART OF THE STABLE PUBLIC API
-    #[doc(hidden)]
-    pub fn with_serializer<A, W>(&self, writer: W, acceptor: A) -> A::Output
-    where
-        A: SerializerAcceptor,
-        W: Write,
-    {
-        config_map!(self, opts => {
-            let mut serializer = ::ser::Serializer::new(writer, opts);
-            acceptor.accept(&mut serializer)
-        })
+    #[inline(always)]
+    pub(crate) fn string_mode(&self) -> StringMode {
+        self.string_mode
+    }
+
+    /// Writes the lengths of strings with `L`, see `CustomSizeType`.
+    #[inline(always)]
+    pub fn string_length_custom<L: CustomSizeType>(&mut self) -> &mut Self {
+        self.string_length(LengthOption::Custom(CustomLength::of::<L>()))
+    }
+
+    /// Writes the lengths of arrays, sequences and maps with `L`, see `CustomSizeType`.
+    #[inline(always)]
+    pub fn array_length_custom<L: CustomSizeType>(&mut self) -> &mut Self {
+        self.array_length(LengthOption::Custom(CustomLength::of::<L>()))
+    }
+
+    #[inline(always)]
+    pub(crate) fn string_length_option(&self) -> LengthOption {
+        self.string_size
+    }
+
+    #[inline(always)]
+    pub(crate) fn array_length_option(&self) -> LengthOption {
+        self.array_size
+    }
+
+    #[inline(always)]
+    pub(crate) fn custom_string_length(&self) -> Option<CustomLength> {
+        match self.string_size {
+            LengthOption::Custom(custom) => Some(custom),
+            _ => None,
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn custom_array_length(&self) -> Option<CustomLength> {
+        match self.array_size {
+            LengthOption::Custom(custom) => Some(custom),
+            _ => None,
+        }
+    }
+
+    /// Enables support for `#[serde(flatten)]`.
+    ///
+    /// Every map is written as a length-prefixed list of entries in which each key and
+    /// value is preceded by a one byte type marker, so that serde can buffer entries it
+    /// does not know the type of. Maps of unknown length (which is how serde writes
+    /// flattened structs) are buffered in memory until their length is known.
+    ///
+    /// This costs one byte per value nested inside a map, plus a length prefix and a
+    /// variant index for enums inside maps. Data written with this option must be read
+    /// with it, and vice versa.
+    ///
+    /// The markers are also what lets a value serde ignores, such as an unknown field
+    /// of a flattened struct or a `serde::de::IgnoredAny`, be skipped without being
+    /// decoded or allocated. Only values inside maps can be skipped: elsewhere, and
+    /// without this option, `deserialize_ignored_any` fails with `ErrorKind::Custom`.
+    /// `allow_untagged` marks every value, so that any of them can be skipped.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn allow_flatten(&mut self) -> &mut Self {
+        self.allow_flatten = true;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn flatten_allowed(&self) -> bool {
+        self.allow_flatten || self.allow_untagged
+    }
+
+    /// Enables support for `#[serde(untagged)]` and `#[serde(tag = "...")]` enums.
+    ///
+    /// Serde reads these enums by buffering the next value with `deserialize_any` and
+    /// then trying each variant against the buffered value in order, so the bytes must
+    /// say what kind of value they hold. With this option every value, not only those
+    /// inside maps, carries the one byte type marker of `allow_flatten`, which this
+    /// option implies; tuples and structs are written as sequences, and enums as maps
+    /// with a single entry keyed by the variant index.
+    ///
+    /// `dedup_subtrees`, `aligned`, `variant_lengths` and the codecs registered with
+    /// `type_codec` are ignored when this is set. Data written with this option must be
+    /// read with it, and vice versa. The headers of `serialize_batch` and the frame
+    /// lengths of `codec::BincodeCodec` are not marked, only the values they hold.
+    ///
+    /// ```
+    /// # extern crate bincode2;
+    /// # #[macro_use] extern crate serde_derive;
+    /// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    /// #[serde(untagged)]
+    /// enum Id {
+    ///     Number(u64),
+    ///     Name(String),
+    ///     Pair { high: u32, low: u32 },
+    /// }
+    ///
+    /// # fn main() {
+    /// let mut config = bincode2::config();
+    /// config.allow_untagged();
+    ///
+    /// let ids = vec![Id::Number(7), Id::Name("seven".into()), Id::Pair { high: 0, low: 7 }];
+    /// let bytes = config.serialize(&ids).unwrap();
+    /// assert_eq!(config.deserialize::<Vec<Id>>(&bytes).unwrap(), ids);
+    /// # }
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn allow_untagged(&mut self) -> &mut Self {
+        self.allow_untagged = true;
+        self
+    }
+
+    /// Whether every value is written self-describing, not only those inside maps.
+    #[inline(always)]
+    pub(crate) fn untagged_allowed(&self) -> bool {
+        self.allow_untagged
+    }
+
+    /// Writes every struct field with a header, allowing the struct definition to
+    /// change between the writer and the reader.
+    ///
+    /// A struct starts with the number of fields written (using the array length
+    /// size) and each field with a 4 byte header: a 29 bit id derived from the
+    /// field's name and a 3 bit wire type. Values that take 1, 2, 4, 8 or 16 bytes
+    /// are written as is; anything else is preceded by its length in bytes.
+    ///
+    /// When reading, fields whose id is unknown are skipped, and fields that are
+    /// absent are treated as missing by serde: they take their `#[serde(default)]`
+    /// value, or `None` for options. Fields are identified by name, so renaming a
+    /// field (without `#[serde(rename)]`) is a breaking change while reordering is not.
+    ///
+    /// Each field is buffered before being written, so that its size is known.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn tagged_fields(&mut self) -> &mut Self {
+        self.tagged_fields = true;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn fields_tagged(&self) -> bool {
+        self.tagged_fields
+    }
+
+    /// Lets a struct end early when the input ends at one of its field boundaries, so that
+    /// fields appended to a struct can be read from data written before they existed.
+    ///
+    /// The fields missing at the end of the input are treated as missing by serde: they
+    /// take their `#[serde(default)]` value, or `None` for options; any other missing field
+    /// is an error. This only works when the struct is the last thing in the input, and
+    /// when reading from a slice or another reader that can tell where its input ends;
+    /// `IoReader` cannot. Nothing changes in how structs are written.
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// # extern crate bincode2;
+    ///
+    /// #[derive(Serialize)]
+    /// struct V1 {
+    ///     id: u32,
+    /// }
+    ///
+    /// #[derive(Deserialize, PartialEq, Debug)]
+    /// struct V2 {
+    ///     id: u32,
+    ///     #[serde(default)]
+    ///     tags: Vec<String>,
+    /// }
+    ///
+    /// # fn main() {
+    /// let old = bincode2::serialize(&V1 { id: 7 }).unwrap();
+    ///
+    /// let mut config = bincode2::config();
+    /// config.allow_trailing_default_fields();
+    /// let new: V2 = config.deserialize(&old).unwrap();
+    /// assert_eq!(new, V2 { id: 7, tags: vec![] });
+    /// # }
+    /// ```
+    #[inline(always)]
+    pub fn allow_trailing_default_fields(&mut self) -> &mut Self {
+        self.trailing_default_fields = true;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn trailing_default_fields(&self) -> bool {
+        self.trailing_default_fields
+    }
+
+    /// Writes the entries of every map sorted by the bytes of their encoded keys, so that
+    /// a map serializes to the same bytes whatever order it iterates in, as `HashMap`
+    /// does differently in every process.
+    ///
+    /// Each entry is buffered until the end of its map. Nothing changes in how maps are
+    /// read. Sets are written as sequences, which are not sorted. Values inside a sorted
+    /// map are never written as back-references when `dedup_subtrees` is set.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// let mut config = bincode2::config();
+    /// config.sort_map_keys();
+    ///
+    /// let a: HashMap<u8, &str> = vec![(2, "b"), (1, "a")].into_iter().collect();
+    /// let b: HashMap<u8, &str> = vec![(1, "a"), (2, "b")].into_iter().collect();
+    /// assert_eq!(config.serialize(&a).unwrap(), config.serialize(&b).unwrap());
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn sort_map_keys(&mut self) -> &mut Self {
+        self.sort_map_keys = true;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn map_keys_sorted(&self) -> bool {
+        self.sort_map_keys
+    }
+
+    /// Writes each struct, tuple struct, sequence and map that repeats one written earlier in
+    /// the same message as a reference to the earlier one, which suits data shaped like a DAG.
+    ///
+    /// Each of these values is preceded by a byte telling whether it is written in full or
+    /// as a back-reference, followed in that case by its distance in bytes to the earlier
+    /// copy as a `u64`. Values are compared by their encoding, so equal values of different
+    /// types are shared too; values of 8 bytes or less are always written in full. Each value
+    /// is buffered while being written, then copied into the buffer of the value enclosing it,
+    /// so every byte is copied once per value it is nested in. The bytes of every distinct
+    /// value are also kept until the end of the message to find the repeats.
+    ///
+    /// Back-references can only be followed when deserializing from a slice; readers report
+    /// an error when they meet one. As a small message may expand into a large value, every
+    /// byte read again to follow a back-reference counts against the deserialize limit, as
+    /// the bytes of the message do. Without a limit, reading fails with `ErrorKind::SizeLimit`
+    /// once 64 MiB have been read this way, the message included; set a limit to read larger
+    /// values.
+    ///
+    /// This is ignored when `tagged_fields`, `allow_flatten` or `allow_untagged` is set,
+    /// and `Encode` types are written in full.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn dedup_subtrees(&mut self) -> &mut Self {
+        self.dedup_subtrees = true;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn subtrees_deduped(&self) -> bool {
+        self.dedup_subtrees && !self.flatten_allowed() && !self.tagged_fields
+    }
+
+    /// Pads the output with zero bytes so that every integer and float wider than a byte,
+    /// including lengths and enum tags, starts at an offset that is a multiple of the
+    /// smaller of its size and `n`. This is the layout of a `#[repr(C)]` struct of those
+    /// primitives when `n` is at least their alignment, except that no padding follows
+    /// the last field of a struct. Readers skip the padding.
+    ///
+    /// Offsets count from the start of each serialized value, so the buffer holding it
+    /// must itself be aligned to `n` for the fields to be. `char`s, the bytes of strings,
+    /// `UsizeMode::Varint` values and integers written as `IntEncoding::Varint` are
+    /// written as they are, unaligned.
+    ///
+    /// This is ignored when `tagged_fields`, `allow_flatten`, `allow_untagged`,
+    /// `dedup_subtrees`, `sort_map_keys` or `variant_lengths` is set, as those write parts
+    /// of the output after what follows them. Data written with this option must be read
+    /// with it, and vice versa.
+    ///
+    /// ```
+    /// let mut config = bincode2::config();
+    /// config.aligned(4);
+    ///
+    /// let bytes = config.serialize(&(1u8, 2u32, 3u16)).unwrap();
+    /// assert_eq!(bytes, [1, 0, 0, 0, 2, 0, 0, 0, 3, 0]);
+    /// assert_eq!(config.deserialize::<(u8, u32, u16)>(&bytes).unwrap(), (1, 2, 3));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `n` is not a power of two.
+    #[inline(always)]
+    pub fn aligned(&mut self, n: usize) -> &mut Self {
+        assert!(n.is_power_of_two(), "alignment must be a power of two");
+        self.alignment = n as u64;
+        self
+    }
+
+    /// The alignment of primitives, 1 when they are not aligned.
+    #[inline(always)]
+    pub(crate) fn alignment(&self) -> u64 {
+        if self.flatten_allowed()
+            || self.tagged_fields
+            || self.dedup_subtrees
+            || self.sort_map_keys
+            || self.variant_lengths
+        {
+            return 1;
+        }
+        self.alignment
+    }
+
+    /// Hands strings and byte arrays to serde as owned buffers, even when they could be
+    /// borrowed from the input.
+    ///
+    /// By default a length-prefixed string or byte array read from a slice, whether by
+    /// `deserialize` or through a `SliceReader`, is passed to serde borrowed, so that
+    /// `Cow<'a, str>` and `Cow<'a, [u8]>` fields marked `#[serde(borrow)]` deserialize
+    /// as `Cow::Borrowed`. Values read from an `io::Read`, and strings written with a
+    /// `StringMode` other than `LengthPrefixed`, are always copied and deserialize as
+    /// `Cow::Owned`. `Cow` fields without `#[serde(borrow)]` are always owned, as serde
+    /// deserializes them.
+    ///
+    /// With this option set every string and byte array is copied, so `Cow` fields are
+    /// always `Cow::Owned`, and types that can only borrow, like `&str` and `&[u8]`, fail
+    /// to deserialize. The bytes written are unchanged.
+    ///
+    /// ```
+    /// # extern crate bincode2;
+    /// # #[macro_use] extern crate serde_derive;
+    /// use std::borrow::Cow;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Message<'a> {
+    ///     #[serde(borrow)]
+    ///     text: Cow<'a, str>,
+    /// }
+    ///
+    /// # fn main() {
+    /// let mut config = bincode2::config();
+    /// let bytes = config.serialize(&Message { text: "hi".into() }).unwrap();
+    ///
+    /// let message: Message = config.deserialize(&bytes).unwrap();
+    /// assert!(matches!(message.text, Cow::Borrowed("hi")));
+    ///
+    /// config.force_owned();
+    /// let message: Message = config.deserialize(&bytes).unwrap();
+    /// assert!(matches!(message.text, Cow::Owned(_)));
+    /// # }
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn force_owned(&mut self) -> &mut Self {
+        self.force_owned = true;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn owned_forced(&self) -> bool {
+        self.force_owned
+    }
+
+    /// The limit set for deserializing, if any.
+    #[inline(always)]
+    pub(crate) fn deserialize_byte_limit(&self) -> Option<u64> {
+        match self.deserialize_limit {
+            LimitOption::Unlimited => None,
+            LimitOption::Limited(limit) => Some(limit),
+        }
+    }
+
+    /// Writes the lengths of strings, sequences and maps, and `usize` and `isize` values
+    /// encoded with `Encode`, as `mode` says, so that the format does not depend on the
+    /// width of `usize` on the writer. This takes precedence over `string_length` and
+    /// `array_length`. With `UsizeMode::Varint`, `isize` values are zigzag encoded.
+    ///
+    /// Serde hands `usize` values to the serializer as `u64`, so `usize` fields of types
+    /// going through serde are still written in 8 bytes. When reading on a target whose
+    /// `usize` is narrower than the value written, lengths fail with `SizeLimit` and
+    /// `usize` values with `SizeLimit` (through `Decode`) or a `Custom` error (through serde).
+    #[inline(always)]
+    pub fn usize_encoding(&mut self, mode: UsizeMode) -> &mut Self {
+        self.usize_encoding = Some(mode);
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn usize_mode(&self) -> Option<UsizeMode> {
+        self.usize_encoding
+    }
+
+    /// Sets how `f32` and `f64` values are written, see `FloatEncoding`.
+    /// The default is `FloatEncoding::Ieee`.
+    ///
+    /// # Panics
+    ///
+    /// If a fixed-point encoding has more fraction bits or decimal places than
+    /// its integer holds, see `FloatEncoding`.
+    #[inline(always)]
+    pub fn float_encoding(&mut self, encoding: FloatEncoding) -> &mut Self {
+        assert!(
+            encoding.is_valid(),
+            "too many fraction digits for the float encoding"
+        );
+        self.float_encoding = encoding;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn floats(&self) -> FloatEncoding {
+        self.float_encoding
+    }
+
+    /// Sets how integers wider than a byte are written, see `IntEncoding`. The
+    /// default is `IntEncoding::Fixed`.
+    ///
+    /// The tags of enum variants and the lengths written as `string_length` and
+    /// `array_length` ask are integers too, and are written the same way. Inside
+    /// the self-describing values of `allow_flatten` and `allow_untagged`,
+    /// integers keep their full width.
+    #[inline(always)]
+    pub fn int_encoding(&mut self, encoding: IntEncoding) -> &mut Self {
+        self.int_encoding = encoding;
+        self
+    }
+
+    #[inline(always)]
+    pub(crate) fn ints(&self) -> IntEncoding {
+        self.int_encoding
+    }
+
+    /// Returns a configuration whose deserializations all draw from `budget`, failing with
+    /// `ErrorKind::SizeLimit` once the bytes read by all of them together would exceed it.
+    ///
+    /// The limits of this configuration still apply to each message on its own. Bytes read by a
+    /// deserialization that fails are not given back.
+    ///
+    /// ```
+    /// use std::sync::atomic::AtomicU64;
+    /// use std::sync::Arc;
+    ///
+    /// let budget = Arc::new(AtomicU64::new(10));
+    /// let config = bincode2::config().with_shared_limit(budget.clone());
+    /// let bytes = bincode2::serialize(&1u32).unwrap();
+    ///
+    /// assert_eq!(config.deserialize::<u32>(&bytes).unwrap(), 1);
+    /// assert_eq!(config.deserialize::<u32>(&bytes).unwrap(), 1);
+    /// assert!(config.deserialize::<u32>(&bytes).is_err());
+    /// assert_eq!(config.remaining(), 2);
+    /// ```
+    #[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+    #[inline(always)]
+    pub fn with_shared_limit(&self, budget: Arc<AtomicU64>) -> BudgetedConfig {
+        BudgetedConfig {
+            config: *self,
+            budget,
+        }
+    }
+
+    /// Returns a configuration whose deserializations fail with `ErrorKind::Cancelled` once
+    /// `cancelled` returns true, so that decoding a huge or hostile input can be abandoned
+    /// from another thread, or when a deadline passes.
+    ///
+    /// `cancelled` is called before the first byte is read and then every 4 KiB or so, from
+    /// the thread deserializing. Values that take no bytes, like `()`, do not count.
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicBool, Ordering};
+    /// use std::sync::Arc;
+    /// use bincode2::ErrorKind;
+    ///
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let flag = stop.clone();
+    /// let config = bincode2::config().with_cancel_token(move || flag.load(Ordering::Relaxed));
+    /// let bytes = bincode2::serialize(&vec![0u8; 1 << 20]).unwrap();
+    ///
+    /// assert!(config.deserialize::<Vec<u8>>(&bytes).is_ok());
+    /// stop.store(true, Ordering::Relaxed);
+    /// match *config.deserialize::<Vec<u8>>(&bytes).unwrap_err() {
+    ///     ErrorKind::Cancelled => {}
+    ///     ref e => panic!("unexpected error {:?}", e),
+    /// }
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn with_cancel_token<F>(&self, cancelled: F) -> CancellableConfig
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        CancellableConfig {
+            config: *self,
+            cancelled: Arc::new(cancelled),
+        }
+    }
+
+    /// Returns a configuration whose `serialize` takes its output buffer, and the buffers it
+    /// needs along the way, from `pool`, so that serializing does not allocate once the pool
+    /// holds buffers large enough.
+    ///
+    /// ```
+    /// use bincode2::Pool;
+    ///
+    /// let pool = Pool::with_buffers(1, 64);
+    /// let config = bincode2::config().with_buffer_pool(&pool);
+    ///
+    /// let bytes = config.serialize(&(1u32, "hello")).unwrap();
+    /// assert_eq!(&bytes[..], &bincode2::serialize(&(1u32, "hello")).unwrap()[..]);
+    /// assert_eq!(pool.idle(), 0);
+    ///
+    /// drop(bytes);
+    /// assert_eq!(pool.idle(), 1);
+    /// ```
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    pub fn with_buffer_pool(&self, pool: &Pool) -> PooledConfig {
+        PooledConfig {
+            config: *self,
+            pool: pool.clone(),
+        }
+    }
+
+    /// Returns a configuration whose messages pass through `layers`, such as compression,
+    /// encryption and checksums: the first layer is applied first when serializing and
+    /// removed last when deserializing. See the `layer` module.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn with_layers(&self, layers: Vec<Box<dyn FrameLayer>>) -> LayeredConfig {
+        LayeredConfig::new(*self, layers)
+    }
+
+    /// Serializes a serializable object into a `Vec` of bytes using this configuration
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn serialize<T: ?Sized + serde::Serialize>(&self, t: &T) -> Result<Vec<u8>> {
+        config_map!(self, serialize_limit, opts => ::internal::serialize(t, opts))
+    }
+
+    /// Returns the size that an object would be if serialized using Bincode with this configuration
+    #[inline(always)]
+    pub fn serialized_size<T: ?Sized + serde::Serialize>(&self, t: &T) -> Result<u64> {
+        config_map!(self, serialize_limit, opts => ::internal::serialized_size(t, opts))
+    }
+
+    /// Returns the size that an object would be if serialized using Bincode with this
+    /// configuration, or `None` if it would be larger than `cap`.
+    ///
+    /// Counting stops as soon as the size passes `cap`, so large values are not walked
+    /// in full just to find out they do not fit.
+    #[inline(always)]
+    pub fn serialized_size_capped<T: ?Sized + serde::Serialize>(
+        &self,
+        t: &T,
+        cap: u64,
+    ) -> Result<Option<u64>> {
+        config_map!(self, serialize_limit, opts => ::internal::serialized_size_capped(t, opts, cap))
+    }
+
+    /// Serializes an object directly into a `Writer` using this configuration
+    ///
+    /// If the serialization would take more bytes than allowed by the size limit, an error
+    /// is returned and *no bytes* will be written into the `Writer`
+    #[inline(always)]
+    pub fn serialize_into<W: Write, T: ?Sized + serde::Serialize>(
+        &self,
+        w: W,
+        t: &T,
+    ) -> Result<()> {
+        config_map!(self, serialize_limit, opts => ::internal::serialize_into(w, t, opts))
+    }
+
+    /// Writes zeros for a length of `width` at the end of `buffer`, to be filled in with
+    /// the number of bytes written after it, see `Placeholder`.
+    ///
+    /// Fails if `width` is a custom length, or if integers are written as varints, as the
+    /// size of the length is then not known in advance.
+    #[cfg(feature = "alloc")]
+    pub fn reserve_length(&self, buffer: &mut Vec<u8>, width: LengthOption) -> Result<Placeholder> {
+        let placeholder = Placeholder::new(self, width, buffer.len() as u64)?;
+        buffer.extend_from_slice(placeholder.zeros());
+        Ok(placeholder)
+    }
+
+    /// Writes zeros for a length of `width` to `writer`, to be filled in by seeking back
+    /// once the bytes it counts are written, see `Placeholder::fill_at`.
+    ///
+    /// Fails if `width` is a custom length, or if integers are written as varints, as the
+    /// size of the length is then not known in advance.
+    pub fn reserve_length_at<W: Write + Seek>(
+        &self,
+        mut writer: W,
+        width: LengthOption,
+    ) -> Result<Placeholder> {
+        let position = writer.seek(SeekFrom::Current(0))?;
+        let placeholder = Placeholder::new(self, width, position)?;
+        writer.write_all(placeholder.zeros())?;
+        Ok(placeholder)
+    }
+
+    /// Serializes an object directly into a `std::io::Write`r using this configuration
+    ///
+    /// If the serialization would take more bytes than allowed by the size limit, an error
+    /// is returned and *no bytes* will be written into the `Writer`
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    pub fn serialize_into_std<W: ::std::io::Write, T: ?Sized + serde::Serialize>(
+        &self,
+        w: W,
+        t: &T,
+    ) -> Result<()> {
+        self.serialize_into(::std_io::StdWriteAdapter::new(w), t)
+    }
+
+    /// Serializes an object directly into a `bytes::BufMut`, such as a pooled `BytesMut`.
+    /// Pass `&mut buf` to keep the buffer.
+    ///
+    /// If the serialization would take more bytes than allowed by the size limit, an error
+    /// is returned and *no bytes* will be written into the buffer
+    #[cfg(feature = "bytes")]
+    #[inline(always)]
+    pub fn serialize_into_buf<B: ::bytes::BufMut, T: ?Sized + serde::Serialize>(
+        &self,
+        buf: B,
+        t: &T,
+    ) -> Result<()> {
+        self.serialize_into(::bytes_io::BufMutWriter::new(buf), t)
+    }
+
+    /// Serializes an object into `Bytes`, allocated once at the serialized size.
+    #[cfg(feature = "bytes")]
+    #[inline(always)]
+    pub fn serialize_to_bytes<T: ?Sized + serde::Serialize>(
+        &self,
+        t: &T,
+    ) -> Result<::bytes::Bytes> {
+        config_map!(self, serialize_limit, opts => ::internal::serialize_to_bytes(t, opts))
+    }
+
+    /// The statistics of the last call to serialize or deserialize a value made on this
+    /// thread, with any configuration. Calls made by other methods, like `serialize_batch`,
+    /// each count as one.
+    ///
+    /// ```
+    /// let config = bincode2::config();
+    /// config.serialize(&vec!["a", "bc"]).unwrap();
+    ///
+    /// let stats = bincode2::Config::last_stats();
+    /// assert_eq!(stats.bytes, 8 + 2 * 8 + 3);
+    /// assert_eq!(stats.values, 3);
+    /// assert_eq!(stats.strings, 2);
+    /// assert_eq!(stats.max_depth, 1);
+    /// ```
+    #[cfg(feature = "metrics")]
+    pub fn last_stats() -> ::metrics::Stats {
+        ::metrics::last()
+    }
+
+    /// Serializes an object into a `Vec` of bytes using this configuration, adding what
+    /// the call went through to `stats`.
+    #[cfg(feature = "metrics")]
+    pub fn serialize_with_stats<T: ?Sized + serde::Serialize>(
+        &self,
+        t: &T,
+        stats: &mut ::metrics::Stats,
+    ) -> Result<Vec<u8>> {
+        let result = self.serialize(t);
+        stats.add(&::metrics::last());
+        result
+    }
+
+    /// Deserializes a slice of bytes using this configuration, adding what the call went
+    /// through to `stats`.
+    #[cfg(feature = "metrics")]
+    pub fn deserialize_with_stats<'a, T: serde::Deserialize<'a>>(
+        &self,
+        bytes: &'a [u8],
+        stats: &mut ::metrics::Stats,
+    ) -> Result<T> {
+        let result = self.deserialize(bytes);
+        stats.add(&::metrics::last());
+        result
+    }
+
+    /// Serializes every item of `items` into one block, preceded by a header holding the
+    /// number of items and the offset of each, so that `deserialize_batch` can decode any
+    /// item without decoding the others.
+    ///
+    /// The limits of this configuration apply to each item, not to the whole block.
+    #[cfg(feature = "alloc")]
+    pub fn serialize_batch<I>(&self, items: I) -> Result<Vec<u8>>
+    where
+        I: IntoIterator,
+        I::Item: serde::Serialize,
+    {
+        batch::serialize(self, items)
+    }
+
+    /// Returns a view over a block written by `serialize_batch` with this configuration.
+    ///
+    /// Only the header is read here; `Batch::get` decodes a single item.
+    ///
+    /// ```
+    /// let config = bincode2::config();
+    /// let block = config.serialize_batch(&["alpha", "beta", "gamma"]).unwrap();
+    ///
+    /// let batch = config.deserialize_batch(&block).unwrap();
+    /// assert_eq!(batch.len(), 3);
+    /// assert_eq!(batch.get::<&str>(2).unwrap(), "gamma");
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn deserialize_batch<'a>(&self, bytes: &'a [u8]) -> Result<Batch<'a>> {
+        Batch::new(self, bytes)
+    }
+
+    /// Serializes `rows`, which must be structs with named fields, column by column: a
+    /// header holding the number of rows and the name and offset of every column, then
+    /// the first field of every row, then the second, and so on. `deserialize_columnar`
+    /// can then decode one field of every row without decoding the others.
+    ///
+    /// The limits of this configuration apply to each field, not to the whole block.
+    #[cfg(feature = "alloc")]
+    pub fn serialize_columnar<I>(&self, rows: I) -> Result<Vec<u8>>
+    where
+        I: IntoIterator,
+        I::Item: serde::Serialize,
+    {
+        columnar::serialize(self, rows)
+    }
+
+    /// Returns a view over a block written by `serialize_columnar` with this configuration.
+    ///
+    /// Only the header is read here; `Columns::column` decodes a single column.
+    ///
+    /// ```
+    /// #[macro_use]
+    /// extern crate serde_derive;
+    /// # extern crate bincode2;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Reading {
+    ///     sensor: String,
+    ///     value: f64,
+    /// }
+    ///
+    /// # fn main() {
+    /// let config = bincode2::config();
+    /// let readings = vec![
+    ///     Reading { sensor: "a".into(), value: 1.5 },
+    ///     Reading { sensor: "b".into(), value: 2.5 },
+    /// ];
+    /// let block = config.serialize_columnar(&readings).unwrap();
+    ///
+    /// let columns = config.deserialize_columnar(&block).unwrap();
+    /// assert_eq!(columns.len(), 2);
+    /// assert_eq!(columns.column::<f64>("value").unwrap(), vec![1.5, 2.5]);
+    /// # }
+    /// ```
+    #[cfg(feature = "alloc")]
+    pub fn deserialize_columnar<'a>(&self, bytes: &'a [u8]) -> Result<Columns<'a>> {
+        Columns::new(self, bytes)
+    }
+
+    /// Deserializes a slice of bytes into an instance of `T` using this configuration
+    #[inline(always)]
+    pub fn deserialize<'a, T: serde::Deserialize<'a>>(&self, bytes: &'a [u8]) -> Result<T> {
+        config_map!(self, deserialize_limit, opts => ::internal::deserialize(bytes, opts))
+    }
+
+    /// Deserializes a slice of bytes into an instance of `T` using this configuration,
+    /// passing every string `T` borrows through `interner`.
+    ///
+    /// Only borrowed strings are interned: fields of type `&'i str`, or `Cow<'i, str>`
+    /// marked `#[serde(borrow)]`. Owned `String`s are allocated as usual, and serde gives
+    /// `Arc<str>` fields no way to share an existing allocation. Borrowed byte slices
+    /// can't be deserialized this way, as the bytes do not live as long as the interner.
+    ///
+    /// ```
+    /// use std::collections::BTreeSet;
+    /// use bincode2::StringInterner;
+    ///
+    /// // Leaks every distinct string; a long-lived process would use an arena.
+    /// #[derive(Default)]
+    /// struct Leaking(BTreeSet<&'static str>);
+    ///
+    /// impl StringInterner<'static> for Leaking {
+    ///     fn intern(&mut self, s: &str) -> &'static str {
+    ///         match self.0.get(s) {
+    ///             Some(interned) => interned,
+    ///             None => {
+    ///                 let interned = Box::leak(s.to_owned().into_boxed_str());
+    ///                 self.0.insert(interned);
+    ///                 interned
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let config = bincode2::config();
+    /// let bytes = config.serialize(&vec!["red", "green", "red"]).unwrap();
+    /// let mut interner = Leaking::default();
+    /// let colors: Vec<&str> = config.deserialize_with_interner(&bytes, &mut interner).unwrap();
+    /// assert_eq!(colors, ["red", "green", "red"]);
+    /// assert!(std::ptr::eq(colors[0], colors[2]));
+    /// ```
+    pub fn deserialize_with_interner<'i, T, I>(&self, bytes: &[u8], interner: &mut I) -> Result<T>
+    where
+        T: serde::Deserialize<'i>,
+        I: StringInterner<'i>,
+    {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_with_interner(bytes, interner, opts)
+        })
+    }
+
+    /// Checks that `bytes` start with a `T` that `deserialize` would read, without
+    /// building it, so that malformed input can be turned away cheaply before it is
+    /// decoded for real.
+    ///
+    /// Enum tags, lengths, UTF-8, the values of `bool`s and `char`s and everything else
+    /// `T`'s `Deserialize` impl reads are checked as `deserialize` does, and the bytes
+    /// read count against the deserialize limit. Nothing is copied or collected: `T`
+    /// is handed empty owned strings and byte buffers once their contents are checked,
+    /// and the elements of sequences and entries of maps are checked and dropped one
+    /// by one, the sequence or map being handed over empty. Values read by a
+    /// `TypeCodec` are built in full.
+    ///
+    /// This is only meant for types whose `Deserialize` impls accept empty owned
+    /// strings, byte buffers, sequences and maps, and read every element of a sequence
+    /// or map as one type, as the impls of serde and its derives do. For others the
+    /// answer can be wrong, and `deserialize`, dropping the value, should be used:
+    ///
+    /// - a type rejecting some of these when empty, such as a non-empty list, a `Vec`
+    ///   converted to an array, or a `#[serde(try_from)]` type checking a length, is
+    ///   rejected even where `deserialize` accepts it;
+    /// - a sequence read with elements of different types, which serde only does for
+    ///   tuples and structs, is checked as if all its elements had the type of the
+    ///   first, so it may be accepted or rejected wrongly.
+    ///
+    /// ```
+    /// # extern crate bincode2;
+    /// # #[macro_use] extern crate serde_derive;
+    /// use std::collections::HashMap;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Request {
+    ///     path: String,
+    ///     headers: HashMap<String, Vec<String>>,
+    ///     body: Vec<u8>,
+    /// }
+    ///
+    /// # fn main() {
+    /// let config = bincode2::config();
+    /// let request = Request {
+    ///     path: "/".into(),
+    ///     headers: HashMap::new(),
+    ///     body: vec![1, 2, 3],
+    /// };
+    /// let bytes = config.serialize(&request).unwrap();
+    ///
+    /// assert!(config.validate_only::<Request>(&bytes).is_ok());
+    /// assert!(config.validate_only::<Request>(&bytes[..bytes.len() - 1]).is_err());
+    /// # }
+    /// ```
+    #[cfg(feature = "validate")]
+    pub fn validate_only<'a, T: serde::Deserialize<'a>>(&self, bytes: &'a [u8]) -> Result<()> {
+        config_map!(self, deserialize_limit, opts => ::internal::validate::<T, _>(bytes, opts))
+    }
+
+    /// Deserializes an object from a `BincodeRead`er into an existing value using this
+    /// configuration, reusing its allocations where serde's `deserialize_in_place` allows.
+    ///
+    /// `Vec`s keep their capacity and deserialize their existing elements in place, so a
+    /// message can be recycled without reallocating its buffers. Other values, `String`s
+    /// included, are replaced by the one read. Structs only deserialize their fields in
+    /// place if their `Deserialize` implementation does, which for derived ones needs the
+    /// `deserialize_in_place` feature of serde_derive.
+    ///
+    /// If this returns an `Error`, `place` is left in a valid but unspecified state.
+    #[inline(always)]
+    pub fn deserialize_in_place<'a, R, T>(&self, reader: R, place: &mut T) -> Result<()>
+    where
+        R: BincodeRead<'a>,
+        T: serde::de::Deserialize<'a>,
+    {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_in_place(reader, opts, place)
+        })
+    }
+
+    /// Deserializes a slice of bytes into an existing value using this configuration,
+    /// see `deserialize_in_place`.
+    #[inline(always)]
+    pub fn deserialize_in_place_slice<'a, T>(&self, bytes: &'a [u8], place: &mut T) -> Result<()>
+    where
+        T: serde::de::Deserialize<'a>,
+    {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_in_place_slice(bytes, opts, place)
+        })
+    }
+
+    /// Deserializes a slice of bytes with state `seed` using this configuration.
+    #[inline(always)]
+    pub fn deserialize_seed<'a, T: serde::de::DeserializeSeed<'a>>(
+        &self,
+        seed: T,
+        bytes: &'a [u8],
+    ) -> Result<T::Value> {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_seed(seed, bytes, opts)
+        })
+    }
+
+    /// Deserializes an object directly from a `Read`er using this configuration
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn deserialize_from<R: Read, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        config_map!(self, deserialize_limit, opts => ::internal::deserialize_from(reader, opts))
+    }
+
+    /// Deserializes an object directly from a `std::io::Read`er using this configuration
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[cfg(feature = "std")]
+    #[inline(always)]
+    pub fn deserialize_from_std<R: ::std::io::Read, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        self.deserialize_from(::std_io::StdReadAdapter::new(reader))
+    }
+
+    /// Deserializes an object directly from a `Read`er with state `seed` using this configuration
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn deserialize_from_seed<'a, R: Read, T: serde::de::DeserializeSeed<'a>>(
+        &self,
+        seed: T,
+        reader: R,
+    ) -> Result<T::Value> {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_from_seed(seed, reader, opts)
+        })
+    }
+
+    /// Deserializes an object directly from a `Read`er using this configuration, lending
+    /// its strings and byte slices out of `scratch` instead of allocating them.
+    /// See `ScratchReader`.
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from_scratch<'s, R: Read, T: serde::Deserialize<'s>>(
+        &self,
+        reader: R,
+        scratch: &'s mut [u8],
+    ) -> Result<T> {
+        self.deserialize_from_custom_seed(
+            PhantomData,
+            ::de::read::ScratchReader::new(reader, scratch),
+        )
+    }
+
+    /// Deserializes an object from a custom `BincodeRead`er using the default configuration.
+    /// It is highly recommended to use `deserialize_from` unless you need to implement
+    /// `BincodeRead` for performance reasons.
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from_custom<'a, R: BincodeRead<'a>, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_from_custom(reader, opts)
+        })
+    }
+
+    /// Deserializes an object from a `ResumableReader`, reading it again from its start
+    /// if an earlier call stopped part way through.
+    ///
+    /// The bytes of the object are dropped from the reader only once it is read in full.
+    /// If this fails with an error for which `is_would_block` is true, call it again
+    /// when the underlying reader is ready; other errors leave the bytes in the reader
+    /// as well, so retrying them fails the same way.
+    #[cfg(feature = "alloc")]
+    pub fn deserialize_resumable<R: Read, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: &mut ::de::read::ResumableReader<R>,
+    ) -> Result<T> {
+        reader.rewind();
+        let value = self.deserialize_from_custom(&mut *reader)?;
+        reader.commit();
+        Ok(value)
+    }
+
+    /// Deserializes an object from a `bytes::Buf`, such as a chain of `Bytes` segments.
+    /// Pass `&mut buf` to keep the buffer, advanced past the bytes read. Only values
+    /// split across chunks of the buffer are copied.
+    ///
+    /// If this returns an `Error`, `buf` may have been advanced past some of the bytes.
+    #[cfg(feature = "bytes")]
+    #[inline(always)]
+    pub fn deserialize_from_buf<B: ::bytes::Buf, T: serde::de::DeserializeOwned>(
+        &self,
+        buf: B,
+    ) -> Result<T> {
+        self.deserialize_from_custom(::de::read::FillBufReader::new(::bytes_io::BufSource(buf)))
+    }
+
+    /// Deserializes an object from a custom `BincodeRead`er with state `seed` using the default
+    /// configuration. It is highly recommended to use `deserialize_from` unless you need to
+    /// implement `BincodeRead` for performance reasons.
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from_custom_seed<
+        'a,
+        R: BincodeRead<'a>,
+        T: serde::de::DeserializeSeed<'a>,
+    >(
+        &self,
+        seed: T,
+        reader: R,
+    ) -> Result<T::Value> {
+        config_map!(self, deserialize_limit, opts => {
+            ::internal::deserialize_from_custom_seed(seed, reader, opts)
+        })
+    }
+
+    /// Encodes an object into a `Vec` of bytes using this configuration, without going through serde
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn encode<T: ?Sized + Encode>(&self, t: &T) -> Result<Vec<u8>> {
+        config_map!(self, serialize_limit, opts => ::internal::encode(t, opts))
+    }
+
+    /// Encodes an object directly into a `Writer` using this configuration, without going through serde
+    ///
+    /// If the encoding would take more bytes than allowed by the size limit, an error
+    /// is returned and *no bytes* will be written into the `Writer`
+    #[inline(always)]
+    pub fn encode_into<W: Write, T: ?Sized + Encode>(&self, w: W, t: &T) -> Result<()> {
+        config_map!(self, serialize_limit, opts => ::internal::encode_into(w, t, opts))
+    }
+
+    /// Decodes a slice of bytes into an instance of `T` using this configuration, without going through serde
+    #[inline(always)]
+    pub fn decode<T: Decode>(&self, bytes: &[u8]) -> Result<T> {
+        config_map!(self, deserialize_limit, opts => ::internal::decode(bytes, opts))
+    }
+
+    /// Decodes an object directly from a `Read`er using this configuration, without going through serde
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[cfg(feature = "alloc")]
+    #[inline(always)]
+    pub fn decode_from<R: Read, T: Decode>(&self, reader: R) -> Result<T> {
+        config_map!(self, deserialize_limit, opts => ::internal::decode_from(reader, opts))
+    }
+
+    /// Creates a serde `Serializer` writing into `writer` with this configuration,
+    /// for code that drives serde itself. See the `raw` module.
+    #[inline(always)]
+    pub fn serializer_for<W: Write>(&self, writer: W) -> ::raw::Serializer<'_, W> {
+        ::raw::Serializer::new(writer, self)
+    }
+
+    /// Creates a serde `Deserializer` reading from `reader` with this configuration,
+    /// for code that drives serde itself. See the `raw` module.
+    #[inline(always)]
+    pub fn deserializer_for<'a, R>(&self, reader: R) -> ::raw::Deserializer<'_, R>
+    where
+        R: BincodeRead<'a>,
+    {
+        ::raw::Deserializer::new(reader, self)
+    }
+}
+
+/// A `Config` whose deserializations draw from a byte budget shared with other
+/// calls, created by `Config::with_shared_limit`. Only on targets with 64-bit atomics.
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+#[derive(Clone, Debug)]
+pub struct BudgetedConfig {
+    config: Config,
+    budget: Arc<AtomicU64>,
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+impl BudgetedConfig {
+    /// The configuration used for each call.
+    #[inline(always)]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The number of bytes left in the budget.
+    #[inline(always)]
+    pub fn remaining(&self) -> u64 {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    /// Deserializes a slice of bytes into an instance of `T`, drawing from the budget
+    #[inline(always)]
+    pub fn deserialize<'a, T: serde::Deserialize<'a>>(&self, bytes: &'a [u8]) -> Result<T> {
+        let reader = ::de::read::SliceReader::new(bytes);
+        config_map!(&self.config, deserialize_limit, opts => {
+            let opts = opts.with_shared_limit(&self.budget);
+            ::internal::deserialize_from_custom_seed(PhantomData, reader, opts)
+        })
+    }
+
+    /// Deserializes an object directly from a `Read`er, drawing from the budget
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from<R: Read, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        config_map!(&self.config, deserialize_limit, opts => {
+            ::internal::deserialize_from(reader, opts.with_shared_limit(&self.budget))
+        })
+    }
+
+    /// Deserializes an object from a custom `BincodeRead`er, drawing from the budget
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from_custom<'a, R: BincodeRead<'a>, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        config_map!(&self.config, deserialize_limit, opts => {
+            ::internal::deserialize_from_custom(reader, opts.with_shared_limit(&self.budget))
+        })
+    }
+}
+
+/// A `Config` whose deserializations can be cancelled, created by
+/// `Config::with_cancel_token`.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub struct CancellableConfig {
+    config: Config,
+    cancelled: Arc<dyn Fn() -> bool + Send + Sync>,
+}
+
+#[cfg(feature = "alloc")]
+impl fmt::Debug for CancellableConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("CancellableConfig")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl CancellableConfig {
+    /// The configuration used for each call.
+    #[inline(always)]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Deserializes a slice of bytes into an instance of `T`, unless cancelled
+    #[inline(always)]
+    pub fn deserialize<'a, T: serde::Deserialize<'a>>(&self, bytes: &'a [u8]) -> Result<T> {
+        let reader = ::de::read::SliceReader::new(bytes);
+        config_map!(&self.config, deserialize_limit, opts => {
+            let opts = opts.with_cancel_token(&self.cancelled);
+            ::internal::deserialize_from_custom_seed(PhantomData, reader, opts)
+        })
+    }
+
+    /// Deserializes an object directly from a `Read`er, unless cancelled
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from<R: Read, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        config_map!(&self.config, deserialize_limit, opts => {
+            ::internal::deserialize_from(reader, opts.with_cancel_token(&self.cancelled))
+        })
+    }
+
+    /// Deserializes an object from a custom `BincodeRead`er, unless cancelled
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    #[inline(always)]
+    pub fn deserialize_from_custom<'a, R: BincodeRead<'a>, T: serde::de::DeserializeOwned>(
+        &self,
+        reader: R,
+    ) -> Result<T> {
+        config_map!(&self.config, deserialize_limit, opts => {
+            ::internal::deserialize_from_custom(reader, opts.with_cancel_token(&self.cancelled))
+        })
+    }
+}
+
+/// A `Config` that serializes into buffers taken from a `Pool`, created by
+/// `Config::with_buffer_pool`.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug)]
+pub struct PooledConfig {
+    config: Config,
+    pool: Pool,
+}
+
+#[cfg(feature = "std")]
+impl PooledConfig {
+    /// The configuration used for each call.
+    #[inline(always)]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The pool buffers are taken from.
+    #[inline(always)]
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Serializes a serializable object into a buffer from the pool, which goes back to the
+    /// pool when the returned `PooledBytes` is dropped.
+    #[inline(always)]
+    pub fn serialize<T: ?Sized + serde::Serialize>(&self, t: &T) -> Result<PooledBytes> {
+        config_map!(&self.config, serialize_limit, opts => {
+            ::internal::serialize_pooled(t, opts, &self.pool)
+        })
+    }
+}
+
+/// Builds a `Config` in a `const` context, so that a configuration can be a
+/// `static` shared by every thread without being set up at run time.
+///
+/// The setters take and return the builder by value and do what the `Config`
+/// methods of the same name do; `build` gives the `Config`. Every option can be
+/// set this way, including the codecs of `type_codec`, the map of
+/// `variant_index_map` and the length types of `string_length_custom` and
+/// `array_length_custom`, and the `Config` built is `Copy`.
+///
+/// ```
+/// # extern crate bincode2;
+/// use bincode2::{Config, ConfigBuilder, LengthOption};
+///
+/// static WIRE: Config = ConfigBuilder::new()
+///     .big_endian()
+///     .string_length(LengthOption::U16)
+///     .limit(1 << 20)
+///     .build();
+///
+/// # fn main() {
+/// let bytes = WIRE.serialize("hi").unwrap();
+/// assert_eq!(bytes, [0, 2, b'h', b'i']);
+///
+/// let thread = std::thread::spawn(|| WIRE.deserialize::<String>(&[0, 2, b'h', b'i']));
+/// assert_eq!(thread.join().unwrap().unwrap(), "hi");
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConfigBuilder {
+    serialize_limit: LimitOption,
+    deserialize_limit: LimitOption,
+    endian: EndianOption,
+    string_size: LengthOption,
+    array_size: LengthOption,
+    string_mode: StringMode,
+    length_overflow: Overflow,
+    allow_flatten: bool,
+    allow_untagged: bool,
+    tagged_fields: bool,
+    float_encoding: FloatEncoding,
+    int_encoding: IntEncoding,
+    usize_encoding: Option<UsizeMode>,
+    dedup_subtrees: bool,
+    trailing_default_fields: bool,
+    sort_map_keys: bool,
+    variant_map: Option<VariantMap>,
+    unknown_variant: UnknownVariant,
+    variant_lengths: bool,
+    codecs: Codecs,
+    alignment: u64,
+    force_owned: bool,
+}
+
+impl ConfigBuilder {
+    /// The default configuration, as returned by `bincode2::config()`.
+    pub const fn new() -> ConfigBuilder {
+        ConfigBuilder {
+            serialize_limit: LimitOption::Unlimited,
+            deserialize_limit: LimitOption::Unlimited,
+            endian: EndianOption::Little,
+            string_size: LengthOption::U64,
+            array_size: LengthOption::U64,
+            string_mode: StringMode::LengthPrefixed,
+            length_overflow: Overflow::Error,
+            allow_flatten: false,
+            allow_untagged: false,
+            tagged_fields: false,
+            float_encoding: FloatEncoding::Ieee,
+            int_encoding: IntEncoding::Fixed,
+            usize_encoding: None,
+            dedup_subtrees: false,
+            trailing_default_fields: false,
+            sort_map_keys: false,
+            variant_map: None,
+            unknown_variant: UnknownVariant::Error,
+            variant_lengths: false,
+            codecs: Codecs::new(),
+            alignment: 1,
+            force_owned: false,
+        }
+    }
+
+    /// The configuration built.
+    pub const fn build(self) -> Config {
+        Config {
+            serialize_limit: self.serialize_limit,
+            deserialize_limit: self.deserialize_limit,
+            endian: self.endian,
+            string_size: self.string_size,
+            array_size: self.array_size,
+            string_mode: self.string_mode,
+            length_overflow: self.length_overflow,
+            allow_flatten: self.allow_flatten,
+            allow_untagged: self.allow_untagged,
+            tagged_fields: self.tagged_fields,
+            float_encoding: self.float_encoding,
+            int_encoding: self.int_encoding,
+            usize_encoding: self.usize_encoding,
+            dedup_subtrees: self.dedup_subtrees,
+            trailing_default_fields: self.trailing_default_fields,
+            sort_map_keys: self.sort_map_keys,
+            variant_map: self.variant_map,
+            unknown_variant: self.unknown_variant,
+            variant_lengths: self.variant_lengths,
+            codecs: self.codecs,
+            alignment: self.alignment,
+            force_owned: self.force_owned,
+        }
+    }
+
+    /// See `Config::no_limit`.
+    pub const fn no_limit(mut self) -> ConfigBuilder {
+        self.serialize_limit = LimitOption::Unlimited;
+        self.deserialize_limit = LimitOption::Unlimited;
+        self
+    }
+
+    /// See `Config::limit`.
+    pub const fn limit(mut self, limit: u64) -> ConfigBuilder {
+        self.serialize_limit = LimitOption::Limited(limit);
+        self.deserialize_limit = LimitOption::Limited(limit);
+        self
+    }
+
+    /// See `Config::serialize_limit`.
+    pub const fn serialize_limit(mut self, limit: u64) -> ConfigBuilder {
+        self.serialize_limit = LimitOption::Limited(limit);
+        self
+    }
+
+    /// See `Config::deserialize_limit`.
+    pub const fn deserialize_limit(mut self, limit: u64) -> ConfigBuilder {
+        self.deserialize_limit = LimitOption::Limited(limit);
+        self
+    }
+
+    /// See `Config::little_endian`.
+    pub const fn little_endian(mut self) -> ConfigBuilder {
+        self.endian = EndianOption::Little;
+        self
+    }
+
+    /// See `Config::big_endian`.
+    pub const fn big_endian(mut self) -> ConfigBuilder {
+        self.endian = EndianOption::Big;
+        self
+    }
+
+    /// See `Config::native_endian`.
+    pub const fn native_endian(mut self) -> ConfigBuilder {
+        self.endian = EndianOption::Native;
+        self
+    }
+
+    /// See `Config::string_length`.
+    pub const fn string_length(mut self, size: LengthOption) -> ConfigBuilder {
+        self.string_size = size;
+        self
+    }
+
+    /// See `Config::array_length`.
+    pub const fn array_length(mut self, size: LengthOption) -> ConfigBuilder {
+        self.array_size = size;
+        self
+    }
+
+    /// See `Config::string_length_custom`.
+    pub const fn string_length_custom<L: CustomSizeType>(self) -> ConfigBuilder {
+        self.string_length(LengthOption::Custom(CustomLength::of::<L>()))
+    }
+
+    /// See `Config::array_length_custom`.
+    pub const fn array_length_custom<L: CustomSizeType>(self) -> ConfigBuilder {
+        self.array_length(LengthOption::Custom(CustomLength::of::<L>()))
+    }
+
+    /// See `Config::on_length_overflow`.
+    pub const fn on_length_overflow(mut self, overflow: Overflow) -> ConfigBuilder {
+        self.length_overflow = overflow;
+        self
+    }
+
+    /// See `Config::on_unknown_variant`.
+    pub const fn on_unknown_variant(mut self, unknown: UnknownVariant) -> ConfigBuilder {
+        self.unknown_variant = unknown;
+        self
+    }
+
+    /// See `Config::unknown_variant_fallback`.
+    pub const fn unknown_variant_fallback(self) -> ConfigBuilder {
+        self.on_unknown_variant(UnknownVariant::Other)
+    }
+
+    /// See `Config::variant_index_map`.
+    pub const fn variant_index_map<M: VariantIndexMap>(mut self) -> ConfigBuilder {
+        self.variant_map = Some(VariantMap::of::<M>());
+        self
+    }
+
+    /// See `Config::type_codec`. As the codecs already registered can't be
+    /// compared in a `const`, a codec for a type that has one already is added
+    /// rather than replacing it, and the last one registered is used.
+    ///
+    /// # Panics
+    ///
+    /// If 8 codecs are registered already, which fails to compile in a `const`.
+    #[cfg(feature = "alloc")]
+    pub const fn type_codec<C: TypeCodec>(mut self) -> ConfigBuilder {
+        self.codecs = self.codecs.with::<C>();
+        self
+    }
+
+    /// See `Config::variant_lengths`.
+    #[cfg(feature = "alloc")]
+    pub const fn variant_lengths(mut self) -> ConfigBuilder {
+        self.variant_lengths = true;
+        self
+    }
+
+    /// See `Config::string_encoding`.
+    pub const fn string_encoding(mut self, mode: StringMode) -> ConfigBuilder {
+        self.string_mode = mode;
+        self
+    }
+
+    /// See `Config::allow_flatten`.
+    #[cfg(feature = "alloc")]
+    pub const fn allow_flatten(mut self) -> ConfigBuilder {
+        self.allow_flatten = true;
+        self
+    }
+
+    /// See `Config::allow_untagged`.
+    #[cfg(feature = "alloc")]
+    pub const fn allow_untagged(mut self) -> ConfigBuilder {
+        self.allow_untagged = true;
+        self
+    }
+
+    /// See `Config::tagged_fields`.
+    #[cfg(feature = "alloc")]
+    pub const fn tagged_fields(mut self) -> ConfigBuilder {
+        self.tagged_fields = true;
+        self
+    }
+
+    /// See `Config::allow_trailing_default_fields`.
+    pub const fn allow_trailing_default_fields(mut self) -> ConfigBuilder {
+        self.trailing_default_fields = true;
+        self
+    }
+
+    /// See `Config::sort_map_keys`.
+    #[cfg(feature = "alloc")]
+    pub const fn sort_map_keys(mut self) -> ConfigBuilder {
+        self.sort_map_keys = true;
+        self
+    }
+
+    /// See `Config::dedup_subtrees`.
+    #[cfg(feature = "alloc")]
+    pub const fn dedup_subtrees(mut self) -> ConfigBuilder {
+        self.dedup_subtrees = true;
+        self
+    }
+
+    /// See `Config::aligned`.
+    ///
+    /// # Panics
+    ///
+    /// If `n` is not a power of two, which fails to compile in a `const`.
+    pub const fn aligned(mut self, n: usize) -> ConfigBuilder {
+        assert!(n.is_power_of_two(), "alignment must be a power of two");
+        self.alignment = n as u64;
+        self
+    }
+
+    /// See `Config::force_owned`.
+    #[cfg(feature = "alloc")]
+    pub const fn force_owned(mut self) -> ConfigBuilder {
+        self.force_owned = true;
+        self
+    }
+
+    /// See `Config::usize_encoding`.
+    pub const fn usize_encoding(mut self, mode: UsizeMode) -> ConfigBuilder {
+        self.usize_encoding = Some(mode);
+        self
+    }
+
+    /// See `Config::float_encoding`.
+    ///
+    /// # Panics
+    ///
+    /// As `Config::float_encoding` does, which fails to compile in a `const`.
+    pub const fn float_encoding(mut self, encoding: FloatEncoding) -> ConfigBuilder {
+        assert!(
+            encoding.is_valid(),
+            "too many fraction digits for the float encoding"
+        );
+        self.float_encoding = encoding;
+        self
+    }
+
+    /// See `Config::int_encoding`.
+    pub const fn int_encoding(mut self, encoding: IntEncoding) -> ConfigBuilder {
+        self.int_encoding = encoding;
+        self
+    }
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+}
+
+impl From<ConfigBuilder> for Config {
+    fn from(builder: ConfigBuilder) -> Config {
+        builder.build()
+    }
+}
+
+/// The byte limit used by `wire()`.
+pub const WIRE_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// A configuration for messages exchanged over the network.
+///
+/// Integers are big-endian (network byte order), string and array lengths are
+/// 4 bytes, and messages are limited to `WIRE_LIMIT` bytes so that a peer
+/// can't make the reader allocate without bound.
+pub const fn wire() -> Config {
+    ConfigBuilder::new()
+        .big_endian()
+        .string_length(LengthOption::U32)
+        .array_length(LengthOption::U32)
+        .limit(WIRE_LIMIT)
+        .build()
+}
+
+/// A configuration for data that is stored and read back later, possibly by a
+/// newer version of the program.
+///
+/// Integers are little-endian, lengths are 8 bytes and there is no limit.
+/// Struct fields are tagged (see `Config::tagged_fields`) so that fields can be
+/// added to or removed from a struct without losing the data already written.
+#[cfg(feature = "alloc")]
+pub const fn storage() -> Config {
+    ConfigBuilder::new().tagged_fields().build()
+}
+
+/// The configuration used by bincode 1's `serialize` and `deserialize`
+/// functions: little-endian, 8 byte lengths and no limit.
+///
+/// This is also the default configuration of this crate. See the `compat`
+/// module for the other upstream formats.
+pub const fn legacy_bincode1() -> Config {
+    ::compat::bincode1()
+}
+
+#[cfg(feature = "std")]
+static PRESETS: ::std::sync::RwLock<::alloc::collections::BTreeMap<String, Config>> =
+    ::std::sync::RwLock::new(::alloc::collections::BTreeMap::new());
+
+/// Registers `config` under `name`, so that any part of the program can get it
+/// back with `preset`.
+///
+/// Returns `false`, leaving the registry unchanged, if the name is already taken,
+/// either by a registered preset or by one of `"wire"`, `"storage"` and
+/// `"legacy_bincode1"`. A preset can't be redefined once other code may have
+/// used it.
+#[cfg(feature = "std")]
+pub fn register_preset(name: &str, config: Config) -> bool {
+    if builtin_preset(name).is_some() {
+        return false;
+    }
+    let mut presets = PRESETS.write().unwrap_or_else(|e| e.into_inner());
+    if presets.contains_key(name) {
+        return false;
+    }
+    presets.insert(name.into(), config);
+    true
+}
+
+/// Returns the configuration registered under `name`, or the built-in preset of
+/// that name.
+#[cfg(feature = "std")]
+pub fn preset(name: &str) -> Option<Config> {
+    if let Some(config) = builtin_preset(name) {
+        return Some(config);
+    }
+    let presets = PRESETS.read().unwrap_or_else(|e| e.into_inner());
+    presets.get(name).cloned()
+}
+
+#[cfg(feature = "std")]
+fn builtin_preset(name: &str) -> Option<Config> {
+    match name {
+        "wire" => Some(wire()),
+        "storage" => Some(storage()),
+        "legacy_bincode1" => Some(legacy_bincode1()),
+        _ => None,
     }
 }
@@ -1,22 +1,48 @@
-use config::Options;
-use core2::io::Read;
+use config::{IntEncoding, Options, StringMode, UsizeMode};
+use core2::io::{self, Read};
 
 use self::read::BincodeRead;
+use encode::Decoder;
 use byteorder::ReadBytesExt;
+use internal::dedup;
+use internal::field;
+use internal::float::{self, Kind, Wire};
+use internal::marker;
+use internal::string_mode;
+use internal::usize_mode;
+use internal::varint::{self, Varint};
 use internal::SizeLimit;
 use internal::SizeType;
+use internal::{padding, promoted, U64};
+use metrics::Recorder;
 use serde;
 use serde::de::Error as DeError;
 use serde::de::IntoDeserializer;
 use core::convert::TryInto;
+use core::mem;
 use {Error, ErrorKind, Result};
 
-use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
 use alloc::string::String;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
 pub mod read;
 
+/// Counts the bytes read through it, for lengths read by a `CustomSizeType`.
+struct CountingReader<'a, R: 'a> {
+    reader: &'a mut R,
+    count: u64,
+}
+
+impl<'a, R: Read> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> ::core2::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.count += read as u64;
+        Ok(read)
+    }
+}
+
 /// A Deserializer that reads bytes from a buffer.
 ///
 /// This struct should rarely be used.
@@ -33,16 +59,71 @@ pub mod read;
 pub(crate) struct Deserializer<R, O: Options> {
     reader: R,
     options: O,
+    /// Set while reading the entries of a self-describing map, see `Config::allow_flatten`,
+    /// or throughout with `Config::allow_untagged`.
+    tagged: bool,
+    /// Bytes read again while following back-references, see `Config::dedup_subtrees`.
+    replayed: u64,
+    /// The number of bytes read, from which `Config::aligned` pads.
+    position: u64,
+    pub(crate) stats: Recorder,
 }
 
 impl<'de, R: BincodeRead<'de>, O: Options> Deserializer<R, O> {
     /// Creates a new Deserializer with a given `Read`er and a size_limit.
     pub(crate) fn new(r: R, options: O) -> Deserializer<R, O> {
-        Deserializer { reader: r, options }
+        Deserializer {
+            reader: r,
+            tagged: options.config().untagged_allowed(),
+            options,
+            replayed: 0,
+            position: 0,
+            stats: Recorder::default(),
+        }
+    }
+
+    /// Reads values without type markers, as `Decode` types are read even with
+    /// `Config::allow_untagged` set.
+    pub(crate) fn plain(mut self) -> Self {
+        self.tagged = false;
+        self
+    }
+
+    /// Deserializes a value whose type serde passed to the serializer, with the
+    /// `TypeCodec` registered for it if any.
+    #[inline(always)]
+    pub(crate) fn deserialize_typed<T>(&mut self, seed: T) -> Result<T::Value>
+    where
+        T: serde::de::DeserializeSeed<'de>,
+    {
+        if !self.tagged {
+            if let Some(codec) = self.options.config().codecs().for_seed::<T>() {
+                return codec.decode::<T>(self);
+            }
+        }
+        seed.deserialize(self)
+    }
+
+    #[inline(always)]
+    pub(crate) fn codecs_registered(&self) -> bool {
+        !self.options.config().codecs().is_empty()
     }
 
     fn read_bytes(&mut self, count: u64) -> Result<()> {
-        self.options.limit().add(count)
+        self.position += count;
+        self.options.limit().add(count)?;
+        self.stats.bytes(count);
+        Ok(())
+    }
+
+    /// Skips the padding before a primitive of `size` bytes, see `Config::aligned`.
+    #[inline(always)]
+    fn align(&mut self, size: u64) -> Result<()> {
+        let padding = padding(self.options.config(), self.position, size);
+        if padding > 0 {
+            self.skip_bytes(padding)?;
+        }
+        Ok(())
     }
 
     fn read_type<T>(&mut self) -> Result<()> {
@@ -50,28 +131,707 @@ impl<'de, R: BincodeRead<'de>, O: Options> Deserializer<R, O> {
         self.read_bytes(size_of::<T>() as u64)
     }
 
+    /// Reads a length prefix. Lengths never carry a type marker.
+    pub(crate) fn read_size<S: SizeType>(&mut self) -> Result<u64> {
+        if let Some(mode) = self.options.config().usize_mode() {
+            return self.read_usize(mode);
+        }
+        if let Some(custom) = S::custom(self.options.config()) {
+            let mut reader = CountingReader {
+                reader: &mut self.reader,
+                count: 0,
+            };
+            let len = custom.read(&mut reader);
+            let count = reader.count;
+            self.read_bytes(count)?;
+            return len;
+        }
+        if let Some(fixed) = S::fixed(self.options.config()) {
+            return fixed_length!(fixed, L => self.read_size::<L>());
+        }
+        let tagged = mem::replace(&mut self.tagged, false);
+        let mark = self.stats.mark();
+        let mut len = S::read(&mut || serde::Deserialize::deserialize(&mut *self));
+        if let Ok(escape) = len {
+            if promoted::<S>(self.options.config(), escape) {
+                len = U64::read(&mut || serde::Deserialize::deserialize(&mut *self));
+            }
+        }
+        self.stats.rewind(mark);
+        self.tagged = tagged;
+        len
+    }
+
+    /// Reads a length or `usize` value written as `Config::usize_encoding` asks.
+    fn read_usize(&mut self, mode: UsizeMode) -> Result<u64> {
+        if let Some(width) = usize_mode::fixed_width(mode) {
+            self.align(width)?;
+        }
+        match mode {
+            UsizeMode::U32 => {
+                self.read_type::<u32>()?;
+                Ok(u64::from(self.reader.read_u32::<O::Endian>()?))
+            }
+            UsizeMode::U64 => {
+                self.read_type::<u64>()?;
+                self.reader.read_u64::<O::Endian>().map_err(Into::into)
+            }
+            UsizeMode::Varint => {
+                self.read_type::<u8>()?;
+                match self.reader.read_u8()? {
+                    usize_mode::VARINT_U16 => {
+                        self.read_type::<u16>()?;
+                        Ok(u64::from(self.reader.read_u16::<O::Endian>()?))
+                    }
+                    usize_mode::VARINT_U32 => {
+                        self.read_type::<u32>()?;
+                        Ok(u64::from(self.reader.read_u32::<O::Endian>()?))
+                    }
+                    usize_mode::VARINT_U64 => {
+                        self.read_type::<u64>()?;
+                        self.reader.read_u64::<O::Endian>().map_err(Into::into)
+                    }
+                    byte if byte < usize_mode::VARINT_U16 => Ok(u64::from(byte)),
+                    byte => Err(ErrorKind::InvalidTagEncoding(byte as usize).into()),
+                }
+            }
+        }
+    }
+
+    /// Whether integers are read as varints, see `Config::int_encoding`. Inside a
+    /// self-describing map they keep their full width.
+    #[inline(always)]
+    fn varint(&self) -> bool {
+        !self.tagged && self.options.config().ints() == IntEncoding::Varint
+    }
+
+    /// Reads an integer written as `IntEncoding::Varint` asks, failing on a marker
+    /// wider than the integer.
+    fn read_varint<T: Varint>(&mut self) -> Result<T> {
+        self.read_type::<u8>()?;
+        let byte = self.reader.read_u8()?;
+        if byte < usize_mode::VARINT_U16 {
+            return Ok(T::from_varint(u128::from(byte)));
+        }
+        if byte > varint::max_marker(mem::size_of::<T>()) {
+            return Err(ErrorKind::InvalidTagEncoding(byte as usize).into());
+        }
+        let value = match byte {
+            usize_mode::VARINT_U16 => {
+                self.read_type::<u16>()?;
+                u128::from(self.reader.read_u16::<O::Endian>()?)
+            }
+            usize_mode::VARINT_U32 => {
+                self.read_type::<u32>()?;
+                u128::from(self.reader.read_u32::<O::Endian>()?)
+            }
+            usize_mode::VARINT_U64 => {
+                self.read_type::<u64>()?;
+                u128::from(self.reader.read_u64::<O::Endian>()?)
+            }
+            _ => {
+                self.read_type::<u128>()?;
+                self.reader.read_u128::<O::Endian>()?
+            }
+        };
+        Ok(T::from_varint(value))
+    }
+
+    #[cfg(feature = "alloc")]
     fn read_vec(&mut self) -> Result<Vec<u8>> {
-        let len = O::ArraySize::read(&mut || serde::Deserialize::deserialize(&mut *self))?;
+        self.stats.allocation();
+        let len = self.read_size::<O::ArraySize>()?;
         self.read_bytes(len)?;
-        let len: usize = len.try_into().map_err(|_e| ErrorKind::SizeLimit)?;
+        let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
         self.reader.get_byte_buffer(len)
     }
 
+    #[cfg(feature = "alloc")]
     fn read_string(&mut self) -> Result<String> {
-        let len = O::StringSize::read(&mut || serde::Deserialize::deserialize(&mut *self))?;
-        self.read_bytes(len)?;
-        let len: usize = len.try_into().map_err(|_e| ErrorKind::SizeLimit)?;
-        let vec = self.reader.get_byte_buffer(len)?;
+        self.stats.allocation();
+        let vec = match self.options.config().string_mode() {
+            StringMode::LengthPrefixed => {
+                let len = self.read_size::<O::StringSize>()?;
+                self.read_bytes(len)?;
+                let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+                self.reader.get_byte_buffer(len)?
+            }
+            StringMode::NulTerminated => {
+                let mut vec = Vec::new();
+                loop {
+                    self.read_type::<u8>()?;
+                    match self.reader.read_u8() {
+                        Ok(0) => break,
+                        Ok(byte) => vec.push(byte),
+                        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                            return Err(ErrorKind::MissingNulTerminator.into())
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                vec
+            }
+            StringMode::FixedWidth(width) => {
+                self.read_bytes(width as u64)?;
+                string_mode::unpad(self.reader.get_byte_buffer(width)?)?
+            }
+        };
         String::from_utf8(vec).map_err(|e| ErrorKind::InvalidUtf8Encoding(e.utf8_error()).into())
     }
+
+    /// Reads a string, borrowing it from the input if it is length-prefixed and
+    /// `Config::force_owned` is not set. Without the `alloc` feature only
+    /// length-prefixed strings can be read.
+    fn forward_str<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.stats.string();
+        let config = self.options.config();
+        if config.string_mode() != StringMode::LengthPrefixed || config.owned_forced() {
+            #[cfg(feature = "alloc")]
+            return visitor.visit_string(self.read_string()?);
+            #[cfg(not(feature = "alloc"))]
+            return Err(Error::custom(
+                "strings that are not length-prefixed need the alloc feature to be read",
+            ));
+        }
+        let len = self.read_size::<O::StringSize>()?;
+        self.read_bytes(len)?;
+        let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+        self.reader.forward_read_str(len, visitor)
+    }
+
+    /// Reads a byte array, borrowing it from the input unless `Config::force_owned`
+    /// is set.
+    fn forward_bytes<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        #[cfg(feature = "alloc")]
+        {
+            if self.options.config().owned_forced() {
+                return visitor.visit_byte_buf(self.read_vec()?);
+            }
+        }
+        let len = self.read_size::<O::ArraySize>()?;
+        self.read_bytes(len)?;
+        let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+        self.reader.forward_read_bytes(len, visitor)
+    }
+
+    fn read_marker(&mut self) -> Result<u8> {
+        self.read_type::<u8>()?;
+        self.reader.read_u8().map_err(Into::into)
+    }
+
+    /// Inside a self-describing map, checks that the next value has the type the
+    /// caller is about to read. Fields of a known type are always written with the
+    /// same marker, so only `deserialize_any` needs to dispatch on it.
+    #[inline(always)]
+    fn expect_marker(&mut self, expected: u8) -> Result<()> {
+        if self.tagged {
+            match self.read_marker()? {
+                found if found == expected => {}
+                found => return Err(ErrorKind::InvalidTagEncoding(found as usize).into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a float written as `Config::float_encoding` asks; `single` is set for
+    /// `f32` values.
+    fn read_float(&mut self, single: bool) -> Result<f64> {
+        let encoding = self.options.config().floats();
+        let len = float::len(encoding, single);
+        self.align(len)?;
+        self.read_bytes(len)?;
+        let wire = match float::kind(encoding, single) {
+            Kind::F32 => Wire::F32(self.reader.read_f32::<O::Endian>()?),
+            Kind::F64 => Wire::F64(self.reader.read_f64::<O::Endian>()?),
+            Kind::I32 => Wire::I32(self.reader.read_i32::<O::Endian>()?),
+            Kind::I64 => Wire::I64(self.reader.read_i64::<O::Endian>()?),
+        };
+        Ok(float::decode(encoding, wire))
+    }
+
+    /// Reads the elements of a tuple, tuple struct or struct, the value itself
+    /// having been counted by the caller.
+    fn read_tuple<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        if self.tagged {
+            self.expect_marker(marker::SEQ)?;
+            let len = self.read_size::<O::ArraySize>()?;
+            let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+            return self.visit_seq(len, visitor);
+        }
+        self.visit_seq(len, visitor)
+    }
+
+    /// Reads a sequence, map or struct with `read`, following the back-reference
+    /// written in its place if there is one, see `Config::dedup_subtrees`.
+    fn read_subtree<V, F>(&mut self, visitor: V, read: F) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+        F: FnOnce(&mut Self, V) -> Result<V::Value>,
+    {
+        if self.tagged || !self.options.config().subtrees_deduped() {
+            return read(self, visitor);
+        }
+        let here = self.reader.seekable_position();
+        self.read_type::<u8>()?;
+        match self.reader.read_u8()? {
+            dedup::INLINE => read(self, visitor),
+            dedup::BACK_REFERENCE => {
+                self.read_type::<u64>()?;
+                let distance = self.reader.read_u64::<O::Endian>()?;
+                let here = match here {
+                    Some(here) => here,
+                    None => {
+                        return Err(Error::custom(
+                            "back-references can only be followed when deserializing from a slice",
+                        ))
+                    }
+                };
+                if distance == 0 || distance > here {
+                    return Err(Error::custom("back-reference out of range"));
+                }
+                let resume = self.reader.seekable_position().unwrap_or(here);
+                let target = here - distance;
+                self.reader.seek(target)?;
+                let value = read(self, visitor)?;
+                let end = self.reader.seekable_position().unwrap_or(target);
+                self.replayed += end - target;
+                let limit = self
+                    .options
+                    .config()
+                    .deserialize_byte_limit()
+                    .unwrap_or(dedup::REPLAY_LIMIT);
+                let attempted = self.replayed.saturating_add(resume);
+                if attempted > limit {
+                    return Err(ErrorKind::SizeLimit { limit, attempted }.into());
+                }
+                self.reader.seek(resume)?;
+                Ok(value)
+            }
+            tag => Err(ErrorKind::InvalidTagEncoding(tag as usize).into()),
+        }
+    }
+
+    fn read_bool(&mut self) -> Result<bool> {
+        self.read_type::<u8>()?;
+        match self.reader.read_u8()? {
+            1 => Ok(true),
+            0 => Ok(false),
+            value => Err(ErrorKind::InvalidBoolEncoding(value).into()),
+        }
+    }
+
+    fn read_char(&mut self) -> Result<char> {
+        use core::str;
+
+        let error = || ErrorKind::InvalidCharEncoding.into();
+
+        let mut buf = [0u8; 4];
+
+        // Look at the first byte to see how many bytes must be read
+        self.reader.read_exact(&mut buf[..1])?;
+        self.position += 1;
+        let width = utf8_char_width(buf[0]);
+        if width == 1 {
+            return Ok(buf[0] as char);
+        }
+        if width == 0 {
+            return Err(error());
+        }
+
+        if self.reader.read_exact(&mut buf[1..width]).is_err() {
+            return Err(error());
+        }
+        self.position += width as u64 - 1;
+
+        str::from_utf8(&buf[..width])
+            .ok()
+            .and_then(|s| s.chars().next())
+            .ok_or_else(error)
+    }
+
+    fn visit_seq<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.visit_elements(len, false, visitor)
+    }
+
+    /// Visits `len` elements, or fewer if `may_end` is set and the input ends
+    /// between two of them.
+    fn visit_elements<V>(&mut self, len: usize, may_end: bool, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        struct Access<'a, R: Read + 'a, O: Options + 'a> {
+            deserializer: &'a mut Deserializer<R, O>,
+            len: usize,
+            may_end: bool,
+        }
+
+        impl<'de, 'a, 'b: 'a, R: BincodeRead<'de> + 'b, O: Options> serde::de::SeqAccess<'de>
+            for Access<'a, R, O>
+        {
+            type Error = Error;
+
+            fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+            where
+                T: serde::de::DeserializeSeed<'de>,
+            {
+                if self.may_end && self.len > 0 && self.deserializer.reader.at_end()? {
+                    self.len = 0;
+                }
+                if self.len > 0 {
+                    self.len -= 1;
+                    let value = self.deserializer.deserialize_typed(seed)?;
+                    Ok(Some(value))
+                } else {
+                    Ok(None)
+                }
+            }
+
+            fn size_hint(&self) -> Option<usize> {
+                Some(self.len)
+            }
+        }
+
+        self.stats.enter();
+        let result = visitor.visit_seq(Access {
+            deserializer: &mut *self,
+            len,
+            may_end,
+        });
+        self.stats.leave();
+        result
+    }
+
+    fn visit_map<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        struct Access<'a, R: Read + 'a, O: Options + 'a> {
+            deserializer: &'a mut Deserializer<R, O>,
+            len: usize,
+        }
+
+        impl<'de, 'a, 'b: 'a, R: BincodeRead<'de> + 'b, O: Options> serde::de::MapAccess<'de>
+            for Access<'a, R, O>
+        {
+            type Error = Error;
+
+            fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+            where
+                K: serde::de::DeserializeSeed<'de>,
+            {
+                if self.len > 0 {
+                    self.len -= 1;
+                    let key = self.deserializer.deserialize_typed(seed)?;
+                    Ok(Some(key))
+                } else {
+                    Ok(None)
+                }
+            }
+
+            fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+            where
+                V: serde::de::DeserializeSeed<'de>,
+            {
+                let value = self.deserializer.deserialize_typed(seed)?;
+                Ok(value)
+            }
+
+            fn size_hint(&self) -> Option<usize> {
+                Some(self.len)
+            }
+        }
+
+        let outer_tagged = self.tagged;
+        self.tagged = self.options.config().flatten_allowed();
+        self.stats.enter();
+        let result = visitor.visit_map(Access {
+            deserializer: &mut *self,
+            len,
+        });
+        self.stats.leave();
+        self.tagged = outer_tagged;
+        result
+    }
+
+    /// Whether struct fields are read with a header, see `Config::tagged_fields`.
+    #[inline(always)]
+    fn fields_tagged(&self) -> bool {
+        !self.tagged && self.options.config().fields_tagged()
+    }
+
+    /// Skips the value of a struct field that the reader does not know about.
+    fn skip_field(&mut self, wire_type: u32) -> Result<()> {
+        let len = match field::fixed_len(wire_type) {
+            Some(len) => len,
+            None if wire_type == field::LENGTH_DELIMITED => self.read_size::<O::ArraySize>()?,
+            None => return Err(ErrorKind::InvalidTagEncoding(wire_type as usize).into()),
+        };
+        self.skip_bytes(len)
+    }
+
+    /// Skips `len` bytes without allocating, counting them towards the limit.
+    fn skip_bytes(&mut self, len: u64) -> Result<()> {
+        self.read_bytes(len)?;
+        let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+        self.reader.skip(len)
+    }
+
+    /// Skips a value of a self-describing map, as `deserialize_tagged` would read
+    /// it, without allocating.
+    ///
+    /// Rather than recursing into sequences, maps and options, this counts the
+    /// values they hold as left to skip, so that no nesting of them, however deep,
+    /// can overflow the stack.
+    fn skip_tagged(&mut self) -> Result<()> {
+        use core::mem::size_of;
+        let mut left: u64 = 1;
+        while left > 0 {
+            left -= 1;
+            let len = match self.read_marker()? {
+                marker::UNIT | marker::NONE => 0,
+                marker::BOOL => {
+                    self.read_bool()?;
+                    continue;
+                }
+                marker::U8 | marker::I8 => size_of::<u8>() as u64,
+                marker::U16 | marker::I16 => size_of::<u16>() as u64,
+                marker::U32 | marker::I32 => size_of::<u32>() as u64,
+                marker::U64 | marker::I64 => size_of::<u64>() as u64,
+                marker::U128 | marker::I128 => size_of::<u128>() as u64,
+                marker::F32 => {
+                    self.read_float(true)?;
+                    continue;
+                }
+                marker::F64 => {
+                    self.read_float(false)?;
+                    continue;
+                }
+                marker::CHAR => {
+                    self.read_char()?;
+                    continue;
+                }
+                marker::STR => {
+                    self.skip_str()?;
+                    continue;
+                }
+                marker::BYTES => self.read_size::<O::ArraySize>()?,
+                marker::SOME => {
+                    left += 1;
+                    continue;
+                }
+                marker::SEQ => {
+                    // A count larger than the input holds fails once it runs out.
+                    left = left.saturating_add(self.read_size::<O::ArraySize>()?);
+                    continue;
+                }
+                marker::MAP => {
+                    let entries = self.read_size::<O::ArraySize>()?;
+                    left = left.saturating_add(entries.saturating_mul(2));
+                    continue;
+                }
+                other => return Err(ErrorKind::InvalidTagEncoding(other as usize).into()),
+            };
+            self.skip_bytes(len)?;
+        }
+        Ok(())
+    }
+
+    /// Skips a string without allocating.
+    fn skip_str(&mut self) -> Result<()> {
+        match self.options.config().string_mode() {
+            StringMode::LengthPrefixed => {
+                let len = self.read_size::<O::StringSize>()?;
+                self.skip_bytes(len)
+            }
+            StringMode::NulTerminated => loop {
+                self.read_type::<u8>()?;
+                match self.reader.read_u8() {
+                    Ok(0) => return Ok(()),
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        return Err(ErrorKind::MissingNulTerminator.into())
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            },
+            StringMode::FixedWidth(width) => self.skip_bytes(width as u64),
+        }
+    }
+
+    /// Reads a struct written with `Config::tagged_fields`, handing the visitor only
+    /// the fields it knows about.
+    fn visit_fields<V>(&mut self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        struct Access<'a, R: Read + 'a, O: Options + 'a> {
+            deserializer: &'a mut Deserializer<R, O>,
+            fields: &'static [&'static str],
+            len: u64,
+            wire_type: u32,
+        }
+
+        impl<'de, 'a, 'b: 'a, R: BincodeRead<'de> + 'b, O: Options> serde::de::MapAccess<'de>
+            for Access<'a, R, O>
+        {
+            type Error = Error;
+
+            fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+            where
+                K: serde::de::DeserializeSeed<'de>,
+            {
+                while self.len > 0 {
+                    self.len -= 1;
+                    self.deserializer.read_type::<u32>()?;
+                    let header = self.deserializer.reader.read_u32::<O::Endian>()?;
+                    let (id, wire_type) = (header >> 3, header & 0b111);
+                    match self.fields.iter().find(|name| field::id(name) == id) {
+                        Some(name) => {
+                            self.wire_type = wire_type;
+                            return seed.deserialize(name.into_deserializer()).map(Some);
+                        }
+                        None => self.deserializer.skip_field(wire_type)?,
+                    }
+                }
+                Ok(None)
+            }
+
+            fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+            where
+                V: serde::de::DeserializeSeed<'de>,
+            {
+                let len = match field::fixed_len(self.wire_type) {
+                    Some(len) => len,
+                    None if self.wire_type == field::LENGTH_DELIMITED => {
+                        self.deserializer.read_size::<O::ArraySize>()?
+                    }
+                    None => {
+                        return Err(ErrorKind::InvalidTagEncoding(self.wire_type as usize).into())
+                    }
+                };
+                let start = self.deserializer.position;
+                let value = self.deserializer.deserialize_typed(seed)?;
+                // A value read as another type than it was written with may end
+                // elsewhere than the field, and the next header be read from it.
+                let read = self.deserializer.position - start;
+                if read != len {
+                    return Err(Error::custom(format_args!(
+                        "a field of {} bytes was read as {} bytes",
+                        len, read
+                    )));
+                }
+                Ok(value)
+            }
+        }
+
+        let len = self.read_size::<O::ArraySize>()?;
+        self.stats.enter();
+        let result = visitor.visit_map(Access {
+            deserializer: &mut *self,
+            fields,
+            len,
+            wire_type: field::LENGTH_DELIMITED,
+        });
+        self.stats.leave();
+        result
+    }
+
+    /// Reads a value of any type from a self-describing map, as written by a
+    /// serializer with `Config::allow_flatten` set.
+    fn deserialize_tagged<V>(&mut self, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        match self.read_marker()? {
+            marker::UNIT => visitor.visit_unit(),
+            marker::BOOL => visitor.visit_bool(self.read_bool()?),
+            marker::U8 => {
+                self.read_type::<u8>()?;
+                visitor.visit_u8(self.reader.read_u8()?)
+            }
+            marker::U16 => {
+                self.read_type::<u16>()?;
+                visitor.visit_u16(self.reader.read_u16::<O::Endian>()?)
+            }
+            marker::U32 => {
+                self.read_type::<u32>()?;
+                visitor.visit_u32(self.reader.read_u32::<O::Endian>()?)
+            }
+            marker::U64 => {
+                self.read_type::<u64>()?;
+                visitor.visit_u64(self.reader.read_u64::<O::Endian>()?)
+            }
+            marker::U128 => {
+                self.read_type::<u128>()?;
+                visitor.visit_u128(self.reader.read_u128::<O::Endian>()?)
+            }
+            marker::I8 => {
+                self.read_type::<i8>()?;
+                visitor.visit_i8(self.reader.read_i8()?)
+            }
+            marker::I16 => {
+                self.read_type::<i16>()?;
+                visitor.visit_i16(self.reader.read_i16::<O::Endian>()?)
+            }
+            marker::I32 => {
+                self.read_type::<i32>()?;
+                visitor.visit_i32(self.reader.read_i32::<O::Endian>()?)
+            }
+            marker::I64 => {
+                self.read_type::<i64>()?;
+                visitor.visit_i64(self.reader.read_i64::<O::Endian>()?)
+            }
+            marker::I128 => {
+                self.read_type::<i128>()?;
+                visitor.visit_i128(self.reader.read_i128::<O::Endian>()?)
+            }
+            marker::F32 => visitor.visit_f32(self.read_float(true)? as f32),
+            marker::F64 => visitor.visit_f64(self.read_float(false)?),
+            marker::CHAR => visitor.visit_char(self.read_char()?),
+            marker::STR => self.forward_str(visitor),
+            marker::BYTES => self.forward_bytes(visitor),
+            marker::NONE => visitor.visit_none(),
+            marker::SOME => visitor.visit_some(self),
+            marker::SEQ => {
+                let len = self.read_size::<O::ArraySize>()?;
+                let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+                self.visit_seq(len, visitor)
+            }
+            marker::MAP => {
+                let len = self.read_size::<O::ArraySize>()?;
+                let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+                self.visit_map(len, visitor)
+            }
+            other => Err(ErrorKind::InvalidTagEncoding(other as usize).into()),
+        }
+    }
 }
 
 macro_rules! impl_nums {
-    ($ty:ty, $dser_method:ident, $visitor_method:ident, $reader_method:ident) => {
+    ($ty:ty, $dser_method:ident, $visitor_method:ident, $reader_method:ident, $marker:ident) => {
         #[inline]
         fn $dser_method<V>(self, visitor: V) -> Result<V::Value>
             where V: serde::de::Visitor<'de>,
         {
+            self.stats.value();
+            self.expect_marker(marker::$marker)?;
+            if self.varint() {
+                let value = self.read_varint::<$ty>()?;
+                return visitor.$visitor_method(value);
+            }
+            self.align(mem::size_of::<$ty>() as u64)?;
             self.read_type::<$ty>()?;
             let value = self.reader.$reader_method::<O::Endian>()?;
             visitor.$visitor_method(value)
@@ -87,37 +847,54 @@ where
     type Error = Error;
 
     #[inline]
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        Err(Box::new(ErrorKind::DeserializeAnyNotSupported))
+        self.stats.value();
+        if self.tagged {
+            return self.deserialize_tagged(visitor);
+        }
+        Err(ErrorKind::DeserializeAnyNotSupported.into())
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        let value: u8 = serde::Deserialize::deserialize(self)?;
-        match value {
-            1 => visitor.visit_bool(true),
-            0 => visitor.visit_bool(false),
-            value => Err(ErrorKind::InvalidBoolEncoding(value).into()),
-        }
+        self.stats.value();
+        self.expect_marker(marker::BOOL)?;
+        visitor.visit_bool(self.read_bool()?)
     }
 
-    impl_nums!(u16, deserialize_u16, visit_u16, read_u16);
-    impl_nums!(u32, deserialize_u32, visit_u32, read_u32);
-    impl_nums!(u64, deserialize_u64, visit_u64, read_u64);
-    impl_nums!(i16, deserialize_i16, visit_i16, read_i16);
-    impl_nums!(i32, deserialize_i32, visit_i32, read_i32);
-    impl_nums!(i64, deserialize_i64, visit_i64, read_i64);
-    impl_nums!(f32, deserialize_f32, visit_f32, read_f32);
-    impl_nums!(f64, deserialize_f64, visit_f64, read_f64);
+    impl_nums!(u16, deserialize_u16, visit_u16, read_u16, U16);
+    impl_nums!(u32, deserialize_u32, visit_u32, read_u32, U32);
+    impl_nums!(u64, deserialize_u64, visit_u64, read_u64, U64);
+    impl_nums!(i16, deserialize_i16, visit_i16, read_i16, I16);
+    impl_nums!(i32, deserialize_i32, visit_i32, read_i32, I32);
+    impl_nums!(i64, deserialize_i64, visit_i64, read_i64, I64);
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.stats.value();
+        self.expect_marker(marker::F32)?;
+        visitor.visit_f32(self.read_float(true)? as f32)
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.stats.value();
+        self.expect_marker(marker::F64)?;
+        visitor.visit_f64(self.read_float(false)?)
+    }
 
     serde_if_integer128! {
-        impl_nums!(u128, deserialize_u128, visit_u128, read_u128);
-        impl_nums!(i128, deserialize_i128, visit_i128, read_i128);
+        impl_nums!(u128, deserialize_u128, visit_u128, read_u128, U128);
+        impl_nums!(i128, deserialize_i128, visit_i128, read_i128, I128);
     }
 
     #[inline]
@@ -125,6 +902,8 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
+        self.stats.value();
+        self.expect_marker(marker::U8)?;
         self.read_type::<u8>()?;
         visitor.visit_u8(self.reader.read_u8()?)
     }
@@ -134,6 +913,8 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
+        self.stats.value();
+        self.expect_marker(marker::I8)?;
         self.read_type::<i8>()?;
         visitor.visit_i8(self.reader.read_i8()?)
     }
@@ -142,6 +923,8 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
+        self.stats.value();
+        self.expect_marker(marker::UNIT)?;
         visitor.visit_unit()
     }
 
@@ -149,141 +932,215 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
-        use core::str;
-
-        let error = || ErrorKind::InvalidCharEncoding.into();
-
-        let mut buf = [0u8; 4];
-
-        // Look at the first byte to see how many bytes must be read
-        self.reader.read_exact(&mut buf[..1])?;
-        let width = utf8_char_width(buf[0]);
-        if width == 1 {
-            return visitor.visit_char(buf[0] as char);
-        }
-        if width == 0 {
-            return Err(error());
-        }
-
-        if self.reader.read_exact(&mut buf[1..width]).is_err() {
-            return Err(error());
-        }
-
-        let res = str::from_utf8(&buf[..width])
-            .ok()
-            .and_then(|s| s.chars().next())
-            .ok_or_else(error)?;
-        visitor.visit_char(res)
+        self.stats.value();
+        self.expect_marker(marker::CHAR)?;
+        visitor.visit_char(self.read_char()?)
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = O::StringSize::read(&mut || serde::Deserialize::deserialize(&mut *self))?;
-        self.read_bytes(len)?;
-        let len: usize = len.try_into().map_err(|_e| ErrorKind::SizeLimit)?;
-        self.reader.forward_read_str(len, visitor)
+        self.stats.value();
+        self.expect_marker(marker::STR)?;
+        self.forward_str(visitor)
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_string(self.read_string()?)
+        self.stats.value();
+        self.expect_marker(marker::STR)?;
+        #[cfg(feature = "alloc")]
+        {
+            self.stats.string();
+            visitor.visit_string(self.read_string()?)
+        }
+        // Without `alloc` the visitor is given the string as `deserialize_str` would.
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.forward_str(visitor)
+        }
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = O::ArraySize::read(&mut || serde::Deserialize::deserialize(&mut *self))?;
-        self.read_bytes(len)?;
-        let len: usize = len.try_into().map_err(|_e| ErrorKind::SizeLimit)?;
-        self.reader.forward_read_bytes(len, visitor)
+        self.stats.value();
+        self.expect_marker(marker::BYTES)?;
+        self.forward_bytes(visitor)
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_byte_buf(self.read_vec()?)
+        self.stats.value();
+        self.expect_marker(marker::BYTES)?;
+        #[cfg(feature = "alloc")]
+        {
+            visitor.visit_byte_buf(self.read_vec()?)
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            self.forward_bytes(visitor)
+        }
     }
 
     fn deserialize_enum<V>(
         self,
-        _enum: &'static str,
-        _variants: &'static [&'static str],
+        name: &'static str,
+        variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        impl<'de, 'a, R: 'a, O> serde::de::EnumAccess<'de> for &'a mut Deserializer<R, O>
+        /// Reads the tag of the enum `name`, which has `len` variants.
+        struct Enum<'a, R: 'a, O: Options + 'a> {
+            de: &'a mut Deserializer<R, O>,
+            name: &'static str,
+            len: usize,
+        }
+
+        impl<'de, 'a, R: 'a, O> serde::de::EnumAccess<'de> for Enum<'a, R, O>
         where
             R: BincodeRead<'de>,
             O: Options,
         {
             type Error = Error;
-            type Variant = Self;
+            type Variant = Payload<'a, R, O>;
 
             fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
             where
                 V: serde::de::DeserializeSeed<'de>,
             {
-                let idx: u32 = serde::de::Deserialize::deserialize(&mut *self)?;
+                // Inside a self-describing map the tag carries a `u32` marker, which
+                // `deserialize_u32` expects.
+                let mark = self.de.stats.mark();
+                let tag: u32 = serde::de::Deserialize::deserialize(&mut *self.de)?;
+                self.de.stats.rewind(mark);
+                let (idx, known) = self
+                    .de
+                    .options
+                    .config()
+                    .variant_from_wire(self.name, self.len, tag)?;
+                let mut skipped = None;
+                if self.de.options.config().variants_sized() {
+                    let len = self.de.read_size::<O::ArraySize>()?;
+                    if !known {
+                        self.de.skip_bytes(len)?;
+                        skipped = Some(tag);
+                    }
+                }
                 let val: Result<_> = seed.deserialize(idx.into_deserializer());
-                Ok((val?, self))
+                let payload = Payload {
+                    de: self.de,
+                    skipped,
+                };
+                Ok((val?, payload))
             }
         }
 
-        visitor.visit_enum(self)
-    }
+        /// What a variant holds, or nothing if it was skipped because its tag was unknown.
+        struct Payload<'a, R: 'a, O: Options + 'a> {
+            de: &'a mut Deserializer<R, O>,
+            /// The unknown tag.
+            skipped: Option<u32>,
+        }
 
-    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
-    where
-        V: serde::de::Visitor<'de>,
-    {
-        struct Access<'a, R: Read + 'a, O: Options + 'a> {
-            deserializer: &'a mut Deserializer<R, O>,
-            len: usize,
+        impl<'a, R: 'a, O: Options + 'a> Payload<'a, R, O> {
+            /// Fails if the payload was skipped, so that it cannot be read as the
+            /// fallback variant.
+            fn read(self) -> Result<&'a mut Deserializer<R, O>> {
+                match self.skipped {
+                    Some(tag) => Err(ErrorKind::InvalidTagEncoding(tag as usize).into()),
+                    None => Ok(self.de),
+                }
+            }
         }
 
-        impl<'de, 'a, 'b: 'a, R: BincodeRead<'de> + 'b, O: Options> serde::de::SeqAccess<'de>
-            for Access<'a, R, O>
+        impl<'de, 'a, R: 'a, O> serde::de::VariantAccess<'de> for Payload<'a, R, O>
+        where
+            R: BincodeRead<'de>,
+            O: Options,
         {
             type Error = Error;
 
-            fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+            fn unit_variant(self) -> Result<()> {
+                if self.skipped.is_some() {
+                    return Ok(());
+                }
+                serde::de::VariantAccess::unit_variant(self.de)
+            }
+
+            fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
             where
                 T: serde::de::DeserializeSeed<'de>,
             {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let value =
-                        serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                    Ok(Some(value))
-                } else {
-                    Ok(None)
-                }
+                serde::de::VariantAccess::newtype_variant_seed(self.read()?, seed)
             }
 
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
+            fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+            where
+                V: serde::de::Visitor<'de>,
+            {
+                serde::de::VariantAccess::tuple_variant(self.read()?, len, visitor)
+            }
+
+            fn struct_variant<V>(
+                self,
+                fields: &'static [&'static str],
+                visitor: V,
+            ) -> Result<V::Value>
+            where
+                V: serde::de::Visitor<'de>,
+            {
+                serde::de::VariantAccess::struct_variant(self.read()?, fields, visitor)
             }
         }
 
-        visitor.visit_seq(Access {
-            deserializer: self,
-            len,
+        self.stats.value();
+        if self.tagged {
+            // Inside a self-describing map an enum is a map with a single entry.
+            self.expect_marker(marker::MAP)?;
+            match self.read_size::<O::ArraySize>()? {
+                1 => {}
+                len => return Err(ErrorKind::InvalidTagEncoding(len as usize).into()),
+            }
+        }
+        visitor.visit_enum(Enum {
+            de: self,
+            name,
+            len: variants.len(),
         })
     }
 
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'de>,
+    {
+        self.stats.value();
+        self.read_tuple(len, visitor)
+    }
+
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
+        self.stats.value();
+        if self.tagged {
+            return match self.read_marker()? {
+                marker::NONE => visitor.visit_none(),
+                marker::SOME => visitor.visit_some(&mut *self),
+                other => Err(ErrorKind::InvalidTagEncoding(other as usize).into()),
+            };
+        }
+        let mark = self.stats.mark();
         let value: u8 = serde::de::Deserialize::deserialize(&mut *self)?;
+        self.stats.rewind(mark);
         match value {
             0 => visitor.visit_none(),
             1 => visitor.visit_some(&mut *self),
@@ -295,57 +1152,25 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
-        let len = O::ArraySize::read(&mut || serde::Deserialize::deserialize(&mut *self))?;
-        let len: usize = len.try_into().map_err(|_e| ErrorKind::SizeLimit)?;
-        self.deserialize_tuple(len, visitor)
+        self.stats.value();
+        self.expect_marker(marker::SEQ)?;
+        self.read_subtree(visitor, |de, visitor| {
+            let len = de.read_size::<O::ArraySize>()?;
+            let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+            de.visit_seq(len, visitor)
+        })
     }
 
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        struct Access<'a, R: Read + 'a, O: Options + 'a> {
-            deserializer: &'a mut Deserializer<R, O>,
-            len: usize,
-        }
-
-        impl<'de, 'a, 'b: 'a, R: BincodeRead<'de> + 'b, O: Options> serde::de::MapAccess<'de>
-            for Access<'a, R, O>
-        {
-            type Error = Error;
-
-            fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
-            where
-                K: serde::de::DeserializeSeed<'de>,
-            {
-                if self.len > 0 {
-                    self.len -= 1;
-                    let key =
-                        serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                    Ok(Some(key))
-                } else {
-                    Ok(None)
-                }
-            }
-
-            fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
-            where
-                V: serde::de::DeserializeSeed<'de>,
-            {
-                let value = serde::de::DeserializeSeed::deserialize(seed, &mut *self.deserializer)?;
-                Ok(value)
-            }
-
-            fn size_hint(&self) -> Option<usize> {
-                Some(self.len)
-            }
-        }
-
-        let len = O::ArraySize::read(&mut || serde::Deserialize::deserialize(&mut *self))?;
-        let len: usize = len.try_into().map_err(|_e| ErrorKind::SizeLimit)?;
-        visitor.visit_map(Access {
-            deserializer: self,
-            len,
+        self.stats.value();
+        self.expect_marker(marker::MAP)?;
+        self.read_subtree(visitor, |de, visitor| {
+            let len = de.read_size::<O::ArraySize>()?;
+            let len: usize = len.try_into().map_err(|_e| ErrorKind::not_usize(len))?;
+            de.visit_map(len, visitor)
         })
     }
 
@@ -358,13 +1183,34 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_tuple(fields.len(), visitor)
+        self.stats.value();
+        if self.fields_tagged() {
+            return self.visit_fields(fields, visitor);
+        }
+        self.read_subtree(visitor, |de, visitor| {
+            if !de.tagged && de.options.config().trailing_default_fields() {
+                return de.visit_elements(fields.len(), true, visitor);
+            }
+            de.read_tuple(fields.len(), visitor)
+        })
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
+        self.stats.value();
+        if self.tagged {
+            // Field names of flattened structs, or variant indices of enums.
+            return match self.read_marker()? {
+                marker::STR => self.forward_str(visitor),
+                marker::U32 => {
+                    self.read_type::<u32>()?;
+                    visitor.visit_u32(self.reader.read_u32::<O::Endian>()?)
+                }
+                other => Err(ErrorKind::InvalidTagEncoding(other as usize).into()),
+            };
+        }
         let message = "Bincode does not support Deserializer::deserialize_identifier";
         Err(Error::custom(message))
     }
@@ -373,6 +1219,7 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
+        self.stats.value();
         visitor.visit_newtype_struct(self)
     }
 
@@ -380,7 +1227,7 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
-        visitor.visit_unit()
+        self.deserialize_unit(visitor)
     }
 
     fn deserialize_tuple_struct<V>(
@@ -392,14 +1239,22 @@ where
     where
         V: serde::de::Visitor<'de>,
     {
-        self.deserialize_tuple(len, visitor)
+        self.stats.value();
+        self.read_subtree(visitor, |de, visitor| de.read_tuple(len, visitor))
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        let message = "Bincode does not support Deserializer::deserialize_ignored_any";
+        self.stats.value();
+        if self.tagged {
+            // Values of self-describing maps are skipped without being decoded.
+            self.skip_tagged()?;
+            return visitor.visit_unit();
+        }
+        // Outside of them the bytes don't say what kind of value follows.
+        let message = "Bincode can only skip ignored values inside maps, see Config::allow_flatten";
         Err(Error::custom(message))
     }
 
@@ -416,6 +1271,9 @@ where
     type Error = Error;
 
     fn unit_variant(self) -> Result<()> {
+        if self.tagged {
+            return serde::de::Deserialize::deserialize(self);
+        }
         Ok(())
     }
 
@@ -423,23 +1281,136 @@ where
     where
         T: serde::de::DeserializeSeed<'de>,
     {
-        serde::de::DeserializeSeed::deserialize(seed, self)
+        self.deserialize_typed(seed)
     }
 
     fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        serde::de::Deserializer::deserialize_tuple(self, len, visitor)
+        self.read_tuple(len, visitor)
     }
 
     fn struct_variant<V>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'de>,
     {
-        serde::de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+        if self.fields_tagged() {
+            return self.visit_fields(fields, visitor);
+        }
+        self.read_tuple(fields.len(), visitor)
     }
 }
+macro_rules! impl_decode_nums {
+    ($($decode:ident($ty:ty) => $read:ident,)*) => {
+        $(
+            #[inline(always)]
+            fn $decode(&mut self) -> Result<$ty> {
+                if self.varint() {
+                    return self.read_varint();
+                }
+                self.align(mem::size_of::<$ty>() as u64)?;
+                self.read_type::<$ty>()?;
+                self.reader.$read::<O::Endian>().map_err(Into::into)
+            }
+        )*
+    }
+}
+
+impl<'de, R: BincodeRead<'de>, O: Options> Decoder for Deserializer<R, O> {
+    impl_decode_nums! {
+        decode_u16(u16) => read_u16,
+        decode_u32(u32) => read_u32,
+        decode_u64(u64) => read_u64,
+        decode_u128(u128) => read_u128,
+        decode_i16(i16) => read_i16,
+        decode_i32(i32) => read_i32,
+        decode_i64(i64) => read_i64,
+        decode_i128(i128) => read_i128,
+    }
+
+    #[inline(always)]
+    fn decode_f32(&mut self) -> Result<f32> {
+        Ok(self.read_float(true)? as f32)
+    }
+
+    #[inline(always)]
+    fn decode_f64(&mut self) -> Result<f64> {
+        self.read_float(false)
+    }
+
+    #[inline(always)]
+    fn decode_bool(&mut self) -> Result<bool> {
+        self.read_bool()
+    }
+
+    #[inline(always)]
+    fn decode_u8(&mut self) -> Result<u8> {
+        self.read_type::<u8>()?;
+        self.reader.read_u8().map_err(Into::into)
+    }
+
+    #[inline(always)]
+    fn decode_i8(&mut self) -> Result<i8> {
+        self.read_type::<i8>()?;
+        self.reader.read_i8().map_err(Into::into)
+    }
+
+    #[inline(always)]
+    fn decode_char(&mut self) -> Result<char> {
+        self.read_char()
+    }
+
+    #[inline(always)]
+    fn decode_len(&mut self) -> Result<usize> {
+        let len = self.read_size::<O::ArraySize>()?;
+        len.try_into()
+            .map_err(|_e| ErrorKind::not_usize(len).into())
+    }
+
+    #[inline(always)]
+    fn decode_usize(&mut self) -> Result<usize> {
+        let value = match self.options.config().usize_mode() {
+            Some(mode) => self.read_usize(mode)?,
+            None => self.decode_u64()?,
+        };
+        value
+            .try_into()
+            .map_err(|_e| ErrorKind::not_usize(value).into())
+    }
+
+    #[inline(always)]
+    fn decode_isize(&mut self) -> Result<isize> {
+        let value = match self.options.config().usize_mode() {
+            Some(mode) => {
+                let value = self.read_usize(mode)?;
+                usize_mode::to_isize(mode, value)
+            }
+            None => self.decode_i64()?,
+        };
+        value
+            .try_into()
+            .map_err(|_e| ErrorKind::not_isize(value).into())
+    }
+
+    #[inline(always)]
+    #[cfg(feature = "alloc")]
+    fn decode_string(&mut self) -> Result<String> {
+        self.read_string()
+    }
+
+    #[inline(always)]
+    #[cfg(feature = "alloc")]
+    fn decode_byte_buf(&mut self) -> Result<Vec<u8>> {
+        self.read_vec()
+    }
+
+    #[inline(always)]
+    fn decode_variant(&mut self) -> Result<u32> {
+        self.decode_u32()
+    }
+}
+
 static UTF8_CHAR_WIDTH: [u8; 256] = [
     1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
     1, // 0x1F
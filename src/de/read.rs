@@ -1,11 +1,16 @@
 use error::Result;
 use serde;
 use core2::io;
+#[cfg(feature = "alloc")]
+use core::convert::TryInto;
+#[cfg(feature = "alloc")]
 use core::slice;
 
-use alloc::boxed::Box;
-use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::borrow::Cow;
+#[cfg(feature = "alloc")]
 use alloc::vec;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
 /// An optional Read trait for advanced Bincode usage.
@@ -19,44 +24,154 @@ pub trait BincodeRead<'storage>: io::Read {
         V: serde::de::Visitor<'storage>;
 
     /// Return the first `length` bytes of the internal byte buffer.
+    #[cfg(feature = "alloc")]
     fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>>;
 
     /// Forwards reading `length` bytes on to the serde reader.
     fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
     where
         V: serde::de::Visitor<'storage>;
+
+    /// Skips `length` bytes. The default reads them into a buffer; readers that
+    /// can skip without allocating should override it.
+    fn skip(&mut self, length: usize) -> Result<()> {
+        #[cfg(feature = "alloc")]
+        {
+            self.get_byte_buffer(length).map(|_| ())
+        }
+        #[cfg(not(feature = "alloc"))]
+        {
+            let mut length = length;
+            let mut scratch = [0; 256];
+            while length > 0 {
+                let chunk = length.min(scratch.len());
+                self.read_exact(&mut scratch[..chunk])?;
+                length -= chunk;
+            }
+            Ok(())
+        }
+    }
+
+    /// Returns true if no bytes are left to read, so that `Config::allow_trailing_default_fields`
+    /// can tell where the input ends. The default returns false, for readers that cannot
+    /// look ahead.
+    fn at_end(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// The number of bytes read so far, if the reader can `seek`.
+    fn seekable_position(&self) -> Option<u64> {
+        None
+    }
+
+    /// Moves to `position` bytes from where the reader started, so that the
+    /// back-references written by `Config::dedup_subtrees` can be followed.
+    fn seek(&mut self, position: u64) -> Result<()> {
+        let _ = position;
+        Err(serde::de::Error::custom(
+            "back-references can only be followed when deserializing from a slice",
+        ))
+    }
 }
 
 /// A BincodeRead implementation for byte slices
-/// NOT A PART OF THE STABLE PUBLIC API
-#[doc(hidden)]
+///
+/// Pass it by `&mut` to a `deserialize_from_custom` function to find out where
+/// the value ended:
+///
+/// ```
+/// let mut bytes = bincode2::serialize(&(1u8, 2u16)).unwrap();
+/// bytes.extend_from_slice(b"trailer");
+///
+/// let mut reader = bincode2::SliceReader::new(&bytes);
+/// let value: (u8, u16) = bincode2::deserialize_from_custom(&mut reader).unwrap();
+/// assert_eq!(value, (1, 2));
+/// assert_eq!(reader.position(), 3);
+/// assert_eq!(reader.remaining(), b"trailer");
+/// ```
 pub struct SliceReader<'storage> {
     slice: &'storage [u8],
+    full: &'storage [u8],
 }
 
 /// A BincodeRead implementation for io::Readers
-/// NOT A PART OF THE STABLE PUBLIC API
-#[doc(hidden)]
+///
+/// Strings and byte slices are read into a buffer reused across the whole value
+/// and passed to `visit_str` and `visit_bytes`, so visitors that do not keep them
+/// allocate nothing. Use `ScratchReader` to deserialize `&str` fields instead.
+#[cfg(feature = "alloc")]
 pub struct IoReader<R> {
     reader: R,
     temp_buffer: Vec<u8>,
+    position: u64,
 }
 
 impl<'storage> SliceReader<'storage> {
     /// Constructs a slice reader
     pub fn new(bytes: &'storage [u8]) -> SliceReader<'storage> {
-        SliceReader { slice: bytes }
+        SliceReader {
+            slice: bytes,
+            full: bytes,
+        }
+    }
+
+    /// The number of bytes read so far.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        (self.full.len() - self.slice.len()) as u64
+    }
+
+    /// The bytes that have not been read yet.
+    #[inline(always)]
+    pub fn remaining(&self) -> &'storage [u8] {
+        self.slice
+    }
+
+    /// Consumes the reader, returning the bytes that have not been read yet.
+    #[inline(always)]
+    pub fn into_remaining(self) -> &'storage [u8] {
+        self.slice
+    }
+}
+
+impl<'storage> From<&'storage [u8]> for SliceReader<'storage> {
+    #[inline(always)]
+    fn from(bytes: &'storage [u8]) -> SliceReader<'storage> {
+        SliceReader::new(bytes)
     }
 }
 
+/// Reads the bytes of a `Cow`, borrowed or owned, which values borrow from.
+#[cfg(feature = "alloc")]
+impl<'storage, 'a> From<&'storage Cow<'a, [u8]>> for SliceReader<'storage> {
+    #[inline(always)]
+    fn from(bytes: &'storage Cow<'a, [u8]>) -> SliceReader<'storage> {
+        SliceReader::new(bytes)
+    }
+}
+
+#[cfg(feature = "alloc")]
 impl<R> IoReader<R> {
     /// Constructs an IoReadReader
     pub fn new(r: R) -> IoReader<R> {
         IoReader {
             reader: r,
             temp_buffer: vec![],
+            position: 0,
         }
     }
+
+    /// The number of bytes read from the underlying reader so far. `IoReader`
+    /// never reads ahead, so this is where the last value ended.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
 }
 
 impl<'storage> io::Read for SliceReader<'storage> {
@@ -70,24 +185,26 @@ impl<'storage> io::Read for SliceReader<'storage> {
     }
 }
 
+#[cfg(feature = "alloc")]
 impl<R: io::Read> io::Read for IoReader<R> {
     #[inline(always)]
     fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
-        self.reader.read(out)
+        let read = self.reader.read(out)?;
+        self.position += read as u64;
+        Ok(read)
     }
     #[inline(always)]
     fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
-        self.reader.read_exact(out)
+        self.reader.read_exact(out)?;
+        self.position += out.len() as u64;
+        Ok(())
     }
 }
 
 impl<'storage> SliceReader<'storage> {
     #[inline(always)]
-    fn unexpected_eof() -> Box<::ErrorKind> {
-        Box::new(::ErrorKind::Io(io::Error::new(
-            io::ErrorKind::UnexpectedEof,
-            "",
-        )))
+    fn unexpected_eof() -> ::Error {
+        ::ErrorKind::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "")).into()
     }
 }
 
@@ -112,6 +229,7 @@ impl<'storage> BincodeRead<'storage> for SliceReader<'storage> {
     }
 
     #[inline(always)]
+    #[cfg(feature = "alloc")]
     fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
         if length > self.slice.len() {
             return Err(SliceReader::unexpected_eof());
@@ -135,8 +253,36 @@ impl<'storage> BincodeRead<'storage> for SliceReader<'storage> {
         self.slice = &self.slice[length..];
         r
     }
+
+    #[inline(always)]
+    fn skip(&mut self, length: usize) -> Result<()> {
+        if length > self.slice.len() {
+            return Err(SliceReader::unexpected_eof());
+        }
+        self.slice = &self.slice[length..];
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn at_end(&mut self) -> Result<bool> {
+        Ok(self.slice.is_empty())
+    }
+
+    #[inline(always)]
+    fn seekable_position(&self) -> Option<u64> {
+        Some(self.position())
+    }
+
+    fn seek(&mut self, position: u64) -> Result<()> {
+        if position > self.full.len() as u64 {
+            return Err(SliceReader::unexpected_eof());
+        }
+        self.slice = &self.full[position as usize..];
+        Ok(())
+    }
 }
 
+#[cfg(feature = "alloc")]
 impl<R> IoReader<R>
 where
     R: io::Read,
@@ -157,6 +303,7 @@ where
         // uninitialized bytes (as ours does). See discussion at the link below.
         // https://github.com/servo/bincode/issues/260
         self.reader.read_exact(buf)?;
+        self.position += length as u64;
 
         // Only after `read_exact` successfully returns do we set the buffer
         // length. By doing this after the call to `read_exact`, we can avoid
@@ -170,6 +317,7 @@ where
     }
 }
 
+#[cfg(feature = "alloc")]
 impl<'a, R> BincodeRead<'a> for IoReader<R>
 where
     R: io::Read,
@@ -200,4 +348,759 @@ where
         self.fill_buffer(length)?;
         visitor.visit_bytes(&self.temp_buffer[..])
     }
+
+    fn skip(&mut self, mut length: usize) -> Result<()> {
+        let mut scratch = [0; 256];
+        while length > 0 {
+            let chunk = length.min(scratch.len());
+            self.reader.read_exact(&mut scratch[..chunk])?;
+            self.position += chunk as u64;
+            length -= chunk;
+        }
+        Ok(())
+    }
+}
+
+/// A BincodeRead implementation for io::Readers that lends strings and bytes out
+/// of a caller-provided scratch buffer instead of allocating them.
+///
+/// Every string or byte slice is read into the unused part of `scratch` and
+/// borrowed from there, so values deserialized from a `Read`er can hold `&str`
+/// and `&[u8]` fields. Reading more than fits in `scratch` fails with
+/// `ErrorKind::ScratchExhausted`; reuse the buffer for the next value once the
+/// borrows have ended.
+///
+/// ```
+/// let bytes = bincode2::serialize(&("hello", 7u8)).unwrap();
+///
+/// let mut scratch = [0; 64];
+/// let reader = bincode2::ScratchReader::new(&bytes[..], &mut scratch);
+/// let value: (&str, u8) = bincode2::config()
+///     .deserialize_from_custom_seed(::std::marker::PhantomData, reader)
+///     .unwrap();
+/// assert_eq!(value, ("hello", 7));
+/// ```
+///
+/// `Config::deserialize_from_scratch` does the same in one call.
+pub struct ScratchReader<'s, R> {
+    reader: R,
+    scratch: &'s mut [u8],
+    used: usize,
+    position: u64,
+}
+
+impl<'s, R> ScratchReader<'s, R> {
+    /// Constructs a scratch reader lending out of `scratch`.
+    pub fn new(reader: R, scratch: &'s mut [u8]) -> ScratchReader<'s, R> {
+        ScratchReader {
+            reader,
+            scratch,
+            used: 0,
+            position: 0,
+        }
+    }
+
+    /// The number of bytes read from the underlying reader so far.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The number of bytes of `scratch` lent out so far.
+    #[inline(always)]
+    pub fn scratch_used(&self) -> usize {
+        self.used
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<'s, R: io::Read> ScratchReader<'s, R> {
+    /// Reads `length` bytes into the front of the unused scratch space, and
+    /// splits them off for the rest of `'s`.
+    fn lend(&mut self, length: usize) -> Result<&'s [u8]> {
+        if length > self.scratch.len() {
+            return Err(::ErrorKind::ScratchExhausted.into());
+        }
+        let scratch = ::core::mem::replace(&mut self.scratch, &mut []);
+        let (lent, rest) = scratch.split_at_mut(length);
+        self.scratch = rest;
+        self.reader.read_exact(lent)?;
+        self.used += length;
+        self.position += length as u64;
+        Ok(lent)
+    }
+}
+
+impl<'s, R: io::Read> io::Read for ScratchReader<'s, R> {
+    #[inline(always)]
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let count = self.reader.read(out)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+
+    #[inline(always)]
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(out)?;
+        self.position += out.len() as u64;
+        Ok(())
+    }
+}
+
+impl<'s, R: io::Read> BincodeRead<'s> for ScratchReader<'s, R> {
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'s>,
+    {
+        let string = match ::core::str::from_utf8(self.lend(length)?) {
+            Ok(s) => s,
+            Err(e) => return Err(::ErrorKind::InvalidUtf8Encoding(e).into()),
+        };
+        visitor.visit_borrowed_str(string)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        self.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'s>,
+    {
+        visitor.visit_borrowed_bytes(self.lend(length)?)
+    }
+
+    fn skip(&mut self, mut length: usize) -> Result<()> {
+        let mut scratch = [0; 256];
+        while length > 0 {
+            let chunk = length.min(scratch.len());
+            self.read_exact(&mut scratch[..chunk])?;
+            length -= chunk;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, 'storage, R> BincodeRead<'storage> for &'a mut R
+where
+    R: BincodeRead<'storage>,
+{
+    #[inline(always)]
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'storage>,
+    {
+        (**self).forward_read_str(length, visitor)
+    }
+
+    #[inline(always)]
+    #[cfg(feature = "alloc")]
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        (**self).get_byte_buffer(length)
+    }
+
+    #[inline(always)]
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'storage>,
+    {
+        (**self).forward_read_bytes(length, visitor)
+    }
+
+    #[inline(always)]
+    fn skip(&mut self, length: usize) -> Result<()> {
+        (**self).skip(length)
+    }
+
+    #[inline(always)]
+    fn at_end(&mut self) -> Result<bool> {
+        (**self).at_end()
+    }
+
+    #[inline(always)]
+    fn seekable_position(&self) -> Option<u64> {
+        (**self).seekable_position()
+    }
+
+    #[inline(always)]
+    fn seek(&mut self, position: u64) -> Result<()> {
+        (**self).seek(position)
+    }
+}
+
+/// A minimal source of bytes, for readers that cannot implement `io::Read`,
+/// such as ring buffers filled by DMA. Wrap it in a `FillBufReader` to
+/// deserialize from it.
+pub trait FillBuf {
+    /// Returns the bytes that can be read next, refilling the buffer if it is
+    /// empty. An empty slice means the input has ended.
+    fn fill_buf(&mut self) -> io::Result<&[u8]>;
+
+    /// Marks the first `amount` bytes returned by `fill_buf` as read.
+    fn consume(&mut self, amount: usize);
+}
+
+impl<'a, F: FillBuf + ?Sized> FillBuf for &'a mut F {
+    #[inline(always)]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        (**self).fill_buf()
+    }
+
+    #[inline(always)]
+    fn consume(&mut self, amount: usize) {
+        (**self).consume(amount)
+    }
+}
+
+/// Copies bytes from `source` until `out` is full.
+#[cfg(feature = "alloc")]
+fn read_exact_from<F: FillBuf + ?Sized>(source: &mut F, mut out: &mut [u8]) -> io::Result<()> {
+    while !out.is_empty() {
+        let read = {
+            let buf = source.fill_buf()?;
+            if buf.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ""));
+            }
+            let read = buf.len().min(out.len());
+            out[..read].copy_from_slice(&buf[..read]);
+            read
+        };
+        source.consume(read);
+        out = &mut out[read..];
+    }
+    Ok(())
+}
+
+/// Discards `length` bytes from `source`.
+#[cfg(feature = "alloc")]
+fn skip_from<F: FillBuf + ?Sized>(source: &mut F, mut length: usize) -> io::Result<()> {
+    while length > 0 {
+        let available = source.fill_buf()?.len();
+        if available == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ""));
+        }
+        let skipped = available.min(length);
+        source.consume(skipped);
+        length -= skipped;
+    }
+    Ok(())
+}
+
+/// A BincodeRead implementation for `FillBuf` sources.
+///
+/// Strings and byte slices lying within one buffer are handed to the visitor from
+/// there; those split across buffers are copied first.
+#[cfg(feature = "alloc")]
+pub struct FillBufReader<F> {
+    source: F,
+    temp_buffer: Vec<u8>,
+    position: u64,
+}
+
+#[cfg(feature = "alloc")]
+impl<F: FillBuf> FillBufReader<F> {
+    /// Constructs a reader over `source`.
+    pub fn new(source: F) -> FillBufReader<F> {
+        FillBufReader {
+            source,
+            temp_buffer: vec![],
+            position: 0,
+        }
+    }
+
+    /// The number of bytes read from the source so far.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Returns the source.
+    pub fn into_inner(self) -> F {
+        self.source
+    }
+
+    /// The next `length` bytes, from the source's buffer if they all lie in it or
+    /// copied into `temp_buffer` otherwise. They still have to be consumed.
+    fn contiguous(&mut self, length: usize) -> Result<Contiguous> {
+        if self.source.fill_buf()?.len() >= length {
+            return Ok(Contiguous::InSource);
+        }
+        self.temp_buffer.clear();
+        self.temp_buffer.resize(length, 0);
+        read_exact_from(&mut self.source, &mut self.temp_buffer)?;
+        self.position += length as u64;
+        Ok(Contiguous::Copied)
+    }
+}
+
+/// Where `FillBufReader::contiguous` found the bytes asked for.
+#[cfg(feature = "alloc")]
+enum Contiguous {
+    InSource,
+    Copied,
+}
+
+#[cfg(feature = "alloc")]
+impl<F: FillBuf> io::Read for FillBufReader<F> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let read = {
+            let buf = self.source.fill_buf()?;
+            let read = buf.len().min(out.len());
+            out[..read].copy_from_slice(&buf[..read]);
+            read
+        };
+        self.source.consume(read);
+        self.position += read as u64;
+        Ok(read)
+    }
+
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        read_exact_from(&mut self.source, out)?;
+        self.position += out.len() as u64;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, F: FillBuf> BincodeRead<'a> for FillBufReader<F> {
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'a>,
+    {
+        match self.contiguous(length)? {
+            Contiguous::InSource => {
+                let result = match ::core::str::from_utf8(&self.source.fill_buf()?[..length]) {
+                    Ok(string) => visitor.visit_str(string),
+                    Err(e) => return Err(::ErrorKind::InvalidUtf8Encoding(e).into()),
+                };
+                self.source.consume(length);
+                self.position += length as u64;
+                result
+            }
+            Contiguous::Copied => match ::core::str::from_utf8(&self.temp_buffer) {
+                Ok(string) => visitor.visit_str(string),
+                Err(e) => Err(::ErrorKind::InvalidUtf8Encoding(e).into()),
+            },
+        }
+    }
+
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        read_exact_from(&mut self.source, &mut buffer)?;
+        self.position += length as u64;
+        Ok(buffer)
+    }
+
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'a>,
+    {
+        match self.contiguous(length)? {
+            Contiguous::InSource => {
+                let result = visitor.visit_bytes(&self.source.fill_buf()?[..length]);
+                self.source.consume(length);
+                self.position += length as u64;
+                result
+            }
+            Contiguous::Copied => visitor.visit_bytes(&self.temp_buffer),
+        }
+    }
+
+    fn skip(&mut self, length: usize) -> Result<()> {
+        skip_from(&mut self.source, length)?;
+        self.position += length as u64;
+        Ok(())
+    }
+
+    fn at_end(&mut self) -> Result<bool> {
+        Ok(self.source.fill_buf()?.is_empty())
+    }
+}
+
+/// A BincodeRead implementation for input split across several byte slices, such
+/// as the chunks of a rope, read as if they were concatenated.
+///
+/// Strings and byte slices lying within one segment are borrowed from it; those
+/// split across segments are copied.
+///
+/// ```
+/// let bytes = bincode2::serialize(&("split".to_string(), 7u32)).unwrap();
+/// let (head, tail) = bytes.split_at(10);
+///
+/// let segments = [head, tail];
+/// let reader = bincode2::ChainedSliceReader::new(&segments);
+/// let value: (String, u32) = bincode2::deserialize_from_custom(reader).unwrap();
+/// assert_eq!(value, ("split".to_string(), 7));
+/// ```
+#[cfg(feature = "alloc")]
+pub struct ChainedSliceReader<'storage> {
+    segments: &'storage [&'storage [u8]],
+    /// The unread part of the current segment.
+    current: &'storage [u8],
+    /// The index of the segment after the current one.
+    next: usize,
+    position: u64,
+    temp_buffer: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl<'storage> ChainedSliceReader<'storage> {
+    /// Constructs a reader over the concatenation of `segments`.
+    pub fn new(segments: &'storage [&'storage [u8]]) -> ChainedSliceReader<'storage> {
+        ChainedSliceReader {
+            segments,
+            current: &[],
+            next: 0,
+            position: 0,
+            temp_buffer: vec![],
+        }
+    }
+
+    /// The number of bytes read so far.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves on to the next non-empty segment once the current one is read,
+    /// returning what is left of the current one.
+    fn current(&mut self) -> &'storage [u8] {
+        while self.current.is_empty() && self.next < self.segments.len() {
+            self.current = self.segments[self.next];
+            self.next += 1;
+        }
+        self.current
+    }
+
+    /// The next `length` bytes, if they lie within the current segment.
+    fn borrow(&mut self, length: usize) -> Option<&'storage [u8]> {
+        let current = self.current();
+        if current.len() < length {
+            return None;
+        }
+        self.current = &current[length..];
+        self.position += length as u64;
+        Some(&current[..length])
+    }
+
+    /// Copies the next `length` bytes into `temp_buffer`.
+    fn copy(&mut self, length: usize) -> Result<()> {
+        let mut buffer = ::core::mem::replace(&mut self.temp_buffer, Vec::new());
+        buffer.clear();
+        buffer.resize(length, 0);
+        let result = read_exact_from(self, &mut buffer);
+        self.temp_buffer = buffer;
+        result.map_err(Into::into)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'storage> FillBuf for ChainedSliceReader<'storage> {
+    #[inline(always)]
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.current())
+    }
+
+    #[inline(always)]
+    fn consume(&mut self, amount: usize) {
+        self.current = &self.current[amount..];
+        self.position += amount as u64;
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'storage> io::Read for ChainedSliceReader<'storage> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let current = self.current();
+        let read = current.len().min(out.len());
+        out[..read].copy_from_slice(&current[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        read_exact_from(self, out)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'storage> BincodeRead<'storage> for ChainedSliceReader<'storage> {
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'storage>,
+    {
+        if let Some(bytes) = self.borrow(length) {
+            return match ::core::str::from_utf8(bytes) {
+                Ok(string) => visitor.visit_borrowed_str(string),
+                Err(e) => Err(::ErrorKind::InvalidUtf8Encoding(e).into()),
+            };
+        }
+        self.copy(length)?;
+        match ::core::str::from_utf8(&self.temp_buffer) {
+            Ok(string) => visitor.visit_str(string),
+            Err(e) => Err(::ErrorKind::InvalidUtf8Encoding(e).into()),
+        }
+    }
+
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut buffer = vec![0; length];
+        read_exact_from(self, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'storage>,
+    {
+        if let Some(bytes) = self.borrow(length) {
+            return visitor.visit_borrowed_bytes(bytes);
+        }
+        self.copy(length)?;
+        visitor.visit_bytes(&self.temp_buffer)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<()> {
+        skip_from(self, length).map_err(Into::into)
+    }
+
+    fn at_end(&mut self) -> Result<bool> {
+        Ok(self.current().is_empty())
+    }
+
+    #[inline(always)]
+    fn seekable_position(&self) -> Option<u64> {
+        Some(self.position)
+    }
+
+    fn seek(&mut self, position: u64) -> Result<()> {
+        self.current = &[];
+        self.next = 0;
+        self.position = 0;
+        let position: usize = position
+            .try_into()
+            .map_err(|_e| ::ErrorKind::not_usize(position))?;
+        self.skip(position)
+    }
+}
+
+/// A BincodeRead implementation for non-blocking `io::Read`ers, which keeps the
+/// bytes of the value being read so that reading it can be retried when the
+/// reader fails with `WouldBlock`.
+///
+/// Every byte read from the underlying reader is kept until `commit` is called,
+/// and `rewind` goes back to the first of them, so a value whose bytes have not
+/// all arrived yet is read again from its start once they have. Bytes are read
+/// in whatever amounts the reader returns, so none are lost to a `WouldBlock`
+/// in the middle of a `read_exact`. `Config::deserialize_resumable` does the
+/// rewinding and committing.
+///
+/// ```
+/// # extern crate bincode2;
+/// # extern crate core2;
+/// use core2::io::{self, Read};
+///
+/// /// Returns its bytes one at a time, failing with `WouldBlock` in between.
+/// struct Trickle<'a>(&'a [u8], bool);
+///
+/// impl<'a> Read for Trickle<'a> {
+///     fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+///         self.1 = !self.1;
+///         if self.1 {
+///             return Err(io::Error::new(io::ErrorKind::WouldBlock, "not yet"));
+///         }
+///         let read = self.0.len().min(out.len()).min(1);
+///         out[..read].copy_from_slice(&self.0[..read]);
+///         self.0 = &self.0[read..];
+///         Ok(read)
+///     }
+/// }
+///
+/// # fn main() {
+///
+/// let bytes = bincode2::serialize(&("hello", 7u8)).unwrap();
+/// let mut reader = bincode2::ResumableReader::new(Trickle(&bytes, false));
+/// let config = bincode2::config();
+/// let value: (String, u8) = loop {
+///     match config.deserialize_resumable(&mut reader) {
+///         Err(ref e) if e.is_would_block() => continue,
+///         result => break result.unwrap(),
+///     }
+/// };
+/// assert_eq!(value, ("hello".to_string(), 7));
+/// # }
+/// ```
+#[cfg(feature = "alloc")]
+pub struct ResumableReader<R> {
+    reader: R,
+    /// The bytes read since the last `commit`.
+    buffer: Vec<u8>,
+    /// How far into `buffer` the current value has been read.
+    position: usize,
+}
+
+/// The least number of bytes asked of the underlying reader at once.
+#[cfg(feature = "alloc")]
+const RESUMABLE_CHUNK: usize = 256;
+
+#[cfg(feature = "alloc")]
+impl<R> ResumableReader<R> {
+    /// Constructs a reader over `reader`.
+    pub fn new(reader: R) -> ResumableReader<R> {
+        ResumableReader {
+            reader,
+            buffer: vec![],
+            position: 0,
+        }
+    }
+
+    /// Goes back to the first byte read since the last `commit`, to read the
+    /// value again.
+    #[inline(always)]
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    /// Drops the bytes read so far, once the value they hold has been read in
+    /// full. Bytes read past the end of the value are kept for the next one.
+    pub fn commit(&mut self) {
+        self.buffer.drain(..self.position);
+        self.position = 0;
+    }
+
+    /// The bytes read from the underlying reader and not yet committed.
+    #[inline(always)]
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Returns a reference to the underlying reader.
+    #[inline(always)]
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Returns the underlying reader, dropping the bytes not yet committed.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<R: io::Read> ResumableReader<R> {
+    /// Reads once from the underlying reader into the end of `buffer`, returning
+    /// the number of bytes read.
+    fn fetch(&mut self, wanted: usize) -> io::Result<usize> {
+        let start = self.buffer.len();
+        self.buffer.resize(start + wanted.max(RESUMABLE_CHUNK), 0);
+        loop {
+            match self.reader.read(&mut self.buffer[start..]) {
+                Ok(read) => {
+                    self.buffer.truncate(start + read);
+                    return Ok(read);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.buffer.truncate(start);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Makes sure `length` bytes after `position` are in `buffer`.
+    fn fill(&mut self, length: usize) -> io::Result<()> {
+        while self.buffer.len() - self.position < length {
+            let missing = length - (self.buffer.len() - self.position);
+            if self.fetch(missing)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, ""));
+            }
+        }
+        Ok(())
+    }
+
+    /// The next `length` bytes, which are then read.
+    fn take(&mut self, length: usize) -> io::Result<&[u8]> {
+        self.fill(length)?;
+        let start = self.position;
+        self.position += length;
+        Ok(&self.buffer[start..self.position])
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<R: io::Read> io::Read for ResumableReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.buffer.len() && !out.is_empty() && self.fetch(out.len())? == 0 {
+            return Ok(0);
+        }
+        let read = (self.buffer.len() - self.position).min(out.len());
+        out[..read].copy_from_slice(&self.buffer[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        let bytes = self.take(out.len())?;
+        out.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, R: io::Read> BincodeRead<'a> for ResumableReader<R> {
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'a>,
+    {
+        match ::core::str::from_utf8(self.take(length)?) {
+            Ok(string) => visitor.visit_str(string),
+            Err(e) => Err(::ErrorKind::InvalidUtf8Encoding(e).into()),
+        }
+    }
+
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        Ok(self.take(length)?.to_vec())
+    }
+
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'a>,
+    {
+        visitor.visit_bytes(self.take(length)?)
+    }
+
+    fn skip(&mut self, length: usize) -> Result<()> {
+        self.take(length)?;
+        Ok(())
+    }
+
+    fn at_end(&mut self) -> Result<bool> {
+        if self.position < self.buffer.len() {
+            return Ok(false);
+        }
+        Ok(self.fetch(1)? == 0)
+    }
+
+    #[inline(always)]
+    fn seekable_position(&self) -> Option<u64> {
+        Some(self.position as u64)
+    }
+
+    fn seek(&mut self, position: u64) -> Result<()> {
+        let position: usize = position
+            .try_into()
+            .map_err(|_e| ::ErrorKind::not_usize(position))?;
+        if position > self.position {
+            self.fill(position - self.position)?;
+        }
+        self.position = position;
+        Ok(())
+    }
 }
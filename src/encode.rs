@@ -0,0 +1,366 @@
+//! Encoding without serde, for types where its visitor indirection shows up in profiles.
+//!
+//! `Encode` and `Decode` write exactly the bytes serde would for the same type
+//! and configuration, so the two can be mixed freely: a value encoded with
+//! `Config::encode` can be deserialized with `Config::deserialize` and the other
+//! way around. The exception is `Config::tagged_fields`, `Config::allow_flatten`,
+//! `Config::allow_untagged`, `Config::dedup_subtrees` and `Config::variant_lengths`,
+//! which only apply to serde:
+//! `Encode` always writes fields one after the other. Nor do `Encode` and `Decode`
+//! use the codecs registered with `Config::type_codec`.
+//! Likewise `usize` and `isize` values follow `Config::usize_encoding` only through
+//! `Encode`, as serde passes them on as 64 bit integers.
+//!
+//! With the `derive` feature both traits can be derived for structs and enums
+//! whose fields implement them; enum variants are numbered in declaration order,
+//! like serde does.
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::string::String;
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+
+use error::{ErrorKind, Result};
+
+/// A destination for `Encode` types, provided by bincode2.
+pub trait Encoder {
+    /// Encodes a `bool` as a single byte.
+    fn encode_bool(&mut self, v: bool) -> Result<()>;
+    /// Encodes a `u8`.
+    fn encode_u8(&mut self, v: u8) -> Result<()>;
+    /// Encodes a `u16`.
+    fn encode_u16(&mut self, v: u16) -> Result<()>;
+    /// Encodes a `u32`.
+    fn encode_u32(&mut self, v: u32) -> Result<()>;
+    /// Encodes a `u64`.
+    fn encode_u64(&mut self, v: u64) -> Result<()>;
+    /// Encodes a `u128`.
+    fn encode_u128(&mut self, v: u128) -> Result<()>;
+    /// Encodes an `i8`.
+    fn encode_i8(&mut self, v: i8) -> Result<()>;
+    /// Encodes an `i16`.
+    fn encode_i16(&mut self, v: i16) -> Result<()>;
+    /// Encodes an `i32`.
+    fn encode_i32(&mut self, v: i32) -> Result<()>;
+    /// Encodes an `i64`.
+    fn encode_i64(&mut self, v: i64) -> Result<()>;
+    /// Encodes an `i128`.
+    fn encode_i128(&mut self, v: i128) -> Result<()>;
+    /// Encodes an `f32`.
+    fn encode_f32(&mut self, v: f32) -> Result<()>;
+    /// Encodes an `f64`.
+    fn encode_f64(&mut self, v: f64) -> Result<()>;
+    /// Encodes a `char` as utf8.
+    fn encode_char(&mut self, v: char) -> Result<()>;
+    /// Encodes a `usize`, as a `u64` unless `Config::usize_encoding` is set.
+    fn encode_usize(&mut self, v: usize) -> Result<()> {
+        self.encode_u64(v as u64)
+    }
+    /// Encodes an `isize`, as an `i64` unless `Config::usize_encoding` is set.
+    fn encode_isize(&mut self, v: isize) -> Result<()> {
+        self.encode_i64(v as i64)
+    }
+    /// Encodes the length of a sequence, using the configured array length size.
+    fn encode_len(&mut self, len: usize) -> Result<()>;
+    /// Encodes a string, preceded by its length.
+    fn encode_str(&mut self, v: &str) -> Result<()>;
+    /// Encodes a byte slice, preceded by its length.
+    fn encode_bytes(&mut self, v: &[u8]) -> Result<()>;
+    /// Encodes the index of an enum variant.
+    fn encode_variant(&mut self, index: u32) -> Result<()>;
+}
+
+/// A source of `Decode` types, provided by bincode2.
+pub trait Decoder {
+    /// Decodes a `bool`, failing on bytes other than 0 and 1.
+    fn decode_bool(&mut self) -> Result<bool>;
+    /// Decodes a `u8`.
+    fn decode_u8(&mut self) -> Result<u8>;
+    /// Decodes a `u16`.
+    fn decode_u16(&mut self) -> Result<u16>;
+    /// Decodes a `u32`.
+    fn decode_u32(&mut self) -> Result<u32>;
+    /// Decodes a `u64`.
+    fn decode_u64(&mut self) -> Result<u64>;
+    /// Decodes a `u128`.
+    fn decode_u128(&mut self) -> Result<u128>;
+    /// Decodes an `i8`.
+    fn decode_i8(&mut self) -> Result<i8>;
+    /// Decodes an `i16`.
+    fn decode_i16(&mut self) -> Result<i16>;
+    /// Decodes an `i32`.
+    fn decode_i32(&mut self) -> Result<i32>;
+    /// Decodes an `i64`.
+    fn decode_i64(&mut self) -> Result<i64>;
+    /// Decodes an `i128`.
+    fn decode_i128(&mut self) -> Result<i128>;
+    /// Decodes an `f32`.
+    fn decode_f32(&mut self) -> Result<f32>;
+    /// Decodes an `f64`.
+    fn decode_f64(&mut self) -> Result<f64>;
+    /// Decodes a utf8 `char`.
+    fn decode_char(&mut self) -> Result<char>;
+    /// Decodes a `usize`, failing with `SizeLimit` if it does not fit on this target.
+    fn decode_usize(&mut self) -> Result<usize> {
+        let value = self.decode_u64()?;
+        if value > usize::MAX as u64 {
+            return Err(ErrorKind::not_usize(value).into());
+        }
+        Ok(value as usize)
+    }
+    /// Decodes an `isize`, failing with `SizeLimit` if it does not fit on this target.
+    fn decode_isize(&mut self) -> Result<isize> {
+        let value = self.decode_i64()?;
+        if value > isize::MAX as i64 || value < isize::MIN as i64 {
+            return Err(ErrorKind::not_isize(value).into());
+        }
+        Ok(value as isize)
+    }
+    /// Decodes the length of a sequence.
+    fn decode_len(&mut self) -> Result<usize>;
+    /// Decodes a string written by `Encoder::encode_str`.
+    #[cfg(feature = "alloc")]
+    fn decode_string(&mut self) -> Result<String>;
+    /// Decodes a byte buffer written by `Encoder::encode_bytes`.
+    #[cfg(feature = "alloc")]
+    fn decode_byte_buf(&mut self) -> Result<Vec<u8>>;
+    /// Decodes the index of an enum variant.
+    fn decode_variant(&mut self) -> Result<u32>;
+}
+
+/// A type that can be written to an `Encoder`.
+pub trait Encode {
+    /// Writes `self` to the encoder.
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()>;
+}
+
+/// A type that can be read from a `Decoder`.
+pub trait Decode: Sized {
+    /// Reads a value from the decoder.
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Self>;
+}
+
+macro_rules! impl_primitive {
+    ($($ty:ty => $encode:ident, $decode:ident;)*) => {
+        $(
+            impl Encode for $ty {
+                #[inline(always)]
+                fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+                    encoder.$encode(*self)
+                }
+            }
+
+            impl Decode for $ty {
+                #[inline(always)]
+                fn decode<D: Decoder>(decoder: &mut D) -> Result<$ty> {
+                    decoder.$decode()
+                }
+            }
+        )*
+    }
+}
+
+impl_primitive! {
+    bool => encode_bool, decode_bool;
+    u8 => encode_u8, decode_u8;
+    u16 => encode_u16, decode_u16;
+    u32 => encode_u32, decode_u32;
+    u64 => encode_u64, decode_u64;
+    u128 => encode_u128, decode_u128;
+    i8 => encode_i8, decode_i8;
+    i16 => encode_i16, decode_i16;
+    i32 => encode_i32, decode_i32;
+    i64 => encode_i64, decode_i64;
+    i128 => encode_i128, decode_i128;
+    f32 => encode_f32, decode_f32;
+    f64 => encode_f64, decode_f64;
+    char => encode_char, decode_char;
+}
+
+impl_primitive! {
+    usize => encode_usize, decode_usize;
+    isize => encode_isize, decode_isize;
+}
+
+impl Encode for () {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, _encoder: &mut E) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Decode for () {
+    #[inline(always)]
+    fn decode<D: Decoder>(_decoder: &mut D) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: ?Sized> Encode for PhantomData<T> {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, _encoder: &mut E) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: ?Sized> Decode for PhantomData<T> {
+    #[inline(always)]
+    fn decode<D: Decoder>(_decoder: &mut D) -> Result<PhantomData<T>> {
+        Ok(PhantomData)
+    }
+}
+
+impl Encode for str {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        encoder.encode_str(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Encode for String {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        encoder.encode_str(self)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Decode for String {
+    #[inline(always)]
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<String> {
+        decoder.decode_string()
+    }
+}
+
+impl<'a, T: Encode + ?Sized> Encode for &'a T {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        (**self).encode(encoder)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        (**self).encode(encoder)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Decode> Decode for Box<T> {
+    #[inline(always)]
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Box<T>> {
+        T::decode(decoder).map(Box::new)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    #[inline]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        match *self {
+            None => encoder.encode_u8(0),
+            Some(ref value) => {
+                encoder.encode_u8(1)?;
+                value.encode(encoder)
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    #[inline]
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Option<T>> {
+        match decoder.decode_u8()? {
+            0 => Ok(None),
+            1 => T::decode(decoder).map(Some),
+            tag => Err(ErrorKind::InvalidTagEncoding(tag as usize).into()),
+        }
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    #[inline]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        encoder.encode_len(self.len())?;
+        for value in self {
+            value.encode(encoder)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Encode> Encode for Vec<T> {
+    #[inline(always)]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        self[..].encode(encoder)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Decode> Decode for Vec<T> {
+    #[inline]
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<Vec<T>> {
+        let len = decoder.decode_len()?;
+        // the length is untrusted, let the vector grow as values are actually read
+        let mut values = Vec::with_capacity(len.min(4096));
+        for _ in 0..len {
+            values.push(T::decode(decoder)?);
+        }
+        Ok(values)
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    #[inline]
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+        for value in self {
+            value.encode(encoder)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Decode + Default + Copy, const N: usize> Decode for [T; N] {
+    #[inline]
+    fn decode<D: Decoder>(decoder: &mut D) -> Result<[T; N]> {
+        let mut values = [T::default(); N];
+        for value in values.iter_mut() {
+            *value = T::decode(decoder)?;
+        }
+        Ok(values)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $index:tt)+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[inline]
+            fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+                $(self.$index.encode(encoder)?;)+
+                Ok(())
+            }
+        }
+
+        impl<$($name: Decode),+> Decode for ($($name,)+) {
+            #[inline]
+            fn decode<D: Decoder>(decoder: &mut D) -> Result<($($name,)+)> {
+                Ok(($($name::decode(decoder)?,)+))
+            }
+        }
+    }
+}
+
+impl_tuple! { T0 0 }
+impl_tuple! { T0 0 T1 1 }
+impl_tuple! { T0 0 T1 1 T2 2 }
+impl_tuple! { T0 0 T1 1 T2 2 T3 3 }
+impl_tuple! { T0 0 T1 1 T2 2 T3 3 T4 4 }
+impl_tuple! { T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 }
+impl_tuple! { T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 }
+impl_tuple! { T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 }
@@ -0,0 +1,175 @@
+//! Serialization of trait objects, enabled by the `erased-serde` feature.
+//!
+//! `serialize_erased` writes any `&dyn erased_serde::Serialize`. For values
+//! whose concrete type is only known at runtime, `serialize_tagged` prefixes
+//! the value with a type tag, and a `Registry` maps each tag back to the
+//! constructor that decodes it:
+//!
+//! ```
+//! # extern crate bincode2;
+//! # extern crate erased_serde;
+//! # #[macro_use] extern crate serde_derive;
+//! use bincode2::erased::{serialize_tagged, Registry};
+//!
+//! trait Message: erased_serde::Serialize {
+//!     fn name(&self) -> &str;
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Ping(u32);
+//!
+//! impl Message for Ping {
+//!     fn name(&self) -> &str { "ping" }
+//! }
+//!
+//! # fn main() {
+//! let mut registry = Registry::<dyn Message>::new();
+//! registry.register("ping", |d| Ok(Box::new(erased_serde::deserialize::<Ping>(d)?)));
+//!
+//! let config = bincode2::config();
+//! let mut bytes = Vec::new();
+//! serialize_tagged("ping", &Ping(7), &mut bytes, &config).unwrap();
+//!
+//! let message = registry.deserialize(&bytes, &config).unwrap();
+//! assert_eq!(message.name(), "ping");
+//! # }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use core::fmt;
+use core2::io::{Read, Write};
+
+use serde::de::{DeserializeSeed, Error as DeError, SeqAccess, Visitor};
+
+use config::Config;
+use error::Result;
+
+/// Decodes one registered type from an erased deserializer.
+pub type Constructor<T> =
+    for<'de> fn(&mut dyn erased_serde::Deserializer<'de>) -> erased_serde::Result<Box<T>>;
+
+/// Serializes a trait object into `writer` using `config`.
+pub fn serialize_erased<W: Write>(
+    value: &dyn erased_serde::Serialize,
+    writer: W,
+    config: &Config,
+) -> Result<()> {
+    config.serialize_into(writer, value)
+}
+
+/// Serializes a trait object preceded by `tag`, in the layout `Registry`
+/// expects: the tag as a string, then the value.
+pub fn serialize_tagged<W: Write>(
+    tag: &str,
+    value: &dyn erased_serde::Serialize,
+    writer: W,
+    config: &Config,
+) -> Result<()> {
+    config.serialize_into(writer, &(tag, value))
+}
+
+/// Constructors for the types that may follow a tag written by
+/// `serialize_tagged`, all producing a `Box<T>`.
+pub struct Registry<T: ?Sized> {
+    constructors: BTreeMap<String, Constructor<T>>,
+}
+
+impl<T: ?Sized> Registry<T> {
+    /// Creates an empty registry.
+    pub fn new() -> Registry<T> {
+        Registry {
+            constructors: BTreeMap::new(),
+        }
+    }
+
+    /// Registers the constructor for `tag`. Returns false, leaving the
+    /// registry unchanged, if the tag is already taken.
+    pub fn register(&mut self, tag: &str, constructor: Constructor<T>) -> bool {
+        if self.constructors.contains_key(tag) {
+            return false;
+        }
+        self.constructors.insert(tag.into(), constructor);
+        true
+    }
+
+    /// Returns whether a constructor is registered for `tag`.
+    pub fn contains(&self, tag: &str) -> bool {
+        self.constructors.contains_key(tag)
+    }
+
+    /// Deserializes a tagged value from a slice of bytes. An unregistered
+    /// tag is reported as a `Custom` error.
+    pub fn deserialize(&self, bytes: &[u8], config: &Config) -> Result<Box<T>> {
+        config.deserialize_seed(TaggedSeed { registry: self }, bytes)
+    }
+
+    /// Deserializes a tagged value directly from a `Read`er.
+    ///
+    /// If this returns an `Error`, `reader` may be in an invalid state.
+    pub fn deserialize_from<R: Read>(&self, reader: R, config: &Config) -> Result<Box<T>> {
+        config.deserialize_from_seed(TaggedSeed { registry: self }, reader)
+    }
+}
+
+impl<T: ?Sized> Default for Registry<T> {
+    fn default() -> Registry<T> {
+        Registry::new()
+    }
+}
+
+struct TaggedSeed<'r, T: ?Sized + 'r> {
+    registry: &'r Registry<T>,
+}
+
+impl<'de, 'r, T: ?Sized> DeserializeSeed<'de> for TaggedSeed<'r, T> {
+    type Value = Box<T>;
+
+    fn deserialize<D>(self, deserializer: D) -> ::core::result::Result<Box<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de, 'r, T: ?Sized> Visitor<'de> for TaggedSeed<'r, T> {
+    type Value = Box<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a type tag followed by a value")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> ::core::result::Result<Box<T>, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let tag: String = match seq.next_element()? {
+            Some(tag) => tag,
+            None => return Err(A::Error::invalid_length(0, &self)),
+        };
+        let constructor = match self.registry.constructors.get(&tag) {
+            Some(constructor) => *constructor,
+            None => return Err(A::Error::custom(format_args!("unknown type tag `{}`", tag))),
+        };
+        match seq.next_element_seed(ConstructorSeed(constructor))? {
+            Some(value) => Ok(value),
+            None => Err(A::Error::invalid_length(1, &self)),
+        }
+    }
+}
+
+struct ConstructorSeed<T: ?Sized>(Constructor<T>);
+
+impl<'de, T: ?Sized> DeserializeSeed<'de> for ConstructorSeed<T> {
+    type Value = Box<T>;
+
+    fn deserialize<D>(self, deserializer: D) -> ::core::result::Result<Box<T>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let mut erased = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0)(&mut erased).map_err(D::Error::custom)
+    }
+}
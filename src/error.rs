@@ -4,9 +4,14 @@ use core::str::Utf8Error;
 
 use core::fmt;
 
+#[cfg(all(feature = "alloc", not(feature = "compact-error")))]
 use alloc::boxed::Box;
+#[cfg(all(feature = "alloc", not(feature = "compact-error")))]
 use alloc::string::String;
+#[cfg(all(feature = "alloc", not(feature = "compact-error")))]
 use alloc::string::ToString;
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+use core::ops::Deref;
 
 use serde;
 
@@ -16,8 +21,35 @@ use thiserror_core2::Error;
 pub type Result<T> = ::core::result::Result<T, Error>;
 
 /// An error that can be produced during (de)serializing.
+#[cfg(all(feature = "alloc", not(feature = "compact-error")))]
 pub type Error = Box<ErrorKind>;
 
+/// An error that can be produced during (de)serializing. With the `compact-error`
+/// feature, or without `alloc`, it is not boxed and creating one allocates nothing.
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+pub type Error = ErrorKind;
+
+/// The `ErrorKind` of an `Error`, whichever form it has.
+#[cfg(feature = "testutil")]
+#[inline(always)]
+pub(crate) fn kind(err: &Error) -> &ErrorKind {
+    err
+}
+
+/// Takes the `ErrorKind` out of an `Error`, whichever form it has.
+#[cfg(all(feature = "std", not(feature = "compact-error")))]
+#[inline(always)]
+pub(crate) fn into_kind(err: Error) -> ErrorKind {
+    *err
+}
+
+/// Takes the `ErrorKind` out of an `Error`, whichever form it has.
+#[cfg(all(feature = "std", feature = "compact-error"))]
+#[inline(always)]
+pub(crate) fn into_kind(err: Error) -> ErrorKind {
+    err
+}
+
 /// The kind of error that can be produced during a serialization or deserialization.
 #[derive(Error,Debug)]
 pub enum ErrorKind {
@@ -39,13 +71,118 @@ pub enum ErrorKind {
     DeserializeAnyNotSupported,
     /// If (de)serializing a message takes more than the provided size limit, this
     /// error is returned.
-    SizeLimit,
+    ///
+    /// The message is not measured or read further, so `attempted` is the number
+    /// of bytes counted so far, counting the write or read that went over, and the
+    /// whole message may be larger. Values that don't fit in a `usize` on this
+    /// target fail with `usize::MAX` as the limit.
+    SizeLimit {
+        /// The limit, in bytes. For a budget shared with `Config::with_shared_limit`,
+        /// what the call had drawn from it plus what was left of it.
+        limit: u64,
+        /// The number of bytes that were needed when the limit was reached.
+        attempted: u64,
+    },
     /// If serializing a string/vec/array requires more bytes to represent the size than the config allows.
     SizeTypeLimit,
     /// Bincode can not encode sequences of unknown length (like iterators).
     SequenceMustHaveLength,
+    /// Returned when serializing a string holding a NUL byte with `StringMode::NulTerminated`
+    /// or `StringMode::FixedWidth`, as it could not be read back.
+    NulInString,
+    /// Returned when serializing a string longer than the width of `StringMode::FixedWidth`.
+    /// Holds the length of the string in bytes.
+    StringTooLong(usize),
+    /// Returned if the deserializer runs out of input before the NUL byte ending a string
+    /// written with `StringMode::NulTerminated`.
+    MissingNulTerminator,
+    /// Returned if the padding of a string written with `StringMode::FixedWidth` holds
+    /// something other than NUL bytes.
+    InvalidStringPadding,
+    /// Returned by `ScratchReader` when a string or byte slice does not fit in what is
+    /// left of its scratch buffer.
+    ScratchExhausted,
+    /// Returned when the token given to `Config::with_cancel_token` asks a deserialization
+    /// to stop.
+    Cancelled,
     /// A custom error message from Serde.
+    #[cfg(all(feature = "alloc", not(feature = "compact-error")))]
     Custom(String),
+    /// A custom error message from Serde, truncated to what an `ErrorMessage` holds.
+    #[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+    Custom(ErrorMessage),
+}
+
+/// The number of bytes of a custom message an `ErrorMessage` keeps.
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+pub const ERROR_MESSAGE_CAPACITY: usize = 64;
+
+/// The message of a custom error held inline, for errors that can't allocate.
+///
+/// Messages longer than `ERROR_MESSAGE_CAPACITY` bytes are cut at the last
+/// character that fits. It derefs to the `str` it holds.
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+#[derive(Clone, Copy)]
+pub struct ErrorMessage {
+    bytes: [u8; ERROR_MESSAGE_CAPACITY],
+    len: u8,
+}
+
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+impl ErrorMessage {
+    /// The message `msg` formats to, truncated if it doesn't fit.
+    pub fn new<T: fmt::Display + ?Sized>(msg: &T) -> ErrorMessage {
+        let mut message = ErrorMessage {
+            bytes: [0; ERROR_MESSAGE_CAPACITY],
+            len: 0,
+        };
+        // Writing never fails, it stops at the capacity instead.
+        let _ = fmt::write(&mut message, format_args!("{}", msg));
+        message
+    }
+
+    /// The message.
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are copied in by `write_str`.
+        unsafe { ::core::str::from_utf8_unchecked(&self.bytes[..self.len as usize]) }
+    }
+}
+
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+impl fmt::Write for ErrorMessage {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.len as usize;
+        let mut end = s.len().min(ERROR_MESSAGE_CAPACITY - start);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.bytes[start..start + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end as u8;
+        Ok(())
+    }
+}
+
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+impl Deref for ErrorMessage {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+impl fmt::Debug for ErrorMessage {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), fmt)
+    }
+}
+
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str(self.as_str())
+    }
 }
 
 
@@ -91,6 +228,34 @@ impl StdError for ErrorKind {
 }
 */
 
+impl ErrorKind {
+    /// The `SizeLimit` error for `value`, which does not fit in a `usize`.
+    pub(crate) fn not_usize(value: u64) -> ErrorKind {
+        ErrorKind::SizeLimit {
+            limit: usize::MAX as u64,
+            attempted: value,
+        }
+    }
+
+    /// The `SizeLimit` error for `value`, which does not fit in an `isize`.
+    pub(crate) fn not_isize(value: i64) -> ErrorKind {
+        ErrorKind::SizeLimit {
+            limit: isize::MAX as u64,
+            attempted: value.unsigned_abs(),
+        }
+    }
+
+    /// Returns true if the reader or writer failed with `io::ErrorKind::WouldBlock`,
+    /// meaning the call can be retried once it is ready. Reading can only be
+    /// retried from where it stopped with a `ResumableReader`.
+    pub fn is_would_block(&self) -> bool {
+        match *self {
+            ErrorKind::Io(ref err) => err.kind() == io::ErrorKind::WouldBlock,
+            _ => false,
+        }
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         ErrorKind::Io(err).into()
@@ -110,7 +275,21 @@ impl fmt::Display for ErrorKind {
                 write!(fmt, "{}, found {}", self, tag)
             }
             ErrorKind::SequenceMustHaveLength => write!(fmt, "{}", self),
-            ErrorKind::SizeLimit => write!(fmt, "{}", self),
+            ErrorKind::NulInString => fmt.write_str("string holds a NUL byte"),
+            ErrorKind::StringTooLong(len) => {
+                write!(fmt, "string of {} bytes is wider than its fixed width", len)
+            }
+            ErrorKind::MissingNulTerminator => fmt.write_str("string is not NUL-terminated"),
+            ErrorKind::InvalidStringPadding => {
+                fmt.write_str("padding of fixed-width string is not all NUL bytes")
+            }
+            ErrorKind::ScratchExhausted => fmt.write_str("scratch buffer is exhausted"),
+            ErrorKind::Cancelled => fmt.write_str("deserialization was cancelled"),
+            ErrorKind::SizeLimit { limit, attempted } => write!(
+                fmt,
+                "message of at least {} bytes exceeds the size limit of {} bytes",
+                attempted, limit
+            ),
             ErrorKind::SizeTypeLimit => write!(fmt, "{}", self),
             ErrorKind::DeserializeAnyNotSupported => write!(
                 fmt,
@@ -122,13 +301,25 @@ impl fmt::Display for ErrorKind {
 }
 
 impl serde::de::Error for Error {
+    #[cfg(all(feature = "alloc", not(feature = "compact-error")))]
     fn custom<T: fmt::Display>(desc: T) -> Error {
         ErrorKind::Custom(desc.to_string()).into()
     }
+
+    #[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+    fn custom<T: fmt::Display>(desc: T) -> Error {
+        ErrorKind::Custom(ErrorMessage::new(&desc))
+    }
 }
 
 impl serde::ser::Error for Error {
+    #[cfg(all(feature = "alloc", not(feature = "compact-error")))]
     fn custom<T: fmt::Display>(msg: T) -> Self {
         ErrorKind::Custom(msg.to_string()).into()
     }
+
+    #[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ErrorKind::Custom(ErrorMessage::new(&msg))
+    }
 }
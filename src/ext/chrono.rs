@@ -0,0 +1,27 @@
+//! `DateTime<Utc>`s as `i64` nanoseconds since the Unix epoch, for use with
+//! `#[serde(with = "bincode2::ext::chrono")]`.
+
+use chrono::{DateTime, TimeZone, Utc};
+use serde::ser::Error as SerError;
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// Writes `time` as nanoseconds since the Unix epoch.
+pub fn serialize<S>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match time.timestamp_nanos_opt() {
+        Some(nanos) => serializer.serialize_i64(nanos),
+        None => Err(S::Error::custom(
+            "timestamp out of the range of i64 nanoseconds",
+        )),
+    }
+}
+
+/// Reads a `DateTime<Utc>` written by `serialize`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    i64::deserialize(deserializer).map(|nanos| Utc.timestamp_nanos(nanos))
+}
@@ -0,0 +1,29 @@
+//! Compact encodings for types from other crates, each enabled by the feature
+//! named after the crate.
+//!
+//! Serde's own encodings of these types are made for self-describing formats: a
+//! `Uuid` is written as a length-prefixed byte string, and timestamps as strings or
+//! structs. The modules here are meant for `#[serde(with = "...")]` and write
+//! them in a fixed-size form instead, following the byte order of the config:
+//!
+//! - `ext::uuid` writes a `uuid::Uuid` as its 16 bytes, without a length.
+//! - `ext::time` writes a `time::OffsetDateTime` as `i64` nanoseconds since the
+//!   Unix epoch.
+//! - `ext::chrono` writes a `chrono::DateTime<Utc>` the same way.
+//! - `ext::num_bigint` writes a `num_bigint::BigInt` as an `i8` sign followed by
+//!   the `u32` digits of its magnitude, least significant first, with the array
+//!   length of the config, and `ext::num_bigint::unsigned` writes a `BigUint` as
+//!   its digits alone. Unlike the others their size depends on the value.
+//!
+//! Timestamps outside of the range of `i64` nanoseconds, 1677 to 2262, cannot
+//! be serialized. The offset of an `OffsetDateTime` is not written, so it is
+//! read back in UTC.
+
+#[cfg(feature = "chrono")]
+pub mod chrono;
+#[cfg(feature = "num-bigint")]
+pub mod num_bigint;
+#[cfg(feature = "time")]
+pub mod time;
+#[cfg(feature = "uuid")]
+pub mod uuid;
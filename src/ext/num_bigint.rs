@@ -0,0 +1,111 @@
+//! `BigInt`s as a sign and the `u32` digits of their magnitude, for use with
+//! `#[serde(with = "bincode2::ext::num_bigint")]`, and `BigUint`s as their
+//! digits alone, with `#[serde(with = "bincode2::ext::num_bigint::unsigned")]`.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use num_bigint::{BigInt, BigUint, Sign};
+use serde::de::{Error as DeError, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// The most digits reserved before they are read, so that a corrupt length can't
+/// make the reader allocate more than the input holds.
+const MAX_PREALLOCATED: usize = 4096;
+
+/// Writes the sign of `value` as an `i8`, -1, 0 or 1, then its magnitude as
+/// `unsigned::serialize` does.
+pub fn serialize<S>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let sign: i8 = match value.sign() {
+        Sign::Minus => -1,
+        Sign::NoSign => 0,
+        Sign::Plus => 1,
+    };
+    let mut tuple = serializer.serialize_tuple(2)?;
+    tuple.serialize_element(&sign)?;
+    tuple.serialize_element(&Magnitude(value.magnitude()))?;
+    tuple.end()
+}
+
+/// Reads a `BigInt` written by `serialize`. The sign must be 0 for zero and only
+/// for zero.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<BigInt, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let (sign, magnitude) = <(i8, Digits)>::deserialize(deserializer)?;
+    let sign = match (sign, magnitude.0 == BigUint::default()) {
+        (0, true) => Sign::NoSign,
+        (1, false) => Sign::Plus,
+        (-1, false) => Sign::Minus,
+        _ => return Err(D::Error::custom("BigInt sign does not match its magnitude")),
+    };
+    Ok(BigInt::from_biguint(sign, magnitude.0))
+}
+
+/// `BigUint`s as the `u32` digits of their value, for use with
+/// `#[serde(with = "bincode2::ext::num_bigint::unsigned")]`.
+pub mod unsigned {
+    use num_bigint::BigUint;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Digits, Magnitude};
+
+    /// Writes the `u32` digits of `value` as a sequence, least significant first, so
+    /// that their number is written with the array length of the config and each
+    /// digit in its byte order. Zero has no digits.
+    pub fn serialize<S>(value: &BigUint, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Magnitude(value).serialize(serializer)
+    }
+
+    /// Reads a `BigUint` written by `serialize`. The digits count against the limit
+    /// of the config as they are read.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BigUint, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Digits::deserialize(deserializer).map(|digits| digits.0)
+    }
+}
+
+struct Magnitude<'a>(&'a BigUint);
+
+impl<'a> Serialize for Magnitude<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter_u32_digits())
+    }
+}
+
+struct Digits(BigUint);
+
+impl<'de> Deserialize<'de> for Digits {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Digits, D::Error> {
+        deserializer.deserialize_seq(DigitsVisitor)
+    }
+}
+
+struct DigitsVisitor;
+
+impl<'de> Visitor<'de> for DigitsVisitor {
+    type Value = Digits;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("the u32 digits of an integer")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Digits, A::Error> {
+        let len = seq.size_hint().unwrap_or(0).min(MAX_PREALLOCATED);
+        let mut digits = Vec::with_capacity(len);
+        while let Some(digit) = seq.next_element::<u32>()? {
+            digits.push(digit);
+        }
+        Ok(Digits(BigUint::new(digits)))
+    }
+}
@@ -0,0 +1,31 @@
+//! `OffsetDateTime`s as `i64` nanoseconds since the Unix epoch, for use with
+//! `#[serde(with = "bincode2::ext::time")]`.
+
+use core::convert::TryFrom;
+
+use serde::de::Error as DeError;
+use serde::ser::Error as SerError;
+use serde::{Deserialize, Deserializer, Serializer};
+use time::OffsetDateTime;
+
+/// Writes `time` as nanoseconds since the Unix epoch. The offset is not written.
+pub fn serialize<S>(time: &OffsetDateTime, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match i64::try_from(time.unix_timestamp_nanos()) {
+        Ok(nanos) => serializer.serialize_i64(nanos),
+        Err(_) => Err(S::Error::custom(
+            "timestamp out of the range of i64 nanoseconds",
+        )),
+    }
+}
+
+/// Reads an `OffsetDateTime` written by `serialize`, in UTC.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<OffsetDateTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let nanos = i64::deserialize(deserializer)?;
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(nanos)).map_err(D::Error::custom)
+}
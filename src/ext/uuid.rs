@@ -0,0 +1,20 @@
+//! `Uuid`s as their 16 bytes, for use with `#[serde(with = "bincode2::ext::uuid")]`.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+/// Writes the 16 bytes of `uuid`, without a length prefix.
+pub fn serialize<S>(uuid: &Uuid, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    uuid.as_bytes().serialize(serializer)
+}
+
+/// Reads a `Uuid` written by `serialize`.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Uuid, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <[u8; 16]>::deserialize(deserializer).map(Uuid::from_bytes)
+}
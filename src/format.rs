@@ -0,0 +1,168 @@
+//! What the wire format promises, and the bytes that pin it down.
+//!
+//! The encoding of every value under every `Config` is part of the format: two
+//! builds of bincode2 with the same `FormatVersion` write the same bytes for the
+//! same value and config, and read each other's output. A change to any of those
+//! bytes is a new `FormatVersion` and a major release.
+//!
+//! `FIXTURES` holds the encodings of a set of values, covering the primitives,
+//! the compound types and each option of `FormatVersion::V1` that changes the
+//! bytes written. The set is frozen: `FORMAT_HASH` is derived from it, so that a
+//! change to the format cannot go unnoticed by the tests or by anyone comparing
+//! the hash between versions.
+//!
+//! Options added since only change the bytes written when they are set, which
+//! leaves the format of existing configs as it was. Their fixtures go in
+//! `ADDITIONS`, which the test suite checks as it checks `FIXTURES`, but which
+//! is not part of the hash.
+
+/// A version of the wire format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FormatVersion {
+    /// The format written by bincode2 2.x, pinned by `FIXTURES`.
+    V1,
+}
+
+impl FormatVersion {
+    /// The version this build writes and reads.
+    pub const CURRENT: FormatVersion = FormatVersion::V1;
+}
+
+/// The bytes a value is serialized to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fixture {
+    /// The options set on the default config, and the value, separated by a `/`.
+    pub name: &'static str,
+    /// The serialized value.
+    pub bytes: &'static [u8],
+}
+
+macro_rules! fixtures {
+    ($($name:expr => [$($byte:expr),*],)*) => {
+        &[$(Fixture { name: $name, bytes: &[$($byte),*] },)*]
+    };
+}
+
+/// The fixtures of `FormatVersion::V1`, which no fixture is added to or removed from.
+///
+/// Values named after a type are a representative value of it; the others are
+/// `"hi"` for strings, `[1, 2]` as `u16` for sequences, `{1: true}` as `u8` to
+/// `bool` for maps, `Point { x: 1i16, y: -1i16 }` for structs, and the variants
+/// of `enum E { A, B(u8), C(u8, u8), D { x: u8 } }`. Custom lengths are a single
+/// byte and the variant index map adds 100 to indices, and the sequences are 255
+/// `()`s for `promote_u8` and two copies of `[1, 2, 3]` as `u32` for
+/// `dedup_subtrees`.
+pub const FIXTURES: &[Fixture] = fixtures! {
+    "default/bool" => [1],
+    "default/u8" => [0xab],
+    "default/u16" => [0x34, 0x12],
+    "default/u32" => [0x78, 0x56, 0x34, 0x12],
+    "default/u64" => [0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01],
+    "default/u128" => [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    "default/i8" => [0xfe],
+    "default/i16" => [0xfe, 0xff],
+    "default/i32" => [0xfe, 0xff, 0xff, 0xff],
+    "default/i64" => [0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+    "default/i128" => [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff
+    ],
+    "default/f32" => [0x00, 0x00, 0xc0, 0x3f],
+    "default/f64" => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xd0, 0xbf],
+    "default/char" => [0xc3, 0xa9],
+    "default/str" => [2, 0, 0, 0, 0, 0, 0, 0, b'h', b'i'],
+    "default/bytes" => [3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3],
+    "default/none" => [0],
+    "default/some" => [1, 7],
+    "default/unit" => [],
+    "default/tuple" => [1, 2, 0],
+    "default/seq" => [2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0],
+    "default/map" => [1, 0, 0, 0, 0, 0, 0, 0, 1, 1],
+    "default/struct" => [1, 0, 0xff, 0xff],
+    "default/unit_variant" => [0, 0, 0, 0],
+    "default/newtype_variant" => [1, 0, 0, 0, 5],
+    "default/tuple_variant" => [2, 0, 0, 0, 1, 2],
+    "default/struct_variant" => [3, 0, 0, 0, 9],
+    "big_endian/u32" => [0x12, 0x34, 0x56, 0x78],
+    "big_endian/f64" => [0xbf, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    "big_endian/str" => [0, 0, 0, 0, 0, 0, 0, 2, b'h', b'i'],
+    "big_endian/unit_variant" => [0, 0, 0, 0],
+    "big_endian/newtype_variant" => [0, 0, 0, 1, 5],
+    "string_length_u8/str" => [2, b'h', b'i'],
+    "string_length_u16/str" => [2, 0, b'h', b'i'],
+    "string_length_u32/str" => [2, 0, 0, 0, b'h', b'i'],
+    "array_length_u8/seq" => [2, 1, 0, 2, 0],
+    "array_length_u16/bytes" => [3, 0, 1, 2, 3],
+    "array_length_u32/map" => [1, 0, 0, 0, 1, 1],
+    "usize_u32/str" => [2, 0, 0, 0, b'h', b'i'],
+    "usize_varint/str" => [2, b'h', b'i'],
+    "usize_varint/seq" => [2, 1, 0, 2, 0],
+    "nul_terminated/str" => [b'h', b'i', 0],
+    "fixed_width_4/str" => [b'h', b'i', 0, 0],
+    "f32_as_f64/f32" => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf8, 0x3f],
+    "f64_as_f32/f64" => [0x00, 0x00, 0x80, 0xbe],
+    "fixed32_16/f64" => [0x00, 0x80, 0x01, 0x00],
+    "fixed64_8/f64" => [0xc0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+    "decimal_2/f64" => [0x7d, 0, 0, 0, 0, 0, 0, 0],
+    "tagged_fields/struct" => [
+        2, 0, 0, 0, 0, 0, 0, 0,
+        0x39, 0x84, 0x62, 0xe8, 1, 0,
+        0xa1, 0x77, 0x62, 0xe0, 0xff, 0xff
+    ],
+    "allow_flatten/map" => [1, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 1],
+    "sort_map_keys/map" => [2, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x0b, 0xff, 0x0a],
+    "custom_length/str" => [2, b'h', b'i'],
+    "custom_length/seq" => [2, 1, 0, 2, 0],
+    "promote_u8/seq" => [255, 255, 0, 0, 0, 0, 0, 0, 0],
+    "variant_index_map/newtype_variant" => [101, 0, 0, 0, 5],
+    "dedup_subtrees/seq" => [
+        0, 2, 0, 0, 0, 0, 0, 0, 0,
+        0, 3, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0,
+        1, 20, 0, 0, 0, 0, 0, 0, 0
+    ],
+};
+
+/// The fixtures of the options added to the format since `FormatVersion::V1`,
+/// named and valued as `FIXTURES` are.
+pub const ADDITIONS: &[Fixture] = fixtures! {
+    "variant_lengths/unit_variant" => [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    "variant_lengths/newtype_variant" => [1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 5],
+    "aligned_4/tuple" => [1, 0, 2, 0],
+    "allow_untagged/newtype_variant" => [20, 1, 0, 0, 0, 0, 0, 0, 0, 4, 1, 0, 0, 0, 2, 5],
+    "int_varint/u16" => [251, 0x34, 0x12],
+    "int_varint/u32" => [252, 0x78, 0x56, 0x34, 0x12],
+    "int_varint/u128" => [254, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0],
+    "int_varint/i64" => [3],
+    "int_varint/str" => [2, b'h', b'i'],
+    "int_varint/newtype_variant" => [1, 5],
+};
+
+/// A hash of `FIXTURES`, which changes whenever the format does. Adding options,
+/// and their fixtures to `ADDITIONS`, leaves it as it is.
+///
+/// This is the 64 bit FNV-1a hash of each fixture's name, a zero byte, the
+/// number of bytes as a little endian `u64`, and the bytes, in order.
+pub const FORMAT_HASH: u64 = hash(FIXTURES);
+
+const fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+const fn hash(fixtures: &[Fixture]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < fixtures.len() {
+        hash = fnv1a(hash, fixtures[i].name.as_bytes());
+        hash = fnv1a(hash, &[0]);
+        hash = fnv1a(hash, &(fixtures[i].bytes.len() as u64).to_le_bytes());
+        hash = fnv1a(hash, fixtures[i].bytes);
+        i += 1;
+    }
+    hash
+}
@@ -0,0 +1,143 @@
+//! String interning during deserialization, see `Config::deserialize_with_interner`.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core2::io;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+use serde;
+
+use de::read::BincodeRead;
+use error::Result;
+
+/// Deduplicates the strings read by `Config::deserialize_with_interner`.
+///
+/// Every string borrowed by the value being deserialized is passed to `intern`, and
+/// the value borrows the returned string instead, so equal strings share one
+/// allocation owned by the interner. Interners usually keep their strings in an arena
+/// that lives for `'i`, and a map from the strings seen so far into it.
+pub trait StringInterner<'i> {
+    /// Returns a string equal to `s` that lives for `'i`.
+    fn intern(&mut self, s: &str) -> &'i str;
+}
+
+/// Hands borrowed strings to an interner, and everything else to the inner reader.
+pub(crate) struct InterningReader<'a, R, I: 'a> {
+    reader: R,
+    interner: &'a mut I,
+}
+
+impl<'a, R, I> InterningReader<'a, R, I> {
+    pub(crate) fn new(reader: R, interner: &'a mut I) -> InterningReader<'a, R, I> {
+        InterningReader { reader, interner }
+    }
+}
+
+impl<'a, R: io::Read, I> io::Read for InterningReader<'a, R, I> {
+    #[inline(always)]
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(out)
+    }
+
+    #[inline(always)]
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(out)
+    }
+}
+
+impl<'a, 'storage, 'i, R, I> BincodeRead<'i> for InterningReader<'a, R, I>
+where
+    R: BincodeRead<'storage>,
+    I: StringInterner<'i>,
+{
+    fn forward_read_str<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'i>,
+    {
+        let interned = self.reader.forward_read_str(
+            length,
+            Intern {
+                interner: &mut *self.interner,
+                _lifetimes: PhantomData,
+            },
+        )?;
+        visitor.visit_borrowed_str(interned)
+    }
+
+    #[cfg(feature = "alloc")]
+    fn get_byte_buffer(&mut self, length: usize) -> Result<Vec<u8>> {
+        self.reader.get_byte_buffer(length)
+    }
+
+    fn forward_read_bytes<V>(&mut self, length: usize, visitor: V) -> Result<V::Value>
+    where
+        V: serde::de::Visitor<'i>,
+    {
+        self.reader.forward_read_bytes(
+            length,
+            TransientBytes {
+                visitor,
+                _lifetimes: PhantomData,
+            },
+        )
+    }
+
+    fn skip(&mut self, length: usize) -> Result<()> {
+        self.reader.skip(length)
+    }
+
+    fn at_end(&mut self) -> Result<bool> {
+        self.reader.at_end()
+    }
+
+    fn seekable_position(&self) -> Option<u64> {
+        self.reader.seekable_position()
+    }
+
+    fn seek(&mut self, position: u64) -> Result<()> {
+        self.reader.seek(position)
+    }
+}
+
+/// Interns the string read by the inner reader.
+struct Intern<'a, 'storage, 'i, I: 'a> {
+    interner: &'a mut I,
+    _lifetimes: PhantomData<(&'storage (), &'i ())>,
+}
+
+impl<'a, 'storage, 'i, I: StringInterner<'i>> serde::de::Visitor<'storage>
+    for Intern<'a, 'storage, 'i, I>
+{
+    type Value = &'i str;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, v: &str) -> ::core::result::Result<&'i str, E> {
+        Ok(self.interner.intern(v))
+    }
+}
+
+/// Passes bytes read by the inner reader on without letting the value borrow them,
+/// as they do not live for the interner's lifetime.
+struct TransientBytes<'storage, 'i, V> {
+    visitor: V,
+    _lifetimes: PhantomData<(&'storage (), &'i ())>,
+}
+
+impl<'storage, 'i, V: serde::de::Visitor<'i>> serde::de::Visitor<'storage>
+    for TransientBytes<'storage, 'i, V>
+{
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> ::core::result::Result<V::Value, E> {
+        self.visitor.visit_bytes(v)
+    }
+}
@@ -2,14 +2,21 @@ use serde;
 use core2::io::{Read, Write};
 use core::marker::PhantomData;
 
-use config::{Options, OptionsExt};
+use config::{Config, CustomLength, LengthOption, Options, OptionsExt, Overflow, StringMode};
 use de::read::BincodeRead;
+use encode::{Decode, Encode};
+#[cfg(feature = "std")]
+use pool::{Pool, PooledBytes, Scratch};
 use core::convert::TryFrom;
 use core::convert::TryInto;
 use {ErrorKind, Result};
 
-use alloc::boxed::Box;
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+use core::sync::atomic::{AtomicU64, Ordering};
 
 #[derive(Clone)]
 struct CountSize<L: SizeLimit> {
@@ -29,10 +36,14 @@ where
         serialized_size(value, &mut options)?;
     }
 
+    let _span = ::metrics::span("serialize");
     let mut serializer = ::ser::Serializer::<_, O>::new(writer, options);
-    serde::Serialize::serialize(value, &mut serializer)
+    let result = serializer.serialize_typed(value);
+    serializer.stats.finish();
+    result
 }
 
+#[cfg(feature = "alloc")]
 pub(crate) fn serialize<T: ?Sized, O>(value: &T, mut options: O) -> Result<Vec<u8>>
 where
     T: serde::Serialize,
@@ -47,6 +58,46 @@ where
     Ok(writer)
 }
 
+/// Like `serialize`, into `Bytes`.
+#[cfg(feature = "bytes")]
+pub(crate) fn serialize_to_bytes<T: ?Sized, O>(value: &T, mut options: O) -> Result<::bytes::Bytes>
+where
+    T: serde::Serialize,
+    O: Options,
+{
+    let actual_size = serialized_size(value, &mut options)?;
+    let mut buf = ::bytes::BytesMut::with_capacity(actual_size as usize);
+    let writer = ::bytes_io::BufMutWriter::new(&mut buf);
+    serialize_into(writer, value, options.with_no_limit())?;
+    Ok(buf.freeze())
+}
+
+/// Like `serialize`, taking the output buffer and any scratch buffers from `pool`.
+#[cfg(feature = "std")]
+pub(crate) fn serialize_pooled<T: ?Sized, O>(
+    value: &T,
+    mut options: O,
+    pool: &Pool,
+) -> Result<PooledBytes>
+where
+    T: serde::Serialize,
+    O: Options,
+{
+    let mut bytes = pool.checkout();
+    let actual_size = serialized_size(value, &mut options)?;
+    bytes.buffer().reserve(actual_size as usize);
+
+    {
+        let _span = ::metrics::span("serialize");
+        let mut serializer = ::ser::Serializer::new(bytes.buffer(), options.with_no_limit());
+        serializer.scratch = Scratch::pooled(pool);
+        let result = serializer.serialize_typed(value);
+        serializer.stats.finish();
+        result?;
+    }
+    Ok(bytes)
+}
+
 impl<L: SizeLimit> SizeLimit for CountSize<L> {
     fn add(&mut self, c: u64) -> Result<()> {
         self.other_limit.add(c)?;
@@ -63,21 +114,135 @@ pub(crate) fn serialized_size<T: ?Sized, O: Options>(value: &T, mut options: O)
 where
     T: serde::Serialize,
 {
+    if options.config().subtrees_deduped() {
+        // Back-references depend on the bytes written before, so the size is
+        // found by writing the value out.
+        let mut counter = ByteCount(0);
+        ::ser::Serializer::new(&mut counter, (&mut options).with_no_limit())
+            .serialize_typed(value)?;
+        options.limit().add(counter.0)?;
+        return Ok(counter.0);
+    }
+
     let old_limiter = options.limit().clone();
-    let mut size_counter = ::ser::SizeChecker {
-        options: ::config::WithOtherLimit::new(
-            options,
-            CountSize {
-                total: 0,
-                other_limit: old_limiter,
-            },
-        ),
-    };
+    let mut size_counter = ::ser::SizeChecker::new(::config::WithOtherLimit::new(
+        options,
+        CountSize {
+            total: 0,
+            other_limit: old_limiter,
+        },
+    ));
 
-    let result = value.serialize(&mut size_counter);
+    let result = size_counter.serialize_typed(value);
     result.map(|_| size_counter.options.new_limit.total)
 }
 
+/// Like `serialized_size`, but gives up with `None` as soon as the size passes `cap`.
+pub(crate) fn serialized_size_capped<T: ?Sized, O: Options>(
+    value: &T,
+    mut options: O,
+    cap: u64,
+) -> Result<Option<u64>>
+where
+    T: serde::Serialize,
+{
+    if options.config().subtrees_deduped() {
+        let mut counter = CappedByteCount { total: 0, cap };
+        let result = ::ser::Serializer::new(&mut counter, (&mut options).with_no_limit())
+            .serialize_typed(value);
+        if counter.total > cap {
+            return Ok(None);
+        }
+        result?;
+        options.limit().add(counter.total)?;
+        return Ok(Some(counter.total));
+    }
+
+    let old_limiter = options.limit().clone();
+    let mut size_counter = ::ser::SizeChecker::new(::config::WithOtherLimit::new(
+        options,
+        CappedSize {
+            total: 0,
+            cap,
+            other_limit: old_limiter,
+        },
+    ));
+
+    let result = size_counter.serialize_typed(value);
+    let total = size_counter.options.new_limit.total;
+    if total > cap {
+        return Ok(None);
+    }
+    result.map(|_| Some(total))
+}
+
+/// Counts like `CountSize`, failing once the total passes `cap`.
+#[derive(Clone)]
+struct CappedSize<L: SizeLimit> {
+    total: u64,
+    cap: u64,
+    other_limit: L,
+}
+
+impl<L: SizeLimit> SizeLimit for CappedSize<L> {
+    fn add(&mut self, c: u64) -> Result<()> {
+        self.other_limit.add(c)?;
+        self.total = self.total.saturating_add(c);
+        if self.total > self.cap {
+            Err(ErrorKind::SizeLimit {
+                limit: self.cap,
+                attempted: self.total,
+            }
+            .into())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn limit(&self) -> Option<u64> {
+        unreachable!();
+    }
+}
+
+/// A writer that counts the bytes written to it, failing once they pass `cap`.
+struct CappedByteCount {
+    total: u64,
+    cap: u64,
+}
+
+impl Write for CappedByteCount {
+    fn write(&mut self, buf: &[u8]) -> ::core2::io::Result<usize> {
+        self.total = self.total.saturating_add(buf.len() as u64);
+        if self.total > self.cap {
+            Err(::core2::io::Error::new(
+                ::core2::io::ErrorKind::Other,
+                "size cap exceeded",
+            ))
+        } else {
+            Ok(buf.len())
+        }
+    }
+
+    fn flush(&mut self) -> ::core2::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A writer that only counts the bytes written to it.
+pub(crate) struct ByteCount(pub(crate) u64);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> ::core2::io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> ::core2::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
 pub(crate) fn deserialize_from<R, T, O>(reader: R, options: O) -> Result<T>
 where
     R: Read,
@@ -87,6 +252,7 @@ where
     deserialize_from_seed(PhantomData, reader, options)
 }
 
+#[cfg(feature = "alloc")]
 pub(crate) fn deserialize_from_seed<'a, R, T, O>(seed: T, reader: R, options: O) -> Result<T::Value>
 where
     R: Read,
@@ -116,8 +282,11 @@ where
     T: serde::de::DeserializeSeed<'a>,
     O: Options,
 {
+    let _span = ::metrics::span("deserialize");
     let mut deserializer = ::de::Deserializer::<_, O>::new(reader, options);
-    seed.deserialize(&mut deserializer)
+    let result = deserializer.deserialize_typed(seed);
+    deserializer.stats.finish();
+    result
 }
 
 pub(crate) fn deserialize_in_place<'a, R, T, O>(reader: R, options: O, place: &mut T) -> Result<()>
@@ -126,8 +295,67 @@ where
     T: serde::de::Deserialize<'a>,
     O: Options,
 {
+    let _span = ::metrics::span("deserialize");
     let mut deserializer = ::de::Deserializer::<_, _>::new(reader, options);
-    serde::Deserialize::deserialize_in_place(&mut deserializer, place)
+    // Deserializing in place does not say which types the fields are, so the
+    // codecs are only applied to a value read afresh.
+    let result = if deserializer.codecs_registered() {
+        deserializer
+            .deserialize_typed(PhantomData)
+            .map(|value| *place = value)
+    } else {
+        serde::Deserialize::deserialize_in_place(&mut deserializer, place)
+    };
+    deserializer.stats.finish();
+    result
+}
+
+pub(crate) fn deserialize_in_place_slice<'a, T, O>(
+    bytes: &'a [u8],
+    options: O,
+    place: &mut T,
+) -> Result<()>
+where
+    T: serde::de::Deserialize<'a>,
+    O: Options,
+{
+    let reader = ::de::read::SliceReader::new(bytes);
+    deserialize_in_place(reader, options, place)
+}
+
+pub(crate) fn deserialize_with_interner<'i, T, I, O>(
+    bytes: &[u8],
+    interner: &mut I,
+    options: O,
+) -> Result<T>
+where
+    T: serde::de::Deserialize<'i>,
+    I: ::intern::StringInterner<'i>,
+    O: Options,
+{
+    let reader = ::de::read::SliceReader::new(bytes);
+    let reader = ::intern::InterningReader::new(reader, interner);
+    deserialize_from_custom_seed(PhantomData, reader, options)
+}
+
+#[cfg(feature = "validate")]
+pub(crate) fn validate<'a, T, O>(bytes: &'a [u8], options: O) -> Result<()>
+where
+    T: serde::de::Deserialize<'a>,
+    O: Options,
+{
+    let _span = ::metrics::span("validate");
+    let reader = ::de::read::SliceReader::new(bytes);
+    let mut deserializer = ::de::Deserializer::<_, O>::new(reader, options);
+    // Codecs are found by the seed each value is read with, which `Validate` wraps,
+    // so they are only applied to a value read in full.
+    let result = if deserializer.codecs_registered() {
+        deserializer.deserialize_typed(PhantomData::<T>).map(drop)
+    } else {
+        T::deserialize(::validate::Validate(&mut deserializer)).map(drop)
+    };
+    deserializer.stats.finish();
+    result
 }
 
 pub(crate) fn deserialize<'a, T, O>(bytes: &'a [u8], options: O) -> Result<T>
@@ -144,10 +372,79 @@ where
     O: Options,
 {
     let reader = ::de::read::SliceReader::new(bytes);
-    let options = ::config::WithOtherLimit::new(options, Infinite);
     deserialize_from_custom_seed(seed, reader, options)
 }
 
+pub(crate) fn encode_into<W, T: ?Sized, O>(writer: W, value: &T, mut options: O) -> Result<()>
+where
+    W: Write,
+    T: Encode,
+    O: Options,
+{
+    if options.limit().limit().is_some() {
+        encoded_size(value, &mut options)?;
+    }
+
+    let mut serializer = ::ser::Serializer::<_, O>::new(writer, options).plain();
+    value.encode(&mut serializer)
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn encode<T: ?Sized, O>(value: &T, mut options: O) -> Result<Vec<u8>>
+where
+    T: Encode,
+    O: Options,
+{
+    let mut writer = {
+        let actual_size = encoded_size(value, &mut options)?;
+        Vec::with_capacity(actual_size as usize)
+    };
+
+    encode_into(&mut writer, value, options.with_no_limit())?;
+    Ok(writer)
+}
+
+/// The encoded size of `value`, counted against the limit of `options`.
+fn encoded_size<T: ?Sized, O: Options>(value: &T, mut options: O) -> Result<u64>
+where
+    T: Encode,
+{
+    let old_limiter = options.limit().clone();
+    let mut size_counter = ::ser::SizeChecker::new(::config::WithOtherLimit::new(
+        options,
+        CountSize {
+            total: 0,
+            other_limit: old_limiter,
+        },
+    ))
+    .plain();
+
+    let result = value.encode(&mut size_counter);
+    result.map(|_| size_counter.options.new_limit.total)
+}
+
+pub(crate) fn decode<T, O>(bytes: &[u8], options: O) -> Result<T>
+where
+    T: Decode,
+    O: Options,
+{
+    let reader = ::de::read::SliceReader::new(bytes);
+    let mut deserializer = ::de::Deserializer::<_, _>::new(reader, options).plain();
+    T::decode(&mut deserializer)
+}
+
+#[cfg(feature = "alloc")]
+pub(crate) fn decode_from<R, T, O>(reader: R, options: O) -> Result<T>
+where
+    R: Read,
+    T: Decode,
+    O: Options,
+{
+    let reader = ::de::read::IoReader::new(reader);
+    let mut deserializer = ::de::Deserializer::<_, O>::new(reader, options).plain();
+    T::decode(&mut deserializer)
+}
+
 pub(crate) trait SizeLimit: Clone {
     /// Tells the SizeLimit that a certain number of bytes has been
     /// read or written.  Returns Err if the limit has been exceeded.
@@ -159,7 +456,16 @@ pub(crate) trait SizeLimit: Clone {
 /// A SizeLimit that restricts serialized or deserialized messages from
 /// exceeding a certain byte length.
 #[derive(Copy, Clone)]
-pub struct Bounded(pub u64);
+pub struct Bounded {
+    limit: u64,
+    used: u64,
+}
+
+impl Bounded {
+    pub(crate) fn new(limit: u64) -> Bounded {
+        Bounded { limit, used: 0 }
+    }
+}
 
 /// A SizeLimit without a limit!
 /// Use this if you don't care about the size of encoded or decoded messages.
@@ -169,17 +475,127 @@ pub struct Infinite;
 impl SizeLimit for Bounded {
     #[inline(always)]
     fn add(&mut self, n: u64) -> Result<()> {
-        if self.0 >= n {
-            self.0 -= n;
+        if self.limit - self.used >= n {
+            self.used += n;
             Ok(())
         } else {
-            Err(Box::new(ErrorKind::SizeLimit))
+            Err(ErrorKind::SizeLimit {
+                limit: self.limit,
+                attempted: self.used.saturating_add(n),
+            }
+            .into())
+        }
+    }
+
+    #[inline(always)]
+    fn limit(&self) -> Option<u64> {
+        Some(self.limit - self.used)
+    }
+}
+
+/// A SizeLimit drawing from a budget shared between calls, on top of the
+/// per-call limit `L`.
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+#[derive(Clone)]
+pub(crate) struct Shared<L: SizeLimit> {
+    limit: L,
+    budget: Arc<AtomicU64>,
+    /// The bytes drawn from the budget so far.
+    used: u64,
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+impl<L: SizeLimit> Shared<L> {
+    pub(crate) fn new(limit: L, budget: Arc<AtomicU64>) -> Shared<L> {
+        Shared {
+            limit,
+            budget,
+            used: 0,
+        }
+    }
+}
+
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+impl<L: SizeLimit> SizeLimit for Shared<L> {
+    #[inline(always)]
+    fn add(&mut self, n: u64) -> Result<()> {
+        self.limit.add(n)?;
+        match self
+            .budget
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                left.checked_sub(n)
+            }) {
+            Ok(_) => {
+                self.used += n;
+                Ok(())
+            }
+            Err(left) => Err(ErrorKind::SizeLimit {
+                limit: self.used.saturating_add(left),
+                attempted: self.used.saturating_add(n),
+            }
+            .into()),
+        }
+    }
+
+    #[inline(always)]
+    fn limit(&self) -> Option<u64> {
+        let left = self.budget.load(Ordering::Relaxed);
+        Some(self.limit.limit().map_or(left, |limit| limit.min(left)))
+    }
+}
+
+/// The number of bytes read between two calls to the cancellation token of a
+/// `Cancellable` limit.
+#[cfg(feature = "alloc")]
+pub(crate) const CANCEL_INTERVAL: u64 = 4096;
+
+/// A SizeLimit that fails with `Cancelled` once `cancelled` returns true, which it
+/// asks before the first read and then every `CANCEL_INTERVAL` bytes, on top of
+/// the limit `L`.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub(crate) struct Cancellable<L: SizeLimit> {
+    limit: L,
+    cancelled: Arc<dyn Fn() -> bool + Send + Sync>,
+    /// Bytes left until the token is checked again.
+    until_check: u64,
+}
+
+#[cfg(feature = "alloc")]
+impl<L: SizeLimit> Cancellable<L> {
+    pub(crate) fn new(
+        limit: L,
+        cancelled: Arc<dyn Fn() -> bool + Send + Sync>,
+    ) -> Cancellable<L> {
+        Cancellable {
+            limit,
+            cancelled,
+            until_check: 0,
         }
     }
+}
+
+#[cfg(feature = "alloc")]
+impl<L: SizeLimit> SizeLimit for Cancellable<L> {
+    #[inline(always)]
+    fn add(&mut self, n: u64) -> Result<()> {
+        self.limit.add(n)?;
+        // Every read counts, so that reads of nothing still reach a check.
+        let n = n.max(1);
+        if n < self.until_check {
+            self.until_check -= n;
+            return Ok(());
+        }
+        self.until_check = CANCEL_INTERVAL;
+        if (self.cancelled)() {
+            return Err(ErrorKind::Cancelled.into());
+        }
+        Ok(())
+    }
 
     #[inline(always)]
     fn limit(&self) -> Option<u64> {
-        Some(self.0)
+        self.limit.limit()
     }
 }
 
@@ -195,9 +611,32 @@ impl SizeLimit for Infinite {
     }
 }
 
+/// A SizeLimit chosen at runtime: `Bounded` when there is a limit, `Infinite` otherwise.
+#[derive(Copy, Clone)]
+pub(crate) struct RuntimeLimit(pub(crate) Option<Bounded>);
+
+impl SizeLimit for RuntimeLimit {
+    #[inline(always)]
+    fn add(&mut self, n: u64) -> Result<()> {
+        match self.0 {
+            Some(ref mut bounded) => bounded.add(n),
+            None => Ok(()),
+        }
+    }
+
+    #[inline(always)]
+    fn limit(&self) -> Option<u64> {
+        self.0.and_then(|bounded| bounded.limit())
+    }
+}
+
 pub(crate) trait SizeType: Clone {
     type Primitive: serde::de::DeserializeOwned + TryFrom<usize> + Into<u64>;
 
+    /// The largest length this type holds. Below `u64::MAX`, it is the escape
+    /// written before a `u64` length under `Overflow::PromoteToLargerType`.
+    const MAX: u64 = u64::MAX;
+
     fn read(reader: &mut dyn FnMut() -> Result<Self::Primitive>) -> Result<u64> {
         let result: Self::Primitive = reader()?;
         Ok(result.into())
@@ -206,7 +645,7 @@ pub(crate) trait SizeType: Clone {
     fn write<S>(writer: S, value: usize) -> Result<S::Ok>
     where
         S: serde::Serializer,
-        Box<ErrorKind>: From<S::Error>,
+        ::Error: From<S::Error>,
     {
         let value: Self::Primitive = value.try_into().map_err(|_e| ErrorKind::SizeTypeLimit)?;
         Self::write_to(writer, value)
@@ -215,7 +654,40 @@ pub(crate) trait SizeType: Clone {
     fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
     where
         S: serde::Serializer,
-        Box<ErrorKind>: From<S::Error>;
+        ::Error: From<S::Error>;
+
+    /// The length prefix from `config` to use instead of this one, if it is
+    /// chosen at runtime.
+    fn custom(_config: &Config) -> Option<CustomLength> {
+        None
+    }
+
+    /// The fixed-width length type from `config` to use instead of this one, if
+    /// it is chosen at runtime. Only consulted when `custom` is `None`.
+    fn fixed(_config: &Config) -> Option<FixedLength> {
+        None
+    }
+}
+
+/// A fixed-width length type chosen at runtime, see `SizeType::fixed`.
+#[derive(Copy, Clone)]
+pub(crate) enum FixedLength {
+    U64,
+    U32,
+    U16,
+    U8,
+}
+
+impl FixedLength {
+    fn of(length: LengthOption) -> Option<FixedLength> {
+        match length {
+            LengthOption::U64 => Some(FixedLength::U64),
+            LengthOption::U32 => Some(FixedLength::U32),
+            LengthOption::U16 => Some(FixedLength::U16),
+            LengthOption::U8 => Some(FixedLength::U8),
+            LengthOption::Custom(_) => None,
+        }
+    }
 }
 
 /// An 8 byte length
@@ -226,7 +698,7 @@ impl SizeType for U64 {
     fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
     where
         S: serde::Serializer,
-        Box<ErrorKind>: From<S::Error>,
+        ::Error: From<S::Error>,
     {
         writer.serialize_u64(value).map_err(Into::into)
     }
@@ -237,10 +709,11 @@ impl SizeType for U64 {
 pub struct U32;
 impl SizeType for U32 {
     type Primitive = u32;
+    const MAX: u64 = u32::MAX as u64;
     fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
     where
         S: serde::Serializer,
-        Box<ErrorKind>: From<S::Error>,
+        ::Error: From<S::Error>,
     {
         writer.serialize_u32(value).map_err(Into::into)
     }
@@ -251,10 +724,11 @@ impl SizeType for U32 {
 pub struct U16;
 impl SizeType for U16 {
     type Primitive = u16;
+    const MAX: u64 = u16::MAX as u64;
     fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
     where
         S: serde::Serializer,
-        Box<ErrorKind>: From<S::Error>,
+        ::Error: From<S::Error>,
     {
         writer.serialize_u16(value).map_err(Into::into)
     }
@@ -265,11 +739,773 @@ impl SizeType for U16 {
 pub struct U8;
 impl SizeType for U8 {
     type Primitive = u8;
+    const MAX: u64 = u8::MAX as u64;
     fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
     where
         S: serde::Serializer,
-        Box<ErrorKind>: From<S::Error>,
+        ::Error: From<S::Error>,
     {
         writer.serialize_u8(value).map_err(Into::into)
     }
 }
+
+/// Stands for `Config::string_length_custom`, which is read from the config.
+#[derive(Copy, Clone)]
+pub(crate) struct CustomString;
+impl SizeType for CustomString {
+    type Primitive = u64;
+    fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
+    where
+        S: serde::Serializer,
+        ::Error: From<S::Error>,
+    {
+        writer.serialize_u64(value).map_err(Into::into)
+    }
+
+    fn custom(config: &Config) -> Option<CustomLength> {
+        config.custom_string_length()
+    }
+}
+
+/// Stands for `Config::array_length_custom`, which is read from the config.
+#[derive(Copy, Clone)]
+pub(crate) struct CustomArray;
+impl SizeType for CustomArray {
+    type Primitive = u64;
+    fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
+    where
+        S: serde::Serializer,
+        ::Error: From<S::Error>,
+    {
+        writer.serialize_u64(value).map_err(Into::into)
+    }
+
+    fn custom(config: &Config) -> Option<CustomLength> {
+        config.custom_array_length()
+    }
+}
+
+/// Stands for `Config::string_length`, which is read from the config. Used by
+/// the types of `raw`, which cannot name the length type statically.
+#[derive(Copy, Clone)]
+pub(crate) struct RuntimeString;
+impl SizeType for RuntimeString {
+    type Primitive = u64;
+    fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
+    where
+        S: serde::Serializer,
+        ::Error: From<S::Error>,
+    {
+        writer.serialize_u64(value).map_err(Into::into)
+    }
+
+    fn custom(config: &Config) -> Option<CustomLength> {
+        config.custom_string_length()
+    }
+
+    fn fixed(config: &Config) -> Option<FixedLength> {
+        FixedLength::of(config.string_length_option())
+    }
+}
+
+/// Stands for `Config::array_length`, which is read from the config, see `RuntimeString`.
+#[derive(Copy, Clone)]
+pub(crate) struct RuntimeArray;
+impl SizeType for RuntimeArray {
+    type Primitive = u64;
+    fn write_to<S>(writer: S, value: Self::Primitive) -> Result<S::Ok>
+    where
+        S: serde::Serializer,
+        ::Error: From<S::Error>,
+    {
+        writer.serialize_u64(value).map_err(Into::into)
+    }
+
+    fn custom(config: &Config) -> Option<CustomLength> {
+        config.custom_array_length()
+    }
+
+    fn fixed(config: &Config) -> Option<FixedLength> {
+        FixedLength::of(config.array_length_option())
+    }
+}
+
+/// Type markers written before every value nested inside a map when
+/// `Config::allow_flatten` is set, or before every value with
+/// `Config::allow_untagged`, making those values self-describing.
+pub(crate) mod marker {
+    pub(crate) const UNIT: u8 = 0;
+    pub(crate) const BOOL: u8 = 1;
+    pub(crate) const U8: u8 = 2;
+    pub(crate) const U16: u8 = 3;
+    pub(crate) const U32: u8 = 4;
+    pub(crate) const U64: u8 = 5;
+    pub(crate) const U128: u8 = 6;
+    pub(crate) const I8: u8 = 7;
+    pub(crate) const I16: u8 = 8;
+    pub(crate) const I32: u8 = 9;
+    pub(crate) const I64: u8 = 10;
+    pub(crate) const I128: u8 = 11;
+    pub(crate) const F32: u8 = 12;
+    pub(crate) const F64: u8 = 13;
+    pub(crate) const CHAR: u8 = 14;
+    pub(crate) const STR: u8 = 15;
+    pub(crate) const BYTES: u8 = 16;
+    pub(crate) const NONE: u8 = 17;
+    pub(crate) const SOME: u8 = 18;
+    pub(crate) const SEQ: u8 = 19;
+    /// Also used for enums, as a map with a single entry keyed by the variant index.
+    pub(crate) const MAP: u8 = 20;
+}
+
+/// The header written before each struct field when `Config::tagged_fields` is set.
+pub(crate) mod field {
+    pub(crate) const FIXED_1: u32 = 0;
+    pub(crate) const FIXED_2: u32 = 1;
+    pub(crate) const FIXED_4: u32 = 2;
+    pub(crate) const FIXED_8: u32 = 3;
+    pub(crate) const FIXED_16: u32 = 4;
+    /// The value is preceded by its length in bytes.
+    pub(crate) const LENGTH_DELIMITED: u32 = 5;
+
+    /// The id of a field: the 32 bit FNV-1a hash of its name, truncated to 29 bits.
+    pub(crate) fn id(name: &str) -> u32 {
+        let mut hash: u32 = 0x811c_9dc5;
+        for byte in name.bytes() {
+            hash ^= u32::from(byte);
+            hash = hash.wrapping_mul(0x0100_0193);
+        }
+        hash & 0x1fff_ffff
+    }
+
+    /// The wire type of a field whose value takes `len` bytes.
+    pub(crate) fn wire_type(len: usize) -> u32 {
+        match len {
+            1 => FIXED_1,
+            2 => FIXED_2,
+            4 => FIXED_4,
+            8 => FIXED_8,
+            16 => FIXED_16,
+            _ => LENGTH_DELIMITED,
+        }
+    }
+
+    /// The size of a value of the given wire type, if it is fixed.
+    pub(crate) fn fixed_len(wire_type: u32) -> Option<u64> {
+        match wire_type {
+            FIXED_1 => Some(1),
+            FIXED_2 => Some(2),
+            FIXED_4 => Some(4),
+            FIXED_8 => Some(8),
+            FIXED_16 => Some(16),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn header(name: &str, wire_type: u32) -> u32 {
+        id(name) << 3 | wire_type
+    }
+}
+
+/// How floats are written under each `FloatEncoding`, see `Config::float_encoding`.
+///
+/// Both `f32` and `f64` values go through `f64`, which holds every `f32` exactly.
+pub(crate) mod float {
+    use config::FloatEncoding;
+    use error::{Error, Result};
+    use serde::ser::Error as _;
+
+    /// A float as it appears on the wire.
+    pub(crate) enum Wire {
+        F32(f32),
+        F64(f64),
+        I32(i32),
+        I64(i64),
+    }
+
+    /// The type a float is written as.
+    pub(crate) enum Kind {
+        F32,
+        F64,
+        I32,
+        I64,
+    }
+
+    /// `single` is set for `f32` values.
+    pub(crate) fn kind(encoding: FloatEncoding, single: bool) -> Kind {
+        match encoding {
+            FloatEncoding::Ieee if single => Kind::F32,
+            FloatEncoding::Ieee | FloatEncoding::F32AsF64 => Kind::F64,
+            FloatEncoding::F64AsF32 => Kind::F32,
+            FloatEncoding::Fixed32 { .. } => Kind::I32,
+            FloatEncoding::Fixed64 { .. } | FloatEncoding::Decimal { .. } => Kind::I64,
+        }
+    }
+
+    /// The number of bytes a float takes.
+    pub(crate) fn len(encoding: FloatEncoding, single: bool) -> u64 {
+        match kind(encoding, single) {
+            Kind::F32 | Kind::I32 => 4,
+            Kind::F64 | Kind::I64 => 8,
+        }
+    }
+
+    pub(crate) fn encode(encoding: FloatEncoding, v: f64, single: bool) -> Result<Wire> {
+        Ok(match encoding {
+            FloatEncoding::Ieee if single => Wire::F32(v as f32),
+            FloatEncoding::Ieee | FloatEncoding::F32AsF64 => Wire::F64(v),
+            FloatEncoding::F64AsF32 => Wire::F32(v as f32),
+            FloatEncoding::Fixed32 { fraction_bits } => {
+                Wire::I32(to_fixed(v, binary_scale(fraction_bits), i32::MIN as i64)? as i32)
+            }
+            FloatEncoding::Fixed64 { fraction_bits } => {
+                Wire::I64(to_fixed(v, binary_scale(fraction_bits), i64::MIN)?)
+            }
+            FloatEncoding::Decimal { places } => {
+                Wire::I64(to_fixed(v, decimal_scale(places), i64::MIN)?)
+            }
+        })
+    }
+
+    pub(crate) fn decode(encoding: FloatEncoding, wire: Wire) -> f64 {
+        match (encoding, wire) {
+            (_, Wire::F32(v)) => v as f64,
+            (_, Wire::F64(v)) => v,
+            (FloatEncoding::Decimal { places }, Wire::I64(v)) => v as f64 / decimal_scale(places),
+            (FloatEncoding::Fixed32 { fraction_bits }, Wire::I32(v)) => {
+                v as f64 / binary_scale(fraction_bits)
+            }
+            (FloatEncoding::Fixed64 { fraction_bits }, Wire::I64(v)) => {
+                v as f64 / binary_scale(fraction_bits)
+            }
+            (_, Wire::I32(v)) => v as f64,
+            (_, Wire::I64(v)) => v as f64,
+        }
+    }
+
+    fn binary_scale(fraction_bits: u8) -> f64 {
+        let mut scale = 1.0;
+        for _ in 0..fraction_bits {
+            scale *= 2.0;
+        }
+        scale
+    }
+
+    fn decimal_scale(places: u8) -> f64 {
+        let mut scale = 1.0;
+        for _ in 0..places {
+            scale *= 10.0;
+        }
+        scale
+    }
+
+    /// Scales `v` and rounds it to the nearest integer, half away from zero,
+    /// failing if the result does not fit between `min` and `-(min + 1)`.
+    fn to_fixed(v: f64, scale: f64, min: i64) -> Result<i64> {
+        let scaled = v * scale;
+        // `as` truncates towards zero, which completes the rounding.
+        let rounded = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        };
+        if rounded > min as f64 - 1.0 && rounded < -(min as f64) {
+            Ok(rounded as i64)
+        } else {
+            Err(Error::custom(format_args!(
+                "{} is out of range for the configured fixed-point float encoding",
+                v
+            )))
+        }
+    }
+}
+
+/// How lengths and `usize` values are written under each `UsizeMode`, see
+/// `Config::usize_encoding`.
+pub(crate) mod usize_mode {
+    use byteorder::ByteOrder;
+    use config::UsizeMode;
+    use error::{ErrorKind, Result};
+
+    /// A varint below this is a single byte holding the value.
+    pub(crate) const VARINT_U16: u8 = 251;
+    /// Followed by a `u32`.
+    pub(crate) const VARINT_U32: u8 = 252;
+    /// Followed by a `u64`.
+    pub(crate) const VARINT_U64: u8 = 253;
+
+    /// The longest encoding, a varint marker followed by a `u64`.
+    pub(crate) const MAX_LEN: usize = 9;
+
+    /// Writes `value` into `buf`, returning the number of bytes used. Values that
+    /// do not fit in `UsizeMode::U32` fail with `SizeTypeLimit`.
+    pub(crate) fn encode<E: ByteOrder>(
+        mode: UsizeMode,
+        value: u64,
+        buf: &mut [u8; MAX_LEN],
+    ) -> Result<usize> {
+        match mode {
+            UsizeMode::U32 => {
+                if value > u64::from(u32::MAX) {
+                    return Err(ErrorKind::SizeTypeLimit.into());
+                }
+                E::write_u32(&mut buf[..4], value as u32);
+                Ok(4)
+            }
+            UsizeMode::U64 => {
+                E::write_u64(&mut buf[..8], value);
+                Ok(8)
+            }
+            UsizeMode::Varint if value < u64::from(VARINT_U16) => {
+                buf[0] = value as u8;
+                Ok(1)
+            }
+            UsizeMode::Varint if value <= u64::from(u16::MAX) => {
+                buf[0] = VARINT_U16;
+                E::write_u16(&mut buf[1..3], value as u16);
+                Ok(3)
+            }
+            UsizeMode::Varint if value <= u64::from(u32::MAX) => {
+                buf[0] = VARINT_U32;
+                E::write_u32(&mut buf[1..5], value as u32);
+                Ok(5)
+            }
+            UsizeMode::Varint => {
+                buf[0] = VARINT_U64;
+                E::write_u64(&mut buf[1..9], value);
+                Ok(9)
+            }
+        }
+    }
+
+    /// The number of bytes every value takes in `mode`, if it is fixed.
+    pub(crate) fn fixed_width(mode: UsizeMode) -> Option<u64> {
+        match mode {
+            UsizeMode::U32 => Some(4),
+            UsizeMode::U64 => Some(8),
+            UsizeMode::Varint => None,
+        }
+    }
+
+    /// Maps an `isize` onto the unsigned value written for it: its two's complement
+    /// bits for the fixed widths, zigzag for varints so that small negative values
+    /// stay small.
+    pub(crate) fn from_isize(mode: UsizeMode, value: i64) -> Result<u64> {
+        match mode {
+            UsizeMode::U32 => {
+                if value < i64::from(i32::MIN) || value > i64::from(i32::MAX) {
+                    return Err(ErrorKind::SizeTypeLimit.into());
+                }
+                Ok(u64::from(value as i32 as u32))
+            }
+            UsizeMode::U64 => Ok(value as u64),
+            UsizeMode::Varint => Ok(((value << 1) ^ (value >> 63)) as u64),
+        }
+    }
+
+    /// The inverse of `from_isize`.
+    pub(crate) fn to_isize(mode: UsizeMode, value: u64) -> i64 {
+        match mode {
+            UsizeMode::U32 => i64::from(value as u32 as i32),
+            UsizeMode::U64 => value as i64,
+            UsizeMode::Varint => ((value >> 1) as i64) ^ -((value & 1) as i64),
+        }
+    }
+}
+
+/// How integers are written under `IntEncoding::Varint`, see `Config::int_encoding`.
+/// Values that fit in a `u64` are written as `UsizeMode::Varint` writes them.
+pub(crate) mod varint {
+    use byteorder::ByteOrder;
+    use internal::usize_mode::{VARINT_U16, VARINT_U32, VARINT_U64};
+
+    /// Followed by a `u128`.
+    pub(crate) const VARINT_U128: u8 = 254;
+
+    /// The longest encoding, a varint marker followed by a `u128`.
+    pub(crate) const MAX_LEN: usize = 17;
+
+    /// Writes `value` into `buf`, returning the number of bytes used.
+    pub(crate) fn encode<E: ByteOrder>(value: u128, buf: &mut [u8; MAX_LEN]) -> usize {
+        if value < u128::from(VARINT_U16) {
+            buf[0] = value as u8;
+            1
+        } else if value <= u128::from(u16::MAX) {
+            buf[0] = VARINT_U16;
+            E::write_u16(&mut buf[1..3], value as u16);
+            3
+        } else if value <= u128::from(u32::MAX) {
+            buf[0] = VARINT_U32;
+            E::write_u32(&mut buf[1..5], value as u32);
+            5
+        } else if value <= u128::from(u64::MAX) {
+            buf[0] = VARINT_U64;
+            E::write_u64(&mut buf[1..9], value as u64);
+            9
+        } else {
+            buf[0] = VARINT_U128;
+            E::write_u128(&mut buf[1..17], value);
+            17
+        }
+    }
+
+    /// The number of bytes `encode` writes for `value`.
+    pub(crate) fn len(value: u128) -> u64 {
+        match value {
+            v if v < u128::from(VARINT_U16) => 1,
+            v if v <= u128::from(u16::MAX) => 3,
+            v if v <= u128::from(u32::MAX) => 5,
+            v if v <= u128::from(u64::MAX) => 9,
+            _ => 17,
+        }
+    }
+
+    /// The widest marker an integer of `size` bytes can be written with.
+    pub(crate) fn max_marker(size: usize) -> u8 {
+        match size {
+            2 => VARINT_U16,
+            4 => VARINT_U32,
+            8 => VARINT_U64,
+            _ => VARINT_U128,
+        }
+    }
+
+    /// An integer written as a varint: unsigned integers as they are, signed ones
+    /// zigzag encoded.
+    pub(crate) trait Varint: Sized {
+        fn to_varint(self) -> u128;
+
+        /// The inverse of `to_varint`, for values read with a marker no wider than
+        /// `max_marker` allows for `Self`, which always fit.
+        fn from_varint(value: u128) -> Self;
+    }
+
+    macro_rules! impl_varint {
+        ($($unsigned:ty),*; $($signed:ty),*) => {
+            $(
+                impl Varint for $unsigned {
+                    #[inline(always)]
+                    fn to_varint(self) -> u128 {
+                        self as u128
+                    }
+
+                    #[inline(always)]
+                    fn from_varint(value: u128) -> $unsigned {
+                        value as $unsigned
+                    }
+                }
+            )*
+            $(
+                impl Varint for $signed {
+                    #[inline(always)]
+                    fn to_varint(self) -> u128 {
+                        let value = self as i128;
+                        ((value << 1) ^ (value >> 127)) as u128
+                    }
+
+                    #[inline(always)]
+                    fn from_varint(value: u128) -> $signed {
+                        (((value >> 1) as i128) ^ -((value & 1) as i128)) as $signed
+                    }
+                }
+            )*
+        };
+    }
+
+    impl_varint!(u16, u32, u64, u128; i16, i32, i64, i128);
+}
+
+/// The most bytes of a string kept when its length must fit in `S`, if `config`
+/// asks to truncate strings that are too long. Strings written without a length
+/// are never truncated.
+pub(crate) fn truncation_cap<S: SizeType>(config: &Config) -> Option<u64> {
+    if config.length_overflow() != Overflow::Truncate
+        || config.string_mode() != StringMode::LengthPrefixed
+        || config.usize_mode().is_some()
+        || S::custom(config).is_some()
+    {
+        return None;
+    }
+    if let Some(fixed) = S::fixed(config) {
+        return fixed_length!(fixed, L => truncation_cap::<L>(config));
+    }
+    Some(S::MAX)
+}
+
+/// Shortens `v` to the longest prefix whose length fits in `S`, on a character
+/// boundary, if `config` asks to truncate strings that are too long.
+pub(crate) fn truncate_str<'a, S: SizeType>(config: &Config, v: &'a str) -> &'a str {
+    match truncation_cap::<S>(config) {
+        Some(cap) if v.len() as u64 > cap => &v[..floor_char_boundary(v, cap as usize)],
+        _ => v,
+    }
+}
+
+/// The largest character boundary of `v` at or below `index`.
+fn floor_char_boundary(v: &str, index: usize) -> usize {
+    let mut end = index.min(v.len());
+    while !v.is_char_boundary(end) {
+        end -= 1;
+    }
+    end
+}
+
+/// The number of padding bytes written at `position` before a primitive of `size`
+/// bytes, see `Config::aligned`.
+#[inline(always)]
+pub(crate) fn padding(config: &Config, position: u64, size: u64) -> u64 {
+    let alignment = config.alignment().min(size);
+    position.wrapping_neg() & (alignment - 1)
+}
+
+/// Whether `len` is written as the escape of `S` followed by a `u64`.
+pub(crate) fn promoted<S: SizeType>(config: &Config, len: u64) -> bool {
+    S::MAX < u64::MAX && len >= S::MAX && config.length_overflow() == Overflow::PromoteToLargerType
+}
+
+/// How strings are written under each `StringMode`, see `Config::string_encoding`.
+pub(crate) mod string_mode {
+    use core::fmt;
+    use core::str;
+
+    use config::{Config, StringMode};
+    use error::{Error, ErrorKind, Result};
+    use serde::ser::Error as _;
+
+    use super::{floor_char_boundary, truncation_cap, SizeType};
+
+    /// NUL bytes to pad strings with.
+    pub(crate) const PADDING: [u8; 64] = [0; 64];
+
+    /// The number of NUL bytes following `v` when it is written under `mode`,
+    /// or `None` if it is length-prefixed.
+    pub(crate) fn padding(mode: StringMode, v: &str) -> Result<Option<usize>> {
+        if mode == StringMode::LengthPrefixed {
+            return Ok(None);
+        }
+        padding_of(mode, v.len(), v.as_bytes().contains(&0))
+    }
+
+    /// Like `padding`, for a string of `len` bytes holding a NUL byte if `nul` is set.
+    pub(crate) fn padding_of(mode: StringMode, len: usize, nul: bool) -> Result<Option<usize>> {
+        let padding = match mode {
+            StringMode::LengthPrefixed => return Ok(None),
+            StringMode::NulTerminated => 1,
+            StringMode::FixedWidth(width) if len > width => {
+                return Err(ErrorKind::StringTooLong(len).into())
+            }
+            StringMode::FixedWidth(width) => width - len,
+        };
+        if nul {
+            return Err(ErrorKind::NulInString.into());
+        }
+        Ok(Some(padding))
+    }
+
+    /// What `Serializer::collect_str` needs to know of the `Display` output of a
+    /// value before writing it: how many of its bytes are kept once truncated as
+    /// `truncate_str` would, and whether those hold a NUL byte.
+    pub(crate) struct Measure {
+        len: usize,
+        cap: usize,
+        pub(crate) kept: usize,
+        pub(crate) nul: bool,
+    }
+
+    impl Measure {
+        /// Formats `value` without keeping its output. The length of the string is
+        /// written with `S`.
+        pub(crate) fn of<S, T>(config: &Config, value: &T) -> Result<Measure>
+        where
+            S: SizeType,
+            T: ?Sized + fmt::Display,
+        {
+            let cap = truncation_cap::<S>(config)
+                .map_or(usize::MAX, |cap| cap.min(usize::MAX as u64) as usize);
+            let mut measure = Measure {
+                len: 0,
+                cap,
+                kept: 0,
+                nul: false,
+            };
+            fmt::write(&mut measure, format_args!("{}", value)).map_err(|_| display_failed())?;
+            Ok(measure)
+        }
+    }
+
+    impl fmt::Write for Measure {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            // Once a piece is cut, the rest of the output is dropped.
+            if self.kept == self.len {
+                let kept = floor_char_boundary(s, self.cap - self.kept);
+                self.nul |= s.as_bytes()[..kept].contains(&0);
+                self.kept += kept;
+            }
+            self.len = self.len.saturating_add(s.len());
+            Ok(())
+        }
+    }
+
+    /// The `Display` output of a value, if it fits in `STACK_BUFFER_LEN` bytes, so
+    /// that `Serializer::collect_str` formats short values once.
+    pub(crate) struct StackBuffer {
+        bytes: [u8; STACK_BUFFER_LEN],
+        len: usize,
+        /// Set once a piece did not fit, so that a `Display` impl going on after
+        /// the error is not taken to have written everything.
+        pub(crate) overflowed: bool,
+    }
+
+    /// The longest output `StackBuffer` holds.
+    pub(crate) const STACK_BUFFER_LEN: usize = 256;
+
+    impl StackBuffer {
+        pub(crate) fn new() -> StackBuffer {
+            StackBuffer {
+                bytes: [0; STACK_BUFFER_LEN],
+                len: 0,
+                overflowed: false,
+            }
+        }
+
+        /// The output, made of whole `str` pieces.
+        pub(crate) fn as_str(&self) -> Result<&str> {
+            str::from_utf8(&self.bytes[..self.len]).map_err(|_| display_failed())
+        }
+    }
+
+    impl fmt::Write for StackBuffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if self.overflowed || s.len() > STACK_BUFFER_LEN - self.len {
+                self.overflowed = true;
+                return Err(fmt::Error);
+            }
+            self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    /// The error for a `Display` impl that failed, or that wrote something else
+    /// the second time `collect_str` formatted it.
+    pub(crate) fn display_failed() -> Error {
+        Error::custom("a Display impl failed or wrote different output when called twice")
+    }
+
+    /// Strips the padding from a string read under `StringMode::FixedWidth`.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn unpad(mut bytes: ::alloc::vec::Vec<u8>) -> Result<::alloc::vec::Vec<u8>> {
+        if let Some(end) = bytes.iter().position(|&byte| byte == 0) {
+            if bytes[end..].iter().any(|&byte| byte != 0) {
+                return Err(ErrorKind::InvalidStringPadding.into());
+            }
+            bytes.truncate(end);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Back-references to repeated subtrees, see `Config::dedup_subtrees`.
+pub(crate) mod dedup {
+    #[cfg(feature = "alloc")]
+    use alloc::collections::BTreeMap;
+    #[cfg(feature = "alloc")]
+    use alloc::vec::Vec;
+
+    /// Precedes a subtree written in full.
+    pub(crate) const INLINE: u8 = 0;
+    /// Precedes the distance back from this byte to an identical subtree.
+    pub(crate) const BACK_REFERENCE: u8 = 1;
+    /// Subtrees this long or shorter are cheaper to repeat than to refer to.
+    #[cfg(feature = "alloc")]
+    pub(crate) const MIN_LEN: usize = 8;
+    /// The most bytes read again following back-references when there is no
+    /// deserialize limit, the message itself included.
+    pub(crate) const REPLAY_LIMIT: u64 = 64 << 20;
+
+    /// The bytes of a subtree outside its nested subtrees, and the offset in them and
+    /// identity of every nested subtree. Subtrees written from the same bytes have the
+    /// same key, whether their nested subtrees were written in full or referred to.
+    #[cfg(feature = "alloc")]
+    type Key = (Vec<u8>, Vec<(usize, u64)>);
+
+    /// A subtree written to the buffer of the subtree enclosing it.
+    #[cfg(feature = "alloc")]
+    struct Nested {
+        start: usize,
+        end: usize,
+        id: u64,
+    }
+
+    /// The subtrees written so far.
+    #[cfg(feature = "alloc")]
+    #[derive(Default)]
+    pub(crate) struct Subtrees {
+        /// The number of bytes written to the writer.
+        pub(crate) written: u64,
+        ids: BTreeMap<Key, u64>,
+        /// Where the subtrees written in full start, by identity.
+        starts: BTreeMap<u64, u64>,
+        /// The keys of `starts` in the order they were added.
+        order: Vec<u64>,
+        /// The nested subtrees of every subtree being buffered.
+        nested: Vec<Vec<Nested>>,
+    }
+
+    #[cfg(feature = "alloc")]
+    impl Subtrees {
+        pub(crate) fn begin(&mut self) {
+            self.nested.push(Vec::new());
+        }
+
+        /// Ends the innermost subtree, which was buffered as `content`, and returns
+        /// its identity.
+        pub(crate) fn end(&mut self, content: &[u8]) -> u64 {
+            let nested = self.nested.pop().unwrap_or_default();
+            let mut own = Vec::with_capacity(content.len());
+            let mut children = Vec::with_capacity(nested.len());
+            let mut from = 0;
+            for subtree in nested {
+                own.extend_from_slice(&content[from..subtree.start]);
+                children.push((own.len(), subtree.id));
+                from = subtree.end;
+            }
+            own.extend_from_slice(&content[from..]);
+            let next = self.ids.len() as u64;
+            *self.ids.entry((own, children)).or_insert(next)
+        }
+
+        /// Records that subtree `id` was written to `start..end` of the buffer of the
+        /// subtree enclosing it, if there is one.
+        pub(crate) fn written_to_parent(&mut self, id: u64, start: usize, end: usize) {
+            if let Some(nested) = self.nested.last_mut() {
+                nested.push(Nested { start, end, id });
+            }
+        }
+
+        /// Where subtree `id` was written in full, if it was and can be referred to.
+        pub(crate) fn start(&self, id: u64) -> Option<u64> {
+            self.starts.get(&id).cloned()
+        }
+
+        /// Remembers that subtree `id` was written in full at `position`.
+        pub(crate) fn insert(&mut self, id: u64, position: u64) {
+            if !self.starts.contains_key(&id) {
+                self.starts.insert(id, position);
+                self.order.push(id);
+            }
+        }
+
+        /// Forgets the subtrees starting at `position` or later, when the bytes they
+        /// were in are replaced by a back-reference.
+        pub(crate) fn forget_from(&mut self, position: u64) {
+            while let Some(&id) = self.order.last() {
+                if self.starts[&id] < position {
+                    break;
+                }
+                self.starts.remove(&id);
+                self.order.pop();
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+//! Transformations of whole messages, such as compression, encryption and checksums,
+//! stacked with `Config::with_layers`.
+//!
+//! A `FrameLayer` turns the bytes of a message into other bytes and back. The
+//! `LayeredConfig` returned by `with_layers` serializes a value as its config does,
+//! then passes the bytes through each layer in order, the first layer seeing the
+//! serialized value and the last one writing the output. Deserializing removes the
+//! layers in the reverse order before reading the value.
+//!
+//! The limits of the config apply to the serialized value, before any layer is
+//! applied when serializing. When deserializing, the deserialize limit bounds the
+//! output of every layer as well as the value: each layer is given the limit, and
+//! what it produced is checked against it before the next layer runs. Layers that
+//! expand their input, like decompression, should stop as soon as their output
+//! would pass the limit rather than produce all of it first.
+//!
+//! ```
+//! # extern crate bincode2;
+//! use bincode2::layer::FrameLayer;
+//! use bincode2::{ErrorKind, Result};
+//!
+//! /// Appends the sum of the bytes, and checks it when removed.
+//! struct Checksum;
+//!
+//! impl FrameLayer for Checksum {
+//!     fn encode(&self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+//!         let sum = input.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+//!         output.extend_from_slice(input);
+//!         output.push(sum);
+//!         Ok(())
+//!     }
+//!
+//!     fn decode(&self, input: &[u8], output: &mut Vec<u8>, _limit: Option<u64>) -> Result<()> {
+//!         let (sum, frame) = match input.split_last() {
+//!             Some(split) => split,
+//!             None => return Err(ErrorKind::Custom("empty frame".into()).into()),
+//!         };
+//!         if frame.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != *sum {
+//!             return Err(ErrorKind::Custom("bad checksum".into()).into());
+//!         }
+//!         output.extend_from_slice(frame);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let config = bincode2::config().with_layers(vec![Box::new(Checksum)]);
+//! let bytes = config.serialize(&(1u8, 2u8)).unwrap();
+//! assert_eq!(bytes, [1, 2, 3]);
+//! assert_eq!(config.deserialize::<(u8, u8)>(&bytes).unwrap(), (1, 2));
+//! assert!(config.deserialize::<(u8, u8)>(&[1, 2, 4]).is_err());
+//! # }
+//! ```
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::type_name;
+use core::cmp;
+use core::fmt;
+use core::mem;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use core2::io::{Read, Write};
+use serde;
+
+use config::Config;
+use error::{ErrorKind, Result};
+
+/// A reversible transformation of the bytes of a message, see the module
+/// documentation.
+pub trait FrameLayer: Send + Sync {
+    /// Appends to `output` the bytes `input` is turned into.
+    fn encode(&self, input: &[u8], output: &mut Vec<u8>) -> Result<()>;
+
+    /// Appends to `output` the bytes `encode` was given to produce `input`.
+    ///
+    /// With a `limit`, a layer that can produce more bytes than it reads should
+    /// fail with `ErrorKind::SizeLimit` once it would append more than `limit`
+    /// bytes. Whatever it appends is checked against the limit afterwards.
+    fn decode(&self, input: &[u8], output: &mut Vec<u8>, limit: Option<u64>) -> Result<()>;
+
+    /// Like `encode`, writing to `output` as the bytes are produced. The
+    /// outermost layer is given the writer of `LayeredConfig::serialize_into`,
+    /// and must write a frame that `decode_from` can find the end of.
+    ///
+    /// The default encodes into a buffer, then writes its length as a little
+    /// endian `u64` followed by the buffer. A layer overriding one of
+    /// `encode_to` and `decode_from` should override the other.
+    fn encode_to(&self, input: &[u8], output: &mut dyn Write) -> Result<()> {
+        let mut buffer = Vec::new();
+        self.encode(input, &mut buffer)?;
+        output.write_u64::<LittleEndian>(buffer.len() as u64)?;
+        output.write_all(&buffer)?;
+        Ok(())
+    }
+
+    /// Like `decode`, reading `input` as it goes. The outermost layer is given
+    /// the reader of `LayeredConfig::deserialize_from`, which it must read no
+    /// further than the end of the frame written by `encode_to`, so that the
+    /// frames following it can be read.
+    ///
+    /// The default reads the length written by the default `encode_to`, then
+    /// that many bytes, and decodes them.
+    fn decode_from(
+        &self,
+        input: &mut dyn Read,
+        output: &mut Vec<u8>,
+        limit: Option<u64>,
+    ) -> Result<()> {
+        let len = input.read_u64::<LittleEndian>()?;
+        let mut buffer = Vec::new();
+        read_frame(input, len, &mut buffer)?;
+        self.decode(&buffer, output, limit)
+    }
+
+    /// A name for the layer, shown by the `Debug` impl of `LayeredConfig`.
+    fn name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+}
+
+/// Reads `len` bytes of `input` into `frame`, growing it as the bytes arrive so
+/// that a corrupt length can't make it allocate more than the input holds.
+fn read_frame(input: &mut dyn Read, len: u64, frame: &mut Vec<u8>) -> Result<()> {
+    let mut chunk = [0; 4096];
+    let mut left = len;
+    while left > 0 {
+        let n = cmp::min(left, chunk.len() as u64) as usize;
+        input.read_exact(&mut chunk[..n])?;
+        frame.extend_from_slice(&chunk[..n]);
+        left -= n as u64;
+    }
+    Ok(())
+}
+
+/// A `Config` whose messages pass through a stack of `FrameLayer`s, created by
+/// `Config::with_layers`.
+#[derive(Clone)]
+pub struct LayeredConfig {
+    config: Config,
+    layers: Arc<[Box<dyn FrameLayer>]>,
+}
+
+impl fmt::Debug for LayeredConfig {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        let layers: Vec<_> = self.layers.iter().map(|layer| layer.name()).collect();
+        formatter
+            .debug_struct("LayeredConfig")
+            .field("config", &self.config)
+            .field("layers", &layers)
+            .finish()
+    }
+}
+
+impl LayeredConfig {
+    #[inline(always)]
+    pub(crate) fn new(config: Config, layers: Vec<Box<dyn FrameLayer>>) -> LayeredConfig {
+        LayeredConfig {
+            config,
+            layers: layers.into(),
+        }
+    }
+
+    /// The configuration values are serialized with.
+    #[inline(always)]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Serializes `t`, then applies the layers in order.
+    pub fn serialize<T: ?Sized + serde::Serialize>(&self, t: &T) -> Result<Vec<u8>> {
+        let frame = self.config.serialize(t)?;
+        self.encode(frame, self.layers.len())
+    }
+
+    /// Serializes `t` into `writer`, the outermost layer writing to it with
+    /// `FrameLayer::encode_to`.
+    pub fn serialize_into<W: Write, T: ?Sized + serde::Serialize>(
+        &self,
+        mut writer: W,
+        t: &T,
+    ) -> Result<()> {
+        let outermost = match self.layers.last() {
+            Some(outermost) => outermost,
+            None => return self.config.serialize_into(writer, t),
+        };
+        let frame = self.config.serialize(t)?;
+        let frame = self.encode(frame, self.layers.len() - 1)?;
+        outermost.encode_to(&frame, &mut writer)
+    }
+
+    /// Removes the layers from `bytes` in reverse order, then deserializes the value.
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        let (outermost, inner) = match self.layers.split_last() {
+            Some(split) => split,
+            None => return self.config.deserialize(bytes),
+        };
+        let limit = self.config.deserialize_byte_limit();
+        let mut frame = Vec::new();
+        outermost.decode(bytes, &mut frame, limit)?;
+        check_limit(&frame, limit)?;
+        let frame = self.decode(frame, inner, limit)?;
+        self.config.deserialize(&frame)
+    }
+
+    /// Reads a message from `reader`, the outermost layer reading it with
+    /// `FrameLayer::decode_from`, then deserializes it as `deserialize` does.
+    pub fn deserialize_from<R: Read, T: serde::de::DeserializeOwned>(
+        &self,
+        mut reader: R,
+    ) -> Result<T> {
+        let (outermost, inner) = match self.layers.split_last() {
+            Some(split) => split,
+            None => return self.config.deserialize_from(reader),
+        };
+        let limit = self.config.deserialize_byte_limit();
+        let mut frame = Vec::new();
+        outermost.decode_from(&mut reader, &mut frame, limit)?;
+        check_limit(&frame, limit)?;
+        let frame = self.decode(frame, inner, limit)?;
+        self.config.deserialize(&frame)
+    }
+
+    /// Applies the first `count` layers to `frame`.
+    fn encode(&self, mut frame: Vec<u8>, count: usize) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        for layer in &self.layers[..count] {
+            output.clear();
+            layer.encode(&frame, &mut output)?;
+            mem::swap(&mut frame, &mut output);
+        }
+        Ok(frame)
+    }
+
+    /// Removes `layers` from `frame`, the last one first, checking the output of
+    /// each against `limit`.
+    fn decode(
+        &self,
+        mut frame: Vec<u8>,
+        layers: &[Box<dyn FrameLayer>],
+        limit: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        for layer in layers.iter().rev() {
+            output.clear();
+            layer.decode(&frame, &mut output, limit)?;
+            check_limit(&output, limit)?;
+            mem::swap(&mut frame, &mut output);
+        }
+        Ok(frame)
+    }
+}
+
+fn check_limit(frame: &[u8], limit: Option<u64>) -> Result<()> {
+    match limit {
+        Some(limit) if frame.len() as u64 > limit => Err(ErrorKind::SizeLimit {
+            limit,
+            attempted: frame.len() as u64,
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
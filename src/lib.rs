@@ -24,8 +24,12 @@
 #![deny(missing_docs)]
 #![no_std]
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 extern crate byteorder;
 
 extern crate core2;
@@ -35,45 +39,117 @@ extern crate thiserror_core2;
 #[macro_use]
 extern crate serde;
 
+#[cfg(feature = "derive")]
+extern crate bincode2_derive;
+
+#[cfg(feature = "erased-serde")]
+extern crate erased_serde;
+
+#[cfg(feature = "codec")]
+extern crate tokio_util;
+
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "num-bigint")]
+extern crate num_bigint;
+#[cfg(feature = "time")]
+extern crate time;
+#[cfg(feature = "uuid")]
+extern crate uuid;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "testutil")]
+extern crate proptest;
+
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
 
-mod config;
+#[cfg(feature = "alloc")]
+mod batch;
+#[cfg(feature = "alloc")]
+mod columnar;
+#[cfg(feature = "bytes")]
+mod bytes_io;
+#[cfg(feature = "codec")]
+pub mod codec;
+pub mod compat;
+#[macro_use]
+pub mod config;
 mod de;
+mod encode;
+#[cfg(feature = "erased-serde")]
+pub mod erased;
 mod error;
+#[cfg(any(
+    feature = "chrono",
+    feature = "num-bigint",
+    feature = "time",
+    feature = "uuid"
+))]
+pub mod ext;
+pub mod format;
+mod intern;
 mod internal;
+#[cfg(feature = "alloc")]
+pub mod layer;
+mod max_size;
+mod metrics;
+#[cfg(feature = "alloc")]
+mod nested;
+#[cfg(feature = "alloc")]
+mod pool;
+pub mod raw;
 mod ser;
-
-pub use config::{Config, LengthOption};
-pub use de::read::{BincodeRead, IoReader, SliceReader};
+#[cfg(feature = "sharing")]
+pub mod sharing;
+#[cfg(feature = "std")]
+mod std_io;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+mod type_codec;
+#[cfg(feature = "validate")]
+mod validate;
+
+#[cfg(feature = "alloc")]
+pub use batch::Batch;
+#[cfg(feature = "alloc")]
+pub use columnar::Columns;
+#[cfg(feature = "std")]
+pub use config::PooledConfig;
+pub use config::{
+    Config, ConfigBuilder, CustomLength, CustomSizeType, FloatEncoding, IntEncoding, LengthOption,
+    Overflow, StringMode, UnknownVariant, UsizeMode, VariantIndexMap,
+};
+#[cfg(all(feature = "alloc", target_has_atomic = "64"))]
+pub use config::BudgetedConfig;
+#[cfg(feature = "alloc")]
+pub use config::CancellableConfig;
+pub use de::read::{BincodeRead, FillBuf, ScratchReader, SliceReader};
+#[cfg(feature = "alloc")]
+pub use de::read::{ChainedSliceReader, FillBufReader, IoReader, ResumableReader};
+#[cfg(feature = "bytes")]
+pub use bytes_io::{BufMutWriter, BufSource, BytesReader};
+pub use encode::{Decode, Decoder, Encode, Encoder};
+#[cfg(feature = "derive")]
+pub use bincode2_derive::{Decode, Encode};
 pub use error::{Error, ErrorKind, Result};
-
-/// An object that implements this trait can be passed a
-/// serde::Deserializer without knowing its concrete type.
-///
-/// This trait should be used only for `with_deserializer` functions.
-#[doc(hidden)]
-pub trait DeserializerAcceptor<'a> {
-    /// The return type for the accept method
-    type Output;
-    /// Accept a serde::Deserializer and do whatever you want with it.
-    fn accept<T>(self, T) -> Self::Output
-    where
-        T: serde::Deserializer<'a, Error = Error>;
-}
-
-/// An object that implements this trait can be passed a
-/// serde::Serializer without knowing its concrete type.
-///
-/// This trait should be used only for `with_serializer` functions.
-#[doc(hidden)]
-pub trait SerializerAcceptor {
-    /// The return type for the accept method
-    type Output;
-    /// Accept a serde::Serializer and do whatever you want with it.
-    fn accept<T>(self, T) -> Self::Output
-    where
-        T: serde::Serializer<Ok = (), Error = Error>;
-}
+#[cfg(any(not(feature = "alloc"), feature = "compact-error"))]
+pub use error::{ErrorMessage, ERROR_MESSAGE_CAPACITY};
+pub use format::{FormatVersion, FORMAT_HASH};
+pub use intern::StringInterner;
+pub use max_size::{max_serialized_size, MaxSize};
+#[cfg(feature = "metrics")]
+pub use metrics::Stats;
+#[cfg(feature = "alloc")]
+pub use nested::NestedMessage;
+#[cfg(feature = "std")]
+pub use pool::{Pool, PooledBytes};
+pub use ser::placeholder::Placeholder;
+#[cfg(feature = "std")]
+pub use std_io::{StdReadAdapter, StdWriteAdapter};
+pub use type_codec::TypeCodec;
 
 /// Get a default configuration object.
 ///
@@ -100,6 +176,7 @@ where
 }
 
 /// Serializes a serializable object into a `Vec` of bytes using the default configuration.
+#[cfg(feature = "alloc")]
 pub fn serialize<T: ?Sized>(value: &T) -> Result<Vec<u8>>
 where
     T: serde::Serialize,
@@ -110,6 +187,7 @@ where
 /// Deserializes an object directly from a `Read`er using the default configuration.
 ///
 /// If this returns an `Error`, `reader` may be in an invalid state.
+#[cfg(feature = "alloc")]
 pub fn deserialize_from<R, T>(reader: R) -> Result<T>
 where
     R: core2::io::Read,
@@ -131,10 +209,10 @@ where
     config().deserialize_from_custom(reader)
 }
 
-/// Only use this if you know what you're doing.
+/// Deserializes an object from a `BincodeRead`er into an existing value using the default
+/// configuration, reusing its allocations where possible. See `Config::deserialize_in_place`.
 ///
-/// This is part of the public API.
-#[doc(hidden)]
+/// If this returns an `Error`, `place` is left in a valid but unspecified state.
 pub fn deserialize_in_place<'a, R, T>(reader: R, place: &mut T) -> Result<()>
 where
     T: serde::de::Deserialize<'a>,
@@ -143,6 +221,15 @@ where
     config().deserialize_in_place(reader, place)
 }
 
+/// Deserializes a slice of bytes into an existing value using the default configuration,
+/// reusing its allocations where possible. See `Config::deserialize_in_place`.
+pub fn deserialize_in_place_slice<'a, T>(bytes: &'a [u8], place: &mut T) -> Result<()>
+where
+    T: serde::de::Deserialize<'a>,
+{
+    config().deserialize_in_place_slice(bytes, place)
+}
+
 /// Deserializes a slice of bytes into an instance of `T` using the default configuration.
 pub fn deserialize<'a, T>(bytes: &'a [u8]) -> Result<T>
 where
@@ -158,25 +245,3 @@ where
 {
     config().serialized_size(value)
 }
-
-/// Executes the acceptor with a serde::Deserializer instance.
-/// NOT A PART OF THE STABLE PUBLIC API
-#[doc(hidden)]
-pub fn with_deserializer<'a, A, R>(reader: R, acceptor: A) -> A::Output
-where
-    A: DeserializerAcceptor<'a>,
-    R: BincodeRead<'a>,
-{
-    config().with_deserializer(reader, acceptor)
-}
-
-/// Executes the acceptor with a serde::Serializer instance.
-/// NOT A PART OF THE STABLE PUBLIC API
-#[doc(hidden)]
-pub fn with_serializer<A, W>(writer: W, acceptor: A) -> A::Output
-where
-    A: SerializerAcceptor,
-    W: core2::io::Write,
-{
-    config().with_serializer(writer, acceptor)
-}
@@ -0,0 +1,131 @@
+//! Upper bounds on the encoded size of types, known at compile time.
+
+use core::marker::PhantomData;
+
+/// A type whose encoding never takes more than `MAX_SIZE` bytes.
+///
+/// Only types without a length prefix can implement this: primitives, arrays,
+/// tuples, options and combinations of those. The bound holds for every
+/// configuration except:
+///
+/// - those with `tagged_fields`, `allow_flatten`, `allow_untagged`,
+///   `variant_lengths` or `dedup_subtrees` set, as those add headers of their own,
+///   `dedup_subtrees` a tag byte before every struct and tuple struct;
+/// - those whose `int_encoding` is `IntEncoding::Varint`, which writes an integer
+///   in up to one byte more than its width, and whose `usize_encoding` is
+///   `UsizeMode::Varint`, which writes `usize` and `isize` values in up to 9 bytes;
+/// - those whose `float_encoding` writes `f32` values in 8 bytes;
+/// - those that are `aligned`.
+///
+/// Types written by a `TypeCodec` take whatever size it writes.
+///
+/// Structs and enums implement it by hand, summing their fields. An enum takes
+/// 4 bytes for the variant index plus its largest variant:
+///
+/// ```
+/// use bincode2::MaxSize;
+///
+/// struct Header {
+///     id: u32,
+///     flags: Option<u8>,
+///     digest: [u8; 16],
+/// }
+///
+/// impl MaxSize for Header {
+///     const MAX_SIZE: usize = u32::MAX_SIZE + <Option<u8>>::MAX_SIZE + <[u8; 16]>::MAX_SIZE;
+/// }
+///
+/// let frame = [0u8; bincode2::max_serialized_size::<Header>()];
+/// assert_eq!(frame.len(), 4 + 2 + 16);
+/// ```
+pub trait MaxSize {
+    /// The largest number of bytes a value of this type serializes to.
+    const MAX_SIZE: usize;
+}
+
+/// Returns the largest number of bytes a `T` can serialize to, see `MaxSize`.
+#[inline(always)]
+pub const fn max_serialized_size<T: MaxSize + ?Sized>() -> usize {
+    T::MAX_SIZE
+}
+
+macro_rules! impl_max_size {
+    ($($ty:ty => $size:expr,)*) => {
+        $(
+            impl MaxSize for $ty {
+                const MAX_SIZE: usize = $size;
+            }
+        )*
+    }
+}
+
+impl_max_size! {
+    () => 0,
+    bool => 1,
+    u8 => 1,
+    u16 => 2,
+    u32 => 4,
+    u64 => 8,
+    u128 => 16,
+    i8 => 1,
+    i16 => 2,
+    i32 => 4,
+    i64 => 8,
+    i128 => 16,
+    f32 => 4,
+    f64 => 8,
+    // 64 bit integers, unless `usize_encoding` says otherwise
+    usize => 8,
+    isize => 8,
+    char => 4,
+}
+
+impl<T: ?Sized> MaxSize for PhantomData<T> {
+    const MAX_SIZE: usize = 0;
+}
+
+impl<T: MaxSize> MaxSize for Option<T> {
+    const MAX_SIZE: usize = 1 + T::MAX_SIZE;
+}
+
+impl<T: MaxSize, E: MaxSize> MaxSize for Result<T, E> {
+    const MAX_SIZE: usize = 4 + if T::MAX_SIZE > E::MAX_SIZE {
+        T::MAX_SIZE
+    } else {
+        E::MAX_SIZE
+    };
+}
+
+impl<T: MaxSize, const N: usize> MaxSize for [T; N] {
+    const MAX_SIZE: usize = T::MAX_SIZE * N;
+}
+
+impl<'a, T: MaxSize + ?Sized> MaxSize for &'a T {
+    const MAX_SIZE: usize = T::MAX_SIZE;
+}
+
+#[cfg(feature = "alloc")]
+impl<T: MaxSize + ?Sized> MaxSize for ::alloc::boxed::Box<T> {
+    const MAX_SIZE: usize = T::MAX_SIZE;
+}
+
+macro_rules! impl_max_size_tuple {
+    ($($name:ident)+) => {
+        impl<$($name: MaxSize),+> MaxSize for ($($name,)+) {
+            const MAX_SIZE: usize = 0 $(+ $name::MAX_SIZE)+;
+        }
+    }
+}
+
+impl_max_size_tuple! { A }
+impl_max_size_tuple! { A B }
+impl_max_size_tuple! { A B C }
+impl_max_size_tuple! { A B C D }
+impl_max_size_tuple! { A B C D E }
+impl_max_size_tuple! { A B C D E F }
+impl_max_size_tuple! { A B C D E F G }
+impl_max_size_tuple! { A B C D E F G H }
+impl_max_size_tuple! { A B C D E F G H I }
+impl_max_size_tuple! { A B C D E F G H I J }
+impl_max_size_tuple! { A B C D E F G H I J K }
+impl_max_size_tuple! { A B C D E F G H I J K L }
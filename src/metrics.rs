@@ -0,0 +1,173 @@
+//! Counters kept while serializing and deserializing, see `Stats`.
+//!
+//! Every `Serializer` and `Deserializer` holds a `Recorder`. Without the `metrics`
+//! feature it has no fields and its methods do nothing, so it costs nothing.
+
+#[cfg(feature = "metrics")]
+use std::cell::Cell;
+
+/// What a call to serialize or deserialize a value went through, returned by
+/// `Config::last_stats` and added up by `Config::serialize_with_stats` and
+/// `Config::deserialize_with_stats`.
+///
+/// Length prefixes are counted in `bytes` but are not values of their own.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of bytes written or read.
+    pub bytes: u64,
+    /// The number of values, counting each struct, sequence, map, option and
+    /// enum as well as the values inside them.
+    pub values: u64,
+    /// The number of strings.
+    pub strings: u64,
+    /// The number of buffers allocated: strings and byte buffers read into owned
+    /// memory when deserializing, and values buffered to find their size when
+    /// serializing. Allocations made by the types being deserialized are not counted,
+    /// nor are buffers reused from a `Pool`.
+    pub allocations: u64,
+    /// How deeply structs, tuples, sequences and maps were nested, 0 for a primitive.
+    pub max_depth: u32,
+}
+
+#[cfg(feature = "metrics")]
+impl Stats {
+    /// Adds the counters of `other` to these, keeping the larger `max_depth`.
+    pub fn add(&mut self, other: &Stats) {
+        self.bytes += other.bytes;
+        self.values += other.values;
+        self.strings += other.strings;
+        self.allocations += other.allocations;
+        self.max_depth = self.max_depth.max(other.max_depth);
+    }
+}
+
+#[cfg(feature = "metrics")]
+::std::thread_local! {
+    static LAST: Cell<Stats> = Cell::new(Stats::default());
+}
+
+/// The statistics of the last top-level call made on this thread.
+#[cfg(feature = "metrics")]
+pub(crate) fn last() -> Stats {
+    LAST.with(Cell::get)
+}
+
+/// Counts what a `Serializer` or `Deserializer` goes through.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    #[cfg(feature = "metrics")]
+    stats: Stats,
+    #[cfg(feature = "metrics")]
+    depth: u32,
+}
+
+/// The value count at some point, so that values read or written as part of
+/// something else, like length prefixes, can be left out.
+pub(crate) struct Mark {
+    #[cfg(feature = "metrics")]
+    values: u64,
+}
+
+impl Recorder {
+    #[inline(always)]
+    pub(crate) fn bytes(&mut self, _count: u64) {
+        #[cfg(feature = "metrics")]
+        {
+            self.stats.bytes += _count;
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn value(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            self.stats.values += 1;
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn string(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            self.stats.strings += 1;
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn allocation(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            self.stats.allocations += 1;
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn enter(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            self.depth += 1;
+            self.stats.max_depth = self.stats.max_depth.max(self.depth);
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn leave(&mut self) {
+        #[cfg(feature = "metrics")]
+        {
+            self.depth = self.depth.saturating_sub(1);
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn mark(&self) -> Mark {
+        Mark {
+            #[cfg(feature = "metrics")]
+            values: self.stats.values,
+        }
+    }
+
+    /// Forgets the values counted since `mark`.
+    #[inline(always)]
+    pub(crate) fn rewind(&mut self, _mark: Mark) {
+        #[cfg(feature = "metrics")]
+        {
+            self.stats.values = _mark.values;
+        }
+    }
+
+    /// Ends a top-level call, making its statistics those returned by `last`.
+    #[inline(always)]
+    pub(crate) fn finish(&self) {
+        #[cfg(feature = "metrics")]
+        {
+            let stats = self.stats;
+            LAST.with(|last| last.set(stats));
+            #[cfg(feature = "tracing")]
+            ::tracing::trace!(
+                bytes = stats.bytes,
+                values = stats.values,
+                strings = stats.strings,
+                allocations = stats.allocations,
+                max_depth = stats.max_depth,
+                "finished"
+            );
+        }
+    }
+}
+
+/// Enters a span for a top-level call, left when the returned guard is dropped.
+#[cfg(all(feature = "metrics", feature = "tracing"))]
+pub(crate) fn span(operation: &'static str) -> ::tracing::span::EnteredSpan {
+    ::tracing::trace_span!("bincode2", operation).entered()
+}
+
+/// Stands in for a span guard when there are no spans.
+#[cfg(not(all(feature = "metrics", feature = "tracing")))]
+pub(crate) struct NoSpan;
+
+#[cfg(not(all(feature = "metrics", feature = "tracing")))]
+#[inline(always)]
+pub(crate) fn span(_operation: &'static str) -> NoSpan {
+    NoSpan
+}
@@ -0,0 +1,166 @@
+//! Messages carried inside other messages, see `NestedMessage`.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde;
+
+use config::Config;
+use error::Result;
+
+/// A message encoded on its own and carried inside another one as a byte array,
+/// which can be read and written again without being decoded.
+///
+/// A `NestedMessage` is written as its length, using the array length of the
+/// config, followed by its bytes: the same as a `serde_bytes` byte buffer
+/// holding the encoded message. Deserializing one reads that length and keeps
+/// the bytes without decoding them, so a router can forward a payload without
+/// decoding and encoding it again, and without its bytes changing.
+///
+/// The length is what makes this possible. The encoding of a value says nothing
+/// of where it ends, so a value written inline, as a field of its own type,
+/// can't be skipped or captured without its type. Both ends of a link must
+/// therefore agree to carry the payload as a `NestedMessage`, and its bytes are
+/// not those of the payload written inline. The nested message may even be
+/// encoded with another config than the message carrying it.
+///
+/// Deserialized from a slice, a `NestedMessage` field marked `#[serde(borrow)]`
+/// borrows its bytes, otherwise they are copied.
+///
+/// ```
+/// # extern crate bincode2;
+/// # #[macro_use] extern crate serde_derive;
+/// use bincode2::NestedMessage;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Envelope<'a> {
+///     route: u16,
+///     #[serde(borrow)]
+///     payload: NestedMessage<'a>,
+/// }
+///
+/// # fn main() {
+/// let config = bincode2::config();
+/// let payload = NestedMessage::from_value(&config, &("ping", 7u32)).unwrap();
+/// let bytes = config.serialize(&Envelope { route: 3, payload }).unwrap();
+///
+/// // A router reads the envelope and forwards the payload untouched.
+/// let envelope: Envelope = config.deserialize(&bytes).unwrap();
+/// assert_eq!(config.serialize(&envelope).unwrap(), bytes);
+///
+/// // The receiver decodes it.
+/// let (name, arg): (&str, u32) = envelope.payload.to_value(&config).unwrap();
+/// assert_eq!((name, arg), ("ping", 7));
+/// # }
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct NestedMessage<'a> {
+    bytes: Cow<'a, [u8]>,
+}
+
+impl<'a> NestedMessage<'a> {
+    /// Wraps the bytes of an encoded message. They are not checked.
+    #[inline(always)]
+    pub fn from_bytes(bytes: &'a [u8]) -> NestedMessage<'a> {
+        NestedMessage {
+            bytes: Cow::Borrowed(bytes),
+        }
+    }
+
+    /// Encodes `value` with `config` as a message of its own.
+    pub fn from_value<T: ?Sized + serde::Serialize>(
+        config: &Config,
+        value: &T,
+    ) -> Result<NestedMessage<'static>> {
+        Ok(NestedMessage {
+            bytes: Cow::Owned(config.serialize(value)?),
+        })
+    }
+
+    /// Decodes the value with `config`, which should be the config it was encoded with.
+    pub fn to_value<'b, T: serde::Deserialize<'b>>(&'b self, config: &Config) -> Result<T> {
+        config.deserialize(&self.bytes)
+    }
+
+    /// The encoded message, without its length.
+    #[inline(always)]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Copies the bytes if they are borrowed.
+    pub fn into_owned(self) -> NestedMessage<'static> {
+        NestedMessage {
+            bytes: Cow::Owned(self.bytes.into_owned()),
+        }
+    }
+}
+
+impl<'a> From<Vec<u8>> for NestedMessage<'a> {
+    /// Wraps the bytes of an encoded message. They are not checked.
+    #[inline(always)]
+    fn from(bytes: Vec<u8>) -> NestedMessage<'a> {
+        NestedMessage {
+            bytes: Cow::Owned(bytes),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for NestedMessage<'a> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_tuple("NestedMessage")
+            .field(&self.as_bytes())
+            .finish()
+    }
+}
+
+impl<'a> serde::Serialize for NestedMessage<'a> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> ::core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.bytes)
+    }
+}
+
+impl<'de: 'a, 'a> serde::Deserialize<'de> for NestedMessage<'a> {
+    fn deserialize<D>(deserializer: D) -> ::core::result::Result<NestedMessage<'a>, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(NestedMessageVisitor)
+    }
+}
+
+struct NestedMessageVisitor;
+
+impl<'de> serde::de::Visitor<'de> for NestedMessageVisitor {
+    type Value = NestedMessage<'de>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an encoded message")
+    }
+
+    fn visit_borrowed_bytes<E: serde::de::Error>(
+        self,
+        v: &'de [u8],
+    ) -> ::core::result::Result<NestedMessage<'de>, E> {
+        Ok(NestedMessage::from_bytes(v))
+    }
+
+    fn visit_bytes<E: serde::de::Error>(
+        self,
+        v: &[u8],
+    ) -> ::core::result::Result<NestedMessage<'de>, E> {
+        Ok(NestedMessage::from(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: serde::de::Error>(
+        self,
+        v: Vec<u8>,
+    ) -> ::core::result::Result<NestedMessage<'de>, E> {
+        Ok(NestedMessage::from(v))
+    }
+}
@@ -0,0 +1,235 @@
+//! Reusing the buffers `serialize` allocates, see `Pool`.
+//!
+//! Every `Serializer` holds a `Scratch` it takes its internal buffers from.
+//! Without a pool, or without the `std` feature, those are plain new `Vec`s.
+
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use core::fmt;
+#[cfg(feature = "std")]
+use core::mem;
+#[cfg(feature = "std")]
+use core::ops::Deref;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// The most buffers a pool from `Pool::new` keeps.
+#[cfg(feature = "std")]
+const DEFAULT_MAX_IDLE: usize = 64;
+
+/// The largest buffer a pool from `Pool::new` keeps, in bytes of capacity.
+#[cfg(feature = "std")]
+const DEFAULT_MAX_CAPACITY: usize = 1 << 20;
+
+/// Buffers kept for reuse by `Config::with_buffer_pool`.
+///
+/// A `PooledConfig::serialize` call takes its output buffer, and the buffers
+/// it needs along the way, out of the pool. The output buffer comes back when
+/// the `PooledBytes` holding it is dropped, the others when the call returns.
+/// Once the pool holds buffers of the sizes the messages need, serializing
+/// allocates nothing.
+///
+/// The pool holds at most a number of buffers, each of at most a capacity, so
+/// that a burst of messages or one large message does not keep their memory
+/// for the life of the pool. Buffers given back past either bound are freed.
+/// `Pool::new` keeps 64 buffers of up to 1 MiB; `Pool::bounded` sets both.
+///
+/// Clones share the same buffers, so one pool can serve many threads.
+#[cfg(feature = "std")]
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+#[cfg(feature = "std")]
+struct Shared {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    max_idle: usize,
+    max_capacity: usize,
+}
+
+#[cfg(feature = "std")]
+impl Pool {
+    /// An empty pool, which fills up as buffers are given back to it.
+    pub fn new() -> Pool {
+        Pool::bounded(DEFAULT_MAX_IDLE, DEFAULT_MAX_CAPACITY)
+    }
+
+    /// An empty pool keeping at most `max_idle` buffers, of at most
+    /// `max_capacity` bytes each.
+    pub fn bounded(max_idle: usize, max_capacity: usize) -> Pool {
+        Pool {
+            shared: Arc::new(Shared {
+                buffers: Mutex::new(Vec::new()),
+                max_idle,
+                max_capacity,
+            }),
+        }
+    }
+
+    /// A pool holding `buffers` buffers of `capacity` bytes each, so that the
+    /// first calls do not allocate either. The bounds of `Pool::new` are raised
+    /// to keep them.
+    pub fn with_buffers(buffers: usize, capacity: usize) -> Pool {
+        let pool = Pool::bounded(
+            buffers.max(DEFAULT_MAX_IDLE),
+            capacity.max(DEFAULT_MAX_CAPACITY),
+        );
+        {
+            let mut idle = pool.lock();
+            idle.reserve_exact(buffers);
+            for _ in 0..buffers {
+                idle.push(Vec::with_capacity(capacity));
+            }
+        }
+        pool
+    }
+
+    /// The number of buffers waiting in the pool.
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.lock().pop().unwrap_or_default()
+    }
+
+    pub(crate) fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() == 0 || buffer.capacity() > self.shared.max_capacity {
+            return;
+        }
+        buffer.clear();
+        let mut idle = self.lock();
+        if idle.len() < self.shared.max_idle {
+            idle.push(buffer);
+        }
+    }
+
+    /// Takes an output buffer, given back when the returned handle is dropped.
+    pub(crate) fn checkout(&self) -> PooledBytes {
+        PooledBytes {
+            bytes: self.take(),
+            pool: self.clone(),
+        }
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        // The buffers are cleared before they are given back, so a panic while
+        // the lock is held cannot leave them in a state worth refusing.
+        self.shared
+            .buffers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Pool {
+    fn default() -> Pool {
+        Pool::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Pool {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_struct("Pool")
+            .field("idle", &self.idle())
+            .field("max_idle", &self.shared.max_idle)
+            .field("max_capacity", &self.shared.max_capacity)
+            .finish()
+    }
+}
+
+/// Bytes serialized by `PooledConfig::serialize`, whose buffer goes back to
+/// the `Pool` when this is dropped.
+#[cfg(feature = "std")]
+pub struct PooledBytes {
+    bytes: Vec<u8>,
+    pool: Pool,
+}
+
+#[cfg(feature = "std")]
+impl PooledBytes {
+    /// Keeps the bytes, so that the buffer does not go back to the pool.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        mem::take(&mut self.bytes)
+    }
+
+    pub(crate) fn buffer(&mut self) -> &mut Vec<u8> {
+        &mut self.bytes
+    }
+}
+
+#[cfg(feature = "std")]
+impl Deref for PooledBytes {
+    type Target = [u8];
+
+    #[inline(always)]
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(feature = "std")]
+impl AsRef<[u8]> for PooledBytes {
+    #[inline(always)]
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for PooledBytes {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.bytes.fmt(formatter)
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for PooledBytes {
+    fn drop(&mut self) {
+        self.pool.give(mem::take(&mut self.bytes));
+    }
+}
+
+/// Where a `Serializer` gets the buffers it fills before writing them out.
+#[derive(Default)]
+pub(crate) struct Scratch {
+    #[cfg(feature = "std")]
+    pool: Option<Pool>,
+}
+
+impl Scratch {
+    /// Takes its buffers from `pool`.
+    #[cfg(feature = "std")]
+    pub(crate) fn pooled(pool: &Pool) -> Scratch {
+        Scratch {
+            pool: Some(pool.clone()),
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        #[cfg(feature = "std")]
+        {
+            if let Some(ref pool) = self.pool {
+                return pool.take();
+            }
+        }
+        Vec::new()
+    }
+
+    /// Gives back a buffer once its bytes have been written out.
+    #[inline(always)]
+    pub(crate) fn give(&mut self, _buffer: Vec<u8>) {
+        #[cfg(feature = "std")]
+        {
+            if let Some(ref pool) = self.pool {
+                pool.give(_buffer);
+            }
+        }
+    }
+}
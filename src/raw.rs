@@ -0,0 +1,453 @@
+//! Direct access to the serde `Serializer` and `Deserializer` of a `Config`.
+//!
+//! Most code serializes whole values with `Config::serialize` and friends. Code
+//! that drives serde itself, such as an RPC framework handing a deserializer to a
+//! handler that picks the type to read, can instead get one from
+//! `Config::serializer_for` and `Config::deserializer_for`:
+//!
+//! ```
+//! # extern crate bincode2;
+//! # extern crate serde;
+//! use bincode2::SliceReader;
+//! use serde::{Deserialize, Serialize};
+//!
+//! # fn main() {
+//! let mut config = bincode2::config();
+//! config.big_endian().string_length(bincode2::LengthOption::U16);
+//!
+//! let mut bytes = Vec::new();
+//! {
+//!     let mut serializer = config.serializer_for(&mut bytes);
+//!     "ping".serialize(&mut serializer).unwrap();
+//!     7u32.serialize(&mut serializer).unwrap();
+//! }
+//! assert_eq!(bytes, config.serialize(&("ping", 7u32)).unwrap());
+//!
+//! let mut deserializer = config.deserializer_for(SliceReader::new(&bytes));
+//! let name = <&str>::deserialize(&mut deserializer).unwrap();
+//! let arg = u32::deserialize(&mut deserializer).unwrap();
+//! assert_eq!((name, arg), ("ping", 7));
+//! # }
+//! ```
+//!
+//! `write_length` and `read_length` write and read a length prefix as the config
+//! does for sequences, maps and byte arrays, for hand-written `Serialize` and
+//! `Deserialize` impls that frame data of their own:
+//!
+//! ```
+//! # extern crate bincode2;
+//! use bincode2::raw::{read_length, write_length};
+//! use bincode2::LengthOption;
+//!
+//! # fn main() {
+//! let mut config = bincode2::config();
+//! config.big_endian().array_length(LengthOption::U16);
+//!
+//! let mut bytes = Vec::new();
+//! write_length(&mut bytes, 300, &config).unwrap();
+//! assert_eq!(bytes, [0x01, 0x2c]);
+//! assert_eq!(read_length(&bytes[..], &config).unwrap(), 300);
+//! # }
+//! ```
+//!
+//! The types here encode exactly as the config does. Values are written as they
+//! are serialized, so the serialize limit of the config is not checked; the
+//! deserialize limit is, and is shared by every value read from one
+//! `Deserializer`.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core2::io::{Read, Write};
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use serde;
+use serde::de::Visitor;
+use serde::ser::SerializeMap as _;
+use serde::Deserializer as _;
+use serde::Serializer as _;
+
+use config::{Config, Options};
+use de::read::{BincodeRead, ScratchReader};
+use error::{Error, Result};
+use internal::{Bounded, Infinite, RuntimeArray, RuntimeLimit, RuntimeString, SizeLimit};
+
+/// The options of the types here: only the byte order is part of the type, the
+/// lengths are read from the config as they are written.
+struct RawOptions<'c, L, E> {
+    limit: L,
+    config: &'c Config,
+    _endian: PhantomData<E>,
+}
+
+impl<'c, L, E> RawOptions<'c, L, E> {
+    fn new(limit: L, config: &'c Config) -> RawOptions<'c, L, E> {
+        RawOptions {
+            limit,
+            config,
+            _endian: PhantomData,
+        }
+    }
+}
+
+impl<'c, L: SizeLimit + 'static, E: ByteOrder + 'static> Options for RawOptions<'c, L, E> {
+    type Limit = L;
+    type Endian = E;
+    type StringSize = RuntimeString;
+    type ArraySize = RuntimeArray;
+
+    #[inline(always)]
+    fn limit(&mut self) -> &mut L {
+        &mut self.limit
+    }
+
+    #[inline(always)]
+    fn config(&self) -> &Config {
+        self.config
+    }
+}
+
+type SerOptions<'c, E> = RawOptions<'c, Infinite, E>;
+type DeOptions<'c, E> = RawOptions<'c, RuntimeLimit, E>;
+
+/// One of two values, for the two byte orders a config may ask for.
+enum Either<L, B> {
+    Little(L),
+    Big(B),
+}
+
+/// Evaluates `$call` with `$inner` bound to the value held by `$either`.
+macro_rules! either {
+    ($either:expr, $inner:ident => $call:expr) => {
+        match $either {
+            Either::Little($inner) => $call,
+            Either::Big($inner) => $call,
+        }
+    };
+}
+
+/// Like `either!`, wrapping the `Ok` value of `$call` in the same variant.
+macro_rules! either_map {
+    ($either:expr, $inner:ident => $call:expr) => {
+        match $either {
+            Either::Little($inner) => Either::Little($call?),
+            Either::Big($inner) => Either::Big($call?),
+        }
+    };
+}
+
+/// A serde `Serializer` writing into `W` as a `Config` would, created by
+/// `Config::serializer_for`.
+///
+/// `&mut Serializer` implements `serde::Serializer`, so several values can be
+/// written one after another.
+pub struct Serializer<'c, W> {
+    inner: Either<
+        ::ser::Serializer<W, SerOptions<'c, LittleEndian>>,
+        ::ser::Serializer<W, SerOptions<'c, BigEndian>>,
+    >,
+}
+
+impl<'c, W: Write> Serializer<'c, W> {
+    pub(crate) fn new(writer: W, config: &'c Config) -> Serializer<'c, W> {
+        let inner = if config.is_big_endian() {
+            Either::Big(::ser::Serializer::new(
+                writer,
+                RawOptions::new(Infinite, config),
+            ))
+        } else {
+            Either::Little(::ser::Serializer::new(
+                writer,
+                RawOptions::new(Infinite, config),
+            ))
+        };
+        Serializer { inner }
+    }
+
+    /// Writes `len` as the length of a sequence, map or byte array.
+    pub fn write_length(&mut self, len: usize) -> Result<()> {
+        either!(&mut self.inner, s => s.write_size::<RuntimeArray>(len))
+    }
+}
+
+/// A serde `Deserializer` reading from `R` as a `Config` would, created by
+/// `Config::deserializer_for`.
+///
+/// `&mut Deserializer` implements `serde::Deserializer`, so several values can
+/// be read one after another.
+pub struct Deserializer<'c, R> {
+    inner: Either<
+        ::de::Deserializer<R, DeOptions<'c, LittleEndian>>,
+        ::de::Deserializer<R, DeOptions<'c, BigEndian>>,
+    >,
+}
+
+impl<'de, 'c, R: BincodeRead<'de>> Deserializer<'c, R> {
+    pub(crate) fn new(reader: R, config: &'c Config) -> Deserializer<'c, R> {
+        let limit = RuntimeLimit(config.deserialize_byte_limit().map(Bounded::new));
+        let inner = if config.is_big_endian() {
+            Either::Big(::de::Deserializer::new(
+                reader,
+                RawOptions::new(limit, config),
+            ))
+        } else {
+            Either::Little(::de::Deserializer::new(
+                reader,
+                RawOptions::new(limit, config),
+            ))
+        };
+        Deserializer { inner }
+    }
+
+    /// Reads the length of a sequence, map or byte array.
+    pub fn read_length(&mut self) -> Result<u64> {
+        either!(&mut self.inner, d => d.read_size::<RuntimeArray>())
+    }
+}
+
+/// Writes `len` into `writer` as `config` writes the length of a sequence, map or
+/// byte array, following its array length, `usize_encoding`, `int_encoding` and
+/// byte order.
+pub fn write_length<W: Write>(writer: W, len: usize, config: &Config) -> Result<()> {
+    Serializer::new(writer, config).write_length(len)
+}
+
+/// Reads a length written by `write_length` with the same config from `reader`.
+///
+/// Only the bytes of the length are read. The deserialize limit of `config` is
+/// checked.
+pub fn read_length<R: Read>(reader: R, config: &Config) -> Result<u64> {
+    Deserializer::new(ScratchReader::new(reader, &mut []), config).read_length()
+}
+
+macro_rules! forward_serialize {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            #[inline(always)]
+            fn $method(self, $($arg: $ty),*) -> Result<()> {
+                either!(&mut self.inner, s => s.$method($($arg),*))
+            }
+        )*
+    };
+}
+
+macro_rules! forward_compound {
+    ($($method:ident($($arg:ident: $ty:ty),*) -> $compound:ident;)*) => {
+        $(
+            #[inline(always)]
+            fn $method(self, $($arg: $ty),*) -> Result<$compound<'a, 'c, W>> {
+                Ok($compound {
+                    inner: either_map!(&mut self.inner, s => s.$method($($arg),*)),
+                })
+            }
+        )*
+    };
+}
+
+impl<'a, 'c, W: Write> serde::Serializer for &'a mut Serializer<'c, W> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = Compound<'a, 'c, W>;
+    type SerializeTuple = Compound<'a, 'c, W>;
+    type SerializeTupleStruct = Compound<'a, 'c, W>;
+    type SerializeTupleVariant = Compound<'a, 'c, W>;
+    type SerializeMap = MapCompound<'a, 'c, W>;
+    type SerializeStruct = Compound<'a, 'c, W>;
+    type SerializeStructVariant = Compound<'a, 'c, W>;
+
+    forward_serialize! {
+        serialize_bool(v: bool);
+        serialize_i8(v: i8);
+        serialize_i16(v: i16);
+        serialize_i32(v: i32);
+        serialize_i64(v: i64);
+        serialize_u8(v: u8);
+        serialize_u16(v: u16);
+        serialize_u32(v: u32);
+        serialize_u64(v: u64);
+        serialize_f32(v: f32);
+        serialize_f64(v: f64);
+        serialize_char(v: char);
+        serialize_str(v: &str);
+        serialize_bytes(v: &[u8]);
+        serialize_none();
+        serialize_unit();
+        serialize_unit_struct(name: &'static str);
+        serialize_unit_variant(name: &'static str, index: u32, variant: &'static str);
+    }
+
+    serde_if_integer128! {
+        forward_serialize! {
+            serialize_i128(v: i128);
+            serialize_u128(v: u128);
+        }
+    }
+
+    forward_compound! {
+        serialize_seq(len: Option<usize>) -> Compound;
+        serialize_tuple(len: usize) -> Compound;
+        serialize_tuple_struct(name: &'static str, len: usize) -> Compound;
+        serialize_tuple_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize
+        ) -> Compound;
+        serialize_map(len: Option<usize>) -> MapCompound;
+        serialize_struct(name: &'static str, len: usize) -> Compound;
+        serialize_struct_variant(
+            name: &'static str,
+            index: u32,
+            variant: &'static str,
+            len: usize
+        ) -> Compound;
+    }
+
+    fn serialize_some<T: ?Sized + serde::Serialize>(self, value: &T) -> Result<()> {
+        either!(&mut self.inner, s => s.serialize_some(value))
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + serde::Serialize>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        either!(&mut self.inner, s => s.serialize_newtype_struct(name, value))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + serde::Serialize>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        either!(&mut self.inner, s => s.serialize_newtype_variant(name, index, variant, value))
+    }
+
+    fn collect_str<T: ?Sized + fmt::Display>(self, value: &T) -> Result<()> {
+        either!(&mut self.inner, s => s.collect_str(value))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// Writes the elements of sequences, tuples and structs for `Serializer`.
+pub struct Compound<'a, 'c: 'a, W: 'a> {
+    inner: Either<
+        ::ser::Compound<'a, W, SerOptions<'c, LittleEndian>>,
+        ::ser::Compound<'a, W, SerOptions<'c, BigEndian>>,
+    >,
+}
+
+/// Writes the entries of maps for `Serializer`.
+pub struct MapCompound<'a, 'c: 'a, W: 'a> {
+    inner: Either<
+        ::ser::MapCompound<'a, W, SerOptions<'c, LittleEndian>>,
+        ::ser::MapCompound<'a, W, SerOptions<'c, BigEndian>>,
+    >,
+}
+
+/// Implements a `SerializeSeq`-like trait for `Compound`, whose `$element` method
+/// takes the arguments `$arg` ahead of the value.
+macro_rules! forward_compound_trait {
+    ($serialize:ident, $element:ident($($arg:ident: $ty:ty),*)) => {
+        impl<'a, 'c, W: Write> serde::ser::$serialize for Compound<'a, 'c, W> {
+            type Ok = ();
+            type Error = Error;
+
+            #[inline(always)]
+            fn $element<T: ?Sized + serde::Serialize>(
+                &mut self,
+                $($arg: $ty,)*
+                value: &T,
+            ) -> Result<()> {
+                either!(&mut self.inner, c => serde::ser::$serialize::$element(c, $($arg,)* value))
+            }
+
+            #[inline(always)]
+            fn end(self) -> Result<()> {
+                either!(self.inner, c => serde::ser::$serialize::end(c))
+            }
+        }
+    };
+}
+
+forward_compound_trait!(SerializeSeq, serialize_element());
+forward_compound_trait!(SerializeTuple, serialize_element());
+forward_compound_trait!(SerializeTupleStruct, serialize_field());
+forward_compound_trait!(SerializeTupleVariant, serialize_field());
+forward_compound_trait!(SerializeStruct, serialize_field(key: &'static str));
+forward_compound_trait!(SerializeStructVariant, serialize_field(key: &'static str));
+
+impl<'a, 'c, W: Write> serde::ser::SerializeMap for MapCompound<'a, 'c, W> {
+    type Ok = ();
+    type Error = Error;
+
+    #[inline(always)]
+    fn serialize_key<K: ?Sized + serde::Serialize>(&mut self, key: &K) -> Result<()> {
+        either!(&mut self.inner, c => c.serialize_key(key))
+    }
+
+    #[inline(always)]
+    fn serialize_value<V: ?Sized + serde::Serialize>(&mut self, value: &V) -> Result<()> {
+        either!(&mut self.inner, c => c.serialize_value(value))
+    }
+
+    #[inline(always)]
+    fn end(self) -> Result<()> {
+        either!(self.inner, c => c.end())
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            #[inline(always)]
+            fn $method<V: Visitor<'de>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value> {
+                either!(&mut self.inner, d => d.$method($($arg,)* visitor))
+            }
+        )*
+    };
+}
+
+impl<'de, 'a, 'c, R: BincodeRead<'de>> serde::Deserializer<'de> for &'a mut Deserializer<'c, R> {
+    type Error = Error;
+
+    forward_deserialize! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_string();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
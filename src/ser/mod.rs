@@ -1,14 +1,37 @@
-use core2::io::Write;
+use core2::io::{self, Write};
+use core::fmt;
+use core::mem;
 use core::u32;
 
 use serde;
 
 use byteorder::WriteBytesExt;
 
+use super::encode::Encoder;
+#[cfg(feature = "alloc")]
+use super::internal::dedup::{self, Subtrees};
+use super::internal::field;
+use super::internal::float::{self, Wire};
+use super::internal::marker;
+use super::internal::string_mode;
+use super::internal::usize_mode;
+use super::internal::varint::{self, Varint};
+use super::internal::ByteCount;
 use super::internal::SizeLimit;
 use super::internal::SizeType;
+use super::internal::{padding, promoted, truncate_str, U64};
 use super::{Error, ErrorKind, Result};
-use config::Options;
+use config::{IntEncoding, Options, Overflow, StringMode, UsizeMode};
+use metrics::Recorder;
+#[cfg(feature = "alloc")]
+use pool::Scratch;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+#[cfg(not(feature = "alloc"))]
+use core::convert::Infallible;
+
+pub mod placeholder;
 
 /// An Serializer that encodes values directly into a Writer.
 ///
@@ -20,16 +43,497 @@ use config::Options;
 pub(crate) struct Serializer<W, O: Options> {
     writer: W,
     _options: O,
+    /// Set while writing the entries of a self-describing map, see `Config::allow_flatten`,
+    /// or throughout with `Config::allow_untagged`.
+    tagged: bool,
+    /// Buffers of maps whose length is not known until their last entry is written, or
+    /// of subtrees that may be replaced by a back-reference.
+    #[cfg(feature = "alloc")]
+    pending: Vec<Vec<u8>>,
+    /// Set when `Config::dedup_subtrees` is.
+    #[cfg(feature = "alloc")]
+    subtrees: Option<Subtrees>,
+    /// The number of bytes written to the writer, from which `Config::aligned` pads.
+    position: u64,
+    pub(crate) stats: Recorder,
+    /// Where the pending buffers come from.
+    #[cfg(feature = "alloc")]
+    pub(crate) scratch: Scratch,
+}
+
+/// The destination of the bytes currently being written: the writer itself, or the
+/// innermost pending map buffer.
+enum Output<'a, W: 'a> {
+    /// The writer, the count of bytes written to it kept to refer to repeated subtrees,
+    /// and the position in it.
+    Writer(&'a mut W, Option<&'a mut u64>, &'a mut u64, &'a mut Recorder),
+    #[cfg(feature = "alloc")]
+    Buffer(&'a mut Vec<u8>),
+}
+
+impl<'a, W: Write> Write for Output<'a, W> {
+    #[inline(always)]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Output::Writer(ref mut writer, ref mut count, ref mut position, ref mut stats) => {
+                let written = writer.write(buf)?;
+                if let Some(ref mut count) = *count {
+                    **count += written as u64;
+                }
+                **position += written as u64;
+                stats.bytes(written as u64);
+                Ok(written)
+            }
+            #[cfg(feature = "alloc")]
+            Output::Buffer(ref mut buffer) => buffer.write(buf),
+        }
+    }
+
+    #[inline(always)]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match *self {
+            Output::Writer(ref mut writer, ref mut count, ref mut position, ref mut stats) => {
+                writer.write_all(buf)?;
+                if let Some(ref mut count) = *count {
+                    **count += buf.len() as u64;
+                }
+                **position += buf.len() as u64;
+                stats.bytes(buf.len() as u64);
+                Ok(())
+            }
+            #[cfg(feature = "alloc")]
+            Output::Buffer(ref mut buffer) => buffer.write_all(buf),
+        }
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Output::Writer(ref mut writer, _, _, _) => writer.flush(),
+            #[cfg(feature = "alloc")]
+            Output::Buffer(_) => Ok(()),
+        }
+    }
+}
+
+/// Writes the `Display` output of a value to `output`, as far as `left` bytes; a
+/// second pass over what `string_mode::Measure` counted.
+struct DisplayWriter<'a, W: 'a> {
+    output: Output<'a, W>,
+    left: usize,
+    /// The error the output failed with, which `fmt::Error` cannot carry.
+    error: Option<io::Error>,
+}
+
+impl<'a, W: Write> fmt::Write for DisplayWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.left);
+        if !s.is_char_boundary(len) {
+            // Not where the first pass cut the output.
+            return Err(fmt::Error);
+        }
+        if let Err(error) = self.output.write_all(&s.as_bytes()[..len]) {
+            self.error = Some(error);
+            return Err(fmt::Error);
+        }
+        self.left -= len;
+        Ok(())
+    }
 }
 
 impl<W: Write, O: Options> Serializer<W, O> {
     /// Creates a new Serializer with the given `Write`r.
     pub fn new(w: W, options: O) -> Serializer<W, O> {
+        #[cfg(feature = "alloc")]
+        let subtrees = if options.config().subtrees_deduped() {
+            Some(Subtrees::default())
+        } else {
+            None
+        };
         Serializer {
             writer: w,
+            tagged: options.config().untagged_allowed(),
             _options: options,
+            #[cfg(feature = "alloc")]
+            pending: Vec::new(),
+            #[cfg(feature = "alloc")]
+            subtrees,
+            position: 0,
+            stats: Recorder::default(),
+            #[cfg(feature = "alloc")]
+            scratch: Scratch::default(),
+        }
+    }
+
+    /// Writes values without type markers, as `Encode` types are written even with
+    /// `Config::allow_untagged` set.
+    pub(crate) fn plain(mut self) -> Self {
+        self.tagged = false;
+        self
+    }
+
+    #[inline(always)]
+    #[cfg(feature = "alloc")]
+    fn output(&mut self) -> Output<'_, W> {
+        match self.pending.last_mut() {
+            Some(buffer) => Output::Buffer(buffer),
+            None => Output::Writer(
+                &mut self.writer,
+                self.subtrees.as_mut().map(|subtrees| &mut subtrees.written),
+                &mut self.position,
+                &mut self.stats,
+            ),
+        }
+    }
+
+    /// Starts a struct, sequence or map, which is buffered to find out if it was
+    /// written before when `Config::dedup_subtrees` is set. Returns whether it is.
+    /// Buffers what is written next, in a buffer taken from the scratch. A
+    /// buffer reused from a pool is not counted as an allocation.
+    #[cfg(feature = "alloc")]
+    fn push_buffer(&mut self) {
+        let buffer = self.scratch.take();
+        if buffer.capacity() == 0 {
+            self.stats.allocation();
+        }
+        self.pending.push(buffer);
+    }
+
+    #[cfg(feature = "alloc")]
+    fn begin_subtree(&mut self) -> bool {
+        if self.tagged {
+            return false;
+        }
+        match self.subtrees {
+            Some(ref mut subtrees) => subtrees.begin(),
+            None => return false,
+        }
+        self.push_buffer();
+        true
+    }
+
+    /// Writes a buffered subtree preceded by its tag, or a back-reference to an
+    /// identical subtree written before.
+    #[cfg(feature = "alloc")]
+    fn end_subtree(&mut self) -> Result<()> {
+        let content = self.pending.pop().unwrap_or_default();
+        let start = self.pending.last().map_or(0, Vec::len);
+        // Every pending buffer is a subtree, which will be preceded by its tag.
+        let pending: u64 = self
+            .pending
+            .iter()
+            .map(|buffer| 1 + buffer.len() as u64)
+            .sum();
+
+        let (id, earlier, position) = match self.subtrees {
+            Some(ref mut subtrees) => {
+                let id = subtrees.end(&content);
+                let position = subtrees.written + pending;
+                let earlier = subtrees.start(id);
+                match earlier {
+                    Some(_) => subtrees.forget_from(position + 1),
+                    None if content.len() > dedup::MIN_LEN => subtrees.insert(id, position + 1),
+                    None => {}
+                }
+                (id, earlier, position)
+            }
+            None => return self.output().write_all(&content).map_err(Into::into),
+        };
+        match earlier {
+            Some(earlier) => {
+                self.output().write_u8(dedup::BACK_REFERENCE)?;
+                self.output()
+                    .write_u64::<O::Endian>(position - earlier)?;
+            }
+            None => {
+                self.output().write_u8(dedup::INLINE)?;
+                self.output().write_all(&content)?;
+            }
+        }
+        self.scratch.give(content);
+
+        let end = self.pending.last().map_or(0, Vec::len);
+        if let Some(ref mut subtrees) = self.subtrees {
+            subtrees.written_to_parent(id, start, end);
+        }
+        Ok(())
+    }
+
+    /// Pads the output up to where a primitive of `size` bytes starts, see
+    /// `Config::aligned`. The options that buffer parts of the output turn alignment
+    /// off, so the position in the writer is where the primitive goes.
+    #[inline(always)]
+    fn align(&mut self, size: u64) -> Result<()> {
+        let padding = padding(self._options.config(), self.position, size);
+        if padding > 0 {
+            self.output()
+                .write_all(&string_mode::PADDING[..padding as usize])?;
+        }
+        Ok(())
+    }
+
+    /// Writes the NUL bytes after a string, see `Config::string_encoding`.
+    fn write_padding(&mut self, mut padding: usize) -> Result<()> {
+        let mut output = self.output();
+        while padding > 0 {
+            let chunk = padding.min(string_mode::PADDING.len());
+            output.write_all(&string_mode::PADDING[..chunk])?;
+            padding -= chunk;
+        }
+        Ok(())
+    }
+
+    /// Writes the type marker of a value if it is inside a self-describing map.
+    #[inline(always)]
+    fn write_marker(&mut self, marker: u8) -> Result<()> {
+        if self.tagged {
+            self.output().write_u8(marker)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a length prefix. Lengths never carry a type marker.
+    #[inline(always)]
+    pub(crate) fn write_size<S: SizeType>(&mut self, len: usize) -> Result<()> {
+        if let Some(mode) = self._options.config().usize_mode() {
+            return self.write_usize(mode, len as u64);
+        }
+        if let Some(custom) = S::custom(self._options.config()) {
+            return custom.write(len as u64, &mut self.output());
+        }
+        if let Some(fixed) = S::fixed(self._options.config()) {
+            return fixed_length!(fixed, L => self.write_size::<L>(len));
+        }
+        let tagged = mem::replace(&mut self.tagged, false);
+        let mark = self.stats.mark();
+        let result = if promoted::<S>(self._options.config(), len as u64) {
+            S::write(&mut *self, S::MAX as usize).and_then(|()| U64::write(&mut *self, len))
+        } else {
+            S::write(&mut *self, len)
+        };
+        self.stats.rewind(mark);
+        self.tagged = tagged;
+        result
+    }
+
+    /// Writes a length or `usize` value as `Config::usize_encoding` asks.
+    fn write_usize(&mut self, mode: UsizeMode, value: u64) -> Result<()> {
+        if let Some(width) = usize_mode::fixed_width(mode) {
+            self.align(width)?;
+        }
+        let mut buf = [0; usize_mode::MAX_LEN];
+        let len = usize_mode::encode::<O::Endian>(mode, value, &mut buf)?;
+        self.output().write_all(&buf[..len]).map_err(Into::into)
+    }
+
+    /// Whether integers are written as varints, see `Config::int_encoding`. Inside a
+    /// self-describing map they keep their full width.
+    #[inline(always)]
+    fn varint(&self) -> bool {
+        !self.tagged && self._options.config().ints() == IntEncoding::Varint
+    }
+
+    /// Writes an integer as `IntEncoding::Varint` asks.
+    fn write_varint<T: Varint>(&mut self, v: T) -> Result<()> {
+        let mut buf = [0; varint::MAX_LEN];
+        let len = varint::encode::<O::Endian>(v.to_varint(), &mut buf);
+        self.output().write_all(&buf[..len]).map_err(Into::into)
+    }
+
+    /// Writes a string as `Config::string_encoding` asks, without its type marker.
+    fn write_str(&mut self, v: &str) -> Result<()> {
+        let config = self._options.config();
+        let plain = config.string_mode() == StringMode::LengthPrefixed
+            && config.length_overflow() != Overflow::Truncate;
+        if plain {
+            // The length and the bytes, in two writes and without copying.
+            self.write_size::<O::StringSize>(v.len())?;
+            return self.output().write_all(v.as_bytes()).map_err(Into::into);
+        }
+        let v = truncate_str::<O::StringSize>(self._options.config(), v);
+        let padding = match string_mode::padding(self._options.config().string_mode(), v)? {
+            Some(padding) => padding,
+            None => {
+                self.write_size::<O::StringSize>(v.len())?;
+                0
+            }
+        };
+        self.output().write_all(v.as_bytes())?;
+        self.write_padding(padding)
+    }
+
+    /// Writes the index of an enum variant. Inside a self-describing map the variant
+    /// is written as a map with a single entry, which is what serde expects of
+    /// buffered enums.
+    fn write_variant(&mut self, variant_index: u32) -> Result<()> {
+        if self.tagged {
+            self.output().write_u8(marker::MAP)?;
+            self.write_size::<O::ArraySize>(1)?;
+            self.output().write_u8(marker::U32)?;
+        }
+        if self.varint() {
+            return self.write_varint(variant_index);
+        }
+        self.align(4)?;
+        self.output()
+            .write_u32::<O::Endian>(variant_index)
+            .map_err(Into::into)
+    }
+
+    /// Writes the tag of a variant of the enum `name`, and starts buffering what the
+    /// variant holds when `Config::variant_lengths` is set, so that its length can
+    /// precede it.
+    #[cfg(feature = "alloc")]
+    fn begin_variant(
+        &mut self,
+        name: &'static str,
+        variant_index: u32,
+    ) -> Result<Option<VariantPayload>> {
+        let tag = self._options.config().variant_to_wire(name, variant_index);
+        self.write_variant(tag)?;
+        if !self._options.config().variants_sized() {
+            return Ok(None);
+        }
+        self.push_buffer();
+        // The payload is written after its length, so it cannot refer to what came before.
+        Ok(Some(VariantPayload {
+            subtrees: self.subtrees.take(),
+        }))
+    }
+
+    /// Writes a payload buffered by `begin_variant`, preceded by its length.
+    #[cfg(feature = "alloc")]
+    fn end_variant(&mut self, payload: Option<VariantPayload>) -> Result<()> {
+        let payload = match payload {
+            Some(payload) => payload,
+            None => return Ok(()),
+        };
+        let buffer = self.pending.pop().unwrap_or_default();
+        self.subtrees = payload.subtrees;
+        self.write_size::<O::ArraySize>(buffer.len())?;
+        self.output().write_all(&buffer)?;
+        self.scratch.give(buffer);
+        Ok(())
+    }
+
+    /// Writes a float as `Config::float_encoding` asks; `single` is set for `f32` values.
+    fn write_float(&mut self, v: f64, single: bool) -> Result<()> {
+        let encoding = self._options.config().floats();
+        self.align(float::len(encoding, single))?;
+        match float::encode(encoding, v, single)? {
+            Wire::F32(v) => self.output().write_f32::<O::Endian>(v),
+            Wire::F64(v) => self.output().write_f64::<O::Endian>(v),
+            Wire::I32(v) => self.output().write_i32::<O::Endian>(v),
+            Wire::I64(v) => self.output().write_i64::<O::Endian>(v),
+        }
+        .map_err(Into::into)
+    }
+
+    /// Writes the header of a tuple or struct, which only has one inside a
+    /// self-describing map.
+    fn write_tuple_header(&mut self, len: usize) -> Result<()> {
+        if self.tagged {
+            self.output().write_u8(marker::SEQ)?;
+            self.write_size::<O::ArraySize>(len)?;
+        }
+        Ok(())
+    }
+
+    /// Whether struct fields are written with a header, see `Config::tagged_fields`.
+    /// Inside a self-describing map the struct is self-describing already.
+    #[inline(always)]
+    fn fields_tagged(&self) -> bool {
+        !self.tagged && self._options.config().fields_tagged()
+    }
+
+    fn write_struct_header(&mut self, len: usize) -> Result<()> {
+        if self.fields_tagged() {
+            self.write_size::<O::ArraySize>(len)
+        } else {
+            self.write_tuple_header(len)
         }
     }
+
+    /// Writes a struct field preceded by its header. The value is buffered first so
+    /// that its size is known.
+    #[cfg(feature = "alloc")]
+    fn write_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: serde::ser::Serialize,
+    {
+        self.push_buffer();
+        let result = self.serialize_typed(value);
+        let buffer = self.pending.pop().unwrap_or_default();
+        result?;
+
+        let wire_type = field::wire_type(buffer.len());
+        self.output()
+            .write_u32::<O::Endian>(field::header(key, wire_type))?;
+        if wire_type == field::LENGTH_DELIMITED {
+            self.write_size::<O::ArraySize>(buffer.len())?;
+        }
+        self.output().write_all(&buffer)?;
+        self.scratch.give(buffer);
+        Ok(())
+    }
+
+    /// Serializes a value whose type serde passes on to the deserializer, with the
+    /// `TypeCodec` registered for it if any.
+    #[inline(always)]
+    pub(crate) fn serialize_typed<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    where
+        T: serde::ser::Serialize,
+    {
+        if !self.tagged {
+            if let Some(codec) = self._options.config().codecs().for_value::<T>() {
+                return codec.encode(value, self);
+            }
+        }
+        value.serialize(&mut *self)
+    }
+}
+
+/// Without `alloc` the options that buffer part of the output cannot be set, so
+/// everything goes straight to the writer.
+#[cfg(not(feature = "alloc"))]
+impl<W: Write, O: Options> Serializer<W, O> {
+    #[inline(always)]
+    fn output(&mut self) -> Output<'_, W> {
+        Output::Writer(&mut self.writer, None, &mut self.position, &mut self.stats)
+    }
+
+    #[inline(always)]
+    fn begin_subtree(&mut self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    fn end_subtree(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn begin_variant(
+        &mut self,
+        name: &'static str,
+        variant_index: u32,
+    ) -> Result<Option<VariantPayload>> {
+        let tag = self._options.config().variant_to_wire(name, variant_index);
+        self.write_variant(tag)?;
+        Ok(None)
+    }
+
+    #[inline(always)]
+    fn end_variant(&mut self, _payload: Option<VariantPayload>) -> Result<()> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn write_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    where
+        T: serde::ser::Serialize,
+    {
+        self.serialize_typed(value)
+    }
 }
 
 impl<'a, W: Write, O: Options> serde::Serializer for &'a mut Serializer<W, O> {
@@ -39,162 +543,364 @@ impl<'a, W: Write, O: Options> serde::Serializer for &'a mut Serializer<W, O> {
     type SerializeTuple = Compound<'a, W, O>;
     type SerializeTupleStruct = Compound<'a, W, O>;
     type SerializeTupleVariant = Compound<'a, W, O>;
-    type SerializeMap = Compound<'a, W, O>;
+    type SerializeMap = MapCompound<'a, W, O>;
     type SerializeStruct = Compound<'a, W, O>;
     type SerializeStructVariant = Compound<'a, W, O>;
 
     fn serialize_unit(self) -> Result<()> {
-        Ok(())
+        self.stats.value();
+        self.write_marker(marker::UNIT)
     }
 
     fn serialize_unit_struct(self, _: &'static str) -> Result<()> {
-        Ok(())
+        self.stats.value();
+        self.write_marker(marker::UNIT)
     }
 
     fn serialize_bool(self, v: bool) -> Result<()> {
-        self.writer
+        self.stats.value();
+        self.write_marker(marker::BOOL)?;
+        self.output()
             .write_u8(if v { 1 } else { 0 })
             .map_err(Into::into)
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
-        self.writer.write_u8(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::U8)?;
+        self.output().write_u8(v).map_err(Into::into)
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        self.writer.write_u16::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::U16)?;
+        if self.varint() {
+            return self.write_varint(v);
+        }
+        self.align(2)?;
+        self.output().write_u16::<O::Endian>(v).map_err(Into::into)
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        self.writer.write_u32::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::U32)?;
+        if self.varint() {
+            return self.write_varint(v);
+        }
+        self.align(4)?;
+        self.output().write_u32::<O::Endian>(v).map_err(Into::into)
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.writer.write_u64::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::U64)?;
+        if self.varint() {
+            return self.write_varint(v);
+        }
+        self.align(8)?;
+        self.output().write_u64::<O::Endian>(v).map_err(Into::into)
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
-        self.writer.write_i8(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::I8)?;
+        self.output().write_i8(v).map_err(Into::into)
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        self.writer.write_i16::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::I16)?;
+        if self.varint() {
+            return self.write_varint(v);
+        }
+        self.align(2)?;
+        self.output().write_i16::<O::Endian>(v).map_err(Into::into)
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        self.writer.write_i32::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::I32)?;
+        if self.varint() {
+            return self.write_varint(v);
+        }
+        self.align(4)?;
+        self.output().write_i32::<O::Endian>(v).map_err(Into::into)
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.writer.write_i64::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::I64)?;
+        if self.varint() {
+            return self.write_varint(v);
+        }
+        self.align(8)?;
+        self.output().write_i64::<O::Endian>(v).map_err(Into::into)
     }
 
     serde_if_integer128! {
         fn serialize_u128(self, v: u128) -> Result<()> {
-            self.writer.write_u128::<O::Endian>(v).map_err(Into::into)
+            self.stats.value();
+            self.write_marker(marker::U128)?;
+            if self.varint() {
+                return self.write_varint(v);
+            }
+            self.align(16)?;
+            self.output().write_u128::<O::Endian>(v).map_err(Into::into)
         }
 
         fn serialize_i128(self, v: i128) -> Result<()> {
-            self.writer.write_i128::<O::Endian>(v).map_err(Into::into)
+            self.stats.value();
+            self.write_marker(marker::I128)?;
+            if self.varint() {
+                return self.write_varint(v);
+            }
+            self.align(16)?;
+            self.output().write_i128::<O::Endian>(v).map_err(Into::into)
         }
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
-        self.writer.write_f32::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::F32)?;
+        self.write_float(v as f64, true)
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.writer.write_f64::<O::Endian>(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::F64)?;
+        self.write_float(v, false)
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        O::StringSize::write(&mut *self, v.len())?;
-        self.writer.write_all(v.as_bytes()).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::STR)?;
+        self.stats.string();
+        self.write_str(v)
+    }
+
+    // Serde's default collects the string into a `String` first. Values whose
+    // output fits on the stack are formatted there instead; longer ones are
+    // formatted twice more, once to find their length and once to write them.
+    fn collect_str<T: ?Sized + fmt::Display>(self, value: &T) -> Result<()> {
+        self.stats.value();
+        self.write_marker(marker::STR)?;
+        self.stats.string();
+        let mut buffer = string_mode::StackBuffer::new();
+        let result = fmt::write(&mut buffer, format_args!("{}", value));
+        if !buffer.overflowed {
+            return match result {
+                Ok(()) => self.write_str(buffer.as_str()?),
+                Err(_) => Err(string_mode::display_failed()),
+            };
+        }
+        let measure = string_mode::Measure::of::<O::StringSize, T>(self._options.config(), value)?;
+        let mode = self._options.config().string_mode();
+        let padding = match string_mode::padding_of(mode, measure.kept, measure.nul)? {
+            Some(padding) => padding,
+            None => {
+                self.write_size::<O::StringSize>(measure.kept)?;
+                0
+            }
+        };
+        let mut writer = DisplayWriter {
+            output: self.output(),
+            left: measure.kept,
+            error: None,
+        };
+        let result = fmt::write(&mut writer, format_args!("{}", value));
+        if let Some(error) = writer.error {
+            return Err(error.into());
+        }
+        if result.is_err() || writer.left != 0 {
+            return Err(string_mode::display_failed());
+        }
+        self.write_padding(padding)
     }
 
     fn serialize_char(self, c: char) -> Result<()> {
-        self.writer
+        self.stats.value();
+        self.write_marker(marker::CHAR)?;
+        self.output()
             .write_all(encode_utf8(c).as_slice())
             .map_err(Into::into)
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
-        O::ArraySize::write(&mut *self, v.len())?;
-        self.writer.write_all(v).map_err(Into::into)
+        self.stats.value();
+        self.write_marker(marker::BYTES)?;
+        self.write_size::<O::ArraySize>(v.len())?;
+        self.output().write_all(v).map_err(Into::into)
     }
 
     fn serialize_none(self) -> Result<()> {
-        self.writer.write_u8(0).map_err(Into::into)
+        self.stats.value();
+        if self.tagged {
+            return self.write_marker(marker::NONE);
+        }
+        self.output().write_u8(0).map_err(Into::into)
     }
 
     fn serialize_some<T: ?Sized>(self, v: &T) -> Result<()>
     where
         T: serde::Serialize,
     {
-        self.writer.write_u8(1)?;
+        self.stats.value();
+        if self.tagged {
+            self.write_marker(marker::SOME)?;
+        } else {
+            self.output().write_u8(1)?;
+        }
         v.serialize(self)
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+        self.stats.value();
         let len = len.ok_or(ErrorKind::SequenceMustHaveLength)?;
-        O::ArraySize::write(&mut *self, len)?;
-        Ok(Compound { ser: self })
-    }
-
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Ok(Compound { ser: self })
+        self.write_marker(marker::SEQ)?;
+        let subtree = self.begin_subtree();
+        self.write_size::<O::ArraySize>(len)?;
+        self.stats.enter();
+        Ok(Compound {
+            ser: self,
+            subtree,
+            payload: None,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.stats.value();
+        self.write_tuple_header(len)?;
+        self.stats.enter();
+        Ok(Compound {
+            ser: self,
+            subtree: false,
+            payload: None,
+        })
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Ok(Compound { ser: self })
+        self.stats.value();
+        self.write_tuple_header(len)?;
+        let subtree = self.begin_subtree();
+        self.stats.enter();
+        Ok(Compound {
+            ser: self,
+            subtree,
+            payload: None,
+        })
     }
 
     fn serialize_tuple_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.serialize_u32(variant_index)?;
-        Ok(Compound { ser: self })
-    }
-
+        self.stats.value();
+        let payload = self.begin_variant(name, variant_index)?;
+        self.write_tuple_header(len)?;
+        self.stats.enter();
+        Ok(Compound {
+            ser: self,
+            subtree: false,
+            payload,
+        })
+    }
+
+    #[cfg(feature = "alloc")]
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.stats.value();
+        let outer_tagged = self.tagged;
+        let mut subtree = false;
+        if self._options.config().flatten_allowed() {
+            self.write_marker(marker::MAP)?;
+            match len {
+                Some(len) => self.write_size::<O::ArraySize>(len)?,
+                None => {
+                    self.push_buffer();
+                }
+            }
+            self.tagged = true;
+        } else {
+            let len = len.ok_or(ErrorKind::SequenceMustHaveLength)?;
+            subtree = self.begin_subtree();
+            self.write_size::<O::ArraySize>(len)?;
+        }
+        let sorted = if self._options.config().map_keys_sorted() {
+            // Entries are written out of order, so they cannot refer to each other.
+            Some(SortedEntries {
+                entries: Vec::new(),
+                subtrees: self.subtrees.take(),
+            })
+        } else {
+            None
+        };
+        self.stats.enter();
+        Ok(MapCompound {
+            ser: self,
+            buffered: len.is_none(),
+            entries: 0,
+            outer_tagged,
+            subtree,
+            sorted,
+        })
+    }
+
+    #[cfg(not(feature = "alloc"))]
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.stats.value();
         let len = len.ok_or(ErrorKind::SequenceMustHaveLength)?;
-        O::ArraySize::write(&mut *self, len)?;
-        Ok(Compound { ser: self })
+        self.write_size::<O::ArraySize>(len)?;
+        self.stats.enter();
+        Ok(MapCompound { ser: self })
     }
 
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Ok(Compound { ser: self })
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.stats.value();
+        self.write_struct_header(len)?;
+        let subtree = self.begin_subtree();
+        self.stats.enter();
+        Ok(Compound {
+            ser: self,
+            subtree,
+            payload: None,
+        })
     }
 
     fn serialize_struct_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.serialize_u32(variant_index)?;
-        Ok(Compound { ser: self })
+        self.stats.value();
+        let payload = self.begin_variant(name, variant_index)?;
+        self.write_struct_header(len)?;
+        self.stats.enter();
+        Ok(Compound {
+            ser: self,
+            subtree: false,
+            payload,
+        })
     }
 
     fn serialize_newtype_struct<T: ?Sized>(self, _name: &'static str, value: &T) -> Result<()>
     where
         T: serde::ser::Serialize,
     {
+        self.stats.value();
         value.serialize(self)
     }
 
     fn serialize_newtype_variant<T: ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
         value: &T,
@@ -202,17 +908,22 @@ impl<'a, W: Write, O: Options> serde::Serializer for &'a mut Serializer<W, O> {
     where
         T: serde::ser::Serialize,
     {
-        self.serialize_u32(variant_index)?;
-        value.serialize(self)
+        self.stats.value();
+        let payload = self.begin_variant(name, variant_index)?;
+        self.serialize_typed(value)?;
+        self.end_variant(payload)
     }
 
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
     ) -> Result<()> {
-        self.serialize_u32(variant_index)
+        self.stats.value();
+        let payload = self.begin_variant(name, variant_index)?;
+        self.write_marker(marker::UNIT)?;
+        self.end_variant(payload)
     }
 
     fn is_human_readable(&self) -> bool {
@@ -222,16 +933,187 @@ impl<'a, W: Write, O: Options> serde::Serializer for &'a mut Serializer<W, O> {
 
 pub(crate) struct SizeChecker<O: Options> {
     pub options: O,
+    tagged: bool,
+    /// The number of bytes counted so far.
+    total: u64,
 }
 
 impl<O: Options> SizeChecker<O> {
+    pub(crate) fn new(options: O) -> SizeChecker<O> {
+        SizeChecker {
+            tagged: options.config().untagged_allowed(),
+            options,
+            total: 0,
+        }
+    }
+
+    /// Counts values without type markers, see `Serializer::plain`.
+    pub(crate) fn plain(mut self) -> Self {
+        self.tagged = false;
+        self
+    }
+
     fn add_raw(&mut self, size: u64) -> Result<()> {
+        self.total += size;
         self.options.limit().add(size)
     }
 
     fn add_value<T>(&mut self, t: T) -> Result<()> {
         use core::mem::size_of_val;
-        self.add_raw(size_of_val(&t) as u64)
+        let size = size_of_val(&t) as u64;
+        self.align(size)?;
+        self.add_raw(size)
+    }
+
+    /// Counts an integer as the `Serializer` writes it, as a varint if
+    /// `Config::int_encoding` asks.
+    fn add_int<T: Varint>(&mut self, v: T) -> Result<()> {
+        if !self.tagged && self.options.config().ints() == IntEncoding::Varint {
+            return self.add_raw(varint::len(v.to_varint()));
+        }
+        self.add_value(v)
+    }
+
+    /// Counts a float as `Serializer::write_float` writes it, failing on the values
+    /// it fails on.
+    fn add_float(&mut self, v: f64, single: bool) -> Result<()> {
+        self.add_marker()?;
+        let encoding = self.options.config().floats();
+        float::encode(encoding, v, single)?;
+        let len = float::len(encoding, single);
+        self.align(len)?;
+        self.add_raw(len)
+    }
+
+    /// Counts the padding before a primitive of `size` bytes, see `Serializer::align`.
+    #[inline(always)]
+    fn align(&mut self, size: u64) -> Result<()> {
+        let padding = padding(self.options.config(), self.total, size);
+        self.add_raw(padding)
+    }
+
+    #[inline(always)]
+    fn add_marker(&mut self) -> Result<()> {
+        if self.tagged {
+            self.add_raw(1)?;
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn add_size<S: SizeType>(&mut self, len: usize) -> Result<()> {
+        if let Some(mode) = self.options.config().usize_mode() {
+            return self.add_usize(mode, len as u64);
+        }
+        if let Some(custom) = S::custom(self.options.config()) {
+            let mut counter = ByteCount(0);
+            custom.write(len as u64, &mut counter)?;
+            return self.add_raw(counter.0);
+        }
+        if let Some(fixed) = S::fixed(self.options.config()) {
+            return fixed_length!(fixed, L => self.add_size::<L>(len));
+        }
+        let tagged = mem::replace(&mut self.tagged, false);
+        let result = if promoted::<S>(self.options.config(), len as u64) {
+            S::write(&mut *self, S::MAX as usize).and_then(|()| U64::write(&mut *self, len))
+        } else {
+            S::write(&mut *self, len)
+        };
+        self.tagged = tagged;
+        result
+    }
+
+    fn add_usize(&mut self, mode: UsizeMode, value: u64) -> Result<()> {
+        if let Some(width) = usize_mode::fixed_width(mode) {
+            self.align(width)?;
+        }
+        let mut buf = [0; usize_mode::MAX_LEN];
+        let len = usize_mode::encode::<O::Endian>(mode, value, &mut buf)?;
+        self.add_raw(len as u64)
+    }
+
+    fn add_variant(&mut self, variant_index: u32) -> Result<()> {
+        if self.tagged {
+            self.add_raw(1)?;
+            self.add_size::<O::ArraySize>(1)?;
+            self.add_raw(1)?;
+        }
+        self.add_int(variant_index)
+    }
+
+    /// Counts the tag of a variant of the enum `name`, as mapped by the config,
+    /// returning where the payload starts if its length is written after it, see
+    /// `Serializer::begin_variant`.
+    fn begin_variant(&mut self, name: &'static str, variant_index: u32) -> Result<Option<u64>> {
+        let tag = self.options.config().variant_to_wire(name, variant_index);
+        self.add_variant(tag)?;
+        if self.options.config().variants_sized() {
+            Ok(Some(self.total))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Counts the length of the payload started at `start`, if it is written.
+    fn end_variant(&mut self, start: Option<u64>) -> Result<()> {
+        match start {
+            Some(start) => {
+                let len = self.total - start;
+                self.add_size::<O::ArraySize>(len as usize)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn add_tuple_header(&mut self, len: usize) -> Result<()> {
+        if self.tagged {
+            self.add_raw(1)?;
+            self.add_size::<O::ArraySize>(len)?;
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn fields_tagged(&self) -> bool {
+        !self.tagged && self.options.config().fields_tagged()
+    }
+
+    fn add_struct_header(&mut self, len: usize) -> Result<()> {
+        if self.fields_tagged() {
+            self.add_size::<O::ArraySize>(len)
+        } else {
+            self.add_tuple_header(len)
+        }
+    }
+
+    fn add_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    where
+        T: serde::ser::Serialize,
+    {
+        let start = self.total;
+        self.serialize_typed(value)?;
+        let len = (self.total - start) as usize;
+
+        self.add_raw(4)?;
+        if field::wire_type(len) == field::LENGTH_DELIMITED {
+            self.add_size::<O::ArraySize>(len)?;
+        }
+        Ok(())
+    }
+
+    /// Counts a value whose type serde passes on to the deserializer, see
+    /// `Serializer::serialize_typed`.
+    #[inline(always)]
+    pub(crate) fn serialize_typed<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    where
+        T: serde::ser::Serialize,
+    {
+        if !self.tagged {
+            if let Some(codec) = self.options.config().codecs().for_value::<T>() {
+                return codec.encode(value, self);
+            }
+        }
+        value.serialize(&mut *self)
     }
 }
 
@@ -242,83 +1124,118 @@ impl<'a, O: Options> serde::Serializer for &'a mut SizeChecker<O> {
     type SerializeTuple = SizeCompound<'a, O>;
     type SerializeTupleStruct = SizeCompound<'a, O>;
     type SerializeTupleVariant = SizeCompound<'a, O>;
-    type SerializeMap = SizeCompound<'a, O>;
+    type SerializeMap = SizeMapCompound<'a, O>;
     type SerializeStruct = SizeCompound<'a, O>;
     type SerializeStructVariant = SizeCompound<'a, O>;
 
     fn serialize_unit(self) -> Result<()> {
-        Ok(())
+        self.add_marker()
     }
 
     fn serialize_unit_struct(self, _: &'static str) -> Result<()> {
-        Ok(())
+        self.add_marker()
     }
 
     fn serialize_bool(self, _: bool) -> Result<()> {
+        self.add_marker()?;
         self.add_value(0 as u8)
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
+        self.add_marker()?;
         self.add_value(v)
     }
 
     fn serialize_u16(self, v: u16) -> Result<()> {
-        self.add_value(v)
+        self.add_marker()?;
+        self.add_int(v)
     }
 
     fn serialize_u32(self, v: u32) -> Result<()> {
-        self.add_value(v)
+        self.add_marker()?;
+        self.add_int(v)
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.add_value(v)
+        self.add_marker()?;
+        self.add_int(v)
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
+        self.add_marker()?;
         self.add_value(v)
     }
 
     fn serialize_i16(self, v: i16) -> Result<()> {
-        self.add_value(v)
+        self.add_marker()?;
+        self.add_int(v)
     }
 
     fn serialize_i32(self, v: i32) -> Result<()> {
-        self.add_value(v)
+        self.add_marker()?;
+        self.add_int(v)
     }
 
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.add_value(v)
+        self.add_marker()?;
+        self.add_int(v)
     }
 
     serde_if_integer128! {
         fn serialize_u128(self, v: u128) -> Result<()> {
-            self.add_value(v)
+            self.add_marker()?;
+            self.add_int(v)
         }
 
         fn serialize_i128(self, v: i128) -> Result<()> {
-            self.add_value(v)
+            self.add_marker()?;
+            self.add_int(v)
         }
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
-        self.add_value(v)
+        self.add_float(f64::from(v), true)
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.add_value(v)
+        self.add_float(v, false)
     }
 
     fn serialize_str(self, v: &str) -> Result<()> {
-        O::StringSize::write(&mut *self, v.len())?;
-        self.add_raw(v.len() as u64)
+        self.add_marker()?;
+        let v = truncate_str::<O::StringSize>(self.options.config(), v);
+        let padding = match string_mode::padding(self.options.config().string_mode(), v)? {
+            Some(padding) => padding,
+            None => {
+                self.add_size::<O::StringSize>(v.len())?;
+                0
+            }
+        };
+        self.add_raw((v.len() + padding) as u64)
+    }
+
+    fn collect_str<T: ?Sized + fmt::Display>(self, value: &T) -> Result<()> {
+        self.add_marker()?;
+        let measure = string_mode::Measure::of::<O::StringSize, T>(self.options.config(), value)?;
+        let mode = self.options.config().string_mode();
+        let padding = match string_mode::padding_of(mode, measure.kept, measure.nul)? {
+            Some(padding) => padding,
+            None => {
+                self.add_size::<O::StringSize>(measure.kept)?;
+                0
+            }
+        };
+        self.add_raw((measure.kept + padding) as u64)
     }
 
     fn serialize_char(self, c: char) -> Result<()> {
+        self.add_marker()?;
         self.add_raw(encode_utf8(c).as_slice().len() as u64)
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<()> {
-        O::ArraySize::write(&mut *self, v.len())?;
+        self.add_marker()?;
+        self.add_size::<O::ArraySize>(v.len())?;
         self.add_raw(v.len() as u64)
     }
 
@@ -336,52 +1253,84 @@ impl<'a, O: Options> serde::Serializer for &'a mut SizeChecker<O> {
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
         let len = len.ok_or(ErrorKind::SequenceMustHaveLength)?;
-        O::ArraySize::write(&mut *self, len)?;
-        Ok(SizeCompound { ser: self })
+        self.add_marker()?;
+        self.add_size::<O::ArraySize>(len)?;
+        Ok(SizeCompound {
+            ser: self,
+            payload: None,
+        })
     }
 
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
-        Ok(SizeCompound { ser: self })
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+        self.add_tuple_header(len)?;
+        Ok(SizeCompound {
+            ser: self,
+            payload: None,
+        })
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
-        Ok(SizeCompound { ser: self })
+        self.add_tuple_header(len)?;
+        Ok(SizeCompound {
+            ser: self,
+            payload: None,
+        })
     }
 
     fn serialize_tuple_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        self.add_value(variant_index)?;
-        Ok(SizeCompound { ser: self })
+        let payload = self.begin_variant(name, variant_index)?;
+        self.add_tuple_header(len)?;
+        Ok(SizeCompound { ser: self, payload })
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
-        let len = len.ok_or(ErrorKind::SequenceMustHaveLength)?;
-        O::ArraySize::write(&mut *self, len)?;
-        Ok(SizeCompound { ser: self })
+        let outer_tagged = self.tagged;
+        if self.options.config().flatten_allowed() {
+            self.add_marker()?;
+            if let Some(len) = len {
+                self.add_size::<O::ArraySize>(len)?;
+            }
+            self.tagged = true;
+        } else {
+            let len = len.ok_or(ErrorKind::SequenceMustHaveLength)?;
+            self.add_size::<O::ArraySize>(len)?;
+        }
+        Ok(SizeMapCompound {
+            ser: self,
+            buffered: len.is_none(),
+            entries: 0,
+            outer_tagged,
+        })
     }
 
-    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
-        Ok(SizeCompound { ser: self })
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+        self.add_struct_header(len)?;
+        Ok(SizeCompound {
+            ser: self,
+            payload: None,
+        })
     }
 
     fn serialize_struct_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStructVariant> {
-        self.add_value(variant_index)?;
-        Ok(SizeCompound { ser: self })
+        let payload = self.begin_variant(name, variant_index)?;
+        self.add_struct_header(len)?;
+        Ok(SizeCompound { ser: self, payload })
     }
 
     fn serialize_newtype_struct<V: serde::Serialize + ?Sized>(
@@ -394,22 +1343,25 @@ impl<'a, O: Options> serde::Serializer for &'a mut SizeChecker<O> {
 
     fn serialize_unit_variant(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
     ) -> Result<()> {
-        self.add_value(variant_index)
+        let payload = self.begin_variant(name, variant_index)?;
+        self.add_marker()?;
+        self.end_variant(payload)
     }
 
     fn serialize_newtype_variant<V: serde::Serialize + ?Sized>(
         self,
-        _name: &'static str,
+        name: &'static str,
         variant_index: u32,
         _variant: &'static str,
         value: &V,
     ) -> Result<()> {
-        self.add_value(variant_index)?;
-        value.serialize(self)
+        let payload = self.begin_variant(name, variant_index)?;
+        self.serialize_typed(value)?;
+        self.end_variant(payload)
     }
 
     fn is_human_readable(&self) -> bool {
@@ -417,8 +1369,151 @@ impl<'a, O: Options> serde::Serializer for &'a mut SizeChecker<O> {
     }
 }
 
+macro_rules! impl_encoder {
+    ($($encode:ident($ty:ty) => $serialize:ident,)*) => {
+        $(
+            #[inline(always)]
+            fn $encode(&mut self, v: $ty) -> Result<()> {
+                serde::Serializer::$serialize(&mut *self, v)
+            }
+        )*
+
+        #[inline(always)]
+        fn encode_str(&mut self, v: &str) -> Result<()> {
+            serde::Serializer::serialize_str(&mut *self, v)
+        }
+
+        #[inline(always)]
+        fn encode_bytes(&mut self, v: &[u8]) -> Result<()> {
+            serde::Serializer::serialize_bytes(&mut *self, v)
+        }
+    }
+}
+
+impl<W: Write, O: Options> Encoder for Serializer<W, O> {
+    impl_encoder! {
+        encode_bool(bool) => serialize_bool,
+        encode_u8(u8) => serialize_u8,
+        encode_u16(u16) => serialize_u16,
+        encode_u32(u32) => serialize_u32,
+        encode_u64(u64) => serialize_u64,
+        encode_u128(u128) => serialize_u128,
+        encode_i8(i8) => serialize_i8,
+        encode_i16(i16) => serialize_i16,
+        encode_i32(i32) => serialize_i32,
+        encode_i64(i64) => serialize_i64,
+        encode_i128(i128) => serialize_i128,
+        encode_f32(f32) => serialize_f32,
+        encode_f64(f64) => serialize_f64,
+        encode_char(char) => serialize_char,
+    }
+
+    #[inline(always)]
+    fn encode_len(&mut self, len: usize) -> Result<()> {
+        self.write_size::<O::ArraySize>(len)
+    }
+
+    #[inline(always)]
+    fn encode_usize(&mut self, v: usize) -> Result<()> {
+        match self._options.config().usize_mode() {
+            Some(mode) => self.write_usize(mode, v as u64),
+            None => self.encode_u64(v as u64),
+        }
+    }
+
+    #[inline(always)]
+    fn encode_isize(&mut self, v: isize) -> Result<()> {
+        match self._options.config().usize_mode() {
+            Some(mode) => {
+                let value = usize_mode::from_isize(mode, v as i64)?;
+                self.write_usize(mode, value)
+            }
+            None => self.encode_i64(v as i64),
+        }
+    }
+
+    #[inline(always)]
+    fn encode_variant(&mut self, index: u32) -> Result<()> {
+        self.write_variant(index)
+    }
+}
+
+impl<O: Options> Encoder for SizeChecker<O> {
+    impl_encoder! {
+        encode_bool(bool) => serialize_bool,
+        encode_u8(u8) => serialize_u8,
+        encode_u16(u16) => serialize_u16,
+        encode_u32(u32) => serialize_u32,
+        encode_u64(u64) => serialize_u64,
+        encode_u128(u128) => serialize_u128,
+        encode_i8(i8) => serialize_i8,
+        encode_i16(i16) => serialize_i16,
+        encode_i32(i32) => serialize_i32,
+        encode_i64(i64) => serialize_i64,
+        encode_i128(i128) => serialize_i128,
+        encode_f32(f32) => serialize_f32,
+        encode_f64(f64) => serialize_f64,
+        encode_char(char) => serialize_char,
+    }
+
+    #[inline(always)]
+    fn encode_len(&mut self, len: usize) -> Result<()> {
+        self.add_size::<O::ArraySize>(len)
+    }
+
+    #[inline(always)]
+    fn encode_usize(&mut self, v: usize) -> Result<()> {
+        match self.options.config().usize_mode() {
+            Some(mode) => self.add_usize(mode, v as u64),
+            None => self.encode_u64(v as u64),
+        }
+    }
+
+    #[inline(always)]
+    fn encode_isize(&mut self, v: isize) -> Result<()> {
+        match self.options.config().usize_mode() {
+            Some(mode) => {
+                let value = usize_mode::from_isize(mode, v as i64)?;
+                self.add_usize(mode, value)
+            }
+            None => self.encode_i64(v as i64),
+        }
+    }
+
+    #[inline(always)]
+    fn encode_variant(&mut self, index: u32) -> Result<()> {
+        self.add_variant(index)
+    }
+}
+
 pub(crate) struct Compound<'a, W: 'a, O: Options + 'a> {
     ser: &'a mut Serializer<W, O>,
+    /// Buffered by `Serializer::begin_subtree`.
+    subtree: bool,
+    /// Buffered by `Serializer::begin_variant`.
+    payload: Option<VariantPayload>,
+}
+
+/// The payload of an enum variant, buffered until its length is known.
+#[cfg(feature = "alloc")]
+struct VariantPayload {
+    /// Taken from the serializer until the payload is written.
+    subtrees: Option<Subtrees>,
+}
+
+/// Payloads are only buffered with `alloc`.
+#[cfg(not(feature = "alloc"))]
+type VariantPayload = Infallible;
+
+impl<'a, W: Write, O: Options> Compound<'a, W, O> {
+    #[inline]
+    fn end_compound(self) -> Result<()> {
+        self.ser.stats.leave();
+        if self.subtree {
+            self.ser.end_subtree()?;
+        }
+        self.ser.end_variant(self.payload)
+    }
 }
 
 impl<'a, W, O> serde::ser::SerializeSeq for Compound<'a, W, O>
@@ -434,12 +1529,12 @@ where
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.end_compound()
     }
 }
 
@@ -456,12 +1551,12 @@ where
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.end_compound()
     }
 }
 
@@ -478,12 +1573,12 @@ where
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.end_compound()
     }
 }
 
@@ -500,16 +1595,129 @@ where
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<()> {
+        self.end_compound()
+    }
+}
+
+pub(crate) struct MapCompound<'a, W: 'a, O: Options + 'a> {
+    ser: &'a mut Serializer<W, O>,
+    /// The entries go to a pending buffer because the length was not known up front.
+    #[cfg(feature = "alloc")]
+    buffered: bool,
+    #[cfg(feature = "alloc")]
+    entries: usize,
+    #[cfg(feature = "alloc")]
+    outer_tagged: bool,
+    /// Buffered by `Serializer::begin_subtree`.
+    #[cfg(feature = "alloc")]
+    subtree: bool,
+    /// Set when `Config::sort_map_keys` is.
+    #[cfg(feature = "alloc")]
+    sorted: Option<SortedEntries>,
+}
+
+/// The entries of a map, buffered to be written in the order of their keys.
+#[cfg(feature = "alloc")]
+struct SortedEntries {
+    /// The bytes of each entry, with the length of its key.
+    entries: Vec<(usize, Vec<u8>)>,
+    /// Taken from the serializer until the entries are written.
+    subtrees: Option<Subtrees>,
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, W: Write, O: Options> MapCompound<'a, W, O> {
+    /// Serializes `value` into a buffer of its own if the entries are sorted, or
+    /// straight to the output otherwise.
+    fn serialize_part<T: ?Sized>(&mut self, value: &T) -> Result<Option<Vec<u8>>>
+    where
+        T: serde::ser::Serialize,
+    {
+        if self.sorted.is_none() {
+            return self.ser.serialize_typed(value).map(|()| None);
+        }
+        self.ser.pending.push(self.ser.scratch.take());
+        let result = self.ser.serialize_typed(value);
+        let buffer = self.ser.pending.pop().unwrap_or_default();
+        result.map(|()| Some(buffer))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<'a, W, O> serde::ser::SerializeMap for MapCompound<'a, W, O>
+where
+    W: Write,
+    O: Options,
+{
+    type Ok = ();
+    type Error = Error;
+
+    #[inline]
+    fn serialize_key<K: ?Sized>(&mut self, value: &K) -> Result<()>
+    where
+        K: serde::ser::Serialize,
+    {
+        self.entries += 1;
+        if let Some(key) = self.serialize_part(value)? {
+            if let Some(ref mut sorted) = self.sorted {
+                sorted.entries.push((key.len(), key));
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_value<V: ?Sized>(&mut self, value: &V) -> Result<()>
+    where
+        V: serde::ser::Serialize,
+    {
+        if let Some(value) = self.serialize_part(value)? {
+            if let Some((_, entry)) = self.sorted.as_mut().and_then(|s| s.entries.last_mut()) {
+                entry.extend_from_slice(&value);
+            }
+            self.ser.scratch.give(value);
+        }
+        Ok(())
     }
 
     #[inline]
     fn end(self) -> Result<()> {
+        self.ser.stats.leave();
+        self.ser.tagged = self.outer_tagged;
+        if let Some(mut sorted) = self.sorted {
+            self.ser.subtrees = sorted.subtrees;
+            sorted
+                .entries
+                .sort_unstable_by(|(a_len, a), (b_len, b)| a[..*a_len].cmp(&b[..*b_len]));
+            let mut output = self.ser.output();
+            for (_, entry) in &sorted.entries {
+                output.write_all(entry)?;
+            }
+            for (_, entry) in sorted.entries {
+                self.ser.scratch.give(entry);
+            }
+        }
+        if self.buffered {
+            let buffer = self.ser.pending.pop().unwrap_or_default();
+            self.ser.write_size::<O::ArraySize>(self.entries)?;
+            self.ser.output().write_all(&buffer)?;
+            self.ser.scratch.give(buffer);
+        }
+        if self.subtree {
+            self.ser.end_subtree()?;
+        }
         Ok(())
     }
 }
 
-impl<'a, W, O> serde::ser::SerializeMap for Compound<'a, W, O>
+/// Without `alloc` maps must have a length, and their entries are written in order.
+#[cfg(not(feature = "alloc"))]
+impl<'a, W, O> serde::ser::SerializeMap for MapCompound<'a, W, O>
 where
     W: Write,
     O: Options,
@@ -522,7 +1730,7 @@ where
     where
         K: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
@@ -530,11 +1738,12 @@ where
     where
         V: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
+        self.ser.stats.leave();
         Ok(())
     }
 }
@@ -548,16 +1757,19 @@ where
     type Error = Error;
 
     #[inline]
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        if self.ser.fields_tagged() {
+            return self.ser.write_field(key, value);
+        }
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.end_compound()
     }
 }
 
@@ -570,21 +1782,26 @@ where
     type Error = Error;
 
     #[inline]
-    fn serialize_field<T: ?Sized>(&mut self, _key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        if self.ser.fields_tagged() {
+            return self.ser.write_field(key, value);
+        }
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.end_compound()
     }
 }
 
 pub(crate) struct SizeCompound<'a, S: Options + 'a> {
     ser: &'a mut SizeChecker<S>,
+    /// Set by `SizeChecker::begin_variant`.
+    payload: Option<u64>,
 }
 
 impl<'a, O: Options> serde::ser::SerializeSeq for SizeCompound<'a, O> {
@@ -596,7 +1813,7 @@ impl<'a, O: Options> serde::ser::SerializeSeq for SizeCompound<'a, O> {
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
@@ -614,7 +1831,7 @@ impl<'a, O: Options> serde::ser::SerializeTuple for SizeCompound<'a, O> {
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
@@ -632,7 +1849,7 @@ impl<'a, O: Options> serde::ser::SerializeTupleStruct for SizeCompound<'a, O> {
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
@@ -650,16 +1867,23 @@ impl<'a, O: Options> serde::ser::SerializeTupleVariant for SizeCompound<'a, O> {
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.ser.end_variant(self.payload)
     }
 }
 
-impl<'a, O: Options + 'a> serde::ser::SerializeMap for SizeCompound<'a, O> {
+pub(crate) struct SizeMapCompound<'a, S: Options + 'a> {
+    ser: &'a mut SizeChecker<S>,
+    buffered: bool,
+    entries: usize,
+    outer_tagged: bool,
+}
+
+impl<'a, O: Options + 'a> serde::ser::SerializeMap for SizeMapCompound<'a, O> {
     type Ok = ();
     type Error = Error;
 
@@ -668,7 +1892,8 @@ impl<'a, O: Options + 'a> serde::ser::SerializeMap for SizeCompound<'a, O> {
     where
         K: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.entries += 1;
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
@@ -676,11 +1901,15 @@ impl<'a, O: Options + 'a> serde::ser::SerializeMap for SizeCompound<'a, O> {
     where
         V: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
+        self.ser.tagged = self.outer_tagged;
+        if self.buffered {
+            self.ser.add_size::<O::ArraySize>(self.entries)?;
+        }
         Ok(())
     }
 }
@@ -694,7 +1923,10 @@ impl<'a, O: Options> serde::ser::SerializeStruct for SizeCompound<'a, O> {
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        if self.ser.fields_tagged() {
+            return self.ser.add_field(value);
+        }
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
@@ -712,12 +1944,15 @@ impl<'a, O: Options> serde::ser::SerializeStructVariant for SizeCompound<'a, O>
     where
         T: serde::ser::Serialize,
     {
-        value.serialize(&mut *self.ser)
+        if self.ser.fields_tagged() {
+            return self.ser.add_field(value);
+        }
+        self.ser.serialize_typed(value)
     }
 
     #[inline]
     fn end(self) -> Result<()> {
-        Ok(())
+        self.ser.end_variant(self.payload)
     }
 }
 const TAG_CONT: u8 = 0b1000_0000;
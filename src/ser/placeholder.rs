@@ -0,0 +1,148 @@
+//! Lengths written ahead of the bytes they count, see `Placeholder`.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use core2::io::{Seek, SeekFrom, Write};
+
+use config::{Config, IntEncoding, LengthOption};
+use error::{ErrorKind, Result};
+use serde::ser::Error;
+
+const ZEROS: [u8; 8] = [0; 8];
+
+/// A length reserved ahead of the bytes it counts, created by `Config::reserve_length`
+/// or `Config::reserve_length_at`, and filled in once those bytes are written.
+///
+/// This lets a message be framed by its size without serializing it into a
+/// buffer of its own first. The length is a fixed-width integer in the byte
+/// order of the config, so it reads back as the matching integer type.
+///
+/// ```
+/// use bincode2::LengthOption;
+///
+/// let config = bincode2::config();
+/// let mut message = Vec::new();
+/// let size = config.reserve_length(&mut message, LengthOption::U32).unwrap();
+/// config.serialize_into(&mut message, &("hello", 7u8)).unwrap();
+/// size.fill(&mut message).unwrap();
+///
+/// let (len, body): (u32, (&str, u8)) = config.deserialize(&message).unwrap();
+/// assert_eq!(len as usize, message.len() - 4);
+/// assert_eq!(body, ("hello", 7));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[must_use = "the reserved length is zero until it is filled in"]
+pub struct Placeholder {
+    position: u64,
+    width: usize,
+    big_endian: bool,
+}
+
+impl Placeholder {
+    pub(crate) fn new(config: &Config, width: LengthOption, position: u64) -> Result<Placeholder> {
+        let width = match width {
+            LengthOption::U64 => 8,
+            LengthOption::U32 => 4,
+            LengthOption::U16 => 2,
+            LengthOption::U8 => 1,
+            LengthOption::Custom(_) => {
+                return Err(Error::custom("a placeholder needs a fixed-width length"))
+            }
+        };
+        if config.ints() == IntEncoding::Varint {
+            return Err(Error::custom("a placeholder needs fixed-width integers"));
+        }
+        Ok(Placeholder {
+            position,
+            width,
+            big_endian: config.is_big_endian(),
+        })
+    }
+
+    /// The bytes written until the length is filled in.
+    pub(crate) fn zeros(&self) -> &'static [u8] {
+        &ZEROS[..self.width]
+    }
+
+    /// Where the placeholder starts, as an index into the buffer or a position in the stream.
+    #[inline(always)]
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// The number of bytes the placeholder takes.
+    #[inline(always)]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Where the bytes counted by the placeholder start.
+    #[inline(always)]
+    fn end(&self) -> u64 {
+        self.position + self.width as u64
+    }
+
+    fn encode(&self, value: u64, bytes: &mut [u8]) -> Result<()> {
+        if self.width < 8 && value >> (8 * self.width) != 0 {
+            return Err(ErrorKind::SizeTypeLimit.into());
+        }
+        if self.big_endian {
+            BigEndian::write_uint(bytes, value, self.width);
+        } else {
+            LittleEndian::write_uint(bytes, value, self.width);
+        }
+        Ok(())
+    }
+
+    /// Fills in the number of bytes in `buffer` after the placeholder.
+    ///
+    /// Fails with `ErrorKind::SizeTypeLimit` if the number does not fit in the
+    /// placeholder, and with `ErrorKind::Custom` if `buffer` ends before the
+    /// placeholder does.
+    pub fn fill(self, buffer: &mut [u8]) -> Result<()> {
+        let len = match (buffer.len() as u64).checked_sub(self.end()) {
+            Some(len) => len,
+            None => return Err(buffer_too_short()),
+        };
+        self.fill_with(buffer, len)
+    }
+
+    /// Fills in `value`, for headers counting something other than the bytes that
+    /// follow them, such as the whole message.
+    ///
+    /// Fails as `fill` does.
+    pub fn fill_with(self, buffer: &mut [u8], value: u64) -> Result<()> {
+        if self.end() > buffer.len() as u64 {
+            return Err(buffer_too_short());
+        }
+        let start = self.position as usize;
+        self.encode(value, &mut buffer[start..start + self.width])
+    }
+
+    /// Fills in the number of bytes written to `writer` after the placeholder, by
+    /// seeking back to it. The stream is left at the position it was at.
+    ///
+    /// Fails with `ErrorKind::SizeTypeLimit` if the number does not fit in the
+    /// placeholder, and with `ErrorKind::Custom` if the stream is before the end
+    /// of the placeholder, having been seeked back past it.
+    pub fn fill_at<W: Write + Seek>(self, mut writer: W) -> Result<()> {
+        let end = writer.seek(SeekFrom::Current(0))?;
+        let len = match end.checked_sub(self.end()) {
+            Some(len) => len,
+            None => {
+                return Err(Error::custom(
+                    "the stream is before the end of the placeholder it fills",
+                ))
+            }
+        };
+        let mut bytes = ZEROS;
+        self.encode(len, &mut bytes[..self.width])?;
+        writer.seek(SeekFrom::Start(self.position))?;
+        writer.write_all(&bytes[..self.width])?;
+        writer.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+}
+
+fn buffer_too_short() -> ::error::Error {
+    Error::custom("the buffer ends before the placeholder it fills")
+}
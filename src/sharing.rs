@@ -0,0 +1,581 @@
+//! Shared and cyclic `Rc`/`Arc` graphs, enabled by the `sharing` feature.
+//!
+//! Serde writes the pointee of every `Rc` and `Arc` in full, so a value shared by
+//! many owners is written, and read back, once per owner. Within a
+//! `PreserveSharing`, pointers serialized with the functions of this module are
+//! given an id instead: each pointee is written once, where it is first met, and
+//! later pointers to it only write its id. Reading them back restores the sharing.
+//!
+//! Mark the fields holding the pointers with `#[serde(with = "bincode2::sharing")]`,
+//! or wrap pointers held in collections in `Shared`:
+//!
+//! ```
+//! # extern crate bincode2;
+//! # #[macro_use] extern crate serde_derive;
+//! use std::sync::Arc;
+//! use bincode2::sharing::{PreserveSharing, Shared};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Material {
+//!     name: String,
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Mesh {
+//!     #[serde(with = "bincode2::sharing")]
+//!     material: Arc<Material>,
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Scene {
+//!     meshes: Vec<Mesh>,
+//!     palette: Vec<Shared<Arc<Material>>>,
+//! }
+//!
+//! # fn main() {
+//! let steel = Arc::new(Material { name: "steel".into() });
+//! let scene = Scene {
+//!     meshes: vec![Mesh { material: steel.clone() }, Mesh { material: steel.clone() }],
+//!     palette: vec![Shared(steel)],
+//! };
+//!
+//! let bytes = bincode2::serialize(&PreserveSharing(&scene)).unwrap();
+//! let PreserveSharing(scene): PreserveSharing<Scene> = bincode2::deserialize(&bytes).unwrap();
+//! assert!(Arc::ptr_eq(&scene.meshes[0].material, &scene.meshes[1].material));
+//! assert!(Arc::ptr_eq(&scene.meshes[0].material, &scene.palette[0]));
+//! # }
+//! ```
+//!
+//! ### Cycles
+//! A pointer to a pointee that is still being written only writes its id, so
+//! cyclic graphs are written without recursing forever. When reading, a cycle can
+//! only be closed by a weak pointer, written with the functions of the `weak`
+//! module, to a pointee read with the `cyclic` module; strong cycles leak in Rust
+//! anyway, and are reported as errors.
+//!
+//! Each pointer is written as a tuple of its id, as a `u64`, and of an `Option`
+//! holding the pointee where it is first met. Pointers serialized outside a
+//! `PreserveSharing` are an error.
+
+use std::any::Any;
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::rc::{self, Rc};
+use std::sync::{self, Arc};
+use std::thread::LocalKey;
+use std::thread_local;
+use std::u64;
+use std::vec::Vec;
+
+use serde::de::{Deserialize, Deserializer, Error as DeError, SeqAccess, Visitor};
+use serde::ser::{Error as SerError, Serialize, Serializer};
+
+/// The id written for a weak pointer whose pointee was dropped.
+const DEAD: u64 = u64::MAX;
+
+thread_local! {
+    static WRITING: RefCell<Option<Writing>> = RefCell::new(None);
+    static READING: RefCell<Option<Reading>> = RefCell::new(None);
+}
+
+/// The ids given to the pointees written so far, by address.
+#[derive(Default)]
+struct Writing {
+    ids: HashMap<usize, u64>,
+}
+
+/// The pointees read so far, by id.
+#[derive(Default)]
+struct Reading {
+    slots: Vec<Slot>,
+}
+
+enum Slot {
+    /// A pointee being read, with the weak pointer to it if it is read with the
+    /// `cyclic` module.
+    Reading(Option<Box<dyn Any>>),
+    /// A pointee read already, held by a strong pointer.
+    Read(Box<dyn Any>),
+}
+
+/// Writes or reads the value it holds keeping track of shared pointers.
+///
+/// Sessions nest: a `PreserveSharing` within another keeps its own ids.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PreserveSharing<T>(pub T);
+
+impl<T: Serialize> Serialize for PreserveSharing<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        session(&WRITING, || self.0.serialize(serializer))
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PreserveSharing<T> {
+    fn deserialize<D>(deserializer: D) -> Result<PreserveSharing<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        session(&READING, || T::deserialize(deserializer).map(PreserveSharing))
+    }
+}
+
+/// Runs `f` in a new session, restoring the enclosing one afterwards, even if `f`
+/// panics.
+fn session<S: Default + 'static, R, F: FnOnce() -> R>(
+    key: &'static LocalKey<RefCell<Option<S>>>,
+    f: F,
+) -> R {
+    struct Restore<S: 'static> {
+        key: &'static LocalKey<RefCell<Option<S>>>,
+        outer: Option<S>,
+    }
+
+    impl<S> Drop for Restore<S> {
+        fn drop(&mut self) {
+            let outer = self.outer.take();
+            self.key.with(|session| *session.borrow_mut() = outer);
+        }
+    }
+
+    let outer = key.with(|session| session.borrow_mut().replace(S::default()));
+    let _restore = Restore { key, outer };
+    f()
+}
+
+/// A strong pointer whose pointee may be shared, implemented for `Rc` and `Arc`.
+pub trait Pointer: Clone + 'static {
+    /// The type pointed to.
+    type Value: 'static;
+    /// The matching weak pointer.
+    type Weak: Clone + 'static;
+
+    /// Allocates `value`.
+    fn new(value: Self::Value) -> Self;
+    /// Allocates the value returned by `f`, which is handed a weak pointer to it.
+    fn new_cyclic<F: FnOnce(&Self::Weak) -> Self::Value>(f: F) -> Self;
+    /// The pointee.
+    fn get(&self) -> &Self::Value;
+    /// Creates a weak pointer to the pointee.
+    fn downgrade(&self) -> Self::Weak;
+    /// Where the pointee lives, which identifies it while it is alive.
+    fn address(&self) -> usize;
+}
+
+/// A weak pointer, implemented for `rc::Weak` and `sync::Weak`.
+pub trait WeakPointer: Clone + 'static {
+    /// The matching strong pointer.
+    type Strong: Pointer<Weak = Self>;
+
+    /// A pointer that never upgrades.
+    fn dead() -> Self;
+    /// A strong pointer to the pointee, if it is alive.
+    fn upgrade(&self) -> Option<Self::Strong>;
+}
+
+impl<T: 'static> Pointer for Rc<T> {
+    type Value = T;
+    type Weak = rc::Weak<T>;
+
+    fn new(value: T) -> Rc<T> {
+        Rc::new(value)
+    }
+
+    fn new_cyclic<F: FnOnce(&rc::Weak<T>) -> T>(f: F) -> Rc<T> {
+        Rc::new_cyclic(f)
+    }
+
+    fn get(&self) -> &T {
+        self
+    }
+
+    fn downgrade(&self) -> rc::Weak<T> {
+        Rc::downgrade(self)
+    }
+
+    fn address(&self) -> usize {
+        Rc::as_ptr(self) as usize
+    }
+}
+
+impl<T: 'static> WeakPointer for rc::Weak<T> {
+    type Strong = Rc<T>;
+
+    fn dead() -> rc::Weak<T> {
+        rc::Weak::new()
+    }
+
+    fn upgrade(&self) -> Option<Rc<T>> {
+        rc::Weak::upgrade(self)
+    }
+}
+
+impl<T: 'static> Pointer for Arc<T> {
+    type Value = T;
+    type Weak = sync::Weak<T>;
+
+    fn new(value: T) -> Arc<T> {
+        Arc::new(value)
+    }
+
+    fn new_cyclic<F: FnOnce(&sync::Weak<T>) -> T>(f: F) -> Arc<T> {
+        Arc::new_cyclic(f)
+    }
+
+    fn get(&self) -> &T {
+        self
+    }
+
+    fn downgrade(&self) -> sync::Weak<T> {
+        Arc::downgrade(self)
+    }
+
+    fn address(&self) -> usize {
+        Arc::as_ptr(self) as usize
+    }
+}
+
+impl<T: 'static> WeakPointer for sync::Weak<T> {
+    type Strong = Arc<T>;
+
+    fn dead() -> sync::Weak<T> {
+        sync::Weak::new()
+    }
+
+    fn upgrade(&self) -> Option<Arc<T>> {
+        sync::Weak::upgrade(self)
+    }
+}
+
+/// Writes a pointer keeping its pointee shared, for use with
+/// `#[serde(with = "bincode2::sharing")]`.
+pub fn serialize<P, S>(pointer: &P, serializer: S) -> Result<S::Ok, S::Error>
+where
+    P: Pointer,
+    P::Value: Serialize,
+    S: Serializer,
+{
+    let found = WRITING.with(|session| {
+        let mut session = session.borrow_mut();
+        let session = session.as_mut()?;
+        let next = session.ids.len() as u64;
+        let id = *session.ids.entry(pointer.address()).or_insert(next);
+        Some((id, id == next))
+    });
+    match found {
+        Some((id, true)) => (id, Some(pointer.get())).serialize(serializer),
+        Some((id, false)) => (id, None::<&P::Value>).serialize(serializer),
+        None => Err(S::Error::custom(
+            "shared pointers can only be serialized within a PreserveSharing",
+        )),
+    }
+}
+
+/// Reads a pointer written by `serialize`, for use with
+/// `#[serde(with = "bincode2::sharing")]`.
+pub fn deserialize<'de, P, D>(deserializer: D) -> Result<P, D::Error>
+where
+    P: Pointer,
+    P::Value: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    deserializer
+        .deserialize_tuple(2, PointerVisitor::<P, Plain>::new(false))
+        .and_then(strong)
+}
+
+/// Reads a pointer whose pointee may be reached through weak pointers while it
+/// is read, closing a cycle. Such pointees are allocated before they are read,
+/// which needs `Default` to fill them if reading fails.
+pub mod cyclic {
+    use super::*;
+
+    pub use super::serialize;
+
+    /// Reads a pointer written by `sharing::serialize`, for use with
+    /// `#[serde(with = "bincode2::sharing::cyclic")]`.
+    pub fn deserialize<'de, P, D>(deserializer: D) -> Result<P, D::Error>
+    where
+        P: Pointer,
+        P::Value: Deserialize<'de> + Default,
+        D: Deserializer<'de>,
+    {
+        deserializer
+            .deserialize_tuple(2, PointerVisitor::<P, Cyclic>::new(false))
+            .and_then(strong)
+    }
+}
+
+/// Weak pointers, written as the id of their pointee.
+pub mod weak {
+    use super::*;
+
+    /// Writes a weak pointer, and its pointee if this is the first pointer to it,
+    /// for use with `#[serde(with = "bincode2::sharing::weak")]`.
+    pub fn serialize<W, S>(pointer: &W, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        W: WeakPointer,
+        <W::Strong as Pointer>::Value: Serialize,
+        S: Serializer,
+    {
+        match pointer.upgrade() {
+            Some(strong) => super::serialize(&strong, serializer),
+            None => (DEAD, None::<()>).serialize(serializer),
+        }
+    }
+
+    /// Reads a weak pointer written by `serialize`, for use with
+    /// `#[serde(with = "bincode2::sharing::weak")]`.
+    ///
+    /// A pointee first met behind a weak pointer is kept alive until the end of
+    /// the `PreserveSharing`, for the strong pointers read later to share.
+    pub fn deserialize<'de, W, D>(deserializer: D) -> Result<W, D::Error>
+    where
+        W: WeakPointer,
+        <W::Strong as Pointer>::Value: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let resolved =
+            deserializer.deserialize_tuple(2, PointerVisitor::<W::Strong, Plain>::new(true))?;
+        Ok(match resolved {
+            Resolved::Strong(strong) => strong.downgrade(),
+            Resolved::Weak(weak) => weak,
+            Resolved::Dead => W::dead(),
+        })
+    }
+}
+
+/// A strong pointer whose pointee is kept shared, for pointers held in
+/// collections or other places `#[serde(with)]` does not reach.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Shared<P>(pub P);
+
+impl<P> Deref for Shared<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.0
+    }
+}
+
+impl<P> Serialize for Shared<P>
+where
+    P: Pointer,
+    P::Value: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, P> Deserialize<'de> for Shared<P>
+where
+    P: Pointer,
+    P::Value: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Shared<P>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize(deserializer).map(Shared)
+    }
+}
+
+/// A pointer read by `PointerVisitor`.
+enum Resolved<P: Pointer> {
+    Strong(P),
+    Weak(P::Weak),
+    Dead,
+}
+
+/// Why a pointer could not be read.
+#[derive(Debug)]
+enum Problem {
+    NoSession,
+    DeadStrong,
+    WrongType,
+    NotCyclic,
+    StrongCycle,
+    InvalidId(u64),
+    MissingPointee,
+    WrittenTwice,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::NoSession => formatter
+                .write_str("shared pointers can only be deserialized within a PreserveSharing"),
+            Problem::DeadStrong => formatter.write_str("a strong pointer cannot be dead"),
+            Problem::WrongType => formatter.write_str("shared pointee read as two different types"),
+            Problem::NotCyclic => formatter.write_str(
+                "a weak pointer to a pointee still being read needs the pointee to be read with `sharing::cyclic`",
+            ),
+            Problem::StrongCycle => {
+                formatter.write_str("a cycle of strong pointers cannot be restored")
+            }
+            Problem::InvalidId(id) => write!(formatter, "invalid shared pointer id {}", id),
+            Problem::MissingPointee => formatter.write_str("missing shared pointee"),
+            Problem::WrittenTwice => formatter.write_str("shared pointee written twice"),
+        }
+    }
+}
+
+/// Where the id of a pointer leads: to a pointer read before, or to a new pointee
+/// that follows.
+enum Found<P: Pointer> {
+    Known(Resolved<P>),
+    New,
+}
+
+/// Looks `id` up in the session, reserving it if it is new. This does not depend on
+/// the deserializer, so it is only compiled once per pointer type.
+fn find<P: Pointer>(id: u64, weak: bool) -> Result<Found<P>, Problem> {
+    if id == DEAD {
+        return if weak {
+            Ok(Found::Known(Resolved::Dead))
+        } else {
+            Err(Problem::DeadStrong)
+        };
+    }
+    READING.with(|session| {
+        let mut session = session.borrow_mut();
+        let slots = &mut session.as_mut().ok_or(Problem::NoSession)?.slots;
+        match slots.get(id as usize) {
+            Some(&Slot::Read(ref pointer)) => match pointer.downcast_ref::<P>() {
+                Some(pointer) => Ok(Found::Known(Resolved::Strong(pointer.clone()))),
+                None => Err(Problem::WrongType),
+            },
+            Some(&Slot::Reading(Some(ref pointer))) if weak => {
+                match pointer.downcast_ref::<P::Weak>() {
+                    Some(pointer) => Ok(Found::Known(Resolved::Weak(pointer.clone()))),
+                    None => Err(Problem::WrongType),
+                }
+            }
+            Some(&Slot::Reading(None)) if weak => Err(Problem::NotCyclic),
+            Some(&Slot::Reading(_)) => Err(Problem::StrongCycle),
+            None if id == slots.len() as u64 => {
+                slots.push(Slot::Reading(None));
+                Ok(Found::New)
+            }
+            None => Err(Problem::InvalidId(id)),
+        }
+    })
+}
+
+/// Reads the pointee of a pointer met for the first time.
+trait Allocate<'de, P: Pointer> {
+    fn read<A: SeqAccess<'de>>(seq: &mut A, id: u64) -> Result<P, A::Error>;
+}
+
+/// Allocates pointees once they are read.
+struct Plain;
+
+/// Allocates pointees before they are read, so weak pointers to them can be
+/// handed out meanwhile.
+struct Cyclic;
+
+impl<'de, P> Allocate<'de, P> for Plain
+where
+    P: Pointer,
+    P::Value: Deserialize<'de>,
+{
+    fn read<A: SeqAccess<'de>>(seq: &mut A, _id: u64) -> Result<P, A::Error> {
+        match seq.next_element::<Option<P::Value>>()? {
+            Some(Some(value)) => Ok(P::new(value)),
+            _ => Err(A::Error::custom(Problem::MissingPointee)),
+        }
+    }
+}
+
+impl<'de, P> Allocate<'de, P> for Cyclic
+where
+    P: Pointer,
+    P::Value: Deserialize<'de> + Default,
+{
+    fn read<A: SeqAccess<'de>>(seq: &mut A, id: u64) -> Result<P, A::Error> {
+        let mut result = Ok(());
+        let pointer = P::new_cyclic(|weak| {
+            set_slot(id, Slot::Reading(Some(Box::new(weak.clone()))));
+            match seq.next_element::<Option<P::Value>>() {
+                Ok(Some(Some(value))) => value,
+                Ok(_) => {
+                    result = Err(A::Error::custom(Problem::MissingPointee));
+                    P::Value::default()
+                }
+                Err(error) => {
+                    result = Err(error);
+                    P::Value::default()
+                }
+            }
+        });
+        result.map(|()| pointer)
+    }
+}
+
+fn set_slot(id: u64, slot: Slot) {
+    READING.with(|session| {
+        if let Some(ref mut session) = *session.borrow_mut() {
+            session.slots[id as usize] = slot;
+        }
+    })
+}
+
+struct PointerVisitor<P, M> {
+    weak: bool,
+    _marker: PhantomData<(P, M)>,
+}
+
+impl<P, M> PointerVisitor<P, M> {
+    fn new(weak: bool) -> PointerVisitor<P, M> {
+        PointerVisitor {
+            weak,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'de, P: Pointer, M: Allocate<'de, P>> Visitor<'de> for PointerVisitor<P, M> {
+    type Value = Resolved<P>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a shared pointer")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Resolved<P>, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let id: u64 = match seq.next_element()? {
+            Some(id) => id,
+            None => return Err(A::Error::invalid_length(0, &self)),
+        };
+        match find::<P>(id, self.weak).map_err(A::Error::custom)? {
+            Found::New => {
+                let pointer: P = M::read(&mut seq, id)?;
+                set_slot(id, Slot::Read(Box::new(pointer.clone())));
+                Ok(Resolved::Strong(pointer))
+            }
+            Found::Known(resolved) => match seq.next_element::<Option<()>>()? {
+                Some(Some(())) => Err(A::Error::custom(Problem::WrittenTwice)),
+                _ => Ok(resolved),
+            },
+        }
+    }
+}
+
+fn strong<P: Pointer, E: DeError>(resolved: Resolved<P>) -> Result<P, E> {
+    match resolved {
+        Resolved::Strong(pointer) => Ok(pointer),
+        _ => Err(E::custom("expected a strong pointer")),
+    }
+}
@@ -0,0 +1,85 @@
+//! Interop with `std::io`, enabled by the `std` feature.
+//!
+//! With `std` enabled `core2` re-exports the standard io traits, so `File`,
+//! `TcpStream` and friends can be passed to bincode2 directly. The adapters
+//! below make that explicit in code that has to name the wrapped type.
+
+use core2::io;
+use std::io as std_io;
+
+use error::{Error, ErrorKind};
+
+/// Exposes a `std::io::Read` as the reader bincode2 expects.
+#[derive(Debug)]
+pub struct StdReadAdapter<R> {
+    inner: R,
+}
+
+/// Exposes a `std::io::Write` as the writer bincode2 expects.
+#[derive(Debug)]
+pub struct StdWriteAdapter<W> {
+    inner: W,
+}
+
+impl<R: std_io::Read> StdReadAdapter<R> {
+    /// Wraps a standard reader.
+    pub fn new(inner: R) -> StdReadAdapter<R> {
+        StdReadAdapter { inner }
+    }
+
+    /// Returns the wrapped reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<W: std_io::Write> StdWriteAdapter<W> {
+    /// Wraps a standard writer.
+    pub fn new(inner: W) -> StdWriteAdapter<W> {
+        StdWriteAdapter { inner }
+    }
+
+    /// Returns the wrapped writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<R: std_io::Read> io::Read for StdReadAdapter<R> {
+    #[inline(always)]
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(out)
+    }
+
+    #[inline(always)]
+    fn read_exact(&mut self, out: &mut [u8]) -> io::Result<()> {
+        self.inner.read_exact(out)
+    }
+}
+
+impl<W: std_io::Write> io::Write for StdWriteAdapter<W> {
+    #[inline(always)]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    #[inline(always)]
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.inner.write_all(buf)
+    }
+
+    #[inline(always)]
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Io errors are returned as they are, anything else becomes `InvalidData`.
+impl From<Error> for std_io::Error {
+    fn from(err: Error) -> std_io::Error {
+        match ::error::into_kind(err) {
+            ErrorKind::Io(err) => err,
+            kind => std_io::Error::new(std_io::ErrorKind::InvalidData, kind),
+        }
+    }
+}
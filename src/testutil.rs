@@ -0,0 +1,193 @@
+//! Round-trip checks for downstream tests, enabled by the `testutil` feature.
+//!
+//! `assert_roundtrip` checks that a value reads back equal to itself under one
+//! configuration, and `assert_roundtrip_all_configs` under every combination of
+//! byte order, string and array length and limit in `all_configs`. For property
+//! tests, `Config` implements proptest's `Arbitrary` over the same combinations,
+//! so `any::<Config>()` can be paired with a strategy for the values:
+//!
+//! ```
+//! # extern crate bincode2;
+//! # extern crate proptest;
+//! # #[macro_use] extern crate serde_derive;
+//! use bincode2::testutil::{assert_roundtrip, assert_roundtrip_all_configs};
+//! use bincode2::Config;
+//! use proptest::prelude::*;
+//! use proptest::test_runner::TestRunner;
+//!
+//! #[derive(Serialize, Deserialize, PartialEq, Debug)]
+//! struct Reading {
+//!     sensor: String,
+//!     values: Vec<i32>,
+//! }
+//!
+//! # fn main() {
+//! assert_roundtrip_all_configs(&Reading {
+//!     sensor: "t1".into(),
+//!     values: vec![-1, 2],
+//! });
+//!
+//! let values = (".*", prop::collection::vec(any::<i32>(), 0..64));
+//! TestRunner::default()
+//!     .run(&(values, any::<Config>()), |((sensor, values), config)| {
+//!         assert_roundtrip(&Reading { sensor, values }, &config);
+//!         Ok(())
+//!     })
+//!     .unwrap();
+//! # }
+//! ```
+
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use proptest::arbitrary::Arbitrary;
+use proptest::sample::select;
+use proptest::strategy::{BoxedStrategy, Strategy};
+use serde;
+
+use config::{Config, LengthOption};
+use error::{kind, ErrorKind};
+
+/// The limits `all_configs` sweeps: none, and two small enough for ordinary values
+/// to exceed.
+static LIMITS: [Option<u64>; 3] = [None, Some(64), Some(4096)];
+
+static LENGTHS: [LengthOption; 4] = [
+    LengthOption::U8,
+    LengthOption::U16,
+    LengthOption::U32,
+    LengthOption::U64,
+];
+
+/// Checks that `value` deserializes back to itself with `config`, panicking if it
+/// does not.
+///
+/// The value is serialized, and `serialized_size` must give the length of the
+/// result. The bytes must then deserialize to a value equal to `value`, both from
+/// a slice and from a reader, the reader having no bytes left over.
+///
+/// A value the config can't write because it is over the limit, or a length too
+/// large for the length type, passes as long as `serialized_size` fails as well.
+/// Any other error fails the check.
+pub fn assert_roundtrip<T>(value: &T, config: &Config)
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = match config.serialize(value) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            match *kind(&err) {
+                ErrorKind::SizeLimit { .. } | ErrorKind::SizeTypeLimit => {}
+                ref other => panic!(
+                    "{:?} failed to serialize with {:?}: {}",
+                    value, config, other
+                ),
+            }
+            assert!(
+                config.serialized_size(value).is_err(),
+                "{:?} failed to serialize with {:?} but has a serialized size",
+                value,
+                config
+            );
+            return;
+        }
+    };
+
+    match config.serialized_size(value) {
+        Ok(size) => assert_eq!(
+            size,
+            bytes.len() as u64,
+            "serialized size of {:?} with {:?}",
+            value,
+            config
+        ),
+        Err(err) => panic!(
+            "no serialized size for {:?} with {:?}: {}",
+            value, config, err
+        ),
+    }
+
+    match config.deserialize::<T>(&bytes) {
+        Ok(ref decoded) => assert_eq!(decoded, value, "deserialized with {:?}", config),
+        Err(err) => panic!(
+            "{:?} failed to deserialize with {:?}: {}",
+            value, config, err
+        ),
+    }
+
+    let mut reader = &bytes[..];
+    match config.deserialize_from::<_, T>(&mut reader) {
+        Ok(ref decoded) => assert_eq!(decoded, value, "read back with {:?}", config),
+        Err(err) => panic!("{:?} failed to read back with {:?}: {}", value, config, err),
+    }
+    assert!(
+        reader.is_empty(),
+        "{} bytes of {:?} left unread with {:?}",
+        reader.len(),
+        value,
+        config
+    );
+}
+
+/// Runs `assert_roundtrip` on `value` with every configuration of `all_configs`.
+pub fn assert_roundtrip_all_configs<T>(value: &T)
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + PartialEq + Debug,
+{
+    for config in all_configs() {
+        assert_roundtrip(value, &config);
+    }
+}
+
+/// Every combination of byte order, string length, array length and limit: little,
+/// big and native endian, each `LengthOption` but `Custom`, and no limit or a limit
+/// of 64 or 4096 bytes.
+pub fn all_configs() -> Vec<Config> {
+    let mut configs = Vec::new();
+    for endian in 0..3 {
+        for &string in &LENGTHS {
+            for &array in &LENGTHS {
+                for &limit in &LIMITS {
+                    configs.push(make_config(endian, string, array, limit));
+                }
+            }
+        }
+    }
+    configs
+}
+
+fn make_config(
+    endian: u8,
+    string: LengthOption,
+    array: LengthOption,
+    limit: Option<u64>,
+) -> Config {
+    let mut config = Config::new();
+    match endian {
+        0 => config.little_endian(),
+        1 => config.big_endian(),
+        _ => config.native_endian(),
+    };
+    config.string_length(string).array_length(array);
+    if let Some(limit) = limit {
+        config.limit(limit);
+    }
+    config
+}
+
+/// Picks one of the combinations of `all_configs`.
+impl Arbitrary for Config {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Config>;
+
+    fn arbitrary_with(_: ()) -> BoxedStrategy<Config> {
+        (
+            0..3u8,
+            select(&LENGTHS[..]),
+            select(&LENGTHS[..]),
+            select(&LIMITS[..]),
+        )
+            .prop_map(|(endian, string, array, limit)| make_config(endian, string, array, limit))
+            .boxed()
+    }
+}
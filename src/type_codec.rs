@@ -0,0 +1,287 @@
+//! Encodings registered per type with `Config::type_codec`, see `TypeCodec`.
+
+use core::any::{type_name, TypeId};
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+
+use encode::{Decoder, Encoder};
+use error::Result;
+use serde;
+
+/// An encoding for values of type `Value` that replaces their `Serialize` and
+/// `Deserialize` impls, registered with `config.type_codec`.
+///
+/// This changes how a type is written without a `#[serde(with)]` attribute on
+/// every field holding it, for types defined in crates that cannot be changed.
+///
+/// Serde only tells the format the type of some values, so a codec applies to:
+///
+/// * the value passed to `serialize` and `deserialize` itself,
+/// * the elements of sequences, tuples and maps, and the keys of maps,
+/// * the fields of structs, tuple structs and enum variants,
+/// * the value of newtype variants.
+///
+/// Anything else keeps its serde encoding on both sides, notably the value of an
+/// `Option` or of a newtype struct, fields with `#[serde(with)]`, values read by
+/// `deserialize_any` (as `#[serde(flatten)]` does), the entries of maps
+/// written with `allow_flatten` and every value written with `allow_untagged`.
+///
+/// # Safety
+///
+/// `Value` must not have lifetime parameters: no references, and no types such
+/// as `Cow<'static, str>` that borrow. Codecs are matched by the type of the
+/// value with its lifetimes erased, so a codec for `&'static str` would also be
+/// handed a `&'a str` as if it lived for `'static`, and could keep it past its
+/// end.
+///
+/// ```
+/// # extern crate bincode2;
+/// # #[macro_use] extern crate serde_derive;
+/// use bincode2::{Decoder, Encoder, Result, TypeCodec};
+///
+/// /// A decimal amount from a crate we do not own, serialized as a string.
+/// #[derive(Serialize, Deserialize, Debug, PartialEq)]
+/// struct Money(String);
+///
+/// /// Writes `Money` as a number of cents.
+/// struct Cents;
+///
+/// unsafe impl TypeCodec for Cents {
+///     type Value = Money;
+///
+///     fn encode(value: &Money, encoder: &mut dyn Encoder) -> Result<()> {
+///         let cents = value.0.replace('.', "").parse::<i64>().unwrap();
+///         encoder.encode_i64(cents)
+///     }
+///
+///     fn decode(decoder: &mut dyn Decoder) -> Result<Money> {
+///         let cents = decoder.decode_i64()?;
+///         Ok(Money(format!("{}.{:02}", cents / 100, cents % 100)))
+///     }
+/// }
+///
+/// # fn main() {
+/// let mut config = bincode2::config();
+/// config.type_codec::<Cents>();
+///
+/// let prices = vec![Money("1.50".into()), Money("20.00".into())];
+/// let bytes = config.serialize(&prices).unwrap();
+/// assert_eq!(bytes.len(), 8 + 2 * 8);
+/// assert_eq!(config.deserialize::<Vec<Money>>(&bytes).unwrap(), prices);
+/// # }
+/// ```
+pub unsafe trait TypeCodec: 'static {
+    /// The type encoded, which must not have lifetime parameters, see the safety
+    /// section.
+    type Value: 'static;
+
+    /// Writes `value`.
+    fn encode(value: &Self::Value, encoder: &mut dyn Encoder) -> Result<()>;
+
+    /// Reads a value written by `encode`.
+    fn decode(decoder: &mut dyn Decoder) -> Result<Self::Value>;
+}
+
+/// The `TypeId` of `T` with its lifetimes erased, which `TypeId::of` cannot give
+/// without `T: 'static`.
+pub(crate) fn type_id<T: ?Sized>() -> TypeId {
+    trait NonStaticAny {
+        fn type_id(&self) -> TypeId
+        where
+            Self: 'static;
+    }
+
+    impl<T: ?Sized> NonStaticAny for PhantomData<T> {
+        fn type_id(&self) -> TypeId
+        where
+            Self: 'static,
+        {
+            TypeId::of::<T>()
+        }
+    }
+
+    let phantom = PhantomData::<T>;
+    // Type ids do not depend on lifetimes, so pretending the lifetimes of `T` are
+    // `'static` gives the id every instantiation of `T` shares.
+    NonStaticAny::type_id(unsafe {
+        mem::transmute::<&dyn NonStaticAny, &(dyn NonStaticAny + 'static)>(&phantom)
+    })
+}
+
+/// A `TypeCodec` with its type erased, so that a `Config` can hold it.
+///
+/// The ids and the name are functions giving them, as a codec may be built in a
+/// `const`, where `TypeId::of` and `type_name` can't be called.
+#[derive(Clone, Copy)]
+pub(crate) struct Codec {
+    codec: fn() -> TypeId,
+    name: fn() -> &'static str,
+    /// The id of `C::Value`, matched by serializers.
+    value: fn() -> TypeId,
+    /// The id of `PhantomData<C::Value>`, the seed deserializers are given for it.
+    seed: fn() -> TypeId,
+    encode: fn(*const (), &mut dyn Encoder) -> Result<()>,
+    decode: fn(&mut dyn Decoder, *mut ()) -> Result<()>,
+}
+
+#[cfg(feature = "alloc")]
+fn encode_erased<C: TypeCodec>(value: *const (), encoder: &mut dyn Encoder) -> Result<()> {
+    // `Codec::encode` only passes values whose type id is that of `C::Value`.
+    C::encode(unsafe { &*(value as *const C::Value) }, encoder)
+}
+
+#[cfg(feature = "alloc")]
+fn decode_erased<C: TypeCodec>(decoder: &mut dyn Decoder, out: *mut ()) -> Result<()> {
+    let value = C::decode(decoder)?;
+    // `Codec::decode` only passes room for a `C::Value`.
+    unsafe { ptr::write(out as *mut C::Value, value) };
+    Ok(())
+}
+
+impl Codec {
+    #[cfg(feature = "alloc")]
+    const fn of<C: TypeCodec>() -> Codec {
+        Codec {
+            codec: TypeId::of::<C>,
+            name: type_name::<C>,
+            value: TypeId::of::<C::Value>,
+            seed: TypeId::of::<PhantomData<C::Value>>,
+            encode: encode_erased::<C>,
+            decode: decode_erased::<C>,
+        }
+    }
+
+    /// Writes `value`, which must be of the type the codec was found for.
+    pub(crate) fn encode<T: ?Sized>(self, value: &T, encoder: &mut dyn Encoder) -> Result<()> {
+        assert!((self.value)() == type_id::<T>());
+        (self.encode)(value as *const T as *const (), encoder)
+    }
+
+    /// Reads the value `S` stands for, which must be the seed the codec was found for.
+    pub(crate) fn decode<'de, S>(self, decoder: &mut dyn Decoder) -> Result<S::Value>
+    where
+        S: serde::de::DeserializeSeed<'de>,
+    {
+        assert!((self.seed)() == type_id::<S>());
+        let mut out = MaybeUninit::<S::Value>::uninit();
+        (self.decode)(decoder, out.as_mut_ptr() as *mut ())?;
+        Ok(unsafe { out.assume_init() })
+    }
+}
+
+/// The most codecs a `Config` can hold.
+pub(crate) const MAX_CODECS: usize = 8;
+
+/// The codecs registered on a `Config`, which can only hold some with `alloc`.
+///
+/// They are held inline, so that a `Config` is `Copy` and can be built in a
+/// `const`. A type may have several codecs registered by `with`, which can't
+/// compare type ids; the last one is used.
+#[derive(Clone, Copy)]
+pub(crate) struct Codecs {
+    codecs: [Option<Codec>; MAX_CODECS],
+    len: usize,
+}
+
+impl Codecs {
+    pub(crate) const fn new() -> Codecs {
+        Codecs {
+            codecs: [None; MAX_CODECS],
+            len: 0,
+        }
+    }
+
+    /// Registers `C`, replacing the codec registered for the same type if any.
+    #[cfg(feature = "alloc")]
+    pub(crate) fn insert<C: TypeCodec>(&mut self) {
+        let codec = Codec::of::<C>();
+        let value = (codec.value)();
+        let mut kept = 0;
+        for i in 0..self.len {
+            let registered = self.codecs[i].take();
+            if registered.map(|registered| (registered.value)()) != Some(value) {
+                self.codecs[kept] = registered;
+                kept += 1;
+            }
+        }
+        self.len = kept;
+        self.push(codec);
+    }
+
+    /// Registers `C` after the codecs already registered.
+    #[cfg(feature = "alloc")]
+    pub(crate) const fn with<C: TypeCodec>(mut self) -> Codecs {
+        self.push(Codec::of::<C>());
+        self
+    }
+
+    #[cfg(feature = "alloc")]
+    const fn push(&mut self, codec: Codec) {
+        assert!(
+            self.len < MAX_CODECS,
+            "a config holds at most 8 type codecs"
+        );
+        self.codecs[self.len] = Some(codec);
+        self.len += 1;
+    }
+
+    #[inline(always)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The registered codecs, the last registered first.
+    #[inline(always)]
+    fn iter<'a>(&'a self) -> impl DoubleEndedIterator<Item = &'a Codec> + 'a {
+        self.codecs[..self.len].iter().rev().flatten()
+    }
+
+    /// The codec for values of type `T`.
+    #[inline(always)]
+    pub(crate) fn for_value<T: ?Sized>(&self) -> Option<Codec> {
+        if self.is_empty() {
+            return None;
+        }
+        let id = type_id::<T>();
+        self.iter().find(|codec| (codec.value)() == id).cloned()
+    }
+
+    /// The codec for the values `S` deserializes.
+    #[inline(always)]
+    pub(crate) fn for_seed<S>(&self) -> Option<Codec> {
+        if self.is_empty() {
+            return None;
+        }
+        let id = type_id::<S>();
+        self.iter().find(|codec| (codec.seed)() == id).cloned()
+    }
+}
+
+impl Default for Codecs {
+    fn default() -> Codecs {
+        Codecs::new()
+    }
+}
+
+impl PartialEq for Codecs {
+    fn eq(&self, other: &Codecs) -> bool {
+        self.len == other.len
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|(a, b)| (a.codec)() == (b.codec)())
+    }
+}
+
+impl Eq for Codecs {}
+
+impl fmt::Debug for Codecs {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter
+            .debug_list()
+            .entries(self.iter().rev().map(|codec| (codec.name)()))
+            .finish()
+    }
+}
@@ -0,0 +1,390 @@
+//! Checking input without building the value it holds, see `Config::validate_only`.
+//!
+//! `Validate` wraps a deserializer and passes what it reads on to the `Deserialize`
+//! impl of the type being checked, except for what would be copied or collected:
+//! owned strings and byte buffers are checked and handed over empty, and the
+//! elements of sequences and the entries of maps are checked and dropped one by one,
+//! the collection being handed over empty. Types that tell these empty values apart
+//! from the real ones are not checked faithfully, see `Config::validate_only`.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use serde;
+use serde::de::{DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor};
+
+use type_codec::type_id;
+
+/// Whether `S` is the seed of a type deserialized by its `Deserialize` impl, which
+/// can be copied to read several values of that type.
+fn is_phantom<'de, S: DeserializeSeed<'de>>() -> bool {
+    type_id::<S>() == type_id::<PhantomData<S::Value>>()
+}
+
+/// A copy of a seed `is_phantom` accepts.
+fn copy_seed<'de, S: DeserializeSeed<'de>>(seed: &S) -> S {
+    debug_assert!(is_phantom::<S>());
+    // `S` is a `PhantomData`, which holds nothing and is `Copy`.
+    unsafe { ptr::read(seed) }
+}
+
+/// A deserializer checking what `D` reads, without copying strings or collecting
+/// sequences and maps.
+pub(crate) struct Validate<D>(pub(crate) D);
+
+macro_rules! forward {
+    ($($method:ident($($arg:ident: $ty:ty),*),)*) => {
+        $(
+            #[inline]
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.0.$method($($arg,)* Wrap::new(visitor))
+            }
+        )*
+    };
+}
+
+impl<'de, D: serde::Deserializer<'de>> serde::Deserializer<'de> for Validate<D> {
+    type Error = D::Error;
+
+    forward! {
+        deserialize_any(),
+        deserialize_bool(),
+        deserialize_i8(),
+        deserialize_i16(),
+        deserialize_i32(),
+        deserialize_i64(),
+        deserialize_i128(),
+        deserialize_u8(),
+        deserialize_u16(),
+        deserialize_u32(),
+        deserialize_u64(),
+        deserialize_u128(),
+        deserialize_f32(),
+        deserialize_f64(),
+        deserialize_char(),
+        deserialize_str(),
+        deserialize_bytes(),
+        deserialize_option(),
+        deserialize_unit(),
+        deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str),
+        deserialize_tuple(len: usize),
+        deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(),
+        deserialize_ignored_any(),
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_str(Checked)?;
+        visitor.visit_borrowed_str("")
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_bytes(Checked)?;
+        visitor.visit_borrowed_bytes(&[])
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_seq(Wrap::collection(visitor))
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_map(Wrap::collection(visitor))
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+/// Accepts the string or byte array the deserializer checked.
+struct Checked;
+
+impl<'de> Visitor<'de> for Checked {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string or byte array")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, _: &[u8]) -> Result<(), E> {
+        Ok(())
+    }
+}
+
+/// Passes what a visitor is given on, wrapping whatever it reads values from.
+struct Wrap<V> {
+    visitor: V,
+    /// Set for sequences and maps whose elements all have the same type.
+    collection: bool,
+}
+
+impl<V> Wrap<V> {
+    fn new(visitor: V) -> Wrap<V> {
+        Wrap {
+            visitor,
+            collection: false,
+        }
+    }
+
+    fn collection(visitor: V) -> Wrap<V> {
+        Wrap {
+            visitor,
+            collection: true,
+        }
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method:ident($ty:ty),)*) => {
+        $(
+            #[inline]
+            fn $method<E: serde::de::Error>(self, v: $ty) -> Result<V::Value, E> {
+                self.visitor.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'de, V: Visitor<'de>> Visitor<'de> for Wrap<V> {
+    type Value = V::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.visitor.expecting(formatter)
+    }
+
+    forward_visit! {
+        visit_bool(bool),
+        visit_i8(i8),
+        visit_i16(i16),
+        visit_i32(i32),
+        visit_i64(i64),
+        visit_i128(i128),
+        visit_u8(u8),
+        visit_u16(u16),
+        visit_u32(u32),
+        visit_u64(u64),
+        visit_u128(u128),
+        visit_f32(f32),
+        visit_f64(f64),
+        visit_char(char),
+        visit_str(&str),
+        visit_borrowed_str(&'de str),
+        visit_string(String),
+        visit_bytes(&[u8]),
+        visit_borrowed_bytes(&'de [u8]),
+        visit_byte_buf(Vec<u8>),
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<V::Value, E> {
+        self.visitor.visit_none()
+    }
+
+    fn visit_unit<E: serde::de::Error>(self) -> Result<V::Value, E> {
+        self.visitor.visit_unit()
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        self.visitor.visit_some(Validate(deserializer))
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<V::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        self.visitor.visit_newtype_struct(Validate(deserializer))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<V::Value, A::Error> {
+        self.visitor.visit_seq(Elements {
+            seq,
+            collection: self.collection,
+        })
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<V::Value, A::Error> {
+        self.visitor.visit_map(Entries {
+            map,
+            collection: self.collection,
+        })
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<V::Value, A::Error> {
+        self.visitor.visit_enum(Variants(data))
+    }
+}
+
+/// A seed reading its value through `Validate`.
+struct Seed<S>(S);
+
+impl<'de, S: DeserializeSeed<'de>> DeserializeSeed<'de> for Seed<S> {
+    type Value = S::Value;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<S::Value, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        self.0.deserialize(Validate(deserializer))
+    }
+}
+
+struct Elements<A> {
+    seq: A,
+    collection: bool,
+}
+
+impl<'de, A: SeqAccess<'de>> SeqAccess<'de> for Elements<A> {
+    type Error = A::Error;
+
+    fn next_element_seed<S>(&mut self, seed: S) -> Result<Option<S::Value>, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        if self.collection && is_phantom::<S>() {
+            // The elements all have the type of the first: check them and hand
+            // the collection over empty.
+            while self
+                .seq
+                .next_element_seed(Seed(copy_seed(&seed)))?
+                .is_some()
+            {}
+            return Ok(None);
+        }
+        self.seq.next_element_seed(Seed(seed))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        if self.collection {
+            return Some(0);
+        }
+        self.seq.size_hint()
+    }
+}
+
+struct Entries<A> {
+    map: A,
+    collection: bool,
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for Entries<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, A::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        self.map.next_key_seed(Seed(seed))
+    }
+
+    fn next_value_seed<S>(&mut self, seed: S) -> Result<S::Value, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        self.map.next_value_seed(Seed(seed))
+    }
+
+    // Maps read their entries with this, while structs read as maps read keys and
+    // values apart, so only maps are handed over empty.
+    fn next_entry_seed<K, S>(
+        &mut self,
+        key: K,
+        value: S,
+    ) -> Result<Option<(K::Value, S::Value)>, A::Error>
+    where
+        K: DeserializeSeed<'de>,
+        S: DeserializeSeed<'de>,
+    {
+        if self.collection && is_phantom::<K>() && is_phantom::<S>() {
+            while self
+                .map
+                .next_entry_seed(Seed(copy_seed(&key)), Seed(copy_seed(&value)))?
+                .is_some()
+            {}
+            return Ok(None);
+        }
+        self.map.next_entry_seed(Seed(key), Seed(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        if self.collection {
+            return Some(0);
+        }
+        self.map.size_hint()
+    }
+}
+
+struct Variants<A>(A);
+
+impl<'de, A: EnumAccess<'de>> EnumAccess<'de> for Variants<A> {
+    type Error = A::Error;
+    type Variant = Variants<A::Variant>;
+
+    fn variant_seed<S>(self, seed: S) -> Result<(S::Value, Variants<A::Variant>), A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        let (value, variant) = self.0.variant_seed(Seed(seed))?;
+        Ok((value, Variants(variant)))
+    }
+}
+
+impl<'de, A: VariantAccess<'de>> VariantAccess<'de> for Variants<A> {
+    type Error = A::Error;
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.0.unit_variant()
+    }
+
+    fn newtype_variant_seed<S>(self, seed: S) -> Result<S::Value, A::Error>
+    where
+        S: DeserializeSeed<'de>,
+    {
+        self.0.newtype_variant_seed(Seed(seed))
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.tuple_variant(len, Wrap::new(visitor))
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.struct_variant(fields, Wrap::new(visitor))
+    }
+}
@@ -1,11 +1,30 @@
 #[macro_use]
 extern crate serde_derive;
 
+extern crate bincode1;
 extern crate bincode2;
+extern crate bincode_upstream;
 extern crate byteorder;
+#[cfg(feature = "bytes")]
+extern crate bytes;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+extern crate core2;
+#[cfg(feature = "erased-serde")]
+extern crate erased_serde;
+#[cfg(feature = "num-bigint")]
+extern crate num_bigint;
+#[cfg(feature = "testutil")]
+extern crate proptest;
 #[macro_use]
 extern crate serde;
 extern crate serde_bytes;
+#[cfg(feature = "time")]
+extern crate time;
+#[cfg(feature = "codec")]
+extern crate tokio_util;
+#[cfg(feature = "uuid")]
+extern crate uuid;
 
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -14,7 +33,7 @@ use std::result::Result as StdResult;
 
 use bincode2::{
     config, deserialize, deserialize_from, deserialize_in_place, serialize, serialized_size,
-    ErrorKind, LengthOption, Result,
+    ErrorKind, FloatEncoding, IntEncoding, LengthOption, Result, UsizeMode,
 };
 use serde::de::{Deserialize, DeserializeSeed, Deserializer, SeqAccess, Visitor};
 
@@ -398,6 +417,68 @@ fn test_serialized_size_bounded() {
         .is_err());
 }
 
+#[test]
+fn test_serialized_size_capped() {
+    use std::cell::Cell;
+
+    assert_eq!(config().serialized_size_capped(&0u32, 4).unwrap(), Some(4));
+    assert_eq!(config().serialized_size_capped(&0u32, 3).unwrap(), None);
+    assert_eq!(
+        config().serialized_size_capped(&"a", 100).unwrap(),
+        Some(8 + 1)
+    );
+
+    // Counting stops once the cap is passed.
+    struct Counted<'a>(&'a Cell<u32>);
+
+    impl<'a> serde::Serialize for Counted<'a> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+            let visited = self.0;
+            serializer.collect_seq((0..1_000_000u32).inspect(|_| visited.set(visited.get() + 1)))
+        }
+    }
+
+    let visited = Cell::new(0);
+    assert_eq!(
+        config()
+            .serialized_size_capped(&Counted(&visited), 64)
+            .unwrap(),
+        None
+    );
+    assert!(visited.get() < 20);
+
+    // The size limit of the configuration still applies.
+    match *config()
+        .limit(4)
+        .serialized_size_capped(&0u64, 100)
+        .unwrap_err()
+    {
+        ErrorKind::SizeLimit {
+            limit: 4,
+            attempted: 8,
+        } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Deduplicated subtrees are counted by writing them out.
+    let value = vec!["ab".to_string(); 10];
+    let exact = config().dedup_subtrees().serialized_size(&value).unwrap();
+    assert_eq!(
+        config()
+            .dedup_subtrees()
+            .serialized_size_capped(&value, exact)
+            .unwrap(),
+        Some(exact)
+    );
+    assert_eq!(
+        config()
+            .dedup_subtrees()
+            .serialized_size_capped(&value, exact - 1)
+            .unwrap(),
+        None
+    );
+}
+
 #[test]
 fn encode_box() {
     the_same(Box::new(5));
@@ -543,6 +624,162 @@ fn test_zero_copy_parse() {
     }
 }
 
+#[test]
+fn test_cow_borrowing() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Foo<'a> {
+        #[serde(borrow)]
+        text: Cow<'a, str>,
+        #[serde(borrow)]
+        data: Cow<'a, [u8]>,
+        plain: Cow<'a, str>,
+    }
+
+    fn borrowed(foo: &Foo) -> (bool, bool, bool) {
+        let is_borrowed = |cow: &Cow<str>| matches!(*cow, Cow::Borrowed(_));
+        (
+            is_borrowed(&foo.text),
+            matches!(foo.data, Cow::Borrowed(_)),
+            is_borrowed(&foo.plain),
+        )
+    }
+
+    let foo = Foo {
+        text: Cow::Borrowed("hi"),
+        data: Cow::Borrowed(&[0, 1, 2]),
+        plain: Cow::Borrowed("there"),
+    };
+    let encoded = serialize(&foo).unwrap();
+
+    // Slices lend `#[serde(borrow)]` fields; serde always owns the others.
+    let out: Foo = deserialize(&encoded).unwrap();
+    assert_eq!(out, foo);
+    assert_eq!(borrowed(&out), (true, true, false));
+    let out: Foo = config()
+        .deserialize_from_custom_seed(
+            std::marker::PhantomData,
+            bincode2::SliceReader::new(&encoded),
+        )
+        .unwrap();
+    assert_eq!(borrowed(&out), (true, true, false));
+
+    // Readers copy.
+    let out: Foo = config()
+        .deserialize_from_custom_seed(
+            std::marker::PhantomData,
+            bincode2::IoReader::new(&encoded[..]),
+        )
+        .unwrap();
+    assert_eq!(out, foo);
+    assert_eq!(borrowed(&out), (false, false, false));
+
+    let mut config = config();
+    config.force_owned();
+    let out: Foo = config.deserialize(&encoded).unwrap();
+    assert_eq!(out, foo);
+    assert_eq!(borrowed(&out), (false, false, false));
+    let encoded = serialize(&foo.text).unwrap();
+    assert!(config.deserialize::<&str>(&encoded).is_err());
+    let encoded = serialize(&foo.data).unwrap();
+    assert!(config.deserialize::<&[u8]>(&encoded).is_err());
+}
+
+#[cfg(feature = "validate")]
+#[test]
+fn test_validate_only() {
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Shape {
+        Dot,
+        Line(Vec<(i32, i32)>),
+        Label { text: String, bold: bool },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Drawing<'a> {
+        name: &'a str,
+        shapes: Vec<Shape>,
+        layers: BTreeMap<u8, Option<String>>,
+        #[serde(with = "serde_bytes")]
+        thumbnail: Vec<u8>,
+        id: [u8; 4],
+    }
+
+    let mut layers = BTreeMap::new();
+    layers.insert(0, None);
+    layers.insert(1, Some("top".to_string()));
+    let drawing = Drawing {
+        name: "sketch",
+        shapes: vec![
+            Shape::Dot,
+            Shape::Line(vec![(0, 0), (3, -4)]),
+            Shape::Label {
+                text: "hi".to_string(),
+                bold: true,
+            },
+        ],
+        layers,
+        thumbnail: vec![1, 2, 3],
+        id: *b"abcd",
+    };
+    let config = config();
+    let encoded = config.serialize(&drawing).unwrap();
+    config.validate_only::<Drawing>(&encoded).unwrap();
+
+    // Every prefix is rejected.
+    for len in 0..encoded.len() {
+        assert!(config.validate_only::<Drawing>(&encoded[..len]).is_err());
+    }
+
+    // A string that is not UTF-8, inside a sequence of enums.
+    let text = encoded.windows(2).position(|w| w == b"hi").unwrap();
+    let mut invalid = encoded.clone();
+    invalid[text] = 0xff;
+    match *config.validate_only::<Drawing>(&invalid).unwrap_err() {
+        ErrorKind::InvalidUtf8Encoding(_) => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+    invalid[text] = b'h';
+    invalid[text + 2] = 2;
+    match *config.validate_only::<Drawing>(&invalid).unwrap_err() {
+        ErrorKind::InvalidBoolEncoding(2) => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+
+    // Both apply the limit of the config to the slice.
+    let mut limited = config;
+    limited.limit(encoded.len() as u64 - 1);
+    assert!(limited.validate_only::<Drawing>(&encoded).is_err());
+    assert!(limited.deserialize::<Drawing>(&encoded).is_err());
+    let seed = std::marker::PhantomData::<Drawing>;
+    assert!(limited.deserialize_seed(seed, &encoded).is_err());
+    limited.limit(encoded.len() as u64);
+    assert!(limited.validate_only::<Drawing>(&encoded).is_ok());
+    assert!(limited.deserialize::<Drawing>(&encoded).is_ok());
+
+    // Types rejecting empty collections are rejected, as documented, since they are
+    // handed over empty.
+    #[derive(Deserialize, Debug)]
+    #[serde(try_from = "Vec<u8>")]
+    struct NonEmpty(#[allow(dead_code)] Vec<u8>);
+
+    impl std::convert::TryFrom<Vec<u8>> for NonEmpty {
+        type Error = &'static str;
+
+        fn try_from(bytes: Vec<u8>) -> StdResult<NonEmpty, &'static str> {
+            if bytes.is_empty() {
+                return Err("empty");
+            }
+            Ok(NonEmpty(bytes))
+        }
+    }
+
+    let encoded = config.serialize(&vec![1u8]).unwrap();
+    assert!(config.deserialize::<NonEmpty>(&encoded).is_ok());
+    assert!(config.validate_only::<NonEmpty>(&encoded).is_err());
+}
+
 #[test]
 fn test_zero_copy_parse_deserialize_into() {
     use bincode2::BincodeRead;
@@ -888,3 +1125,3601 @@ fn test_vec_size() {
     assert_eq!(16 + 1, expected);
     assert_eq!(16 + 1, actual);
 }
+
+#[test]
+fn test_custom_length() {
+    use bincode2::CustomSizeType;
+    use core2::io::{Read, Write};
+
+    /// LEB128, as protobuf writes lengths.
+    struct Leb128;
+
+    impl CustomSizeType for Leb128 {
+        fn write(mut len: u64, writer: &mut dyn Write) -> Result<()> {
+            loop {
+                let byte = (len & 0x7f) as u8;
+                len >>= 7;
+                if len == 0 {
+                    writer.write_all(&[byte])?;
+                    return Ok(());
+                }
+                writer.write_all(&[byte | 0x80])?;
+            }
+        }
+
+        fn read(reader: &mut dyn Read) -> Result<u64> {
+            let mut len = 0;
+            for shift in (0..64).step_by(7) {
+                let mut byte = [0];
+                reader.read_exact(&mut byte)?;
+                len |= u64::from(byte[0] & 0x7f) << shift;
+                if byte[0] & 0x80 == 0 {
+                    return Ok(len);
+                }
+            }
+            Err(Box::new(ErrorKind::SizeTypeLimit))
+        }
+    }
+
+    struct Small;
+
+    impl CustomSizeType for Small {
+        fn write(len: u64, writer: &mut dyn Write) -> Result<()> {
+            if len > 3 {
+                return Err(Box::new(ErrorKind::SizeTypeLimit));
+            }
+            writer.write_all(&[len as u8])?;
+            Ok(())
+        }
+
+        fn read(reader: &mut dyn Read) -> Result<u64> {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            Ok(u64::from(byte[0]))
+        }
+    }
+
+    let mut config = config();
+    config.array_length_custom::<Leb128>();
+    let v = vec![7u8; 300];
+    let bytes = config.serialize(&v).unwrap();
+    assert_eq!(&bytes[..2], &[0xac, 0x02]);
+    assert_eq!(bytes.len(), 2 + 300);
+    assert_eq!(config.serialized_size(&v).unwrap(), 2 + 300);
+    assert_eq!(config.deserialize::<Vec<u8>>(&bytes).unwrap(), v);
+    // Strings keep their default length.
+    assert_eq!(config.serialize("ab").unwrap().len(), 8 + 2);
+
+    config.string_length_custom::<Small>();
+    let bytes = config.serialize(&("abc", vec![1u8])).unwrap();
+    assert_eq!(bytes, [3, b'a', b'b', b'c', 1, 1]);
+    assert_eq!(
+        config.deserialize::<(String, Vec<u8>)>(&bytes).unwrap(),
+        ("abc".to_string(), vec![1])
+    );
+    match *config.serialize("abcd").unwrap_err() {
+        ErrorKind::SizeTypeLimit => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Bytes read for the length count towards the limit.
+    config.limit(3);
+    assert!(config
+        .deserialize::<String>(&[3, b'a', b'b', b'c'])
+        .is_err());
+    config.limit(4);
+    assert_eq!(
+        config
+            .deserialize::<String>(&[3, b'a', b'b', b'c'])
+            .unwrap(),
+        "abc"
+    );
+
+    let mut small = config;
+    small.string_length_custom::<Small>();
+    assert_eq!(small, config);
+    let mut leb128 = config;
+    leb128.string_length_custom::<Leb128>();
+    assert_ne!(leb128, config);
+}
+
+#[test]
+fn test_string_encoding() {
+    use bincode2::StringMode;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Record {
+        name: String,
+        id: u16,
+    }
+
+    let record = Record {
+        name: "abc".to_string(),
+        id: 7,
+    };
+
+    let mut config = config();
+    config.string_encoding(StringMode::NulTerminated);
+    let bytes = config.serialize(&record).unwrap();
+    assert_eq!(bytes, [b'a', b'b', b'c', 0, 7, 0]);
+    assert_eq!(config.serialized_size(&record).unwrap(), 6);
+    assert_eq!(config.deserialize::<Record>(&bytes).unwrap(), record);
+    match *config.deserialize::<String>(b"abc").unwrap_err() {
+        ErrorKind::MissingNulTerminator => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+    match *config.serialize("a\0b").unwrap_err() {
+        ErrorKind::NulInString => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    config.string_encoding(StringMode::FixedWidth(5));
+    let bytes = config.serialize(&record).unwrap();
+    assert_eq!(bytes, [b'a', b'b', b'c', 0, 0, 7, 0]);
+    assert_eq!(config.serialized_size(&record).unwrap(), 7);
+    assert_eq!(config.deserialize::<Record>(&bytes).unwrap(), record);
+    assert_eq!(
+        config.deserialize::<String>(b"abcde").unwrap(),
+        "abcde".to_string()
+    );
+    match *config.deserialize::<String>(b"ab\0cd").unwrap_err() {
+        ErrorKind::InvalidStringPadding => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+    match *config.serialize("abcdef").unwrap_err() {
+        ErrorKind::StringTooLong(6) => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Padding wider than a single write.
+    config.string_encoding(StringMode::FixedWidth(200));
+    let bytes = config.serialize("x").unwrap();
+    assert_eq!(bytes.len(), 200);
+    assert_eq!(config.deserialize::<String>(&bytes).unwrap(), "x");
+
+    // The terminator is read within the limit.
+    config.string_encoding(StringMode::NulTerminated).limit(3);
+    match *config.deserialize::<String>(b"abc\0").unwrap_err() {
+        ErrorKind::SizeLimit { limit: 3, .. } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_string_writes() {
+    use bincode2::{Overflow, StringMode};
+    use core2::io;
+    use serde::Serialize;
+
+    /// Counts the calls made to it.
+    struct Calls(Vec<Vec<u8>>);
+
+    impl io::Write for Calls {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.write(buf).map(|_| ())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // A length-prefixed string is its length and its bytes, written as they are.
+    let long = "x".repeat(1 << 16);
+    let mut calls = Calls(Vec::new());
+    config().serialize_into(&mut calls, &long[..]).unwrap();
+    assert_eq!(calls.0.len(), 2);
+    assert_eq!(calls.0[0], (long.len() as u64).to_le_bytes());
+    assert_eq!(calls.0[1], long.as_bytes());
+
+    /// Written with `collect_str`, in several pieces.
+    struct Joined<'a>(&'a [&'a str]);
+
+    impl<'a> fmt::Display for Joined<'a> {
+        fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            self.0
+                .iter()
+                .try_for_each(|piece| formatter.write_str(piece))
+        }
+    }
+
+    impl<'a> Serialize for Joined<'a> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    // `collect_str` writes what `serialize_str` writes for the whole string.
+    let pieces: &[&str] = &["ab", "", "\u{e9}t\u{e9}", "\u{1f600}cd"];
+    let joined: String = pieces.concat();
+    let mut configs = vec![config(), config(), config(), config()];
+    configs[1].string_encoding(StringMode::NulTerminated);
+    configs[2].string_encoding(StringMode::FixedWidth(16));
+    configs[3]
+        .string_length(LengthOption::U8)
+        .on_length_overflow(Overflow::Truncate);
+    for config in &configs {
+        let bytes = config.serialize(&Joined(pieces)).unwrap();
+        assert_eq!(bytes, config.serialize(&joined).unwrap());
+        assert_eq!(
+            config.serialized_size(&Joined(pieces)).unwrap(),
+            bytes.len() as u64
+        );
+    }
+
+    /// Counts the times it is formatted.
+    struct Counted<'a>(&'a str, &'a std::cell::Cell<u32>);
+
+    impl<'a> fmt::Display for Counted<'a> {
+        fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            self.1.set(self.1.get() + 1);
+            formatter.write_str(self.0)
+        }
+    }
+
+    impl<'a> Serialize for Counted<'a> {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    // Short values are formatted once, on the stack. Longer ones fill it, then are
+    // formatted to be measured and again to be written.
+    let calls = std::cell::Cell::new(0);
+    let mut bytes = Vec::new();
+    config()
+        .serialize_into(&mut bytes, &Counted("short", &calls))
+        .unwrap();
+    assert_eq!(bytes, config().serialize("short").unwrap());
+    assert_eq!(calls.get(), 1);
+    let wide = "x".repeat(300);
+    calls.set(0);
+    bytes.clear();
+    config()
+        .serialize_into(&mut bytes, &Counted(&wide, &calls))
+        .unwrap();
+    assert_eq!(bytes, config().serialize(&wide).unwrap());
+    assert_eq!(calls.get(), 3);
+
+    // Truncating stops on the character boundary `serialize_str` would.
+    let long: Vec<String> = (0..200).map(|_| "\u{e9}".to_string()).collect();
+    let long: Vec<&str> = long.iter().map(|piece| &piece[..]).collect();
+    let bytes = configs[3].serialize(&Joined(&long)).unwrap();
+    assert_eq!(bytes, configs[3].serialize(&long.concat()).unwrap());
+    assert_eq!(bytes[0], 254);
+
+    match *configs[1].serialize(&Joined(&["a", "\0b"])).unwrap_err() {
+        ErrorKind::NulInString => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_length_overflow() {
+    use bincode2::{Overflow, StringMode};
+
+    let long = "é".repeat(200);
+    let mut config = config();
+    config.string_length(LengthOption::U8);
+    match *config.serialize(&long).unwrap_err() {
+        ErrorKind::SizeTypeLimit => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Cut on a character boundary below 255 bytes.
+    config.on_length_overflow(Overflow::Truncate);
+    let bytes = config.serialize(&long).unwrap();
+    assert_eq!(bytes[0], 254);
+    assert_eq!(config.serialized_size(&long).unwrap(), 255);
+    assert_eq!(
+        config.deserialize::<String>(&bytes).unwrap(),
+        "é".repeat(127)
+    );
+    // Sequences cannot be truncated.
+    config.array_length(LengthOption::U8);
+    assert!(config.serialize(&vec![0u8; 300]).is_err());
+
+    // Strings without a length are written whole.
+    let mut nul = config;
+    nul.string_encoding(StringMode::NulTerminated);
+    let bytes = nul.serialize(&long).unwrap();
+    assert_eq!(bytes.len(), 401);
+    assert_eq!(nul.serialized_size(&long).unwrap(), 401);
+    assert_eq!(nul.deserialize::<String>(&bytes).unwrap(), long);
+    nul.string_encoding(StringMode::FixedWidth(300));
+    match *nul.serialize(&long).unwrap_err() {
+        ErrorKind::StringTooLong(400) => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    config.on_length_overflow(Overflow::PromoteToLargerType);
+    let bytes = config.serialize(&long).unwrap();
+    assert_eq!(bytes[0], 255);
+    assert_eq!(&bytes[1..9], &400u64.to_le_bytes());
+    assert_eq!(config.serialized_size(&long).unwrap(), 9 + 400);
+    assert_eq!(config.deserialize::<String>(&bytes).unwrap(), long);
+    let v = vec![1u16; 255];
+    let bytes = config.serialize(&v).unwrap();
+    assert_eq!(bytes.len(), 9 + 2 * 255);
+    assert_eq!(config.deserialize::<Vec<u16>>(&bytes).unwrap(), v);
+    // Short lengths are unchanged.
+    assert_eq!(config.serialize("ab").unwrap(), [2, b'a', b'b']);
+}
+
+#[test]
+fn test_flatten() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Inner {
+        id: u32,
+        name: String,
+        tag: Option<char>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Kind {
+        Plain,
+        Weighted(f64),
+        Ranged { low: i16, high: i16 },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Outer {
+        version: u8,
+        #[serde(flatten)]
+        inner: Inner,
+        kinds: Vec<Kind>,
+    }
+
+    let value = Outer {
+        version: 2,
+        inner: Inner {
+            id: 7,
+            name: "seven".to_string(),
+            tag: Some('x'),
+        },
+        kinds: vec![
+            Kind::Plain,
+            Kind::Weighted(0.5),
+            Kind::Ranged { low: -1, high: 1 },
+        ],
+    };
+
+    assert!(serialize(&value).is_err());
+
+    let mut config = config();
+    config.allow_flatten();
+    let encoded = config.serialize(&value).unwrap();
+    assert_eq!(
+        config.serialized_size(&value).unwrap(),
+        encoded.len() as u64
+    );
+    let decoded: Outer = config.deserialize(&encoded).unwrap();
+    assert_eq!(value, decoded);
+    let decoded: Outer = config.deserialize_from(&mut &encoded[..]).unwrap();
+    assert_eq!(value, decoded);
+
+    config.big_endian().array_length(LengthOption::U16);
+    let encoded = config.serialize(&value).unwrap();
+    let decoded: Outer = config.deserialize(&encoded).unwrap();
+    assert_eq!(value, decoded);
+}
+
+#[test]
+fn test_flatten_map_encoding() {
+    let mut m = HashMap::new();
+    m.insert(1u16, "a".to_string());
+
+    let mut config = config();
+    config.allow_flatten();
+    let encoded = config.serialize(&m).unwrap();
+    // length, then a type marker before the key and the value
+    assert_eq!(encoded.len(), 8 + (1 + 2) + (1 + 8 + 1));
+    let decoded: HashMap<u16, String> = config.deserialize(&encoded).unwrap();
+    assert_eq!(m, decoded);
+}
+
+#[test]
+fn test_allow_untagged() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Value {
+        Flag(bool),
+        Number(i64),
+        Text(String),
+        List(Vec<Value>),
+        Point { x: f32, y: f32 },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(tag = "type")]
+    enum Event {
+        Start,
+        Move { to: (u8, u8) },
+        Stop { reason: Option<String> },
+    }
+
+    let value = Value::List(vec![
+        Value::Flag(true),
+        Value::Number(-3),
+        Value::Text("three".to_string()),
+        Value::Point { x: 1.5, y: -1.0 },
+        Value::List(vec![]),
+    ]);
+    let events = vec![
+        Event::Start,
+        Event::Move { to: (1, 2) },
+        Event::Stop { reason: None },
+    ];
+
+    let encoded = serialize(&value).unwrap();
+    match *config().deserialize::<Value>(&encoded).unwrap_err() {
+        ErrorKind::DeserializeAnyNotSupported => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+
+    let mut config = config();
+    config.allow_untagged();
+    let encoded = config.serialize(&value).unwrap();
+    assert_eq!(
+        config.serialized_size(&value).unwrap(),
+        encoded.len() as u64
+    );
+    assert_eq!(config.deserialize::<Value>(&encoded).unwrap(), value);
+    assert_eq!(
+        config
+            .deserialize_from::<_, Value>(&mut &encoded[..])
+            .unwrap(),
+        value
+    );
+
+    let encoded = config.serialize(&events).unwrap();
+    assert_eq!(config.deserialize::<Vec<Event>>(&encoded).unwrap(), events);
+
+    // Plain types still round-trip, each value preceded by its type marker.
+    let encoded = config.serialize(&(7u8, "hi")).unwrap();
+    assert_eq!(encoded.len(), (1 + 8) + (1 + 1) + (1 + 8 + 2));
+    assert_eq!(
+        config.deserialize::<(u8, String)>(&encoded).unwrap(),
+        (7, "hi".to_string())
+    );
+}
+
+#[test]
+fn test_skip_ignored_values() {
+    use serde::de::IgnoredAny;
+    use std::collections::BTreeMap;
+
+    let mut m = BTreeMap::new();
+    m.insert(
+        "a",
+        (vec!["x".repeat(1000); 3], Some(vec![1u8; 500]), 1.5f64),
+    );
+    m.insert("b", (vec![], None, -2.0));
+    let mut nested = BTreeMap::new();
+    nested.insert('k', m.clone());
+
+    let mut config = config();
+    config.allow_flatten();
+    let encoded = config.serialize(&(&m, &nested, 9u8)).unwrap();
+
+    // Values are skipped without being decoded, from slices and readers alike.
+    type Skipped = (BTreeMap<String, IgnoredAny>, BTreeMap<char, IgnoredAny>, u8);
+    let (keys, _, last): Skipped = config.deserialize(&encoded).unwrap();
+    assert_eq!(keys.keys().collect::<Vec<_>>(), ["a", "b"]);
+    assert_eq!(last, 9);
+    let (keys, _, last): Skipped = config.deserialize_from(&encoded[..]).unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(last, 9);
+
+    // Skipped bytes count towards the limit.
+    config.limit(encoded.len() as u64 - 1);
+    assert!(config.deserialize::<Skipped>(&encoded).is_err());
+
+    // Without the markers there is nothing to skip by.
+    assert!(bincode2::config().deserialize::<IgnoredAny>(&[0]).is_err());
+
+    // Nesting is skipped without recursion: here a sequence holding a sequence, and
+    // so on 100000 times, around a unit.
+    let mut deep = Vec::new();
+    for _ in 0..100_000 {
+        deep.push(19);
+        deep.extend_from_slice(&1u64.to_le_bytes());
+    }
+    deep.push(0);
+    let mut untagged = bincode2::config();
+    untagged.allow_untagged();
+    untagged.deserialize::<IgnoredAny>(&deep).unwrap();
+    let truncated = &deep[..deep.len() - 1];
+    assert!(untagged.deserialize::<IgnoredAny>(truncated).is_err());
+}
+
+#[test]
+fn test_tagged_fields() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct V1 {
+        id: u32,
+        name: String,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct V2 {
+        name: String,
+        id: u32,
+        #[serde(default)]
+        tags: Vec<String>,
+        score: Option<f64>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Event {
+        Created { item: V2, at: u64 },
+    }
+
+    let mut config = config();
+    config.tagged_fields();
+
+    let v1 = V1 {
+        id: 7,
+        name: "seven".to_string(),
+    };
+    let encoded = config.serialize(&v1).unwrap();
+    // field count, then a header per field; the string is length delimited
+    assert_eq!(encoded.len(), 8 + (4 + 4) + (4 + 8 + 8 + 5));
+    assert_eq!(config.serialized_size(&v1).unwrap(), encoded.len() as u64);
+    assert_eq!(config.deserialize::<V1>(&encoded).unwrap(), v1);
+
+    // a newer reader fills in the missing fields
+    let v2: V2 = config.deserialize(&encoded).unwrap();
+    assert_eq!(
+        v2,
+        V2 {
+            name: "seven".to_string(),
+            id: 7,
+            tags: vec![],
+            score: None,
+        }
+    );
+
+    // an older reader skips the fields it doesn't know
+    let v2 = V2 {
+        tags: vec!["a".to_string(), "b".to_string()],
+        score: Some(0.5),
+        ..v2
+    };
+    let encoded = config.serialize(&v2).unwrap();
+    assert_eq!(config.deserialize::<V1>(&encoded).unwrap(), v1);
+    assert_eq!(config.deserialize::<V2>(&encoded).unwrap(), v2);
+
+    let event = Event::Created { item: v2, at: 3 };
+    let encoded = config.serialize(&event).unwrap();
+    assert_eq!(
+        config.serialized_size(&event).unwrap(),
+        encoded.len() as u64
+    );
+    assert_eq!(config.deserialize::<Event>(&encoded).unwrap(), event);
+    assert_eq!(
+        config
+            .deserialize_from::<_, Event>(&mut &encoded[..])
+            .unwrap(),
+        event
+    );
+
+    // a field read as a type of another size is an error, not the start of the next one
+    #[derive(Deserialize, Debug)]
+    struct Narrow {
+        #[allow(dead_code)]
+        id: u16,
+    }
+
+    #[derive(Deserialize, Debug)]
+    struct Short {
+        #[allow(dead_code)]
+        name: (u64, u8),
+    }
+
+    let encoded = config.serialize(&v1).unwrap();
+    assert!(config.deserialize::<Narrow>(&encoded).is_err());
+    assert!(config.deserialize::<Short>(&encoded).is_err());
+
+    // the plain encoding has no headers
+    assert_eq!(serialize(&v1).unwrap().len(), 4 + 8 + 5);
+}
+
+#[test]
+fn test_reader_position() {
+    use bincode2::{IoReader, SliceReader};
+
+    let mut bytes = serialize(&("header".to_string(), 5u32)).unwrap();
+    let value_len = bytes.len() as u64;
+    bytes.extend_from_slice(&[0xde, 0xad]);
+
+    let mut reader = SliceReader::new(&bytes);
+    let (name, n): (&str, u32) = config()
+        .deserialize_from_custom_seed(std::marker::PhantomData, &mut reader)
+        .unwrap();
+    assert_eq!((name, n), ("header", 5));
+    assert_eq!(reader.position(), value_len);
+    assert_eq!(reader.remaining(), &[0xde, 0xad]);
+
+    let mut reader = IoReader::new(&bytes[..]);
+    let value: (String, u32) = bincode2::deserialize_from_custom(&mut reader).unwrap();
+    assert_eq!(value, ("header".to_string(), 5));
+    assert_eq!(reader.position(), value_len);
+    assert_eq!(reader.into_inner(), &[0xde, 0xad]);
+}
+
+#[test]
+fn test_slice_reader_into_remaining() {
+    use bincode2::SliceReader;
+
+    let mut bytes = serialize(&("header".to_string(), 5u32)).unwrap();
+    bytes.extend_from_slice(&[0xde, 0xad]);
+    let owned: Cow<[u8]> = Cow::Owned(bytes.clone());
+    let borrowed: Cow<[u8]> = Cow::Borrowed(&bytes);
+
+    for cow in &[owned, borrowed] {
+        let mut reader = SliceReader::from(cow);
+        let (name, n): (&str, u32) = config()
+            .deserialize_from_custom_seed(std::marker::PhantomData, &mut reader)
+            .unwrap();
+        assert_eq!((name, n), ("header", 5));
+        assert_eq!(reader.into_remaining(), &[0xde, 0xad]);
+    }
+
+    let reader = SliceReader::from(&bytes[..]);
+    assert_eq!(reader.into_remaining(), &bytes[..]);
+}
+
+#[test]
+fn test_segmented_readers() {
+    use bincode2::{ChainedSliceReader, FillBuf, FillBufReader};
+    use core2::io;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Packet<'a> {
+        id: u64,
+        name: &'a str,
+        #[serde(with = "serde_bytes")]
+        payload: &'a [u8],
+    }
+
+    let packet = Packet {
+        id: 3,
+        name: "segmented",
+        payload: &[1, 2, 3, 4, 5],
+    };
+    let bytes = serialize(&packet).unwrap();
+
+    // Split at every point, and into single bytes.
+    type Owned = (u64, String, Vec<u8>);
+    let owned: Owned = (3, "segmented".to_string(), vec![1, 2, 3, 4, 5]);
+    for split in 0..=bytes.len() {
+        let (head, tail) = bytes.split_at(split);
+        let segments = [head, &[][..], tail];
+        let mut reader = ChainedSliceReader::new(&segments);
+        let decoded: Owned = bincode2::deserialize_from_custom(&mut reader).unwrap();
+        assert_eq!(decoded, owned);
+        assert_eq!(reader.position(), bytes.len() as u64);
+    }
+    let segments: Vec<&[u8]> = bytes.chunks(1).collect();
+    let reader = ChainedSliceReader::new(&segments);
+    let decoded: Owned = bincode2::deserialize_from_custom(reader).unwrap();
+    assert_eq!(decoded, owned);
+
+    // Values within one segment are borrowed.
+    let segments = [&bytes[..8], &bytes[8..]];
+    let decoded: Packet = config()
+        .deserialize_from_custom_seed(std::marker::PhantomData, ChainedSliceReader::new(&segments))
+        .unwrap();
+    assert_eq!(decoded, packet);
+
+    // A ring buffer handing out at most 4 bytes at a time.
+    struct Ring<'a> {
+        data: &'a [u8],
+        start: usize,
+    }
+
+    impl<'a> FillBuf for Ring<'a> {
+        fn fill_buf(&mut self) -> io::Result<&[u8]> {
+            let end = (self.start + 4).min(self.data.len());
+            Ok(&self.data[self.start..end])
+        }
+
+        fn consume(&mut self, amount: usize) {
+            self.start += amount;
+        }
+    }
+
+    let mut reader = FillBufReader::new(Ring {
+        data: &bytes,
+        start: 0,
+    });
+    let decoded: Owned = bincode2::deserialize_from_custom(&mut reader).unwrap();
+    assert_eq!(decoded, owned);
+    assert_eq!(reader.position(), bytes.len() as u64);
+
+    let mut truncated = FillBufReader::new(Ring {
+        data: &bytes[..bytes.len() - 1],
+        start: 0,
+    });
+    assert!(bincode2::deserialize_from_custom::<_, Owned>(&mut truncated).is_err());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_deserialize_from_buf() {
+    use bytes::{Buf, Bytes};
+
+    let first = serialize(&("chained".to_string(), 1u16)).unwrap();
+    let second = serialize(&2u64).unwrap();
+    let (head, tail) = first.split_at(9);
+    let mut buf = Bytes::copy_from_slice(head)
+        .chain(Bytes::copy_from_slice(tail))
+        .chain(Bytes::copy_from_slice(&second));
+
+    let value: (String, u16) = config().deserialize_from_buf(&mut buf).unwrap();
+    assert_eq!(value, ("chained".to_string(), 1));
+    assert_eq!(buf.remaining(), 8);
+    assert_eq!(
+        config().deserialize_from_buf::<_, u64>(&mut buf).unwrap(),
+        2
+    );
+    assert!(config().deserialize_from_buf::<_, u8>(&mut buf).is_err());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_serialize_into_buf() {
+    use bytes::{BufMut, BytesMut};
+
+    let value = ("pooled".to_string(), 7u32);
+    let mut buf = BytesMut::with_capacity(4);
+    buf.put_u8(0xff);
+    config().serialize_into_buf(&mut buf, &value).unwrap();
+    assert_eq!(&buf[1..], &serialize(&value).unwrap()[..]);
+
+    let bytes = config().serialize_to_bytes(&value).unwrap();
+    assert_eq!(&bytes[..], &buf[1..]);
+    assert_eq!(
+        config()
+            .deserialize_from_buf::<_, (String, u32)>(bytes)
+            .unwrap(),
+        value
+    );
+
+    // Buffers that cannot grow fail once full.
+    let mut fixed = [0u8; 4];
+    assert!(config().serialize_into_buf(&mut fixed[..], &value).is_err());
+    // Nothing is written past the limit.
+    let mut buf = BytesMut::new();
+    assert!(config()
+        .limit(4)
+        .serialize_into_buf(&mut buf, &value)
+        .is_err());
+    assert!(buf.is_empty());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_bytes_reader() {
+    use bincode2::BytesReader;
+    use bytes::Bytes;
+
+    let mut bytes = serialize(&(7u16, &b"payload"[..])).unwrap();
+    bytes.extend_from_slice(&[0xde, 0xad]);
+    let bytes = Bytes::from(bytes);
+
+    let mut reader = BytesReader::new(&bytes);
+    let (n, body): (u16, &[u8]) = config()
+        .deserialize_from_custom_seed(std::marker::PhantomData, &mut reader)
+        .unwrap();
+    assert_eq!(n, 7);
+    let body = reader.slice_ref(body);
+    assert_eq!(body, &b"payload"[..]);
+    // The payload shares the allocation of the input.
+    assert_eq!(body.as_ptr(), bytes[10..].as_ptr());
+    assert_eq!(reader.position(), bytes.len() as u64 - 2);
+    assert_eq!(reader.into_remaining(), &b"\xde\xad"[..]);
+
+    let mut reader = BytesReader::new(&bytes);
+    assert!(config()
+        .deserialize_from_custom::<_, (u16, String, u32)>(&mut reader)
+        .is_err());
+}
+
+#[test]
+fn test_scratch_reader() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Borrowing<'a> {
+        name: &'a str,
+        data: &'a [u8],
+        id: u32,
+    }
+
+    let value = Borrowing {
+        name: "scratch",
+        data: &[1, 2, 3],
+        id: 9,
+    };
+    let bytes = serialize(&value).unwrap();
+
+    let mut scratch = [0; 10];
+    let decoded: Borrowing = config()
+        .deserialize_from_scratch(&bytes[..], &mut scratch)
+        .unwrap();
+    assert_eq!(decoded, value);
+
+    let mut reader = bincode2::ScratchReader::new(&bytes[..], &mut scratch);
+    let _: Borrowing = config()
+        .deserialize_from_custom_seed(std::marker::PhantomData, &mut reader)
+        .unwrap();
+    assert_eq!(reader.scratch_used(), 10);
+    assert_eq!(reader.position(), bytes.len() as u64);
+
+    let mut small = [0; 8];
+    let result: Result<Borrowing> = config().deserialize_from_scratch(&bytes[..], &mut small);
+    match *result.unwrap_err() {
+        ErrorKind::ScratchExhausted => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[cfg(feature = "codec")]
+#[test]
+fn test_codec() {
+    use bincode2::codec::BincodeCodec;
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    let mut config = config();
+    config.big_endian().limit(64);
+    let mut codec = BincodeCodec::<Vec<u16>>::new(config);
+    let mut buf = BytesMut::new();
+    codec.encode(vec![1, 2, 3], &mut buf).unwrap();
+    codec.encode(vec![], &mut buf).unwrap();
+    assert_eq!(&buf[..4], &[0, 0, 0, 8 + 6]);
+
+    // Frames arriving a byte at a time.
+    let mut incoming = BytesMut::new();
+    let mut decoded = Vec::new();
+    for byte in buf.iter() {
+        incoming.extend_from_slice(&[*byte]);
+        if let Some(frame) = codec.decode(&mut incoming).unwrap() {
+            decoded.push(frame);
+        }
+    }
+    assert_eq!(decoded, vec![vec![1, 2, 3], vec![]]);
+    assert!(incoming.is_empty());
+
+    // Oversized frames are rejected from their header alone, and a value that
+    // fails to encode leaves nothing behind.
+    let mut buf = BytesMut::from(&[9u8][..]);
+    assert!(codec.encode(vec![0; 100], &mut buf).is_err());
+    assert_eq!(&buf[..], &[9]);
+    let mut oversized = BytesMut::from(&[0u8, 0, 1, 0][..]);
+    match *codec.decode(&mut oversized).unwrap_err() {
+        ErrorKind::SizeLimit { .. } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // A frame longer than its value.
+    let mut padded = BytesMut::from(&[0u8, 0, 0, 9, 0, 0, 0, 0, 0, 0, 0, 0, 7][..]);
+    match *codec.decode(&mut padded).unwrap_err() {
+        ErrorKind::Custom(ref message) => assert!(message.contains("left over")),
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // The header is a fixed-width u32 whatever the config writes values with.
+    let mut varint = BincodeCodec::<Vec<u16>>::new(bincode2::compat::bincode2_standard());
+    let large = vec![7u16; 70_000];
+    let mut buf = BytesMut::new();
+    varint.encode(large.clone(), &mut buf).unwrap();
+    assert_eq!(&buf[..4], &((buf.len() - 4) as u32).to_le_bytes());
+    assert!(buf.len() - 4 > 1 << 16);
+    assert_eq!(varint.decode(&mut buf).unwrap(), Some(large));
+    assert!(buf.is_empty());
+
+    let mut config = bincode2::config();
+    config.allow_untagged();
+    let mut untagged = BincodeCodec::<Vec<u16>>::new(config);
+    untagged.encode(vec![1, 2, 3], &mut buf).unwrap();
+    assert_eq!(&buf[..4], &((buf.len() - 4) as u32).to_le_bytes());
+    assert_eq!(untagged.decode(&mut buf).unwrap(), Some(vec![1, 2, 3]));
+    assert!(buf.is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_std_io() {
+    use std::io::Cursor;
+
+    let value = (1u32, "std".to_string());
+    let mut cursor = Cursor::new(Vec::new());
+    config().serialize_into_std(&mut cursor, &value).unwrap();
+    assert_eq!(cursor.get_ref(), &serialize(&value).unwrap());
+
+    cursor.set_position(0);
+    let decoded: (u32, String) = config().deserialize_from_std(&mut cursor).unwrap();
+    assert_eq!(decoded, value);
+
+    let err = config()
+        .deserialize_from_std::<_, (u32, String)>(&mut cursor)
+        .unwrap_err();
+    let err: std::io::Error = err.into();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+    let err: std::io::Error = deserialize::<bool>(&[2]).unwrap_err().into();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_max_size() {
+    use bincode2::{max_serialized_size, MaxSize};
+
+    fn check<T: serde::Serialize + MaxSize>(value: T) {
+        assert_eq!(
+            serialized_size(&value).unwrap(),
+            max_serialized_size::<T>() as u64
+        );
+    }
+
+    check(u64::max_value());
+    check('\u{10FFFF}');
+    check(Some((1u8, 2usize)));
+    check([Some(1i16); 5]);
+    check(Ok::<[u8; 4], u16>([1; 4]));
+    check(Err::<u8, u16>(3));
+
+    const BUF: usize = max_serialized_size::<(u32, [Option<char>; 2])>();
+    assert_eq!(BUF, 4 + 2 * 5);
+    assert!(serialized_size(&(0u32, [None::<char>; 2])).unwrap() < BUF as u64);
+}
+
+#[test]
+fn test_encode_decode() {
+    use bincode2::{Decode, Decoder, Encode, Encoder};
+
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    impl Encode for Point {
+        fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<()> {
+            self.x.encode(encoder)?;
+            self.y.encode(encoder)
+        }
+    }
+
+    impl Decode for Point {
+        fn decode<D: Decoder>(decoder: &mut D) -> Result<Point> {
+            Ok(Point {
+                x: i32::decode(decoder)?,
+                y: i32::decode(decoder)?,
+            })
+        }
+    }
+
+    let value = (
+        Some("name".to_string()),
+        vec![1u16, 2, 3],
+        [7u8; 3],
+        'é',
+        (true, -1i64, 0.5f32),
+    );
+    let mut config = config();
+    config.big_endian().string_length(LengthOption::U8);
+    let encoded = config.encode(&value).unwrap();
+    assert_eq!(encoded, config.serialize(&value).unwrap());
+    assert_eq!(
+        config
+            .decode::<(Option<String>, Vec<u16>, [u8; 3], char, (bool, i64, f32))>(&encoded)
+            .unwrap(),
+        value
+    );
+
+    let point = Point { x: 3, y: -4 };
+    let encoded = config.encode(&point).unwrap();
+    assert_eq!(encoded, config.serialize(&(3i32, -4i32)).unwrap());
+    let decoded: Point = config.decode_from(&encoded[..]).unwrap();
+    assert_eq!((decoded.x, decoded.y), (3, -4));
+
+    config.limit(4);
+    assert!(config.encode(&point).is_err());
+    assert!(config.encode_into(&mut Vec::new(), &point).is_err());
+    assert!(config.decode_from::<_, Point>(&encoded[..]).is_err());
+    assert!(config.decode::<bool>(&[2]).is_err());
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derive_encode_decode() {
+    use bincode2::{Decode, Encode};
+
+    #[derive(Encode, Decode, Serialize, Deserialize, PartialEq, Debug)]
+    struct Unit;
+
+    #[derive(Encode, Decode, Serialize, Deserialize, PartialEq, Debug)]
+    struct Pair<T>(T, u8);
+
+    #[derive(Encode, Decode, Serialize, Deserialize, PartialEq, Debug)]
+    enum Shape {
+        Empty,
+        Circle(Pair<f64>),
+        Rect {
+            w: u32,
+            h: u32,
+            label: Option<String>,
+        },
+    }
+
+    #[derive(Encode, Decode, Serialize, Deserialize, PartialEq, Debug)]
+    struct Scene {
+        unit: Unit,
+        shapes: Vec<Shape>,
+    }
+
+    let scene = Scene {
+        unit: Unit,
+        shapes: vec![
+            Shape::Empty,
+            Shape::Circle(Pair(1.5, 2)),
+            Shape::Rect {
+                w: 3,
+                h: 4,
+                label: Some("r".to_string()),
+            },
+        ],
+    };
+    let encoded = config().encode(&scene).unwrap();
+    assert_eq!(encoded, serialize(&scene).unwrap());
+    assert_eq!(config().decode::<Scene>(&encoded).unwrap(), scene);
+    assert_eq!(deserialize::<Scene>(&encoded).unwrap(), scene);
+
+    match config().decode::<Shape>(&[3, 0, 0, 0]).map_err(|e| *e) {
+        Err(ErrorKind::InvalidTagEncoding(3)) => {}
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_presets() {
+    use bincode2::config::{legacy_bincode1, storage, wire, WIRE_LIMIT};
+
+    let encoded = wire().serialize(&(1u16, "ab")).unwrap();
+    assert_eq!(encoded, vec![0, 1, 0, 0, 0, 2, b'a', b'b']);
+    let big = vec![0u8; WIRE_LIMIT as usize];
+    assert!(wire().serialize(&big).is_err());
+
+    assert_eq!(legacy_bincode1(), config());
+    assert_eq!(
+        legacy_bincode1().serialize(&(1u16, "ab")).unwrap(),
+        serialize(&(1u16, "ab")).unwrap()
+    );
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Old {
+        a: u8,
+    }
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct New {
+        a: u8,
+        #[serde(default)]
+        b: u8,
+    }
+    let encoded = storage().serialize(&Old { a: 1 }).unwrap();
+    assert_eq!(
+        storage().deserialize::<New>(&encoded).unwrap(),
+        New { a: 1, b: 0 }
+    );
+}
+
+#[test]
+fn test_config_builder() {
+    use bincode2::config::wire;
+    use bincode2::{
+        Config, ConfigBuilder, CustomSizeType, Decoder, Encoder, Overflow, StringMode, TypeCodec,
+        VariantIndexMap,
+    };
+    use core2::io::{Read, Write};
+
+    static WIRE: Config = wire();
+    static SHORT: Config = ConfigBuilder::new()
+        .big_endian()
+        .string_length(LengthOption::U8)
+        .on_length_overflow(Overflow::Truncate)
+        .string_encoding(StringMode::NulTerminated)
+        .aligned(4)
+        .build();
+
+    let mut short = config();
+    short
+        .big_endian()
+        .string_length(LengthOption::U8)
+        .on_length_overflow(Overflow::Truncate)
+        .string_encoding(StringMode::NulTerminated)
+        .aligned(4);
+    assert_eq!(SHORT, short);
+    assert_eq!(WIRE, wire());
+    assert_eq!(ConfigBuilder::new().build(), config());
+    assert_eq!(Config::from(ConfigBuilder::default()), config());
+
+    let threads: Vec<_> = (0..4u16)
+        .map(|i| std::thread::spawn(move || WIRE.serialize(&(i, "ab")).unwrap()))
+        .collect();
+    for (i, thread) in threads.into_iter().enumerate() {
+        let bytes = thread.join().unwrap();
+        assert_eq!(bytes, wire().serialize(&(i as u16, "ab")).unwrap());
+    }
+
+    // Options naming a type can be set in a `const` too, and configs are `Copy`.
+    struct Shifted;
+
+    impl VariantIndexMap for Shifted {
+        fn to_wire(_: &'static str, index: u32) -> u32 {
+            index + 10
+        }
+
+        fn from_wire(_: &'static str, tag: u32) -> Option<u32> {
+            tag.checked_sub(10)
+        }
+    }
+
+    struct Byte;
+
+    unsafe impl TypeCodec for Byte {
+        type Value = u32;
+
+        fn encode(value: &u32, encoder: &mut dyn Encoder) -> Result<()> {
+            encoder.encode_u8(*value as u8)
+        }
+
+        fn decode(decoder: &mut dyn Decoder) -> Result<u32> {
+            decoder.decode_u8().map(u32::from)
+        }
+    }
+
+    static MAPPED: Config = ConfigBuilder::new()
+        .variant_index_map::<Shifted>()
+        .type_codec::<Byte>()
+        .build();
+
+    let mut mapped = config();
+    mapped.variant_index_map::<Shifted>().type_codec::<Byte>();
+    assert_eq!(MAPPED, mapped);
+    let copy = MAPPED;
+    let value: (StdResult<u8, u8>, u32) = (Err(2), 7);
+    let bytes = copy.serialize(&value).unwrap();
+    assert_eq!(bytes, [11, 0, 0, 0, 2, 7]);
+    let read: (StdResult<u8, u8>, u32) = MAPPED.deserialize(&bytes).unwrap();
+    assert_eq!(read, value);
+
+    struct OneByte;
+
+    impl CustomSizeType for OneByte {
+        fn write(len: u64, writer: &mut dyn Write) -> Result<()> {
+            writer.write_all(&[len as u8])?;
+            Ok(())
+        }
+
+        fn read(reader: &mut dyn Read) -> Result<u64> {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            Ok(u64::from(byte[0]))
+        }
+    }
+
+    static CUSTOM: Config = ConfigBuilder::new()
+        .string_length_custom::<OneByte>()
+        .array_length_custom::<OneByte>()
+        .unknown_variant_fallback()
+        .build();
+
+    let mut custom = config();
+    custom
+        .string_length_custom::<OneByte>()
+        .array_length_custom::<OneByte>()
+        .unknown_variant_fallback();
+    assert_eq!(CUSTOM, custom);
+    let bytes = CUSTOM.serialize(&("ab", vec![1u8])).unwrap();
+    assert_eq!(bytes, [2, b'a', b'b', 1, 1]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_register_preset() {
+    use bincode2::config::{preset, register_preset, wire};
+
+    let mut ours = wire();
+    ours.limit(1024);
+    assert!(register_preset("test_register_preset", ours));
+    assert!(!register_preset("test_register_preset", wire()));
+    assert!(!register_preset("wire", ours));
+
+    assert_eq!(preset("test_register_preset"), Some(ours));
+    assert_eq!(preset("wire"), Some(wire()));
+    assert_eq!(preset("unknown"), None);
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+enum CompatEnum {
+    Unit,
+    Newtype(u16),
+    Tuple(i8, char),
+    Struct { flag: bool, name: String },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+struct CompatValue {
+    small: u8,
+    wide: i128,
+    float: f64,
+    missing: Option<u32>,
+    present: Option<String>,
+    variants: Vec<CompatEnum>,
+    map: std::collections::BTreeMap<String, (u64, i32)>,
+    #[serde(with = "serde_bytes")]
+    bytes: Vec<u8>,
+    unit: (),
+}
+
+fn compat_value() -> CompatValue {
+    let mut map = std::collections::BTreeMap::new();
+    map.insert("one".to_string(), (1, -1));
+    map.insert("two".to_string(), (u64::max_value(), i32::min_value()));
+    CompatValue {
+        small: 200,
+        wide: -(1 << 100),
+        float: 1.25,
+        missing: None,
+        present: Some("héllo".to_string()),
+        variants: vec![
+            CompatEnum::Unit,
+            CompatEnum::Newtype(0xbeef),
+            CompatEnum::Tuple(-3, '€'),
+            CompatEnum::Struct {
+                flag: true,
+                name: "s".to_string(),
+            },
+        ],
+        map,
+        bytes: vec![0, 1, 2, 255],
+        unit: (),
+    }
+}
+
+#[test]
+fn test_compat_bincode1() {
+    let value = compat_value();
+    let ours = bincode2::compat::bincode1().serialize(&value).unwrap();
+    let theirs = bincode1::serialize(&value).unwrap();
+    assert_eq!(ours, theirs);
+
+    let decoded: CompatValue = bincode2::compat::bincode1().deserialize(&theirs).unwrap();
+    assert_eq!(decoded, value);
+    let decoded: CompatValue = bincode1::deserialize(&ours).unwrap();
+    assert_eq!(decoded, value);
+
+    // trailing bytes are ignored by both
+    let mut trailing = ours.clone();
+    trailing.push(0);
+    assert_eq!(
+        bincode2::compat::bincode1()
+            .deserialize::<CompatValue>(&trailing)
+            .unwrap(),
+        value
+    );
+    assert_eq!(
+        bincode1::deserialize::<CompatValue>(&trailing).unwrap(),
+        value
+    );
+}
+
+#[test]
+fn test_compat_bincode2_legacy() {
+    use bincode_upstream::config::legacy;
+
+    let value = compat_value();
+    let ours = bincode2::compat::bincode2_legacy()
+        .serialize(&value)
+        .unwrap();
+    let theirs = bincode_upstream::serde::encode_to_vec(&value, legacy()).unwrap();
+    assert_eq!(ours, theirs);
+
+    let decoded: CompatValue = bincode2::compat::bincode2_legacy()
+        .deserialize(&theirs)
+        .unwrap();
+    assert_eq!(decoded, value);
+    let (decoded, read): (CompatValue, usize) =
+        bincode_upstream::serde::decode_from_slice(&ours, legacy()).unwrap();
+    assert_eq!((decoded, read), (value, ours.len()));
+}
+
+#[test]
+fn test_compat_bincode1_default_options() {
+    use bincode1::Options;
+
+    let value = compat_value();
+    let config = bincode2::compat::bincode1_default_options();
+    let ours = config.serialize(&value).unwrap();
+    let theirs = bincode1::DefaultOptions::new().serialize(&value).unwrap();
+    assert_eq!(ours, theirs);
+    let fixed = bincode2::compat::bincode1().serialize(&value).unwrap();
+    assert!(ours.len() < fixed.len());
+
+    let decoded: CompatValue = config.deserialize(&theirs).unwrap();
+    assert_eq!(decoded, value);
+    let decoded: CompatValue = bincode1::DefaultOptions::new().deserialize(&ours).unwrap();
+    assert_eq!(decoded, value);
+
+    // Every width of varint, both ways.
+    let ints = (
+        (250u16, 251u16, u16::max_value()),
+        (u32::max_value(), u64::max_value(), u128::max_value()),
+        (-126i16, i32::min_value(), i64::min_value()),
+        i128::min_value(),
+    );
+    let ours = config.serialize(&ints).unwrap();
+    let theirs = bincode1::DefaultOptions::new().serialize(&ints).unwrap();
+    assert_eq!(ours, theirs);
+    assert_eq!(config.serialized_size(&ints).unwrap(), ours.len() as u64);
+    assert_eq!(config.deserialize::<(_, _, _, _)>(&ours).unwrap(), ints);
+
+    // A marker too wide for the integer read.
+    match *config.deserialize::<u16>(&[252, 0, 0, 1, 0]).unwrap_err() {
+        ErrorKind::InvalidTagEncoding(252) => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_compat_bincode2_standard() {
+    use bincode_upstream::config::standard;
+
+    let value = compat_value();
+    let config = bincode2::compat::bincode2_standard();
+    let ours = config.serialize(&value).unwrap();
+    let theirs = bincode_upstream::serde::encode_to_vec(&value, standard()).unwrap();
+    assert_eq!(ours, theirs);
+
+    let decoded: CompatValue = config.deserialize(&theirs).unwrap();
+    assert_eq!(decoded, value);
+    let (decoded, read): (CompatValue, usize) =
+        bincode_upstream::serde::decode_from_slice(&ours, standard()).unwrap();
+    assert_eq!((decoded, read), (value, ours.len()));
+}
+
+#[test]
+fn test_split_limits() {
+    let small = vec![0u8; 8];
+    let large = vec![0u8; 1024];
+
+    let mut config = config();
+    config.deserialize_limit(64).serialize_limit(4096);
+    let encoded = config.serialize(&large).unwrap();
+    assert_eq!(config.serialized_size(&large).unwrap(), 8 + 1024);
+    match *config
+        .deserialize_from::<_, Vec<u8>>(&encoded[..])
+        .unwrap_err()
+    {
+        // Reading stops at the limit, so the rest of the message is not counted.
+        ErrorKind::SizeLimit { limit: 64, .. } => {}
+        ref other => panic!("{:?}", other),
+    }
+    let encoded = config.serialize(&small).unwrap();
+    assert_eq!(
+        config.deserialize_from::<_, Vec<u8>>(&encoded[..]).unwrap(),
+        small
+    );
+
+    config.serialize_limit(16);
+    // Measuring stops at the limit, so the rest of the message is not counted.
+    match *config.serialize(&large).unwrap_err() {
+        ErrorKind::SizeLimit {
+            limit: 16,
+            attempted: 17,
+        } => {}
+        ref other => panic!("{:?}", other),
+    }
+    assert_eq!(
+        config.serialize(&large).unwrap_err().to_string(),
+        "message of at least 17 bytes exceeds the size limit of 16 bytes"
+    );
+    assert!(config.serialize(&small).is_ok());
+
+    // `limit` sets both
+    config.limit(4);
+    assert!(config.serialize(&small).is_err());
+    assert!(config.deserialize_from::<_, Vec<u8>>(&encoded[..]).is_err());
+    config.no_limit();
+    assert!(config.serialize(&large).is_ok());
+}
+
+#[test]
+#[cfg(target_has_atomic = "64")]
+fn test_shared_limit() {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    let message = serialize(&"abcd").unwrap();
+    assert_eq!(message.len(), 12);
+
+    let budget = Arc::new(AtomicU64::new(30));
+    let mut config = config();
+    config.deserialize_limit(100);
+    let first = config.with_shared_limit(budget.clone());
+    let second = config.with_shared_limit(budget.clone());
+
+    assert_eq!(first.deserialize::<String>(&message).unwrap(), "abcd");
+    assert_eq!(
+        second.deserialize_from::<_, String>(&message[..]).unwrap(),
+        "abcd"
+    );
+    assert_eq!(first.remaining(), 6);
+    match *second
+        .deserialize_from_custom::<_, String>(bincode2::SliceReader::new(&message))
+        .unwrap_err()
+    {
+        ErrorKind::SizeLimit {
+            limit: 6,
+            attempted: 8,
+        } => {}
+        ref other => panic!("{:?}", other),
+    }
+
+    // The bytes counted so far are reported, not only the read that failed.
+    let budget = Arc::new(AtomicU64::new(10));
+    let budgeted = config.with_shared_limit(budget.clone());
+    match *budgeted.deserialize::<String>(&message).unwrap_err() {
+        ErrorKind::SizeLimit {
+            limit: 10,
+            attempted: 12,
+        } => {}
+        ref other => panic!("{:?}", other),
+    }
+
+    // the per message limit still applies
+    let budget = Arc::new(AtomicU64::new(1000));
+    config.deserialize_limit(10);
+    let budgeted = config.with_shared_limit(budget.clone());
+    assert!(budgeted
+        .deserialize_from::<_, String>(&message[..])
+        .is_err());
+    assert!(budgeted.deserialize::<String>(&message).is_err());
+}
+
+#[test]
+fn test_deserialize_in_place_reuse() {
+    let first = vec![vec![1u16; 50], vec![2; 50], vec![3; 50]];
+    let second = vec![vec![4u16; 5], vec![5; 20]];
+
+    let mut place: Vec<Vec<u16>> = Vec::new();
+    bincode2::deserialize_in_place_slice(&serialize(&first).unwrap(), &mut place).unwrap();
+    assert_eq!(place, first);
+
+    let outer = place.as_ptr();
+    let inner = place[1].as_ptr();
+    config()
+        .deserialize_in_place_slice(&serialize(&second).unwrap(), &mut place)
+        .unwrap();
+    assert_eq!(place, second);
+    // vectors keep their buffers, down to the nested ones
+    assert_eq!(place.as_ptr(), outer);
+    assert_eq!(place[1].as_ptr(), inner);
+    assert!(place[1].capacity() >= 50);
+
+    // strings are replaced
+    let mut name = String::with_capacity(100);
+    bincode2::deserialize_in_place_slice(&serialize("name").unwrap(), &mut name).unwrap();
+    assert_eq!(name, "name");
+
+    // a failed deserialization leaves a valid value behind
+    let truncated = serialize(&first).unwrap();
+    assert!(
+        bincode2::deserialize_in_place_slice(&truncated[..truncated.len() - 1], &mut place)
+            .is_err()
+    );
+    assert!(place.len() <= first.len());
+}
+
+#[cfg(feature = "erased-serde")]
+#[test]
+fn test_erased_registry() {
+    use bincode2::erased::{serialize_erased, serialize_tagged, Registry};
+
+    trait Plugin: erased_serde::Serialize {
+        fn describe(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Counter {
+        count: u64,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Label(String);
+
+    impl Plugin for Counter {
+        fn describe(&self) -> String {
+            format!("counter {}", self.count)
+        }
+    }
+
+    impl Plugin for Label {
+        fn describe(&self) -> String {
+            format!("label {}", self.0)
+        }
+    }
+
+    let config = config();
+
+    let mut erased = Vec::new();
+    serialize_erased(&Counter { count: 3 }, &mut erased, &config).unwrap();
+    assert_eq!(erased, serialize(&3u64).unwrap());
+
+    let mut registry = Registry::<dyn Plugin>::new();
+    assert!(registry.register("counter", |d| {
+        Ok(Box::new(erased_serde::deserialize::<Counter>(d)?))
+    }));
+    assert!(registry.register("label", |d| {
+        Ok(Box::new(erased_serde::deserialize::<Label>(d)?))
+    }));
+    assert!(!registry.register("label", |d| {
+        Ok(Box::new(erased_serde::deserialize::<Counter>(d)?))
+    }));
+
+    let plugins: Vec<(&str, Box<dyn Plugin>)> = vec![
+        ("counter", Box::new(Counter { count: 42 })),
+        ("label", Box::new(Label("hi".to_string()))),
+    ];
+    let mut bytes = Vec::new();
+    for &(tag, ref plugin) in &plugins {
+        serialize_tagged(tag, &**plugin, &mut bytes, &config).unwrap();
+    }
+
+    let mut reader = &bytes[..];
+    let first = registry.deserialize_from(&mut reader, &config).unwrap();
+    let second = registry.deserialize_from(&mut reader, &config).unwrap();
+    assert_eq!(first.describe(), "counter 42");
+    assert_eq!(second.describe(), "label hi");
+    assert!(reader.is_empty());
+
+    let mut unknown = Vec::new();
+    serialize_tagged("gauge", &Label("x".to_string()), &mut unknown, &config).unwrap();
+    match *registry.deserialize(&unknown, &config).err().unwrap() {
+        ErrorKind::Custom(ref msg) => assert!(msg.contains("gauge")),
+        ref other => panic!("unexpected error {:?}", other),
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_stats() {
+    use bincode2::Stats;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Message {
+        id: u32,
+        name: String,
+        items: Vec<(u8, Option<String>)>,
+    }
+
+    let message = Message {
+        id: 1,
+        name: "stats".into(),
+        items: vec![(1, None), (2, Some("x".into()))],
+    };
+
+    let config = config();
+    let mut written = Stats::default();
+    let bytes = config.serialize_with_stats(&message, &mut written).unwrap();
+    assert_eq!(written, bincode2::Config::last_stats());
+    assert_eq!(written.bytes, bytes.len() as u64);
+    // The struct, its 3 fields, 2 tuples of 2 and the string in the option.
+    assert_eq!(written.values, 1 + 3 + 2 * 3 + 1);
+    assert_eq!(written.strings, 2);
+    assert_eq!(written.max_depth, 3);
+
+    let mut read = Stats::default();
+    let decoded: Message = config.deserialize_with_stats(&bytes, &mut read).unwrap();
+    assert_eq!(decoded, message);
+    assert_eq!(read.bytes, written.bytes);
+    assert_eq!(read.values, written.values);
+    assert_eq!(read.strings, 2);
+    assert_eq!(read.allocations, 2);
+    assert_eq!(read.max_depth, 3);
+
+    let _: Message = config.deserialize_with_stats(&bytes, &mut read).unwrap();
+    assert_eq!(read.values, 2 * written.values);
+    assert_eq!(read.max_depth, 3);
+}
+
+#[test]
+fn test_sort_map_keys() {
+    use std::collections::BTreeMap;
+
+    let map: HashMap<u32, String> = (0..100).map(|i| (i * 7919 % 1000, i.to_string())).collect();
+    let ordered: BTreeMap<u32, String> = map.clone().into_iter().collect();
+
+    let mut config = config();
+    config.big_endian().sort_map_keys();
+    let bytes = config.serialize(&map).unwrap();
+    assert_eq!(bytes, config.serialize(&ordered).unwrap());
+    assert_eq!(config.serialized_size(&map).unwrap(), bytes.len() as u64);
+    assert_eq!(
+        config.deserialize::<HashMap<u32, String>>(&bytes).unwrap(),
+        map
+    );
+
+    // Repeated values are written in full inside sorted maps.
+    let shared = vec![vec![1u64, 2, 3]; 4];
+    let nested: HashMap<u8, Vec<Vec<u64>>> = (0..4).map(|i| (i, shared.clone())).collect();
+    config.dedup_subtrees();
+    let bytes = config.serialize(&nested).unwrap();
+    assert_eq!(
+        config
+            .deserialize::<HashMap<u8, Vec<Vec<u64>>>>(&bytes)
+            .unwrap(),
+        nested
+    );
+}
+
+#[test]
+fn test_variant_index_map() {
+    use bincode2::{UnknownVariant, VariantIndexMap};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Frame {
+        Data(Vec<u8>),
+        Ping,
+        Close { code: u16 },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Level {
+        Low,
+        High,
+        Unknown,
+    }
+
+    /// Frames are tagged 1, 9 and 8; levels keep their indices.
+    struct Tags;
+
+    impl VariantIndexMap for Tags {
+        fn to_wire(name: &'static str, index: u32) -> u32 {
+            match name {
+                "Frame" => [1, 9, 8][index as usize],
+                _ => index,
+            }
+        }
+
+        fn from_wire(name: &'static str, tag: u32) -> Option<u32> {
+            match (name, tag) {
+                ("Frame", 1) => Some(0),
+                ("Frame", 9) => Some(1),
+                ("Frame", 8) => Some(2),
+                ("Frame", _) => None,
+                _ => Some(tag),
+            }
+        }
+    }
+
+    let mut config = config();
+    config.variant_index_map::<Tags>();
+    let frames = vec![
+        Frame::Data(vec![1, 2]),
+        Frame::Ping,
+        Frame::Close { code: 7 },
+    ];
+    let bytes = config.serialize(&frames).unwrap();
+    assert_eq!(&bytes[8..12], &[1, 0, 0, 0]);
+    assert_eq!(&bytes[22..26], &[9, 0, 0, 0]);
+    assert_eq!(&bytes[26..30], &[8, 0, 0, 0]);
+    assert_eq!(config.deserialize::<Vec<Frame>>(&bytes).unwrap(), frames);
+
+    match *config.deserialize::<Frame>(&[0, 0, 0, 0]).unwrap_err() {
+        ErrorKind::InvalidTagEncoding(0) => {}
+        ref err => panic!("unexpected error {:?}", err),
+    }
+    match *config.deserialize::<Level>(&[5, 0, 0, 0]).unwrap_err() {
+        ErrorKind::InvalidTagEncoding(5) => {}
+        ref err => panic!("unexpected error {:?}", err),
+    }
+
+    config.on_unknown_variant(UnknownVariant::Fallback(2));
+    assert_eq!(
+        config.deserialize::<Level>(&[5, 0, 0, 0]).unwrap(),
+        Level::Unknown
+    );
+
+    // The tags are mapped inside self-describing maps too.
+    let mut flat = config;
+    flat.allow_flatten();
+    let map: HashMap<u8, Frame> = vec![(1, Frame::Ping)].into_iter().collect();
+    let bytes = flat.serialize(&map).unwrap();
+    assert_eq!(flat.deserialize::<HashMap<u8, Frame>>(&bytes).unwrap(), map);
+
+    /// Tags too large for a one byte varint.
+    struct Wide;
+
+    impl VariantIndexMap for Wide {
+        fn to_wire(_: &'static str, index: u32) -> u32 {
+            index + 1000
+        }
+
+        fn from_wire(_: &'static str, tag: u32) -> Option<u32> {
+            tag.checked_sub(1000)
+        }
+    }
+
+    // The size counts the mapped tags, not the indices.
+    let mut varint = bincode2::compat::bincode2_standard();
+    varint.variant_index_map::<Wide>();
+    let bytes = varint.serialize(&frames).unwrap();
+    assert_eq!(varint.serialized_size(&frames).unwrap(), bytes.len() as u64);
+    assert_eq!(varint.deserialize::<Vec<Frame>>(&bytes).unwrap(), frames);
+}
+
+#[test]
+fn test_format_fixtures() {
+    use bincode2::format::{ADDITIONS, FIXTURES};
+    use bincode2::{
+        CustomSizeType, FormatVersion, Overflow, StringMode, VariantIndexMap, FORMAT_HASH,
+    };
+    use core2::io::{Read, Write};
+    use serde_bytes::Bytes;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Point {
+        x: i16,
+        y: i16,
+    }
+
+    #[derive(Serialize)]
+    enum E {
+        A,
+        B(u8),
+        C(u8, u8),
+        D { x: u8 },
+    }
+
+    struct Byte;
+
+    impl CustomSizeType for Byte {
+        fn write(len: u64, writer: &mut dyn Write) -> Result<()> {
+            writer.write_all(&[len as u8])?;
+            Ok(())
+        }
+
+        fn read(reader: &mut dyn Read) -> Result<u64> {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            Ok(u64::from(byte[0]))
+        }
+    }
+
+    struct Plus100;
+
+    impl VariantIndexMap for Plus100 {
+        fn to_wire(_name: &'static str, index: u32) -> u32 {
+            index + 100
+        }
+
+        fn from_wire(_name: &'static str, tag: u32) -> Option<u32> {
+            tag.checked_sub(100)
+        }
+    }
+
+    let mut checked = 0;
+    macro_rules! check {
+        ($name:expr, $config:expr, $value:expr) => {{
+            let fixture = FIXTURES
+                .iter()
+                .chain(ADDITIONS)
+                .find(|fixture| fixture.name == $name)
+                .unwrap_or_else(|| panic!("no fixture named {}", $name));
+            assert_eq!(
+                $config.serialize($value).unwrap(),
+                fixture.bytes,
+                "{}",
+                $name
+            );
+            checked += 1;
+        }};
+    }
+
+    let mut map = BTreeMap::new();
+    map.insert(1u8, true);
+    let point = Point { x: 1, y: -1 };
+
+    let c = config();
+    check!("default/bool", c, &true);
+    check!("default/u8", c, &0xabu8);
+    check!("default/u16", c, &0x1234u16);
+    check!("default/u32", c, &0x1234_5678u32);
+    check!("default/u64", c, &0x0102_0304_0506_0708u64);
+    check!("default/u128", c, &1u128);
+    check!("default/i8", c, &-2i8);
+    check!("default/i16", c, &-2i16);
+    check!("default/i32", c, &-2i32);
+    check!("default/i64", c, &-2i64);
+    check!("default/i128", c, &-1i128);
+    check!("default/f32", c, &1.5f32);
+    check!("default/f64", c, &-0.25f64);
+    check!("default/char", c, &'é');
+    check!("default/str", c, "hi");
+    check!("default/bytes", c, Bytes::new(&[1, 2, 3]));
+    check!("default/none", c, &None::<u8>);
+    check!("default/some", c, &Some(7u8));
+    check!("default/unit", c, &());
+    check!("default/tuple", c, &(1u8, 2u16));
+    check!("default/seq", c, &vec![1u16, 2]);
+    check!("default/map", c, &map);
+    check!("default/struct", c, &point);
+    check!("default/unit_variant", c, &E::A);
+    check!("default/newtype_variant", c, &E::B(5));
+    check!("default/tuple_variant", c, &E::C(1, 2));
+    check!("default/struct_variant", c, &E::D { x: 9 });
+
+    let mut c = config();
+    c.big_endian();
+    check!("big_endian/u32", c, &0x1234_5678u32);
+    check!("big_endian/f64", c, &-0.25f64);
+    check!("big_endian/str", c, "hi");
+    check!("big_endian/unit_variant", c, &E::A);
+    check!("big_endian/newtype_variant", c, &E::B(5));
+
+    for (name, length) in vec![
+        ("string_length_u8/str", LengthOption::U8),
+        ("string_length_u16/str", LengthOption::U16),
+        ("string_length_u32/str", LengthOption::U32),
+    ] {
+        let mut c = config();
+        c.string_length(length);
+        check!(name, c, "hi");
+    }
+
+    let mut c = config();
+    c.array_length(LengthOption::U8);
+    check!("array_length_u8/seq", c, &vec![1u16, 2]);
+    c.array_length(LengthOption::U16);
+    check!("array_length_u16/bytes", c, Bytes::new(&[1, 2, 3]));
+    c.array_length(LengthOption::U32);
+    check!("array_length_u32/map", c, &map);
+
+    let mut c = config();
+    c.usize_encoding(UsizeMode::U32);
+    check!("usize_u32/str", c, "hi");
+    c.usize_encoding(UsizeMode::Varint);
+    check!("usize_varint/str", c, "hi");
+    check!("usize_varint/seq", c, &vec![1u16, 2]);
+
+    let mut c = config();
+    c.string_encoding(StringMode::NulTerminated);
+    check!("nul_terminated/str", c, "hi");
+    c.string_encoding(StringMode::FixedWidth(4));
+    check!("fixed_width_4/str", c, "hi");
+
+    let mut c = config();
+    c.float_encoding(FloatEncoding::F32AsF64);
+    check!("f32_as_f64/f32", c, &1.5f32);
+    c.float_encoding(FloatEncoding::F64AsF32);
+    check!("f64_as_f32/f64", c, &-0.25f64);
+    c.float_encoding(FloatEncoding::Fixed32 { fraction_bits: 16 });
+    check!("fixed32_16/f64", c, &1.5f64);
+    c.float_encoding(FloatEncoding::Fixed64 { fraction_bits: 8 });
+    check!("fixed64_8/f64", c, &-0.25f64);
+    c.float_encoding(FloatEncoding::Decimal { places: 2 });
+    check!("decimal_2/f64", c, &1.25f64);
+
+    let mut c = config();
+    c.tagged_fields();
+    check!("tagged_fields/struct", c, &point);
+
+    let mut c = config();
+    c.allow_flatten();
+    check!("allow_flatten/map", c, &map);
+
+    let mut sorted = BTreeMap::new();
+    sorted.insert(-1i8, 10u8);
+    sorted.insert(1i8, 11u8);
+    let mut c = config();
+    c.sort_map_keys();
+    check!("sort_map_keys/map", c, &sorted);
+
+    let mut c = config();
+    c.string_length_custom::<Byte>()
+        .array_length_custom::<Byte>();
+    check!("custom_length/str", c, "hi");
+    check!("custom_length/seq", c, &vec![1u16, 2]);
+
+    let mut c = config();
+    c.array_length(LengthOption::U8)
+        .on_length_overflow(Overflow::PromoteToLargerType);
+    check!("promote_u8/seq", c, &vec![(); 255]);
+
+    let mut c = config();
+    c.variant_index_map::<Plus100>();
+    check!("variant_index_map/newtype_variant", c, &E::B(5));
+
+    let mut c = config();
+    c.dedup_subtrees();
+    check!("dedup_subtrees/seq", c, &vec![vec![1u32, 2, 3]; 2]);
+
+    let mut c = config();
+    c.variant_lengths();
+    check!("variant_lengths/unit_variant", c, &E::A);
+    check!("variant_lengths/newtype_variant", c, &E::B(5));
+
+    let mut c = config();
+    c.aligned(4);
+    check!("aligned_4/tuple", c, &(1u8, 2u16));
+
+    let mut c = config();
+    c.allow_untagged();
+    check!("allow_untagged/newtype_variant", c, &E::B(5));
+
+    let mut c = config();
+    c.int_encoding(IntEncoding::Varint);
+    check!("int_varint/u16", c, &0x1234u16);
+    check!("int_varint/u32", c, &0x1234_5678u32);
+    check!("int_varint/u128", c, &(1u128 << 64));
+    check!("int_varint/i64", c, &-2i64);
+    check!("int_varint/str", c, "hi");
+    check!("int_varint/newtype_variant", c, &E::B(5));
+
+    assert_eq!(checked, FIXTURES.len() + ADDITIONS.len());
+    assert_eq!(FormatVersion::CURRENT, FormatVersion::V1);
+    // Changing this is changing the format: see the `format` module.
+    assert_eq!(FORMAT_HASH, 0x1901_b6f6_2465_7951);
+}
+
+#[test]
+fn test_resumable_reader() {
+    use bincode2::ResumableReader;
+    use core2::io;
+
+    /// Hands out at most three bytes per read, failing with `WouldBlock` before each.
+    struct Stalling<'a> {
+        bytes: &'a [u8],
+        ready: bool,
+    }
+
+    impl<'a> io::Read for Stalling<'a> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if !self.ready {
+                self.ready = true;
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "stalled"));
+            }
+            self.ready = false;
+            let read = self.bytes.len().min(out.len()).min(3);
+            out[..read].copy_from_slice(&self.bytes[..read]);
+            self.bytes = &self.bytes[read..];
+            Ok(read)
+        }
+    }
+
+    let first = ("resumable".to_string(), vec![1u32, 2, 3], Some(-7i64));
+    let second = 0x1234_5678u32;
+    let mut bytes = serialize(&first).unwrap();
+    bytes.extend(serialize(&second).unwrap());
+
+    let config = config();
+    let mut reader = ResumableReader::new(Stalling {
+        bytes: &bytes,
+        ready: false,
+    });
+    let mut stalls = 0;
+    let read: (String, Vec<u32>, Option<i64>) = loop {
+        match config.deserialize_resumable(&mut reader) {
+            Err(ref e) if e.is_would_block() => stalls += 1,
+            result => break result.unwrap(),
+        }
+    };
+    assert!(stalls > 1);
+    assert_eq!(read, first);
+    let read_second: u32 = loop {
+        match config.deserialize_resumable(&mut reader) {
+            Err(ref e) if e.is_would_block() => {}
+            result => break result.unwrap(),
+        }
+    };
+    assert_eq!(read_second, second);
+    assert!(reader.buffered().is_empty());
+
+    // Once the input ends, the error is final.
+    match config.deserialize_resumable::<_, u8>(&mut reader) {
+        Err(ref e) if e.is_would_block() => {}
+        result => panic!("expected WouldBlock, got {:?}", result),
+    }
+    let err = config
+        .deserialize_resumable::<_, u8>(&mut reader)
+        .unwrap_err();
+    assert!(!err.is_would_block());
+    match *err {
+        ErrorKind::Io(ref e) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+        ref other => panic!("expected UnexpectedEof, got {:?}", other),
+    }
+
+    // Other readers report it too, though the bytes read so far are lost.
+    let stalling = Stalling {
+        bytes: &bytes,
+        ready: false,
+    };
+    let err = config.deserialize_from::<_, u32>(stalling).unwrap_err();
+    assert!(err.is_would_block());
+}
+
+#[test]
+fn test_unknown_variant_fallback() {
+    use bincode2::UnknownVariant;
+
+    #[derive(Serialize)]
+    enum New {
+        Ping,
+        Data(u16),
+        Resize { width: u32, height: u32 },
+        Note(String, u8),
+        Wake,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Old {
+        Ping,
+        Data(u16),
+        #[serde(other)]
+        Unknown,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Strict {
+        Ping,
+        Data(u16),
+    }
+
+    let mut config = config();
+    config.variant_lengths().unknown_variant_fallback();
+
+    let values = vec![
+        New::Ping,
+        New::Data(300),
+        New::Resize {
+            width: 640,
+            height: 480,
+        },
+        New::Note("later".to_string(), 3),
+        New::Wake,
+    ];
+    let bytes = config.serialize(&(&values, 7u8)).unwrap();
+    assert_eq!(
+        config.serialized_size(&(&values, 7u8)).unwrap(),
+        bytes.len() as u64
+    );
+    let read: (Vec<Old>, u8) = config.deserialize(&bytes).unwrap();
+    assert_eq!(
+        read,
+        (
+            vec![
+                Old::Ping,
+                Old::Data(300),
+                Old::Unknown,
+                Old::Unknown,
+                Old::Unknown
+            ],
+            7
+        )
+    );
+
+    // The length follows the tag, using the array length size.
+    assert_eq!(
+        config.serialize(&New::Data(300)).unwrap(),
+        [1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0x2c, 0x01]
+    );
+
+    // Enums without a `#[serde(other)]` variant still fail.
+    let bytes = config.serialize(&New::Wake).unwrap();
+    match *config.deserialize::<Strict>(&bytes).unwrap_err() {
+        ErrorKind::Custom(_) => {}
+        ref e => panic!("expected Custom, got {:?}", e),
+    }
+
+    // The payload is gone, so only a unit variant can stand in for it.
+    let bytes = config
+        .serialize(&(New::Note("x".to_string(), 1), 7u8))
+        .unwrap();
+    config.on_unknown_variant(UnknownVariant::Fallback(0));
+    let read: (Strict, u8) = config.deserialize(&bytes).unwrap();
+    assert_eq!(read, (Strict::Ping, 7));
+    config.on_unknown_variant(UnknownVariant::Fallback(1));
+    match *config.deserialize::<(Strict, u8)>(&bytes).unwrap_err() {
+        ErrorKind::InvalidTagEncoding(3) => {}
+        ref e => panic!("expected InvalidTagEncoding, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_placeholder() {
+    let mut config = config();
+    config.big_endian();
+
+    let mut message = vec![0xff];
+    let size = config
+        .reserve_length(&mut message, LengthOption::U16)
+        .unwrap();
+    assert_eq!((size.position(), size.width()), (1, 2));
+    config.serialize_into(&mut message, &vec![1u32, 2]).unwrap();
+    size.fill(&mut message).unwrap();
+    assert_eq!(&message[..3], &[0xff, 0, 16]);
+
+    // The whole message, header included.
+    size.fill_with(&mut message, 19).unwrap();
+    assert_eq!(&message[1..3], &[0, 19]);
+
+    let mut message = Vec::new();
+    let size = config
+        .reserve_length(&mut message, LengthOption::U8)
+        .unwrap();
+    message.extend_from_slice(&[0; 256]);
+    match *size.fill(&mut message).unwrap_err() {
+        ErrorKind::SizeTypeLimit => {}
+        ref err => panic!("unexpected error {:?}", err),
+    }
+
+    // A buffer ending before the placeholder does has no room to fill it in.
+    let mut message = vec![0xff];
+    let size = config
+        .reserve_length(&mut message, LengthOption::U16)
+        .unwrap();
+    for len in 0..3 {
+        match *size.fill(&mut message[..len]).unwrap_err() {
+            ErrorKind::Custom(ref message) => assert!(message.contains("placeholder")),
+            ref other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(size.fill_with(&mut message[..len], 0).is_err());
+    }
+    size.fill(&mut message).unwrap();
+
+    let mut cursor = core2::io::Cursor::new(Vec::new());
+    let size = config
+        .reserve_length_at(&mut cursor, LengthOption::U32)
+        .unwrap();
+    config.serialize_into(&mut cursor, "framed").unwrap();
+    size.fill_at(&mut cursor).unwrap();
+    assert_eq!(cursor.position(), 4 + 8 + 6);
+    let (len, body): (u32, String) = config.deserialize(cursor.get_ref()).unwrap();
+    assert_eq!((len, &body[..]), (14, "framed"));
+
+    // A stream seeked back into the placeholder has no length to fill in.
+    cursor.set_position(size.position() + 2);
+    match *size.fill_at(&mut cursor).unwrap_err() {
+        ErrorKind::Custom(ref message) => assert!(message.contains("placeholder")),
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+    assert_eq!(cursor.position(), size.position() + 2);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_buffer_pool() {
+    use bincode2::Pool;
+
+    let map: HashMap<String, Vec<u32>> = (0..10)
+        .map(|i| (i.to_string(), vec![i; i as usize]))
+        .collect();
+    let mut config = config();
+    config.sort_map_keys();
+    let expected = config.serialize(&map).unwrap();
+
+    let pool = Pool::new();
+    let pooled = config.with_buffer_pool(&pool);
+    let bytes = pooled.serialize(&map).unwrap();
+    assert_eq!(&bytes[..], &expected[..]);
+    // The entries were buffered to sort them, and those buffers are back already.
+    let scratch = pool.idle();
+    assert!(scratch > 0);
+    drop(bytes);
+    assert_eq!(pool.idle(), scratch + 1);
+
+    // Later calls take every buffer they need from the pool.
+    for _ in 0..3 {
+        let bytes = pooled.serialize(&map).unwrap();
+        assert_eq!(&bytes[..], &expected[..]);
+        assert_eq!(pool.idle(), scratch);
+    }
+    assert_eq!(pool.idle(), scratch + 1);
+
+    let kept = pooled.serialize(&7u8).unwrap().into_vec();
+    assert_eq!(kept, [7]);
+    assert_eq!(pool.idle(), scratch);
+
+    // A failed call gives its buffers back too.
+    let limited = config.limit(4).with_buffer_pool(&pool);
+    assert!(limited.serialize(&map).is_err());
+    assert_eq!(pool.idle(), scratch);
+
+    // Buffers past either bound of the pool are freed.
+    let bounded = Pool::bounded(1, 64);
+    let pooled = bincode2::config().with_buffer_pool(&bounded);
+    drop(pooled.serialize(&vec![0u8; 100]).unwrap());
+    assert_eq!(bounded.idle(), 0);
+    let first = pooled.serialize(&1u8).unwrap();
+    let second = pooled.serialize(&2u8).unwrap();
+    drop(first);
+    drop(second);
+    assert_eq!(bounded.idle(), 1);
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_stats_count_once() {
+    use bincode2::Pool;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Unit;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Pair(u8, u8);
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Shape {
+        Point(u8, u8),
+        Line { from: u8, to: u8 },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Message {
+        unit: Unit,
+        pair: Pair,
+        shapes: Vec<Shape>,
+    }
+
+    let message = Message {
+        unit: Unit,
+        pair: Pair(1, 2),
+        shapes: vec![Shape::Point(3, 4), Shape::Line { from: 5, to: 6 }],
+    };
+
+    // Each struct, tuple struct and variant is one value, and so is each field.
+    let mut config = config();
+    let bytes = config.serialize(&message).unwrap();
+    let written = bincode2::Config::last_stats();
+    assert_eq!(written.values, 1 + 1 + 3 + 1 + 2 * 3);
+    let _: Message = config.deserialize(&bytes).unwrap();
+    assert_eq!(bincode2::Config::last_stats().values, written.values);
+
+    // Once the pool has the buffers a call needs, none are counted as allocated.
+    config.variant_lengths();
+    let pooled = config.with_buffer_pool(&Pool::new());
+    // The first variant buffer is allocated, then reused by the second.
+    pooled.serialize(&message).unwrap();
+    assert_eq!(bincode2::Config::last_stats().allocations, 1);
+    pooled.serialize(&message).unwrap();
+    assert_eq!(bincode2::Config::last_stats().allocations, 0);
+}
+
+#[test]
+fn test_raw_access() {
+    use bincode2::SliceReader;
+    use serde::Serialize;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Call {
+        Ping,
+        Echo(String),
+        Add { a: u16, b: u16 },
+    }
+
+    let calls = vec![
+        Call::Ping,
+        Call::Echo("hi".into()),
+        Call::Add { a: 1, b: 2 },
+    ];
+    for &big_endian in &[false, true] {
+        let mut config = config();
+        config
+            .string_length(LengthOption::U8)
+            .array_length(LengthOption::U16);
+        if big_endian {
+            config.big_endian();
+        }
+
+        let mut bytes = Vec::new();
+        {
+            let mut serializer = config.serializer_for(&mut bytes);
+            "calls".serialize(&mut serializer).unwrap();
+            calls.serialize(&mut serializer).unwrap();
+        }
+        assert_eq!(bytes, config.serialize(&("calls", &calls)).unwrap());
+
+        let mut deserializer = config.deserializer_for(SliceReader::new(&bytes));
+        assert_eq!(<&str>::deserialize(&mut deserializer).unwrap(), "calls");
+        assert_eq!(Vec::<Call>::deserialize(&mut deserializer).unwrap(), calls);
+    }
+
+    // The deserialize limit covers every value read from one deserializer.
+    let mut config = config();
+    config.deserialize_limit(12);
+    let bytes = config.serialize(&(1u64, 2u64)).unwrap();
+    let mut deserializer = config.deserializer_for(SliceReader::new(&bytes));
+    assert_eq!(u64::deserialize(&mut deserializer).unwrap(), 1);
+    match *u64::deserialize(&mut deserializer).unwrap_err() {
+        ErrorKind::SizeLimit {
+            limit: 12,
+            attempted: 16,
+        } => {}
+        ref err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn test_raw_length() {
+    use bincode2::raw::{read_length, write_length};
+
+    let items = [7u8; 300];
+    let mut configs = vec![config(), config(), config(), config()];
+    configs[1].big_endian().array_length(LengthOption::U16);
+    configs[2].usize_encoding(UsizeMode::Varint);
+    configs[3]
+        .array_length(LengthOption::U8)
+        .on_length_overflow(bincode2::Overflow::PromoteToLargerType);
+    for config in &configs {
+        // A length followed by the elements is how the config writes a sequence.
+        let mut bytes = Vec::new();
+        write_length(&mut bytes, items.len(), config).unwrap();
+        let prefix = bytes.len();
+        bytes.extend_from_slice(&items);
+        assert_eq!(bytes, config.serialize(&items[..]).unwrap());
+
+        let mut reader = &bytes[..];
+        assert_eq!(read_length(&mut reader, config).unwrap(), 300);
+        assert_eq!(reader.len(), bytes.len() - prefix);
+    }
+
+    let mut config = config();
+    config.array_length(LengthOption::U8);
+    match *write_length(&mut Vec::new(), 300, &config).unwrap_err() {
+        ErrorKind::SizeTypeLimit => {}
+        ref err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn test_trailing_default_fields() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct V1 {
+        id: u32,
+        name: String,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct V2 {
+        id: u32,
+        name: String,
+        #[serde(default)]
+        score: u64,
+        #[serde(default)]
+        tags: Option<Vec<String>>,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Required {
+        id: u32,
+        name: String,
+        score: u64,
+    }
+
+    let old = serialize(&V1 {
+        id: 3,
+        name: "old".into(),
+    })
+    .unwrap();
+
+    assert!(deserialize::<V2>(&old).is_err());
+
+    let mut config = config();
+    config.allow_trailing_default_fields();
+    assert_eq!(
+        config.deserialize::<V2>(&old).unwrap(),
+        V2 {
+            id: 3,
+            name: "old".into(),
+            score: 0,
+            tags: None,
+        }
+    );
+    assert!(config.deserialize::<Required>(&old).is_err());
+
+    // Ending in the middle of a field is still an error.
+    assert!(config.deserialize::<V2>(&old[..old.len() - 1]).is_err());
+
+    // Data written with every field reads the same.
+    let mut full = old.clone();
+    full.extend_from_slice(&serialize(&(9u64, Some(vec!["t".to_string()]))).unwrap());
+    assert_eq!(config.deserialize::<V2>(&full).unwrap().score, 9);
+
+    let segments = [&old[..5], &old[5..]];
+    let decoded: V2 = config
+        .deserialize_from_custom(bincode2::ChainedSliceReader::new(&segments))
+        .unwrap();
+    assert_eq!(decoded.id, 3);
+}
+
+#[test]
+fn test_columnar() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Row {
+        id: u32,
+        name: String,
+        tags: Vec<u8>,
+    }
+
+    let rows: Vec<Row> = (0..4)
+        .map(|id| Row {
+            id,
+            name: "n".repeat(id as usize),
+            tags: vec![id as u8; 2],
+        })
+        .collect();
+
+    let mut config = config();
+    config.big_endian();
+    let block = config.serialize_columnar(&rows).unwrap();
+    let columns = config.deserialize_columnar(&block).unwrap();
+    assert_eq!(columns.len(), 4);
+    assert_eq!(columns.names().collect::<Vec<_>>(), ["id", "name", "tags"]);
+    assert_eq!(columns.column::<u32>("id").unwrap(), [0, 1, 2, 3]);
+    assert_eq!(
+        columns.column::<&str>("name").unwrap(),
+        ["", "n", "nn", "nnn"]
+    );
+    assert!(columns.column::<u32>("missing").is_err());
+    assert_eq!(columns.rows::<Row>().unwrap(), rows);
+
+    let empty = config.serialize_columnar(Vec::<Row>::new()).unwrap();
+    let columns = config.deserialize_columnar(&empty).unwrap();
+    assert!(columns.is_empty());
+    assert_eq!(columns.rows::<Row>().unwrap(), []);
+
+    assert!(config.serialize_columnar(&[1u32, 2]).is_err());
+
+    // A header claiming more rows of no bytes than the block holds is rejected
+    // rather than decoded row by row.
+    #[derive(Deserialize, Debug)]
+    struct Unit {
+        #[allow(dead_code)]
+        a: (),
+    }
+
+    let bomb = config
+        .serialize(&(u64::from(u32::MAX), vec![("a", 0u64)]))
+        .unwrap();
+    let columns = config.deserialize_columnar(&bomb).unwrap();
+    assert!(columns.column::<()>("a").is_err());
+    assert!(columns.rows::<Unit>().is_err());
+}
+
+#[test]
+fn test_batch() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Record {
+        id: u32,
+        name: String,
+    }
+
+    let records: Vec<Record> = (0..5)
+        .map(|id| Record {
+            id,
+            name: "r".repeat(id as usize),
+        })
+        .collect();
+
+    let mut config = config();
+    config.big_endian();
+    let block = config.serialize_batch(&records).unwrap();
+    let batch = config.deserialize_batch(&block).unwrap();
+    assert_eq!(batch.len(), 5);
+    assert!(!batch.is_empty());
+    for i in (0..5).rev() {
+        assert_eq!(batch.get::<Record>(i).unwrap(), records[i]);
+        assert_eq!(
+            batch.get_bytes(i).unwrap(),
+            &config.serialize(&records[i]).unwrap()[..]
+        );
+    }
+    assert!(batch.get::<Record>(5).is_err());
+
+    let empty = config.serialize_batch(Vec::<u8>::new()).unwrap();
+    assert_eq!(empty.len(), 8);
+    assert!(config.deserialize_batch(&empty).unwrap().is_empty());
+
+    // A header claiming more items than the block can hold is rejected.
+    assert!(config.deserialize_batch(&block[..8 + 4 * 8]).is_err());
+    // Offsets past the end of the block are reported by `get`.
+    let truncated = config.deserialize_batch(&block[..8 + 5 * 8 + 3]).unwrap();
+    assert!(truncated.get::<Record>(4).is_err());
+
+    // The header stays fixed-width when integers are varints.
+    let config = bincode2::compat::bincode2_standard();
+    let block = config.serialize_batch(&records).unwrap();
+    assert_eq!(&block[..8], &5u64.to_le_bytes());
+    let batch = config.deserialize_batch(&block).unwrap();
+    assert_eq!(batch.len(), 5);
+    for i in 0..5 {
+        assert_eq!(batch.get::<Record>(i).unwrap(), records[i]);
+    }
+
+    // So does it when every value carries a type marker.
+    let mut config = bincode2::config();
+    config.allow_untagged();
+    let block = config.serialize_batch(&records).unwrap();
+    assert_eq!(&block[..8], &5u64.to_le_bytes());
+    let batch = config.deserialize_batch(&block).unwrap();
+    for i in 0..5 {
+        assert_eq!(batch.get::<Record>(i).unwrap(), records[i]);
+    }
+}
+
+#[test]
+fn test_float_encoding() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Sample {
+        temperature: f32,
+        pressure: f64,
+    }
+
+    let sample = Sample {
+        temperature: 21.5,
+        pressure: 1013.25,
+    };
+    let encodings = [
+        (FloatEncoding::Ieee, 12),
+        (FloatEncoding::F32AsF64, 16),
+        (FloatEncoding::F64AsF32, 8),
+        (FloatEncoding::Fixed32 { fraction_bits: 16 }, 8),
+        (FloatEncoding::Fixed64 { fraction_bits: 32 }, 16),
+        (FloatEncoding::Decimal { places: 2 }, 16),
+    ];
+    for &(encoding, len) in &encodings {
+        let mut config = config();
+        config.float_encoding(encoding);
+        let bytes = config.serialize(&sample).unwrap();
+        assert_eq!(bytes.len(), len, "{:?}", encoding);
+        assert_eq!(config.serialized_size(&sample).unwrap(), len as u64);
+        assert_eq!(config.deserialize::<Sample>(&bytes).unwrap(), sample);
+    }
+
+    let mut config = config();
+    config.float_encoding(FloatEncoding::Fixed32 { fraction_bits: 16 });
+    assert_eq!(config.serialize(&1.5f64).unwrap(), 0x18000i32.to_le_bytes());
+    assert_eq!(
+        config.serialize(&-1.5f32).unwrap(),
+        (-0x18000i32).to_le_bytes()
+    );
+    // Rounded to the nearest step of 2^-16.
+    let third: f64 = config
+        .deserialize(&config.serialize(&(1.0 / 3.0)).unwrap())
+        .unwrap();
+    assert!((third - 1.0 / 3.0).abs() <= 0.5 / 65536.0);
+    assert!(config.serialize(&32768.0f64).is_err());
+    assert!(config.serialize(&std::f64::NAN).is_err());
+    // Measuring fails on the same values.
+    assert!(config.serialized_size(&32768.0f64).is_err());
+    assert!(config.serialized_size(&std::f32::INFINITY).is_err());
+
+    config.float_encoding(FloatEncoding::Decimal { places: 3 });
+    assert_eq!(config.serialize(&0.001f64).unwrap(), 1i64.to_le_bytes());
+    assert_eq!(
+        config.deserialize::<f64>(&1234i64.to_le_bytes()).unwrap(),
+        1.234
+    );
+
+    config.float_encoding(FloatEncoding::F64AsF32);
+    let narrowed: f64 = config
+        .deserialize(&config.serialize(&0.1f64).unwrap())
+        .unwrap();
+    assert_eq!(narrowed, 0.1f32 as f64);
+}
+
+#[test]
+#[should_panic(expected = "too many fraction digits")]
+fn test_float_encoding_fraction_bits() {
+    config().float_encoding(FloatEncoding::Fixed32 { fraction_bits: 32 });
+}
+
+#[test]
+#[should_panic(expected = "too many fraction digits")]
+fn test_float_encoding_places() {
+    config().float_encoding(FloatEncoding::Decimal { places: 19 });
+}
+
+#[test]
+fn test_usize_encoding() {
+    let bytes: Vec<u8> = (0..300).map(|i| i as u8).collect();
+    let names = vec!["a".to_string(), "bc".to_string()];
+
+    let mut config = config();
+    config
+        .string_length(LengthOption::U8)
+        .usize_encoding(UsizeMode::U32);
+    assert_eq!(
+        config.serialize(&names).unwrap(),
+        vec![2, 0, 0, 0, 1, 0, 0, 0, b'a', 2, 0, 0, 0, b'b', b'c']
+    );
+    assert_eq!(config.serialized_size(&bytes).unwrap(), 304);
+    assert_eq!(config.encode(&7usize).unwrap(), vec![7, 0, 0, 0]);
+    assert_eq!(
+        config.encode(&-2isize).unwrap(),
+        vec![0xfe, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(
+        config.decode::<isize>(&[0xfe, 0xff, 0xff, 0xff]).unwrap(),
+        -2
+    );
+    match *config.encode(&(1usize << 40)).unwrap_err() {
+        ErrorKind::SizeTypeLimit => {}
+        ref other => panic!("unexpected error {:?}", other),
+    }
+
+    config.usize_encoding(UsizeMode::Varint);
+    let encoded = config.serialize(&bytes).unwrap();
+    assert_eq!(&encoded[..3], &[251, 0x2c, 0x01]);
+    assert_eq!(
+        encoded,
+        bincode_upstream::serde::encode_to_vec(&bytes, bincode_upstream::config::standard())
+            .unwrap()
+    );
+    assert_eq!(config.deserialize::<Vec<u8>>(&encoded).unwrap(), bytes);
+    assert_eq!(config.serialized_size(&bytes).unwrap(), 303);
+    assert_eq!(
+        config.serialize(&names).unwrap(),
+        vec![2, 1, b'a', 2, b'b', b'c']
+    );
+    assert_eq!(
+        config
+            .deserialize::<Vec<String>>(&[2, 1, b'a', 2, b'b', b'c'])
+            .unwrap(),
+        names
+    );
+    for &value in &[0usize, 250, 251, 65535, 65536, 1 << 40] {
+        let encoded = config.encode(&value).unwrap();
+        assert_eq!(config.decode::<usize>(&encoded).unwrap(), value);
+    }
+    assert_eq!(config.encode(&-1isize).unwrap(), vec![1]);
+    for &value in &[0isize, -1, 1, -200, 1 << 40, isize::MIN, isize::MAX] {
+        assert_eq!(
+            config
+                .decode::<isize>(&config.encode(&value).unwrap())
+                .unwrap(),
+            value
+        );
+    }
+    assert!(config.deserialize::<Vec<u8>>(&[254]).is_err());
+}
+
+#[test]
+fn test_deserialize_with_interner() {
+    use bincode2::StringInterner;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Row<'a> {
+        id: u32,
+        kind: &'a str,
+        #[serde(borrow)]
+        status: Cow<'a, str>,
+        note: String,
+    }
+
+    // Interns into a fixed vocabulary, counting the lookups.
+    struct Vocabulary<'v> {
+        words: &'v [String],
+        lookups: usize,
+    }
+
+    impl<'v> StringInterner<'v> for Vocabulary<'v> {
+        fn intern(&mut self, s: &str) -> &'v str {
+            self.lookups += 1;
+            self.words.iter().find(|word| *word == s).unwrap()
+        }
+    }
+
+    let rows: Vec<Row> = (0..6)
+        .map(|id| Row {
+            id,
+            kind: if id % 2 == 0 { "even" } else { "odd" },
+            status: Cow::Borrowed("ok"),
+            note: "free text".to_string(),
+        })
+        .collect();
+    let bytes = serialize(&rows).unwrap();
+
+    let words: Vec<String> = vec!["even".into(), "odd".into(), "ok".into()];
+    let mut interner = Vocabulary {
+        words: &words,
+        lookups: 0,
+    };
+    let decoded: Vec<Row> = config()
+        .deserialize_with_interner(&bytes, &mut interner)
+        .unwrap();
+    drop(bytes);
+
+    assert_eq!(decoded, rows);
+    assert_eq!(interner.lookups, 12);
+    for row in &decoded {
+        let word = if row.id % 2 == 0 {
+            &words[0]
+        } else {
+            &words[1]
+        };
+        assert!(std::ptr::eq(row.kind, word.as_str()));
+        match row.status {
+            Cow::Borrowed(status) => assert!(std::ptr::eq(status, words[2].as_str())),
+            Cow::Owned(_) => panic!("status was not borrowed"),
+        }
+    }
+}
+
+#[test]
+fn test_dedup_subtrees() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+    struct Node {
+        name: String,
+        values: Vec<u32>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Parent {
+        id: u32,
+        node: Node,
+        tags: HashMap<String, u8>,
+    }
+
+    let shared = Node {
+        name: "a node shared by every parent".to_string(),
+        values: (0..64).collect(),
+    };
+    let mut tags = HashMap::new();
+    tags.insert("tag".to_string(), 1);
+    let parents: Vec<Parent> = (0..100)
+        .map(|id| Parent {
+            id,
+            node: shared.clone(),
+            tags: tags.clone(),
+        })
+        .collect();
+
+    let mut config = config();
+    config.dedup_subtrees();
+    let bytes = config.serialize(&parents).unwrap();
+    let plain = serialize(&parents).unwrap();
+    assert!(bytes.len() * 10 < plain.len());
+    assert_eq!(
+        config.serialized_size(&parents).unwrap(),
+        bytes.len() as u64
+    );
+    assert_eq!(config.deserialize::<Vec<Parent>>(&bytes).unwrap(), parents);
+
+    // Back-references can only be followed in a slice.
+    assert!(config
+        .deserialize_from::<_, Vec<Parent>>(&mut &bytes[..])
+        .is_err());
+
+    // Values without repeated subtrees round-trip too.
+    let single = Parent {
+        id: 1,
+        node: shared.clone(),
+        tags: HashMap::new(),
+    };
+    let single_bytes = config.serialize(&single).unwrap();
+    assert_eq!(config.deserialize::<Parent>(&single_bytes).unwrap(), single);
+    assert!(config
+        .deserialize_from::<_, Parent>(&mut &single_bytes[..])
+        .is_ok());
+
+    // Following back-references counts against the limit.
+    config.limit(bytes.len() as u64);
+    let bytes = config.serialize(&parents).unwrap();
+    match *config.deserialize::<Vec<Parent>>(&bytes).unwrap_err() {
+        ErrorKind::SizeLimit { .. } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_dedup_subtrees_bomb() {
+    #[derive(Deserialize)]
+    enum Tree<'a> {
+        Leaf(&'a str),
+        Node(Vec<Tree<'a>>),
+    }
+
+    fn len(tree: &Tree) -> usize {
+        match *tree {
+            Tree::Leaf(leaf) => leaf.len(),
+            Tree::Node(ref children) => children.iter().map(len).sum(),
+        }
+    }
+
+    // A node holding a leaf, inside `depth` nodes each holding the node inside
+    // twice, the second time as a back-reference to the first.
+    fn bomb(depth: u32, leaf: &str) -> Vec<u8> {
+        let mut bytes = vec![1, 0, 0, 0, 0];
+        if depth == 0 {
+            bytes.extend_from_slice(&1u64.to_le_bytes());
+            bytes.extend_from_slice(&[0, 0, 0, 0]);
+            bytes.extend_from_slice(&(leaf.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(leaf.as_bytes());
+            return bytes;
+        }
+        let child = bomb(depth - 1, leaf);
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&child);
+        bytes.extend_from_slice(&[1, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&(child.len() as u64 - 1).to_le_bytes());
+        bytes
+    }
+
+    let leaf = "x".repeat(1 << 16);
+    let mut config = config();
+    config.dedup_subtrees();
+
+    // 32 MiB of leaves are read from a message of 64 KiB.
+    let small = bomb(9, &leaf);
+    assert!(small.len() < 66_000);
+    assert_eq!(len(&config.deserialize::<Tree>(&small).unwrap()), 32 << 20);
+
+    // Without a limit, no more than 64 MiB are read again.
+    let large = bomb(11, &leaf);
+    match *config.deserialize::<Tree>(&large).unwrap_err() {
+        ErrorKind::SizeLimit { limit, .. } => assert_eq!(limit, 64 << 20),
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // A limit replaces that bound.
+    config.limit(1 << 28);
+    assert_eq!(len(&config.deserialize::<Tree>(&large).unwrap()), 128 << 20);
+}
+
+#[cfg(feature = "sharing")]
+#[test]
+fn test_preserve_sharing() {
+    use bincode2::sharing::{PreserveSharing, Shared};
+    use std::rc::{Rc, Weak};
+    use std::sync::Arc;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Material {
+        name: String,
+        texture: Vec<u8>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Mesh {
+        #[serde(with = "bincode2::sharing")]
+        material: Arc<Material>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Scene {
+        meshes: Vec<Mesh>,
+        palette: Vec<Shared<Arc<Material>>>,
+    }
+
+    let steel = Arc::new(Material {
+        name: "steel".to_string(),
+        texture: vec![7; 1000],
+    });
+    let glass = Arc::new(Material {
+        name: "glass".to_string(),
+        texture: vec![9; 1000],
+    });
+    let scene = Scene {
+        meshes: (0..50)
+            .map(|i| Mesh {
+                material: if i % 2 == 0 {
+                    steel.clone()
+                } else {
+                    glass.clone()
+                },
+            })
+            .collect(),
+        palette: vec![Shared(glass.clone()), Shared(steel.clone())],
+    };
+
+    let bytes = serialize(&PreserveSharing(&scene)).unwrap();
+    assert!(bytes.len() < 2 * 1100 + 52 * 10);
+    let PreserveSharing(decoded): PreserveSharing<Scene> = deserialize(&bytes).unwrap();
+    assert_eq!(decoded.meshes.len(), 50);
+    assert_eq!(*decoded.meshes[0].material, *steel);
+    assert_eq!(*decoded.meshes[1].material, *glass);
+    for (i, mesh) in decoded.meshes.iter().enumerate() {
+        assert!(Arc::ptr_eq(&mesh.material, &decoded.palette[1 - i % 2]));
+    }
+    // Shared pointers need a session.
+    assert!(serialize(&scene).is_err());
+
+    // Cycles are closed by weak pointers to pointees read with `cyclic`.
+    #[derive(Serialize, Deserialize, Default)]
+    struct Node {
+        name: String,
+        #[serde(with = "bincode2::sharing::weak")]
+        this: Weak<Node>,
+        #[serde(with = "bincode2::sharing::cyclic")]
+        child: Rc<Child>,
+    }
+
+    #[derive(Serialize, Deserialize, Default)]
+    struct Child {
+        #[serde(with = "bincode2::sharing::weak")]
+        parent: Weak<Node>,
+        #[serde(with = "bincode2::sharing::weak")]
+        gone: Weak<Node>,
+    }
+
+    let node = Rc::new_cyclic(|this: &Weak<Node>| Node {
+        name: "root".to_string(),
+        this: this.clone(),
+        child: Rc::new(Child {
+            parent: this.clone(),
+            gone: Weak::new(),
+        }),
+    });
+    #[derive(Serialize, Deserialize)]
+    struct Graph {
+        #[serde(with = "bincode2::sharing::cyclic")]
+        root: Rc<Node>,
+    }
+
+    let bytes = serialize(&PreserveSharing(Graph { root: node })).unwrap();
+    let PreserveSharing(graph): PreserveSharing<Graph> = deserialize(&bytes).unwrap();
+    assert_eq!(graph.root.name, "root");
+    assert!(Rc::ptr_eq(&graph.root.this.upgrade().unwrap(), &graph.root));
+    assert!(Rc::ptr_eq(
+        &graph.root.child.parent.upgrade().unwrap(),
+        &graph.root
+    ));
+    assert!(graph.root.child.gone.upgrade().is_none());
+
+    // Without `cyclic`, the weak pointer cannot be restored.
+    let result = deserialize::<PreserveSharing<Shared<Rc<Node>>>>(&bytes);
+    match *result.err().unwrap() {
+        ErrorKind::Custom(ref message) => assert!(message.contains("sharing::cyclic")),
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[cfg(feature = "uuid")]
+#[test]
+fn test_ext_uuid() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Record {
+        #[serde(with = "bincode2::ext::uuid")]
+        id: uuid::Uuid,
+    }
+
+    let bytes: [u8; 16] = [
+        0x67, 0xe5, 0x50, 0x44, 0x10, 0xb1, 0x42, 0x6f, 0x92, 0x47, 0xbb, 0x68, 0x0e, 0x5f, 0xe0,
+        0xc8,
+    ];
+    let record = Record {
+        id: uuid::Uuid::from_bytes(bytes),
+    };
+    let encoded = serialize(&record).unwrap();
+    assert_eq!(encoded, bytes);
+    assert_eq!(deserialize::<Record>(&encoded).unwrap(), record);
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn test_ext_time() {
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Event {
+        #[serde(with = "bincode2::ext::time")]
+        at: time::OffsetDateTime,
+    }
+
+    let event = Event {
+        at: time::OffsetDateTime::from_unix_timestamp_nanos(1_600_000_000_123_456_789).unwrap(),
+    };
+    let encoded = config().big_endian().serialize(&event).unwrap();
+    assert_eq!(encoded, 1_600_000_000_123_456_789i64.to_be_bytes());
+    assert_eq!(
+        config()
+            .big_endian()
+            .deserialize::<Event>(&encoded)
+            .unwrap(),
+        event
+    );
+
+    // Out of the range of i64 nanoseconds.
+    let event = Event {
+        at: time::OffsetDateTime::from_unix_timestamp(1 << 36).unwrap(),
+    };
+    assert!(serialize(&event).is_err());
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn test_ext_chrono() {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Event {
+        #[serde(with = "bincode2::ext::chrono")]
+        at: DateTime<Utc>,
+    }
+
+    let event = Event {
+        at: Utc.timestamp_nanos(-1_234_567_890),
+    };
+    let encoded = serialize(&event).unwrap();
+    assert_eq!(encoded, (-1_234_567_890i64).to_le_bytes());
+    assert_eq!(deserialize::<Event>(&encoded).unwrap(), event);
+}
+
+#[cfg(feature = "num-bigint")]
+#[test]
+fn test_ext_num_bigint() {
+    use num_bigint::{BigInt, BigUint};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Accumulator {
+        #[serde(with = "bincode2::ext::num_bigint")]
+        value: BigInt,
+        #[serde(with = "bincode2::ext::num_bigint::unsigned")]
+        modulus: BigUint,
+    }
+
+    let accumulator = Accumulator {
+        value: -BigInt::from(0x1_0000_0002u64),
+        modulus: BigUint::from(7u8),
+    };
+    let mut config = config();
+    config.big_endian().array_length(LengthOption::U8);
+    let encoded = config.serialize(&accumulator).unwrap();
+    assert_eq!(encoded, [0xff, 2, 0, 0, 0, 2, 0, 0, 0, 1, 1, 0, 0, 0, 7]);
+    assert_eq!(
+        config.deserialize::<Accumulator>(&encoded).unwrap(),
+        accumulator
+    );
+
+    let zero = Accumulator {
+        value: BigInt::default(),
+        modulus: BigUint::default(),
+    };
+    let encoded = config.serialize(&zero).unwrap();
+    assert_eq!(encoded, [0, 0, 0]);
+    assert_eq!(config.deserialize::<Accumulator>(&encoded).unwrap(), zero);
+
+    // A sign that does not match the magnitude.
+    assert!(config.deserialize::<Accumulator>(&[1, 0, 0]).is_err());
+    // The digits count against the limit.
+    let large = Accumulator {
+        value: BigInt::from(1u8) << 640,
+        modulus: BigUint::from(7u8),
+    };
+    let encoded = config.serialize(&large).unwrap();
+    config.limit(64);
+    match *config.deserialize::<Accumulator>(&encoded).unwrap_err() {
+        ErrorKind::SizeLimit { limit: 64, .. } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_type_codec() {
+    use bincode2::{Decoder, Encoder, TypeCodec};
+    use std::collections::BTreeMap;
+
+    // Stands for a type from another crate, serialized as a string.
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Decimal(String);
+
+    struct Cents;
+
+    unsafe impl TypeCodec for Cents {
+        type Value = Decimal;
+
+        fn encode(value: &Decimal, encoder: &mut dyn Encoder) -> Result<()> {
+            encoder.encode_i64(value.0.replace('.', "").parse().unwrap())
+        }
+
+        fn decode(decoder: &mut dyn Decoder) -> Result<Decimal> {
+            let cents = decoder.decode_i64()?;
+            Ok(Decimal(format!("{}.{:02}", cents / 100, cents % 100)))
+        }
+    }
+
+    struct SmallCents;
+
+    unsafe impl TypeCodec for SmallCents {
+        type Value = Decimal;
+
+        fn encode(value: &Decimal, encoder: &mut dyn Encoder) -> Result<()> {
+            encoder.encode_i32(value.0.replace('.', "").parse().unwrap())
+        }
+
+        fn decode(decoder: &mut dyn Decoder) -> Result<Decimal> {
+            let cents = decoder.decode_i32()?;
+            Ok(Decimal(format!("{}.{:02}", cents / 100, cents % 100)))
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    enum Event {
+        Paid(Decimal),
+        Refunded { amount: Decimal },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Order {
+        id: u32,
+        price: Decimal,
+        history: Vec<Decimal>,
+        // Serde reads the value of an option without saying its type.
+        tip: Option<Decimal>,
+        events: Vec<Event>,
+        totals: BTreeMap<u8, Decimal>,
+    }
+
+    let mut config = config();
+    config.type_codec::<Cents>();
+
+    assert_eq!(
+        config.serialize(&Decimal("1.50".into())).unwrap(),
+        150i64.to_le_bytes()
+    );
+
+    let order = Order {
+        id: 7,
+        price: Decimal("12.99".into()),
+        history: vec![Decimal("10.00".into()), Decimal("11.50".into())],
+        tip: Some(Decimal("0.25".into())),
+        events: vec![
+            Event::Paid(Decimal("12.99".into())),
+            Event::Refunded {
+                amount: Decimal("1.00".into()),
+            },
+        ],
+        totals: vec![(1, Decimal("3.00".into()))].into_iter().collect(),
+    };
+    let bytes = config.serialize(&order).unwrap();
+    assert_eq!(
+        bytes.len(),
+        4 + 8 + (8 + 2 * 8) + (1 + 8 + 4) + (8 + 2 * (4 + 8)) + (8 + 1 + 8)
+    );
+    assert_eq!(&bytes[4..12], &1299i64.to_le_bytes());
+    assert_eq!(config.serialized_size(&order).unwrap(), bytes.len() as u64);
+    assert_eq!(config.deserialize::<Order>(&bytes).unwrap(), order);
+
+    let mut place = Order {
+        id: 0,
+        price: Decimal("0.00".into()),
+        history: vec![],
+        tip: None,
+        events: vec![],
+        totals: BTreeMap::new(),
+    };
+    config.deserialize_in_place_slice(&bytes, &mut place).unwrap();
+    assert_eq!(place, order);
+
+    let mut tagged = config;
+    tagged.tagged_fields();
+    let bytes = tagged.serialize(&order).unwrap();
+    assert_eq!(tagged.serialized_size(&order).unwrap(), bytes.len() as u64);
+    assert_eq!(tagged.deserialize::<Order>(&bytes).unwrap(), order);
+
+    // Registering another codec for the same type replaces the first.
+    let mut small = config;
+    small.type_codec::<SmallCents>();
+    assert_ne!(small, config);
+    assert_eq!(
+        small.serialize(&Decimal("1.50".into())).unwrap(),
+        150i32.to_le_bytes()
+    );
+    assert!(format!("{:?}", small).contains("SmallCents"));
+    assert!(!format!("{:?}", small).contains("::Cents"));
+}
+
+#[test]
+fn test_aligned() {
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+    struct Sample {
+        flag: bool,
+        value: u32,
+        tag: char,
+        wide: u64,
+        name: String,
+        ratio: f32,
+        small: i16,
+    }
+
+    let mut config = config();
+    config.aligned(8);
+
+    let sample = Sample {
+        flag: true,
+        value: 7,
+        tag: 'é',
+        wide: 9,
+        name: "abc".to_string(),
+        ratio: 0.5,
+        small: -1,
+    };
+    let bytes = config.serialize(&sample).unwrap();
+    let mut expected = vec![1, 0, 0, 0, 7, 0, 0, 0, 0xc3, 0xa9, 0, 0, 0, 0, 0, 0];
+    expected.extend_from_slice(&9u64.to_le_bytes());
+    expected.extend_from_slice(&3u64.to_le_bytes());
+    expected.extend_from_slice(b"abc");
+    expected.extend_from_slice(&[0]);
+    expected.extend_from_slice(&0.5f32.to_le_bytes());
+    expected.extend_from_slice(&(-1i16).to_le_bytes());
+    assert_eq!(bytes, expected);
+    assert_eq!(config.serialized_size(&sample).unwrap(), bytes.len() as u64);
+    assert_eq!(config.deserialize::<Sample>(&bytes).unwrap(), sample);
+    assert_eq!(
+        config.deserialize_from::<_, Sample>(&mut &bytes[..]).unwrap(),
+        sample
+    );
+
+    // Smaller alignments cap the padding of wide primitives.
+    let mut narrow = bincode2::config();
+    narrow.aligned(2).usize_encoding(UsizeMode::U32);
+    let bytes = narrow.serialize(&(1u8, 2u64, vec![3u8])).unwrap();
+    assert_eq!(bytes, [1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 3]);
+    assert_eq!(
+        narrow.deserialize::<(u8, u64, Vec<u8>)>(&bytes).unwrap(),
+        (1, 2, vec![3])
+    );
+
+    // `Encode` pads like serde does.
+    let bytes = config.encode(&(1u8, 2u32)).unwrap();
+    assert_eq!(bytes, config.serialize(&(1u8, 2u32)).unwrap());
+    assert_eq!(config.decode::<(u8, u32)>(&bytes).unwrap(), (1, 2));
+
+    // Options that write parts of the output out of order turn alignment off.
+    config.tagged_fields();
+    let bytes = config.serialize(&(1u8, 2u32)).unwrap();
+    assert_eq!(bytes, [1, 2, 0, 0, 0]);
+}
+
+#[test]
+fn test_cancel_token() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let checks = Arc::new(AtomicUsize::new(0));
+    let allowed = Arc::new(AtomicUsize::new(usize::MAX));
+    let config = {
+        let checks = checks.clone();
+        let allowed = allowed.clone();
+        config().with_cancel_token(move || {
+            checks.fetch_add(1, Ordering::Relaxed) >= allowed.load(Ordering::Relaxed)
+        })
+    };
+
+    let values: Vec<u32> = (0..10_000).collect();
+    let bytes = serialize(&values).unwrap();
+    assert_eq!(config.deserialize::<Vec<u32>>(&bytes).unwrap(), values);
+    // Once before reading, then every 4096 bytes of the 40_008.
+    assert_eq!(checks.load(Ordering::Relaxed), 10);
+
+    for &(allowed_checks, expect_cancel) in &[(0, true), (5, true), (20, false)] {
+        checks.store(0, Ordering::Relaxed);
+        allowed.store(allowed_checks, Ordering::Relaxed);
+        let results = vec![
+            config.deserialize::<Vec<u32>>(&bytes),
+            config.deserialize_from::<_, Vec<u32>>(&mut &bytes[..]),
+        ];
+        for result in results {
+            match result {
+                Err(ref e) if expect_cancel => match **e {
+                    ErrorKind::Cancelled => {}
+                    ref e => panic!("expected Cancelled, got {:?}", e),
+                },
+                Ok(ref read) if !expect_cancel => assert_eq!(read, &values),
+                ref other => panic!("unexpected result {:?}", other.as_ref().map(Vec::len)),
+            }
+            checks.store(0, Ordering::Relaxed);
+        }
+    }
+
+    assert_eq!(config.config(), &bincode2::config());
+    assert!(format!("{:?}", config).starts_with("CancellableConfig"));
+
+    // The limit of the config applies to slices as well as readers.
+    let mut limited = bincode2::config();
+    limited.limit(1000);
+    let config = limited.with_cancel_token(|| false);
+    match *config.deserialize::<Vec<u32>>(&bytes).unwrap_err() {
+        ErrorKind::SizeLimit { limit: 1000, .. } => {}
+        ref e => panic!("unexpected error {:?}", e),
+    }
+    assert!(config
+        .deserialize_from::<_, Vec<u32>>(&mut &bytes[..])
+        .is_err());
+}
+
+#[test]
+fn test_layers() {
+    use bincode2::layer::FrameLayer;
+
+    /// XORs every byte with a key.
+    struct Xor(u8);
+
+    impl FrameLayer for Xor {
+        fn encode(&self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+            output.extend(input.iter().map(|b| b ^ self.0));
+            Ok(())
+        }
+
+        fn decode(&self, input: &[u8], output: &mut Vec<u8>, _limit: Option<u64>) -> Result<()> {
+            self.encode(input, output)
+        }
+    }
+
+    /// Prefixes the length of the frame as a byte, so that frames can follow
+    /// each other in a stream.
+    struct Framed;
+
+    impl FrameLayer for Framed {
+        fn encode(&self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+            output.push(input.len() as u8);
+            output.extend_from_slice(input);
+            Ok(())
+        }
+
+        fn decode(&self, input: &[u8], output: &mut Vec<u8>, _limit: Option<u64>) -> Result<()> {
+            output.extend_from_slice(&input[1..]);
+            Ok(())
+        }
+
+        fn encode_to(&self, input: &[u8], output: &mut dyn core2::io::Write) -> Result<()> {
+            output.write_all(&[input.len() as u8])?;
+            output.write_all(input)?;
+            Ok(())
+        }
+
+        fn decode_from(
+            &self,
+            input: &mut dyn core2::io::Read,
+            output: &mut Vec<u8>,
+            _limit: Option<u64>,
+        ) -> Result<()> {
+            let mut len = [0];
+            input.read_exact(&mut len)?;
+            output.resize(len[0] as usize, 0);
+            input.read_exact(output)?;
+            Ok(())
+        }
+    }
+
+    /// Repeats each byte as many times as the byte before it says, without
+    /// looking at the limit.
+    struct Expand;
+
+    impl FrameLayer for Expand {
+        fn encode(&self, input: &[u8], output: &mut Vec<u8>) -> Result<()> {
+            for &byte in input {
+                output.extend_from_slice(&[1, byte]);
+            }
+            Ok(())
+        }
+
+        fn decode(&self, input: &[u8], output: &mut Vec<u8>, _limit: Option<u64>) -> Result<()> {
+            for pair in input.chunks(2) {
+                output.extend(std::iter::repeat(pair[1]).take(pair[0] as usize));
+            }
+            Ok(())
+        }
+    }
+
+    let value = (1u16, "ab".to_string());
+    let plain = config().serialize(&value).unwrap();
+    let layered = config().with_layers(vec![Box::new(Xor(0xff)), Box::new(Framed)]);
+
+    // The first layer is applied first.
+    let bytes = layered.serialize(&value).unwrap();
+    let mut expected = vec![plain.len() as u8];
+    expected.extend(plain.iter().map(|b| b ^ 0xff));
+    assert_eq!(bytes, expected);
+    assert_eq!(layered.deserialize::<(u16, String)>(&bytes).unwrap(), value);
+
+    // Streams of frames, read one at a time by the outermost layer.
+    let mut stream = Vec::new();
+    layered.serialize_into(&mut stream, &value).unwrap();
+    layered
+        .serialize_into(&mut stream, &(2u16, "c".to_string()))
+        .unwrap();
+    assert_eq!(&stream[..bytes.len()], &bytes[..]);
+    let mut reader = &stream[..];
+    let first: (u16, String) = layered.deserialize_from(&mut reader).unwrap();
+    let second: (u16, String) = layered.deserialize_from(&mut reader).unwrap();
+    assert_eq!((first, second), (value.clone(), (2, "c".to_string())));
+    assert!(reader.is_empty());
+
+    // The limit applies to the value, not to the layered bytes.
+    let mut limited = config();
+    limited.limit(plain.len() as u64);
+    let layered = limited.with_layers(vec![Box::new(Xor(1)), Box::new(Framed)]);
+    let bytes = layered.serialize(&value).unwrap();
+    assert!(bytes.len() > plain.len());
+    assert_eq!(layered.deserialize::<(u16, String)>(&bytes).unwrap(), value);
+    limited.limit(plain.len() as u64 - 1);
+    let layered = limited.with_layers(vec![Box::new(Xor(1)), Box::new(Framed)]);
+    match *layered.deserialize::<(u16, String)>(&bytes).unwrap_err() {
+        ErrorKind::SizeLimit { .. } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // The limit bounds what each layer produces, before the next one runs.
+    let mut limited = config();
+    limited.limit(64);
+    let layered = limited.with_layers(vec![Box::new(Expand), Box::new(Xor(1))]);
+    let bomb: Vec<u8> = [255, 0].iter().cycle().take(20).map(|b| b ^ 1).collect();
+    match *layered.deserialize::<Vec<u8>>(&bomb).unwrap_err() {
+        ErrorKind::SizeLimit { limit: 64, .. } => {}
+        ref other => panic!("unexpected error: {:?}", other),
+    }
+
+    // The default framing of the outermost layer lets frames follow each other.
+    let layered = config().with_layers(vec![Box::new(Xor(0xff))]);
+    let mut stream = Vec::new();
+    layered.serialize_into(&mut stream, &value).unwrap();
+    layered.serialize_into(&mut stream, &value).unwrap();
+    assert_eq!(&stream[..8], &(plain.len() as u64).to_le_bytes());
+    let mut reader = &stream[..];
+    for _ in 0..2 {
+        assert_eq!(
+            layered
+                .deserialize_from::<_, (u16, String)>(&mut reader)
+                .unwrap(),
+            value
+        );
+    }
+    assert!(reader.is_empty());
+
+    // Without layers, the config is used as it is.
+    let bare = config().with_layers(Vec::new());
+    assert_eq!(bare.serialize(&value).unwrap(), plain);
+    assert!(format!("{:?}", layered).contains("Xor"));
+}
+
+#[cfg(feature = "compact-error")]
+#[test]
+fn test_compact_error() {
+    use bincode2::{ErrorMessage, ERROR_MESSAGE_CAPACITY};
+    use serde::de::Error;
+
+    // Errors are returned by value.
+    match deserialize::<bool>(&[2]).unwrap_err() {
+        ErrorKind::InvalidBoolEncoding(2) => {}
+        e => panic!("expected InvalidBoolEncoding, got {:?}", e),
+    }
+
+    let err = bincode2::Error::custom(format_args!("no variant {}", 7));
+    assert_eq!(err.to_string(), "no variant 7");
+
+    // Long messages are cut at the last whole character that fits.
+    let long = "é".repeat(ERROR_MESSAGE_CAPACITY);
+    let message = ErrorMessage::new(&long);
+    assert_eq!(message.len(), ERROR_MESSAGE_CAPACITY);
+    assert!(long.starts_with(&*message));
+    let message = ErrorMessage::new(&format_args!("x{}", long));
+    assert_eq!(message.len(), ERROR_MESSAGE_CAPACITY - 1);
+    match bincode2::Error::custom(&long) {
+        ErrorKind::Custom(ref m) => assert_eq!(m.as_str(), &*ErrorMessage::new(&long)),
+        e => panic!("expected Custom, got {:?}", e),
+    }
+}
+
+#[test]
+fn test_nested_message() {
+    use bincode2::NestedMessage;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Inner {
+        id: u32,
+        tags: Vec<String>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Envelope<'a> {
+        route: u16,
+        #[serde(borrow)]
+        payload: NestedMessage<'a>,
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Plain {
+        route: u16,
+        #[serde(with = "serde_bytes")]
+        payload: Vec<u8>,
+    }
+
+    let mut config = config();
+    config.array_length(LengthOption::U16);
+    let inner = Inner {
+        id: 9,
+        tags: vec!["a".to_string(), "bc".to_string()],
+    };
+    let payload = NestedMessage::from_value(&config, &inner).unwrap();
+    assert_eq!(payload.as_bytes(), &config.serialize(&inner).unwrap()[..]);
+
+    // Written as a byte array holding the encoded message.
+    let envelope = Envelope { route: 1, payload };
+    let bytes = config.serialize(&envelope).unwrap();
+    let plain: Plain = config.deserialize(&bytes).unwrap();
+    assert_eq!(plain.payload, envelope.payload.as_bytes());
+
+    // Read from a slice the payload is borrowed, and forwarded verbatim.
+    let read: Envelope = config.deserialize(&bytes).unwrap();
+    assert_eq!(read.payload.as_bytes().as_ptr(), bytes[4..].as_ptr());
+    assert_eq!(config.serialize(&read).unwrap(), bytes);
+    assert_eq!(read.payload.to_value::<Inner>(&config).unwrap(), inner);
+
+    // With `force_owned` it is copied.
+    config.force_owned();
+    let read: Envelope = config.deserialize(&bytes).unwrap();
+    assert_ne!(read.payload.as_bytes().as_ptr(), bytes[4..].as_ptr());
+    assert_eq!(read, envelope);
+    assert_eq!(
+        read.payload.into_owned(),
+        NestedMessage::from_bytes(&plain.payload)
+    );
+}
+
+#[cfg(feature = "testutil")]
+#[test]
+fn test_testutil() {
+    use bincode2::testutil::{all_configs, assert_roundtrip, assert_roundtrip_all_configs};
+    use bincode2::Config;
+    use proptest::prelude::*;
+    use proptest::test_runner::TestRunner;
+
+    assert_eq!(all_configs().len(), 3 * 4 * 4 * 3);
+    assert_roundtrip_all_configs(&(1u8, "a".repeat(300), vec![7u16; 300]));
+
+    let values = (
+        any::<i64>(),
+        ".*",
+        prop::collection::vec(any::<u8>(), 0..512),
+    );
+    TestRunner::default()
+        .run(&(values, any::<Config>()), |(value, config)| {
+            assert_roundtrip(&value, &config);
+            Ok(())
+        })
+        .unwrap();
+}
+
+#[cfg(feature = "testutil")]
+#[test]
+#[should_panic(expected = "failed to deserialize")]
+fn test_testutil_mismatch() {
+    use bincode2::testutil::assert_roundtrip;
+    use serde::Serialize;
+
+    /// Written as a `u8` but read as a `u16`.
+    #[derive(PartialEq, Debug)]
+    struct Lopsided(u8);
+
+    impl Serialize for Lopsided {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+            serializer.serialize_u8(self.0)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Lopsided {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> StdResult<Lopsided, D::Error> {
+            u16::deserialize(deserializer).map(|v| Lopsided(v as u8))
+        }
+    }
+
+    assert_roundtrip(&Lopsided(1), &config());
+}